@@ -14,11 +14,11 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use axum::body::{Body, BoxBody};
+use axum::body::{Body, BoxBody, StreamBody};
 use axum::extract::{Path, Query, State};
 use axum::http::{Method, Request, Response, StatusCode};
 use axum::response::sse::{Event, KeepAlive};
-use axum::response::{Html, Sse};
+use axum::response::{Html, IntoResponse, Sse};
 use axum::routing::{get, post, put};
 use axum::{Json, Router};
 use clap::Args;
@@ -119,6 +119,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
 
     let app = Router::new()
         .route("/text-query", post(text_query))
+        .route("/text-query-stream", post(text_query_stream))
         .route("/export/:relations", get(export_relations))
         .route("/import", put(import_relations))
         .route("/backup", post(backup))
@@ -131,6 +132,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
         ) // +keep alive
         .route("/transact", post(start_transact))
         .route("/transact/:id", post(transact_query).put(finish_query))
+        .route("/transact/:id/savepoint", post(transact_savepoint))
         .with_state(state)
         .layer(RequireAuthorizationLayer::custom(
             move |request: &mut Request<Body>| {
@@ -245,6 +247,45 @@ async fn transact_query(
     }
 }
 
+#[derive(serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SavepointOp {
+    Savepoint,
+    RollbackTo,
+    Release,
+}
+
+#[derive(serde_derive::Deserialize)]
+struct SavepointPayload {
+    op: SavepointOp,
+    name: String,
+}
+
+async fn transact_savepoint(
+    State(st): State<DbState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<SavepointPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let tx = match st.txs.lock().unwrap().get(&id) {
+        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(tx) => tx.clone(),
+    };
+    let result = spawn_blocking(move || match payload.op {
+        SavepointOp::Savepoint => tx.savepoint(&payload.name),
+        SavepointOp::RollbackTo => tx.rollback_to(&payload.name),
+        SavepointOp::Release => tx.release(&payload.name),
+    })
+    .await;
+    match result {
+        Ok(Ok(_)) => (StatusCode::OK, json!({"ok": true}).into()),
+        Ok(Err(err)) => (
+            StatusCode::BAD_REQUEST,
+            json!({"ok": false, "message": err.to_string()}).into(),
+        ),
+        Err(err) => internal_error(err),
+    }
+}
+
 #[derive(serde_derive::Deserialize)]
 struct FinishTransactPayload {
     abort: bool,
@@ -295,6 +336,63 @@ async fn text_query(
     }
 }
 
+/// Streams the result as newline-delimited JSON: a line holding the headers,
+/// followed by a line for each row. An error found while the rows are computed
+/// ends the stream with a line holding the error.
+async fn text_query_stream(
+    State(st): State<DbState>,
+    Json(payload): Json<QueryPayload>,
+) -> axum::response::Response {
+    let params = payload
+        .params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect();
+    let script = payload.script;
+    let result = spawn_blocking({
+        let script = script.clone();
+        move || {
+            st.db
+                .run_script_iter(&script, params)
+                .map_err(|err| format_error_as_json(err, Some(&script)))
+        }
+    })
+    .await;
+    let rows = match result {
+        Ok(Ok(rows)) => rows,
+        Ok(Err(err)) => return wrap_json(err).into_response(),
+        Err(err) => return internal_error(err).into_response(),
+    };
+    let headers = json!({ "headers": rows.headers });
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+    spawn_blocking(move || {
+        for row in rows {
+            let line = match row {
+                Ok(row) => row
+                    .into_iter()
+                    .map(serde_json::Value::from)
+                    .collect::<serde_json::Value>(),
+                Err(err) => format_error_as_json(err, Some(&script)),
+            };
+            // the client is gone, and dropping the rows kills the query
+            if sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let stream = async_stream::stream! {
+        yield Ok::<_, Infallible>(format!("{}\n", headers));
+        while let Some(line) = receiver.recv().await {
+            yield Ok(format!("{}\n", line));
+        }
+    };
+    (
+        [("content-type", "application/x-ndjson")],
+        StreamBody::new(stream),
+    )
+        .into_response()
+}
+
 async fn export_relations(
     State(st): State<DbState>,
     Path(relations): Path<String>,
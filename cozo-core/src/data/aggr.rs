@@ -6,49 +6,97 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
 use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
 
+use itertools::Itertools;
 use miette::{bail, ensure, miette, Result};
 use rand::prelude::*;
 
-use crate::data::value::DataValue;
+use crate::data::decimal::Decimal;
+use crate::data::functions::{int_from_i128, IntegerOverflowError};
+use crate::data::program::ArithMode;
+use crate::data::sketch::{HyperLogLog, TDigest};
+use crate::data::value::{DataValue, Num};
+use crate::utils::{arith_mode, with_rng};
 
-pub(crate) struct Aggregation {
-    pub(crate) name: &'static str,
+pub(crate) struct Aggr {
+    pub(crate) name: Cow<'static, str>,
     pub(crate) is_meet: bool,
     pub(crate) meet_op: Option<Box<dyn MeetAggrObj>>,
     pub(crate) normal_op: Option<Box<dyn NormalAggrObj>>,
+    pub(crate) custom: Option<Arc<dyn Aggregation>>,
 }
 
-impl Clone for Aggregation {
+impl Clone for Aggr {
     fn clone(&self) -> Self {
         Self {
-            name: self.name,
+            name: self.name.clone(),
             is_meet: self.is_meet,
             meet_op: None,
             normal_op: None,
+            custom: self.custom.clone(),
         }
     }
 }
 
-pub(crate) trait NormalAggrObj: Send + Sync {
+/// Implement this trait and register the implementation with
+/// [`Db::register_aggregation`](crate::Db::register_aggregation) to use custom aggregations
+/// in rule heads.
+pub trait Aggregation: Send + Sync {
+    /// Create the state for aggregating a group of values, used in non-recursive rules.
+    /// `args` are the arguments after the aggregated variable in the rule head,
+    /// as in `collect(x, 10)`.
+    fn normal_init(&self, args: &[DataValue]) -> Result<Box<dyn NormalAggrObj>>;
+    /// Create the meet operation of the aggregation, if it has one.
+    fn meet_init(&self, _args: &[DataValue]) -> Result<Option<Box<dyn MeetAggrObj>>> {
+        Ok(None)
+    }
+    /// Whether the meet operation is idempotent, commutative and associative.
+    /// Only then is the meet operation used, and the aggregation usable in recursive rules.
+    fn is_idempotent(&self) -> bool {
+        false
+    }
+}
+
+/// The state of a normal aggregation for a group of values.
+pub trait NormalAggrObj: Send + Sync {
+    /// Add a value to the group.
     fn set(&mut self, value: &DataValue) -> Result<()>;
+    /// The result of the aggregation for the values added so far.
     fn get(&self) -> Result<DataValue>;
+    /// The state of the aggregation for the values added so far, so that parts of a group can
+    /// be aggregated separately, as when the partitions of a scan are evaluated in parallel,
+    /// and combined by [`NormalAggrObj::merge`].
+    /// Returns `None` if the aggregation cannot be split this way, which is the default.
+    fn partial(&self) -> Result<Option<DataValue>> {
+        Ok(None)
+    }
+    /// Add the values of another part of the group, whose state is `partial` as returned
+    /// by [`NormalAggrObj::partial`].
+    fn merge(&mut self, _partial: &DataValue) -> Result<()> {
+        bail!("the aggregation cannot be computed in parts")
+    }
 }
 
-pub(crate) trait MeetAggrObj: Send + Sync {
+/// The meet operation of an aggregation, updating the aggregated value in place.
+pub trait MeetAggrObj: Send + Sync {
+    /// The result of the aggregation for an empty group.
     fn init_val(&self) -> DataValue;
+    /// Combine `right` into `left`, returning whether `left` is changed.
     fn update(&self, left: &mut DataValue, right: &DataValue) -> Result<bool>;
 }
 
-impl PartialEq for Aggregation {
+impl PartialEq for Aggr {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name
     }
 }
 
-impl Debug for Aggregation {
+impl Debug for Aggr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Aggr<{}>", self.name)
     }
@@ -56,11 +104,12 @@ impl Debug for Aggregation {
 
 macro_rules! define_aggr {
     ($name:ident, $is_meet:expr) => {
-        const $name: Aggregation = Aggregation {
-            name: stringify!($name),
+        const $name: Aggr = Aggr {
+            name: Cow::Borrowed(stringify!($name)),
             is_meet: $is_meet,
             meet_op: None,
             normal_op: None,
+            custom: None,
         };
     };
 }
@@ -89,6 +138,14 @@ impl NormalAggrObj for AggrAnd {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.accum))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrAnd;
@@ -129,6 +186,14 @@ impl NormalAggrObj for AggrOr {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.accum))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrOr;
@@ -166,6 +231,20 @@ impl NormalAggrObj for AggrUnique {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::List(self.accum.iter().cloned().collect()))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        match partial {
+            DataValue::List(l) => {
+                self.accum.extend(l.iter().cloned());
+                Ok(())
+            }
+            v => bail!("bad partial state for 'unique': {:?}", v),
+        }
+    }
 }
 
 define_aggr!(AGGR_GROUP_COUNT, false);
@@ -212,6 +291,17 @@ impl NormalAggrObj for AggrCountUnique {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.count))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        Ok(Some(DataValue::List(self.accum.iter().cloned().collect())))
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        match partial {
+            DataValue::List(l) => l.iter().try_for_each(|v| self.set(v)),
+            v => bail!("bad partial state for 'count_unique': {:?}", v),
+        }
+    }
 }
 
 define_aggr!(AGGR_UNION, true);
@@ -233,6 +323,14 @@ impl NormalAggrObj for AggrUnion {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::List(self.accum.iter().cloned().collect()))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrUnion;
@@ -302,6 +400,21 @@ impl NormalAggrObj for AggrIntersection {
             Some(l) => Ok(DataValue::List(l.iter().cloned().collect())),
         }
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        Ok(Some(match &self.accum {
+            None => DataValue::Null,
+            Some(l) => DataValue::List(l.iter().cloned().collect()),
+        }))
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        match partial {
+            // a part without values
+            DataValue::Null => Ok(()),
+            l => self.set(l),
+        }
+    }
 }
 
 pub(crate) struct MeetAggrIntersection;
@@ -405,7 +518,7 @@ impl NormalAggrObj for AggrChoiceRand {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         self.count += 1;
         let prob = 1. / (self.count as f64);
-        let rd = thread_rng().gen::<f64>();
+        let rd = with_rng(|rng| rng.gen::<f64>());
         if rd < prob {
             self.value = value.clone();
         }
@@ -433,6 +546,20 @@ impl NormalAggrObj for AggrCount {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.count))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        match partial {
+            DataValue::Num(Num::Int(i)) => {
+                self.count += i;
+                Ok(())
+            }
+            v => bail!("bad partial state for 'count': {:?}", v),
+        }
+    }
 }
 
 define_aggr!(AGGR_VARIANCE, false);
@@ -519,20 +646,55 @@ impl NormalAggrObj for AggrMean {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.sum / (self.count as f64)))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        Ok(Some(DataValue::List(vec![
+            DataValue::from(self.count),
+            DataValue::from(self.sum),
+        ])))
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        match partial {
+            DataValue::List(l) if l.len() == 2 => {
+                self.count += l[0].get_int().unwrap_or_default();
+                self.sum += l[1].get_float().unwrap_or_default();
+                Ok(())
+            }
+            v => bail!("bad partial state for 'mean': {:?}", v),
+        }
+    }
 }
 
 define_aggr!(AGGR_SUM, false);
 
+/// Integers and decimals are summed exactly. The arithmetic mode of the query applies to the
+/// total of the integers, so that it does not depend on the order of the values.
+/// The sum is a decimal if decimals but no floats are encountered, otherwise a float.
 #[derive(Default)]
 pub(crate) struct AggrSum {
-    sum: f64,
+    int_sum: i128,
+    decimal_sum: Option<Decimal>,
+    float_sum: Option<f64>,
 }
 
 impl NormalAggrObj for AggrSum {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         match value {
-            DataValue::Num(n) => {
-                self.sum += n.get_float();
+            DataValue::Num(Num::Int(i)) => {
+                self.int_sum = self
+                    .int_sum
+                    .checked_add(*i as i128)
+                    .ok_or(IntegerOverflowError("addition"))?;
+            }
+            DataValue::Num(Num::Decimal(d)) => {
+                self.decimal_sum = Some(match self.decimal_sum {
+                    None => *d,
+                    Some(sum) => sum.checked_add(*d)?,
+                });
+            }
+            DataValue::Num(Num::Float(f)) => {
+                *self.float_sum.get_or_insert(0.) += f;
             }
             v => bail!("cannot compute 'sum': encountered value {:?}", v),
         }
@@ -540,27 +702,110 @@ impl NormalAggrObj for AggrSum {
     }
 
     fn get(&self) -> Result<DataValue> {
-        Ok(DataValue::from(self.sum))
+        let int_sum = int_from_i128("addition", self.int_sum)?;
+        Ok(match (self.decimal_sum, self.float_sum) {
+            (None, None) => DataValue::from(int_sum),
+            (Some(d), None) => DataValue::Num(Num::Decimal(d.checked_add(Decimal::from(int_sum))?)),
+            (d, f) => DataValue::from(
+                int_sum as f64 + d.map(Decimal::to_f64).unwrap_or(0.) + f.unwrap_or(0.),
+            ),
+        })
+    }
+
+    /// The integer part of the state is a decimal, which can hold an `i128` exactly.
+    fn partial(&self) -> Result<Option<DataValue>> {
+        let opt_num = |n: Option<Num>| n.map(DataValue::Num).unwrap_or(DataValue::Null);
+        Ok(Some(DataValue::List(vec![
+            DataValue::Num(Num::Decimal(Decimal::new(self.int_sum, 0)?)),
+            opt_num(self.decimal_sum.map(Num::Decimal)),
+            opt_num(self.float_sum.map(Num::Float)),
+        ])))
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        match partial {
+            DataValue::List(l) if l.len() == 3 => {
+                if let DataValue::Num(Num::Decimal(d)) = &l[0] {
+                    self.int_sum = self
+                        .int_sum
+                        .checked_add(d.mantissa())
+                        .ok_or(IntegerOverflowError("addition"))?;
+                }
+                if !matches!(l[1], DataValue::Null) {
+                    self.set(&l[1])?;
+                }
+                if !matches!(l[2], DataValue::Null) {
+                    self.set(&l[2])?;
+                }
+                Ok(())
+            }
+            v => bail!("bad partial state for 'sum': {:?}", v),
+        }
     }
 }
 
 define_aggr!(AGGR_PRODUCT, false);
 
+/// Integers and decimals are multiplied exactly. The arithmetic mode of the query applies to
+/// the product of the integers, so that it does not depend on the order of the values.
+/// The product is a decimal if decimals but no floats are encountered, otherwise a float.
 pub(crate) struct AggrProduct {
-    product: f64,
+    /// `None` once the magnitude of the product exceeds `i128`
+    int_product: Option<i128>,
+    /// the product modulo 2^64, which is exact for wrapping arithmetic
+    wrapped_product: i64,
+    negative: bool,
+    has_zero: bool,
+    decimal_product: Option<Decimal>,
+    float_product: Option<f64>,
 }
 
 impl Default for AggrProduct {
     fn default() -> Self {
-        Self { product: 1.0 }
+        Self {
+            int_product: Some(1),
+            wrapped_product: 1,
+            negative: false,
+            has_zero: false,
+            decimal_product: None,
+            float_product: None,
+        }
+    }
+}
+
+impl AggrProduct {
+    fn int_result(&self) -> Result<i64> {
+        if self.has_zero {
+            return Ok(0);
+        }
+        match self.int_product {
+            Some(p) => int_from_i128("multiplication", p),
+            None => match arith_mode() {
+                ArithMode::Checked => bail!(IntegerOverflowError("multiplication")),
+                ArithMode::Wrapping => Ok(self.wrapped_product),
+                ArithMode::Saturating => Ok(if self.negative { i64::MIN } else { i64::MAX }),
+            },
+        }
     }
 }
 
 impl NormalAggrObj for AggrProduct {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         match value {
-            DataValue::Num(n) => {
-                self.product *= n.get_float();
+            DataValue::Num(Num::Int(i)) => {
+                self.int_product = self.int_product.and_then(|p| p.checked_mul(*i as i128));
+                self.wrapped_product = self.wrapped_product.wrapping_mul(*i);
+                self.negative ^= *i < 0;
+                self.has_zero |= *i == 0;
+            }
+            DataValue::Num(Num::Decimal(d)) => {
+                self.decimal_product = Some(match self.decimal_product {
+                    None => *d,
+                    Some(product) => product.checked_mul(*d)?,
+                });
+            }
+            DataValue::Num(Num::Float(f)) => {
+                *self.float_product.get_or_insert(1.) *= f;
             }
             v => bail!("cannot compute 'product': encountered value {:?}", v),
         }
@@ -568,7 +813,16 @@ impl NormalAggrObj for AggrProduct {
     }
 
     fn get(&self) -> Result<DataValue> {
-        Ok(DataValue::from(self.product))
+        let int_product = self.int_result()?;
+        Ok(match (self.decimal_product, self.float_product) {
+            (None, None) => DataValue::from(int_product),
+            (Some(d), None) => {
+                DataValue::Num(Num::Decimal(d.checked_mul(Decimal::from(int_product))?))
+            }
+            (d, f) => DataValue::from(
+                int_product as f64 * d.map(Decimal::to_f64).unwrap_or(1.) * f.unwrap_or(1.),
+            ),
+        })
     }
 }
 
@@ -611,6 +865,14 @@ impl NormalAggrObj for AggrMin {
     fn get(&self) -> Result<DataValue> {
         Ok(self.found.clone())
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrMin;
@@ -683,6 +945,14 @@ impl NormalAggrObj for AggrMax {
     fn get(&self) -> Result<DataValue> {
         Ok(self.found.clone())
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrMax;
@@ -1015,6 +1285,18 @@ impl NormalAggrObj for AggrBitAnd {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::Bytes(self.res.clone()))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        // a part without values
+        if partial == &DataValue::Bytes(vec![]) {
+            return Ok(());
+        }
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrBitAnd;
@@ -1084,6 +1366,18 @@ impl NormalAggrObj for AggrBitOr {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::Bytes(self.res.clone()))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        // a part without values
+        if partial == &DataValue::Bytes(vec![]) {
+            return Ok(());
+        }
+        self.set(partial)
+    }
 }
 
 pub(crate) struct MeetAggrBitOr;
@@ -1153,9 +1447,361 @@ impl NormalAggrObj for AggrBitXor {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::Bytes(self.res.clone()))
     }
+
+    fn partial(&self) -> Result<Option<DataValue>> {
+        self.get().map(Some)
+    }
+
+    fn merge(&mut self, partial: &DataValue) -> Result<()> {
+        // a part without values
+        if partial == &DataValue::Bytes(vec![]) {
+            return Ok(());
+        }
+        self.set(partial)
+    }
 }
 
-pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
+define_aggr!(AGGR_MEDIAN, false);
+
+define_aggr!(AGGR_PERCENTILE, false);
+
+pub(crate) struct AggrPercentile {
+    name: &'static str,
+    p: f64,
+    values: Vec<f64>,
+}
+
+impl AggrPercentile {
+    fn new(name: &'static str, p: f64) -> Self {
+        Self {
+            name,
+            p,
+            values: vec![],
+        }
+    }
+}
+
+impl NormalAggrObj for AggrPercentile {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Num(n) => self.values.push(n.get_float()),
+            v => bail!("cannot compute '{}': encountered value {:?}", self.name, v),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        if self.values.is_empty() {
+            return Ok(DataValue::Null);
+        }
+        let mut values = self.values.clone();
+        values.sort_by(|a, b| a.total_cmp(b));
+        // linear interpolation between the closest ranks
+        let rank = self.p * (values.len() - 1) as f64;
+        let lower = rank.floor() as usize;
+        let upper = rank.ceil() as usize;
+        let frac = rank - lower as f64;
+        Ok(DataValue::from(
+            values[lower] + (values[upper] - values[lower]) * frac,
+        ))
+    }
+}
+
+define_aggr!(AGGR_APPROX_PERCENTILE, false);
+
+pub(crate) struct AggrApproxPercentile {
+    p: f64,
+    digest: TDigest,
+}
+
+impl NormalAggrObj for AggrApproxPercentile {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Num(n) => self.digest.add(n.get_float()),
+            v => bail!(
+                "cannot compute 'approx_percentile': encountered value {:?}",
+                v
+            ),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match self.digest.quantile(self.p) {
+            None => DataValue::Null,
+            Some(f) => DataValue::from(f),
+        })
+    }
+}
+
+fn percentile_arg(name: &str, args: &[DataValue]) -> Result<f64> {
+    let p = args
+        .first()
+        .and_then(|v| v.get_float())
+        .ok_or_else(|| miette!("'{}' requires a percentile argument", name))?;
+    ensure!(
+        (0. ..=1.).contains(&p),
+        "the percentile argument to '{}' must be between 0 and 1, got {}",
+        name,
+        p
+    );
+    Ok(p)
+}
+
+define_aggr!(AGGR_APPROX_COUNT_DISTINCT, false);
+
+#[derive(Default)]
+pub(crate) struct AggrApproxCountDistinct {
+    hll: HyperLogLog,
+}
+
+impl NormalAggrObj for AggrApproxCountDistinct {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.hll.add(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.hll.count() as i64))
+    }
+}
+
+define_aggr!(AGGR_MODE, false);
+
+#[derive(Default)]
+pub(crate) struct AggrMode {
+    counts: BTreeMap<DataValue, usize>,
+}
+
+impl NormalAggrObj for AggrMode {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        *self.counts.entry(value.clone()).or_default() += 1;
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        // ties are broken by taking the smallest value
+        let found = self
+            .counts
+            .iter()
+            .max_by(|(v1, c1), (v2, c2)| c1.cmp(c2).then_with(|| v2.cmp(v1)));
+        Ok(found.map_or(DataValue::Null, |(v, _)| v.clone()))
+    }
+}
+
+/// The central moments of a group of numbers, updated one number at a time as in Welford's
+/// algorithm, extended to the third and fourth moments by Terriberry. Unlike moments computed
+/// from power sums, these do not cancel out catastrophically when the mean is large.
+#[derive(Default)]
+pub(crate) struct Moments {
+    count: f64,
+    mean: f64,
+    /// Sums of the powers of the deviations from the mean, from the second to the fourth
+    m2: f64,
+    m3: f64,
+    m4: f64,
+}
+
+impl Moments {
+    fn add(&mut self, name: &str, value: &DataValue) -> Result<()> {
+        let x = match value {
+            DataValue::Num(n) => n.get_float(),
+            v => bail!("cannot compute '{}': encountered value {:?}", name, v),
+        };
+        let n1 = self.count;
+        self.count += 1.;
+        let n = self.count;
+        let delta = x - self.mean;
+        let delta_n = delta / n;
+        let delta_n2 = delta_n * delta_n;
+        let term = delta * delta_n * n1;
+        self.mean += delta_n;
+        self.m4 += term * delta_n2 * (n * n - 3. * n + 3.) + 6. * delta_n2 * self.m2
+            - 4. * delta_n * self.m3;
+        self.m3 += term * delta_n * (n - 2.) - 3. * delta_n * self.m2;
+        self.m2 += term;
+        Ok(())
+    }
+    /// The second to fourth central moments, or `None` if there are fewer than two numbers
+    /// or they are all the same, in which case the shape of the distribution is undefined
+    fn central_moments(&self) -> Option<(f64, f64, f64)> {
+        if self.count < 2. || self.m2 == 0. {
+            return None;
+        }
+        let n = self.count;
+        Some((self.m2 / n, self.m3 / n, self.m4 / n))
+    }
+}
+
+define_aggr!(AGGR_SKEWNESS, false);
+
+#[derive(Default)]
+pub(crate) struct AggrSkewness {
+    moments: Moments,
+}
+
+impl NormalAggrObj for AggrSkewness {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("skewness", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match self.moments.central_moments() {
+            Some((m2, m3, _)) => DataValue::from(m3 / m2.powf(1.5)),
+            None => DataValue::Null,
+        })
+    }
+}
+
+define_aggr!(AGGR_KURTOSIS, false);
+
+#[derive(Default)]
+pub(crate) struct AggrKurtosis {
+    moments: Moments,
+}
+
+impl NormalAggrObj for AggrKurtosis {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("kurtosis", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match self.moments.central_moments() {
+            Some((m2, _, m4)) => DataValue::from(m4 / (m2 * m2) - 3.),
+            None => DataValue::Null,
+        })
+    }
+}
+
+/// The co-moments of a group of pairs of numbers, for computing their covariance and
+/// correlation, updated one pair at a time as in Welford's algorithm.
+#[derive(Default)]
+pub(crate) struct CoMoments {
+    count: f64,
+    mean_x: f64,
+    mean_y: f64,
+    /// Sums of the products of the deviations from the means
+    m_xx: f64,
+    m_yy: f64,
+    m_xy: f64,
+}
+
+impl CoMoments {
+    fn add(&mut self, name: &str, value: &DataValue) -> Result<()> {
+        if let DataValue::List(l) = value {
+            if let [DataValue::Num(x), DataValue::Num(y)] = l.as_slice() {
+                let (x, y) = (x.get_float(), y.get_float());
+                self.count += 1.;
+                let dx = x - self.mean_x;
+                let dy = y - self.mean_y;
+                self.mean_x += dx / self.count;
+                self.mean_y += dy / self.count;
+                self.m_xx += dx * (x - self.mean_x);
+                self.m_yy += dy * (y - self.mean_y);
+                self.m_xy += dx * (y - self.mean_y);
+                return Ok(());
+            }
+        }
+        bail!(
+            "'{}' requires pairs of numbers as a list of length two, got {:?}",
+            name,
+            value
+        )
+    }
+}
+
+define_aggr!(AGGR_COVARIANCE, false);
+
+#[derive(Default)]
+pub(crate) struct AggrCovariance {
+    moments: CoMoments,
+}
+
+impl NormalAggrObj for AggrCovariance {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("covariance", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let m = &self.moments;
+        // the sample covariance is undefined for a single pair
+        if m.count < 2. {
+            return Ok(DataValue::Null);
+        }
+        Ok(DataValue::from(m.m_xy / (m.count - 1.)))
+    }
+}
+
+define_aggr!(AGGR_CORR, false);
+
+#[derive(Default)]
+pub(crate) struct AggrCorr {
+    moments: CoMoments,
+}
+
+impl NormalAggrObj for AggrCorr {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("corr", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let m = &self.moments;
+        // undefined unless both sides vary
+        if m.count < 2. || m.m_xx == 0. || m.m_yy == 0. {
+            return Ok(DataValue::Null);
+        }
+        Ok(DataValue::from(m.m_xy / (m.m_xx * m.m_yy).sqrt()))
+    }
+}
+
+define_aggr!(AGGR_STRING_AGG, false);
+
+pub(crate) struct AggrStringAgg {
+    separator: String,
+    parts: Vec<String>,
+}
+
+impl NormalAggrObj for AggrStringAgg {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Str(s) => self.parts.push(s.to_string()),
+            v => bail!("cannot compute 'string_agg': encountered value {:?}", v),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.parts.join(&self.separator)))
+    }
+}
+
+define_aggr!(AGGR_TOP_K, false);
+
+pub(crate) struct AggrTopK {
+    k: usize,
+    heap: BinaryHeap<Reverse<DataValue>>,
+}
+
+impl NormalAggrObj for AggrTopK {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        if self.heap.len() < self.k {
+            self.heap.push(Reverse(value.clone()));
+        } else if let Some(mut smallest) = self.heap.peek_mut() {
+            if smallest.0 < *value {
+                *smallest = Reverse(value.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let mut values = self.heap.iter().map(|v| v.0.clone()).collect_vec();
+        values.sort_by(|a, b| b.cmp(a));
+        Ok(DataValue::List(values))
+    }
+}
+
+pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggr> {
     Some(match name {
         "and" => &AGGR_AND,
         "or" => &AGGR_OR,
@@ -1182,13 +1828,50 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "latest_by" => &AGGR_LATEST_BY,
         "smallest_by" => &AGGR_SMALLEST_BY,
         "choice_rand" => &AGGR_CHOICE_RAND,
+        "median" => &AGGR_MEDIAN,
+        "percentile" => &AGGR_PERCENTILE,
+        "approx_percentile" => &AGGR_APPROX_PERCENTILE,
+        "approx_count_distinct" => &AGGR_APPROX_COUNT_DISTINCT,
+        "mode" => &AGGR_MODE,
+        "skewness" => &AGGR_SKEWNESS,
+        "kurtosis" => &AGGR_KURTOSIS,
+        "covariance" => &AGGR_COVARIANCE,
+        "corr" => &AGGR_CORR,
+        "string_agg" => &AGGR_STRING_AGG,
+        "top_k" => &AGGR_TOP_K,
         _ => return None,
     })
 }
 
-impl Aggregation {
-    pub(crate) fn meet_init(&mut self, _args: &[DataValue]) -> Result<()> {
-        self.meet_op.replace(match self.name {
+impl Aggr {
+    pub(crate) fn new_custom(name: &str, aggr: Arc<dyn Aggregation>) -> Self {
+        Self {
+            name: Cow::Owned(name.to_string()),
+            is_meet: aggr.is_idempotent(),
+            meet_op: None,
+            normal_op: None,
+            custom: Some(aggr),
+        }
+    }
+    /// The name of the aggregation as written in queries
+    pub(crate) fn query_name(&self) -> Cow<'_, str> {
+        match self.name.strip_prefix("AGGR_") {
+            Some(name) if self.custom.is_none() => Cow::Owned(name.to_ascii_lowercase()),
+            _ => Cow::Borrowed(&self.name),
+        }
+    }
+    pub(crate) fn meet_init(&mut self, args: &[DataValue]) -> Result<()> {
+        if let Some(custom) = &self.custom {
+            let op = custom.meet_init(args)?.ok_or_else(|| {
+                miette!(
+                    "aggregation '{}' is declared idempotent but has no meet operation",
+                    self.name
+                )
+            })?;
+            self.meet_op.replace(op);
+            return Ok(());
+        }
+        self.meet_op.replace(match &*self.name {
             name if name == AGGR_AND.name => Box::new(MeetAggrAnd),
             name if name == AGGR_OR.name => Box::new(MeetAggrOr),
             name if name == AGGR_MIN.name => Box::new(MeetAggrMin),
@@ -1205,8 +1888,12 @@ impl Aggregation {
         Ok(())
     }
     pub(crate) fn normal_init(&mut self, args: &[DataValue]) -> Result<()> {
+        if let Some(custom) = &self.custom {
+            self.normal_op.replace(custom.normal_init(args)?);
+            return Ok(());
+        }
         #[allow(clippy::box_default)]
-        self.normal_op.replace(match self.name {
+        self.normal_op.replace(match &*self.name {
             name if name == AGGR_AND.name => Box::new(AggrAnd::default()),
             name if name == AGGR_OR.name => Box::new(AggrOr::default()),
             name if name == AGGR_COUNT.name => Box::new(AggrCount::default()),
@@ -1231,6 +1918,44 @@ impl Aggregation {
             name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
             name if name == AGGR_SMALLEST_BY.name => Box::new(AggrSmallestBy::default()),
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
+            name if name == AGGR_MEDIAN.name => Box::new(AggrPercentile::new("median", 0.5)),
+            name if name == AGGR_PERCENTILE.name => Box::new(AggrPercentile::new(
+                "percentile",
+                percentile_arg("percentile", args)?,
+            )),
+            name if name == AGGR_APPROX_PERCENTILE.name => Box::new(AggrApproxPercentile {
+                p: percentile_arg("approx_percentile", args)?,
+                digest: TDigest::default(),
+            }),
+            name if name == AGGR_APPROX_COUNT_DISTINCT.name => {
+                Box::new(AggrApproxCountDistinct::default())
+            }
+            name if name == AGGR_MODE.name => Box::new(AggrMode::default()),
+            name if name == AGGR_SKEWNESS.name => Box::new(AggrSkewness::default()),
+            name if name == AGGR_KURTOSIS.name => Box::new(AggrKurtosis::default()),
+            name if name == AGGR_COVARIANCE.name => Box::new(AggrCovariance::default()),
+            name if name == AGGR_CORR.name => Box::new(AggrCorr::default()),
+            name if name == AGGR_STRING_AGG.name => Box::new(AggrStringAgg {
+                separator: match args.first() {
+                    None => ",".to_string(),
+                    Some(DataValue::Str(s)) => s.to_string(),
+                    Some(v) => bail!(
+                        "the separator argument to 'string_agg' must be a string, got {:?}",
+                        v
+                    ),
+                },
+                parts: vec![],
+            }),
+            name if name == AGGR_TOP_K.name => Box::new({
+                let k = args.first().and_then(|v| v.get_int()).ok_or_else(|| {
+                    miette!("'top_k' requires an integer argument for the number of values")
+                })?;
+                ensure!(k > 0, "argument to 'top_k' must be positive, got {}", k);
+                AggrTopK {
+                    k: k as usize,
+                    heap: BinaryHeap::new(),
+                }
+            }),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
                     AggrCollect::default()
@@ -0,0 +1,71 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::{Display, Formatter};
+
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+
+use crate::parse::SourceSpan;
+
+/// How strings are compared, for sorting and for the keys of stored relations.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    serde_derive::Deserialize,
+    serde_derive::Serialize,
+)]
+pub(crate) enum Collation {
+    /// Case-insensitive comparison
+    NoCase,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown collation '{0}'")]
+#[diagnostic(code(parser::unknown_collation))]
+#[diagnostic(help("The only available collation is 'nocase'"))]
+pub(crate) struct UnknownCollation(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+impl Display for Collation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Collation::NoCase => f.write_str("nocase"),
+        }
+    }
+}
+
+impl Collation {
+    pub(crate) fn from_name(name: &str, span: SourceSpan) -> Result<Self> {
+        Ok(match name {
+            "nocase" => Collation::NoCase,
+            _ => return Err(UnknownCollation(name.to_string(), span).into()),
+        })
+    }
+    /// The tag identifying the collation in the memcmp encoding.
+    pub(crate) fn tag(&self) -> u8 {
+        match self {
+            Collation::NoCase => 0x01,
+        }
+    }
+    /// The bytes whose lexicographic order is the order of the collation.
+    /// Strings equal under the collation have the same sort key.
+    pub(crate) fn sort_key(&self, s: &str) -> Vec<u8> {
+        match self {
+            Collation::NoCase => s
+                .chars()
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+                .into(),
+        }
+    }
+}
@@ -0,0 +1,397 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+
+use miette::{bail, ensure, miette, Diagnostic, Result};
+use thiserror::Error;
+
+/// The largest number of digits after the decimal point
+pub(crate) const MAX_SCALE: u8 = 38;
+
+/// A decimal number with a fixed number of digits after the decimal point, i.e. its scale.
+/// Decimals hold up to 38 significant digits exactly.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, serde_derive::Deserialize, serde_derive::Serialize)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u8,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Decimal overflow in {0}")]
+#[diagnostic(code(eval::decimal_overflow))]
+#[diagnostic(help("Decimals hold up to 38 significant digits"))]
+pub(crate) struct DecimalOverflowError(pub(crate) &'static str);
+
+fn pow10(exp: u8) -> i128 {
+    10i128.pow(exp as u32)
+}
+
+/// Divide, rounding half away from zero.
+fn div_round(n: i128, d: i128) -> i128 {
+    let q = n / d;
+    let r = n % d;
+    if r.unsigned_abs() >= d.unsigned_abs() - r.unsigned_abs() {
+        if (n < 0) == (d < 0) {
+            q + 1
+        } else {
+            q - 1
+        }
+    } else {
+        q
+    }
+}
+
+impl Decimal {
+    /// The decimal `mantissa * 10^(-scale)`
+    pub fn new(mantissa: i128, scale: u8) -> Result<Self> {
+        ensure!(
+            scale <= MAX_SCALE,
+            "the scale of a decimal must be at most {}, got {}",
+            MAX_SCALE,
+            scale
+        );
+        Ok(Self { mantissa, scale })
+    }
+    /// The value is `mantissa * 10^(-scale)`
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+    /// The number of digits after the decimal point
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+    pub(crate) fn from_f64(f: f64) -> Result<Self> {
+        ensure!(f.is_finite(), "cannot convert {} to decimal", f);
+        // the shortest representation that reads back as the same float
+        Decimal::from_str(&format!("{f:?}"))
+    }
+    /// The nearest float
+    pub(crate) fn to_f64(self) -> f64 {
+        // parsing rounds correctly, which keeps the conversion monotonic
+        self.to_string().parse().unwrap()
+    }
+    /// The value if it is an integer
+    pub(crate) fn to_i64(self) -> Option<i64> {
+        let unit = pow10(self.scale);
+        if self.mantissa % unit == 0 {
+            i64::try_from(self.mantissa / unit).ok()
+        } else {
+            None
+        }
+    }
+    /// The same value with the given scale, rounded half away from zero if the scale is reduced.
+    pub(crate) fn rescale(self, scale: u8) -> Result<Self> {
+        ensure!(
+            scale <= MAX_SCALE,
+            "the scale of a decimal must be at most {}, got {}",
+            MAX_SCALE,
+            scale
+        );
+        let mantissa = match scale.cmp(&self.scale) {
+            Ordering::Equal => self.mantissa,
+            Ordering::Less => div_round(self.mantissa, pow10(self.scale - scale)),
+            Ordering::Greater => self
+                .mantissa
+                .checked_mul(pow10(scale - self.scale))
+                .ok_or(DecimalOverflowError("rescaling"))?,
+        };
+        Ok(Self { mantissa, scale })
+    }
+    pub(crate) fn checked_add(self, other: Self) -> Result<Self> {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .rescale(scale)?
+            .mantissa
+            .checked_add(other.rescale(scale)?.mantissa)
+            .ok_or(DecimalOverflowError("addition"))?;
+        Ok(Self { mantissa, scale })
+    }
+    pub(crate) fn checked_sub(self, other: Self) -> Result<Self> {
+        self.checked_add(other.checked_neg()?)
+    }
+    /// The scale of the product is the sum of the scales, up to the largest scale.
+    pub(crate) fn checked_mul(self, other: Self) -> Result<Self> {
+        let mantissa = self
+            .mantissa
+            .checked_mul(other.mantissa)
+            .ok_or(DecimalOverflowError("multiplication"))?;
+        let scale = self.scale as u32 + other.scale as u32;
+        if scale > MAX_SCALE as u32 {
+            let mantissa = div_round(mantissa, pow10((scale - MAX_SCALE as u32) as u8));
+            Ok(Self {
+                mantissa,
+                scale: MAX_SCALE,
+            })
+        } else {
+            Ok(Self {
+                mantissa,
+                scale: scale as u8,
+            })
+        }
+    }
+    /// The quotient has the larger scale of the operands, rounded half away from zero.
+    pub(crate) fn checked_div(self, other: Self) -> Result<Self> {
+        ensure!(other.mantissa != 0, "division of decimal by zero");
+        let scale = self.scale.max(other.scale);
+        // self.mantissa * 10^(scale - self.scale + other.scale) / other.mantissa
+        let shift = scale as u32 - self.scale as u32 + other.scale as u32;
+        let numerator = (0..shift)
+            .try_fold(self.mantissa, |n, _| n.checked_mul(10))
+            .ok_or(DecimalOverflowError("division"))?;
+        Ok(Self {
+            mantissa: div_round(numerator, other.mantissa),
+            scale,
+        })
+    }
+    /// The remainder has the sign of the dividend and the larger scale of the operands.
+    pub(crate) fn checked_rem(self, other: Self) -> Result<Self> {
+        ensure!(other.mantissa != 0, "modulo of decimal by zero");
+        let scale = self.scale.max(other.scale);
+        let (l, r) = (self.rescale(scale)?, other.rescale(scale)?);
+        Ok(Self {
+            mantissa: l.mantissa.wrapping_rem(r.mantissa),
+            scale,
+        })
+    }
+    pub(crate) fn checked_neg(self) -> Result<Self> {
+        let mantissa = self
+            .mantissa
+            .checked_neg()
+            .ok_or(DecimalOverflowError("negation"))?;
+        Ok(Self { mantissa, ..self })
+    }
+    /// The largest integer not greater than the value, with scale 0
+    pub(crate) fn floor(self) -> Self {
+        Self {
+            mantissa: self.split().0,
+            scale: 0,
+        }
+    }
+    /// The smallest integer not less than the value, with scale 0
+    pub(crate) fn ceil(self) -> Self {
+        let (int_part, frac_part) = self.split();
+        Self {
+            mantissa: if frac_part == 0 {
+                int_part
+            } else {
+                int_part + 1
+            },
+            scale: 0,
+        }
+    }
+    pub(crate) fn signum(self) -> i64 {
+        self.mantissa.signum() as i64
+    }
+    /// The integral and fractional parts, with the fractional part in `0..10^scale`.
+    fn split(self) -> (i128, i128) {
+        let unit = pow10(self.scale);
+        (
+            self.mantissa.div_euclid(unit),
+            self.mantissa.rem_euclid(unit),
+        )
+    }
+    /// Compare the values, ignoring the scales.
+    pub(crate) fn value_cmp(&self, other: &Self) -> Ordering {
+        let (l_int, l_frac) = self.split();
+        let (r_int, r_frac) = other.split();
+        let scale = self.scale.max(other.scale);
+        l_int.cmp(&r_int).then_with(|| {
+            (l_frac * pow10(scale - self.scale)).cmp(&(r_frac * pow10(scale - other.scale)))
+        })
+    }
+    /// Compare the value with a float exactly. Floats are dyadic fractions, each with at most
+    /// 767 significant decimal digits, which formatting with enough precision spells out.
+    pub(crate) fn cmp_f64(&self, f: f64) -> Option<Ordering> {
+        if f.is_nan() {
+            return None;
+        }
+        if f.is_infinite() {
+            return Some(if f > 0. {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            });
+        }
+        let formatted = format!("{:.767e}", f.abs());
+        let (digits, exponent) = formatted.split_once('e').unwrap();
+        let mut digits: Vec<u8> = digits
+            .bytes()
+            .filter(u8::is_ascii_digit)
+            .map(|b| b - b'0')
+            .collect();
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let exponent = if digits.is_empty() {
+            0
+        } else {
+            exponent.parse::<i16>().unwrap() + 1
+        };
+        let (negative, l_exponent, l_digits) = self.to_parts();
+        let sign = |negative: bool, digits: &[u8]| match (digits.is_empty(), negative) {
+            (true, _) => 0,
+            (false, true) => -1,
+            (false, false) => 1,
+        };
+        let l_sign = sign(negative, &l_digits);
+        let r_sign = sign(f < 0., &digits);
+        Some(l_sign.cmp(&r_sign).then_with(|| {
+            let magnitude = l_exponent
+                .cmp(&exponent)
+                .then_with(|| l_digits.cmp(&digits));
+            if l_sign < 0 {
+                magnitude.reverse()
+            } else {
+                magnitude
+            }
+        }))
+    }
+    /// The sign, the position of the decimal point and the significant digits, for encoding:
+    /// the value is `0.d1d2d3... * 10^exponent`. The digits of zero are empty.
+    pub(crate) fn to_parts(self) -> (bool, i16, Vec<u8>) {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let digits = digits.trim_end_matches('0');
+        if digits.is_empty() {
+            return (false, 0, vec![]);
+        }
+        let int_digits = self.mantissa.unsigned_abs().to_string().len() as i16;
+        (
+            self.mantissa < 0,
+            int_digits - self.scale as i16,
+            digits.bytes().map(|b| b - b'0').collect(),
+        )
+    }
+    /// The inverse of [`Decimal::to_parts`]
+    pub(crate) fn from_parts(negative: bool, exponent: i16, digits: &[u8], scale: u8) -> Self {
+        let mut mantissa = digits.iter().fold(0i128, |m, d| m * 10 + *d as i128);
+        let trailing = scale as i16 + exponent - digits.len() as i16;
+        for _ in 0..trailing.max(0) {
+            mantissa *= 10;
+        }
+        Self {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale,
+        }
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Self {
+            mantissa: i as i128,
+            scale: 0,
+        }
+    }
+}
+
+/// Decimals are ordered by their values, with ties broken by their scales.
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value_cmp(other)
+            .then_with(|| self.scale.cmp(&other.scale))
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = miette::Error;
+
+    /// Parse numbers like `-12.340` or `1.5e3`. The scale is the number of digits after the
+    /// decimal point, less the exponent.
+    fn from_str(s: &str) -> Result<Self> {
+        let make_err = || miette!("cannot parse {:?} as a decimal", s);
+        let (s, exponent) = match s.find(['e', 'E']) {
+            None => (s, 0),
+            Some(pos) => (
+                &s[..pos],
+                s[pos + 1..].parse::<i32>().map_err(|_| make_err())?,
+            ),
+        };
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int_part, frac_part) = s.split_once('.').unwrap_or((s, ""));
+        if int_part.is_empty() && frac_part.is_empty()
+            || !int_part
+                .bytes()
+                .chain(frac_part.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            bail!(make_err())
+        }
+        let mut mantissa = 0i128;
+        for b in int_part.bytes().chain(frac_part.bytes()) {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add((b - b'0') as i128))
+                .ok_or(DecimalOverflowError("parsing"))?;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+        let scale = frac_part.len() as i32 - exponent;
+        if scale > MAX_SCALE as i32 {
+            // digits beyond the largest scale are rounded off
+            let excess = scale - MAX_SCALE as i32;
+            let mantissa = if excess > MAX_SCALE as i32 {
+                0
+            } else {
+                div_round(mantissa, pow10(excess as u8))
+            };
+            Ok(Self {
+                mantissa,
+                scale: MAX_SCALE,
+            })
+        } else if scale >= 0 {
+            Ok(Self {
+                mantissa,
+                scale: scale as u8,
+            })
+        } else {
+            Self { mantissa, scale: 0 }.checked_mul(Self {
+                mantissa: 10i128
+                    .checked_pow(-scale as u32)
+                    .ok_or(DecimalOverflowError("parsing"))?,
+                scale: 0,
+            })
+        }
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if self.mantissa < 0 {
+            f.write_str("-")?;
+        }
+        if scale == 0 {
+            f.write_str(&digits)
+        } else if digits.len() > scale {
+            let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+            write!(f, "{int_part}.{frac_part}")
+        } else {
+            write!(f, "0.{}{digits}", "0".repeat(scale - digits.len()))
+        }
+    }
+}
+
+impl Debug for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
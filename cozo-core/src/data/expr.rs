@@ -10,16 +10,18 @@ use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::mem;
+use std::sync::Arc;
 
 use itertools::Itertools;
 use miette::{bail, Diagnostic, Result};
 use serde::de::{Error, Visitor};
 use serde::{Deserializer, Serializer};
-use smartstring::SmartString;
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::functions::*;
 use crate::data::symb::Symbol;
+use crate::data::tuple::TupleBatch;
 use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
 use crate::parse::expr::expr2bytecode;
 use crate::parse::SourceSpan;
@@ -44,6 +46,13 @@ pub enum Bytecode {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// pop n, push 1
+    ApplyCustom {
+        func: CustomFunction,
+        arity: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// pop 1
     JumpIfFalse {
         jump_to: usize,
@@ -134,6 +143,15 @@ pub fn eval_bytecode(
                 stack.push(result);
                 pointer += 1;
             }
+            Bytecode::ApplyCustom { func, arity, span } => {
+                let frame_start = stack.len() - *arity;
+                let args_frame = &stack[frame_start..];
+                let result = (func.inner)(args_frame)
+                    .map_err(|err| EvalRaisedError(*span, err.to_string()))?;
+                stack.truncate(frame_start);
+                stack.push(result);
+                pointer += 1;
+            }
             Bytecode::JumpIfFalse { jump_to, span } => {
                 let val = stack.pop().unwrap();
                 let cond = val
@@ -153,6 +171,115 @@ pub fn eval_bytecode(
     Ok(stack.pop().unwrap())
 }
 
+/// Whether the bytecode can be evaluated column by column, i.e. it contains no jumps.
+pub(crate) fn is_vectorizable(bytecodes: &[Bytecode]) -> bool {
+    bytecodes.iter().all(|b| {
+        !matches!(
+            b,
+            Bytecode::JumpIfFalse { .. } | Bytecode::Goto { .. } | Bytecode::ApplyCustom { .. }
+        )
+    })
+}
+
+/// Replace the parameter placeholders among the constants of the bytecode.
+pub(crate) fn bind_params_in_bytecode(
+    bytecodes: &mut [Bytecode],
+    params: &BTreeMap<String, DataValue>,
+) {
+    for b in bytecodes {
+        if let Bytecode::Const { val, .. } = b {
+            val.bind_params(params)
+        }
+    }
+}
+
+enum BatchOperand<'a> {
+    Column(&'a [DataValue]),
+    Owned(Vec<DataValue>),
+    Const(DataValue),
+}
+
+impl BatchOperand<'_> {
+    fn get(&self, idx: usize) -> &DataValue {
+        match self {
+            BatchOperand::Column(c) => &c[idx],
+            BatchOperand::Owned(c) => &c[idx],
+            BatchOperand::Const(v) => v,
+        }
+    }
+}
+
+/// Evaluate a predicate over every row of `batch`, returning the selection mask.
+///
+/// Jump-free bytecode is evaluated one instruction at a time over whole columns, with
+/// operations whose arguments are all constant evaluated only once. Other bytecode, including
+/// bytecode calling custom functions, falls back to row-by-row evaluation.
+pub(crate) fn eval_bytecode_pred_batch(
+    bytecodes: &[Bytecode],
+    batch: &TupleBatch,
+    stack: &mut Vec<DataValue>,
+    span: SourceSpan,
+) -> Result<Vec<bool>> {
+    if !is_vectorizable(bytecodes) {
+        let mut row = vec![];
+        return (0..batch.len)
+            .map(|i| {
+                batch.fill_row(i, &mut row);
+                eval_bytecode_pred(bytecodes, &row, stack, span)
+            })
+            .collect();
+    }
+    let mut operands: Vec<BatchOperand<'_>> = vec![];
+    for code in bytecodes {
+        match code {
+            Bytecode::Binding { var, tuple_pos } => match tuple_pos {
+                None => {
+                    bail!(UnboundVariableError(var.name.to_string(), var.span))
+                }
+                Some(i) => {
+                    let col = batch.columns.get(*i).ok_or_else(|| {
+                        TupleTooShortError(var.name.to_string(), *i, batch.width(), var.span)
+                    })?;
+                    operands.push(BatchOperand::Column(col));
+                }
+            },
+            Bytecode::Const { val, .. } => operands.push(BatchOperand::Const(val.clone())),
+            Bytecode::Apply { op, arity, span } => {
+                let args = operands.split_off(operands.len() - *arity);
+                let result = if args.iter().all(|a| matches!(a, BatchOperand::Const(_))) {
+                    stack.clear();
+                    stack.extend(args.iter().map(|a| a.get(0).clone()));
+                    BatchOperand::Const(
+                        (op.inner)(stack).map_err(|err| EvalRaisedError(*span, err.to_string()))?,
+                    )
+                } else {
+                    let mut col = Vec::with_capacity(batch.len);
+                    for i in 0..batch.len {
+                        stack.clear();
+                        stack.extend(args.iter().map(|a| a.get(i).clone()));
+                        col.push(
+                            (op.inner)(stack)
+                                .map_err(|err| EvalRaisedError(*span, err.to_string()))?,
+                        );
+                    }
+                    BatchOperand::Owned(col)
+                };
+                operands.push(result);
+            }
+            Bytecode::ApplyCustom { .. } | Bytecode::JumpIfFalse { .. } | Bytecode::Goto { .. } => {
+                unreachable!()
+            }
+        }
+    }
+    let result = operands.pop().unwrap();
+    (0..batch.len)
+        .map(|i| match result.get(i) {
+            DataValue::Bool(b) => Ok(*b),
+            v => bail!(PredicateTypeError(span, v.clone())),
+        })
+        .collect()
+}
+
 /// Expression can be evaluated to yield a DataValue
 #[derive(Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub enum Expr {
@@ -181,6 +308,16 @@ pub enum Expr {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// Application of a custom function
+    ApplyCustom {
+        /// The function to apply
+        func: CustomFunction,
+        /// Arguments to the application
+        args: Box<[Expr]>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// Conditional expressions
     Cond {
         /// Conditional clauses, the first expression in each tuple should evaluate to a boolean
@@ -214,6 +351,13 @@ impl Display for Expr {
                 }
                 writer.finish()
             }
+            Expr::ApplyCustom { func, args, .. } => {
+                let mut writer = f.debug_tuple(&func.name);
+                for arg in args.iter() {
+                    writer.field(arg);
+                }
+                writer.finish()
+            }
             Expr::Cond { clauses, .. } => {
                 let mut writer = f.debug_tuple("cond");
                 for (cond, expr) in clauses {
@@ -251,7 +395,10 @@ impl Expr {
     pub(crate) fn span(&self) -> SourceSpan {
         match self {
             Expr::Binding { var, .. } => var.span,
-            Expr::Const { span, .. } | Expr::Apply { span, .. } | Expr::Cond { span, .. } => *span,
+            Expr::Const { span, .. }
+            | Expr::Apply { span, .. }
+            | Expr::ApplyCustom { span, .. }
+            | Expr::Cond { span, .. } => *span,
         }
     }
     pub(crate) fn get_binding(&self) -> Option<&Symbol> {
@@ -313,7 +460,7 @@ impl Expr {
                 *tuple_pos = Some(found_idx)
             }
             Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Apply { args, .. } | Expr::ApplyCustom { args, .. } => {
                 for arg in args.iter_mut() {
                     arg.fill_binding_indices(binding_map)?;
                 }
@@ -342,7 +489,7 @@ impl Expr {
                 }
             }
             Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Apply { args, .. } | Expr::ApplyCustom { args, .. } => {
                 for arg in args.iter() {
                     arg.do_binding_indices(coll);
                 }
@@ -359,6 +506,24 @@ impl Expr {
               // }
         }
     }
+    /// Replace the parameter placeholders within the expression.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        match self {
+            Expr::Binding { .. } => {}
+            Expr::Const { val, .. } => val.bind_params(params),
+            Expr::Apply { args, .. } | Expr::ApplyCustom { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.bind_params(params)
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.bind_params(params);
+                    val.bind_params(params)
+                }
+            }
+        }
+    }
     pub(crate) fn eval_to_const(mut self) -> Result<DataValue> {
         #[derive(Error, Diagnostic, Debug)]
         #[error("Expression contains unevaluated constant")]
@@ -372,14 +537,28 @@ impl Expr {
         }
     }
     pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
+        // custom functions are only ever called during evaluation
+        if let Expr::ApplyCustom { args, .. } = self {
+            for arg in args.iter_mut() {
+                arg.partial_eval()?;
+            }
+        }
+        if let Expr::Apply { op, args, span } = self {
             let span = *span;
             let mut all_evaluated = true;
+            let mut has_placeholder = false;
             for arg in args.iter_mut() {
                 arg.partial_eval()?;
-                all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
+                match arg {
+                    Expr::Const { val, .. } => {
+                        has_placeholder = has_placeholder || val.contains_placeholder()
+                    }
+                    _ => all_evaluated = false,
+                }
             }
-            if all_evaluated {
+            // parameter placeholders can only be collected into lists: anything else
+            // must wait until the parameters are bound
+            if all_evaluated && (!has_placeholder || op.name == OP_LIST.name) {
                 let result = self.eval(&vec![])?;
                 mem::swap(self, &mut Expr::Const { val: result, span });
             }
@@ -418,7 +597,7 @@ impl Expr {
                 coll.insert(var.clone());
             }
             Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Apply { args, .. } | Expr::ApplyCustom { args, .. } => {
                 for arg in args.iter() {
                     arg.collect_bindings(coll)
                 }
@@ -459,6 +638,14 @@ impl Expr {
                 Ok((op.inner)(&args)
                     .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?)
             }
+            Expr::ApplyCustom { func, args, .. } => {
+                let args: Box<[DataValue]> = args
+                    .iter()
+                    .map(|v| v.eval(bindings.as_ref()))
+                    .try_collect()?;
+                Ok((func.inner)(&args)
+                    .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?)
+            }
             Expr::Cond { clauses, .. } => {
                 for (cond, val) in clauses {
                     let cond_val = cond.eval(bindings.as_ref())?;
@@ -476,7 +663,10 @@ impl Expr {
     }
     pub(crate) fn extract_bound(&self, target: &Symbol) -> Result<ValueRange> {
         Ok(match self {
-            Expr::Binding { .. } | Expr::Const { .. } | Expr::Cond { .. } => ValueRange::default(),
+            Expr::Binding { .. }
+            | Expr::Const { .. }
+            | Expr::ApplyCustom { .. }
+            | Expr::Cond { .. } => ValueRange::default(),
             Expr::Apply { op, args, .. } => match op.name {
                 n if n == OP_GE.name || n == OP_GT.name => {
                     if let Some(symb) = args[0].get_binding() {
@@ -633,6 +823,8 @@ pub struct Op {
     pub(crate) name: &'static str,
     pub(crate) min_arity: usize,
     pub(crate) vararg: bool,
+    /// Whether the results are the same for the same arguments, unlike those of `rand_int`
+    pub(crate) deterministic: bool,
     pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
 }
 
@@ -750,6 +942,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "is_int" => &OP_IS_INT,
         "is_float" => &OP_IS_FLOAT,
         "is_num" => &OP_IS_NUM,
+        "is_decimal" => &OP_IS_DECIMAL,
         "is_string" => &OP_IS_STRING,
         "is_list" => &OP_IS_LIST,
         "is_bytes" => &OP_IS_BYTES,
@@ -787,6 +980,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "windows" => &OP_WINDOWS,
         "to_int" => &OP_TO_INT,
         "to_float" => &OP_TO_FLOAT,
+        "to_decimal" => &OP_TO_DECIMAL,
         "to_string" => &OP_TO_STRING,
         "rand_float" => &OP_RAND_FLOAT,
         "rand_bernoulli" => &OP_RAND_BERNOULLI,
@@ -809,6 +1003,49 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
     })
 }
 
+/// The implementation of a custom function: takes the arguments and returns the result.
+pub type CustomFunctionImpl = dyn Fn(&[DataValue]) -> Result<DataValue> + Send + Sync;
+
+/// A scalar function registered with [`Db::register_function`](crate::Db::register_function).
+#[derive(Clone)]
+pub struct CustomFunction {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) arity: usize,
+    pub(crate) inner: Arc<CustomFunctionImpl>,
+}
+
+impl serde::Serialize for CustomFunction {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.name)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CustomFunction {
+    fn deserialize<D>(_deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Err(D::Error::custom("custom functions cannot be deserialized"))
+    }
+}
+
+impl PartialEq for CustomFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for CustomFunction {}
+
+impl Debug for CustomFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
 impl Op {
     pub(crate) fn post_process_args(&self, args: &mut [Expr]) {
         if self.name.starts_with("OP_REGEX_") {
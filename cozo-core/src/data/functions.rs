@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::cmp::Reverse;
+use std::cmp::{Ordering, Reverse};
 use std::collections::BTreeSet;
 use std::ops::{Div, Rem};
 use std::str::FromStr;
@@ -18,23 +18,31 @@ use chrono::{DateTime, TimeZone, Utc};
 use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Date;
-use miette::{bail, ensure, miette, Result};
+use miette::{bail, ensure, miette, Diagnostic, Result};
 use num_traits::FloatConst;
 use rand::prelude::*;
 use smartstring::SmartString;
+use thiserror::Error;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
+use crate::data::decimal::Decimal;
 use crate::data::expr::Op;
 use crate::data::json::JsonValue;
+use crate::data::program::ArithMode;
 use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+use crate::utils::{arith_mode, with_rng};
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
+        define_op!($name, $min_arity, $vararg, true);
+    };
+    ($name:ident, $min_arity:expr, $vararg:expr, $deterministic:expr) => {
         pub(crate) const $name: Op = Op {
             name: stringify!($name),
             min_arity: $min_arity,
             vararg: $vararg,
+            deterministic: $deterministic,
             inner: ::casey::lower!($name),
         };
     };
@@ -83,6 +91,9 @@ pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            l.numeric_cmp(r) == Some(Ordering::Equal)
+        }
         (a, b) => a == b,
     }))
 }
@@ -106,6 +117,9 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 != *f,
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            l.numeric_cmp(r) != Some(Ordering::Equal)
+        }
         (a, b) => a != b,
     }))
 }
@@ -116,6 +130,9 @@ pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            l.numeric_cmp(r) == Some(Ordering::Greater)
+        }
         (a, b) => a > b,
     }))
 }
@@ -126,6 +143,9 @@ pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            matches!(l.numeric_cmp(r), Some(Ordering::Greater | Ordering::Equal))
+        }
         (a, b) => a >= b,
     }))
 }
@@ -136,6 +156,9 @@ pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            l.numeric_cmp(r) == Some(Ordering::Less)
+        }
         (a, b) => a < b,
     }))
 }
@@ -146,25 +169,108 @@ pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            matches!(l.numeric_cmp(r), Some(Ordering::Less | Ordering::Equal))
+        }
         (a, b) => a <= b,
     }))
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Integer overflow in {0}")]
+#[diagnostic(code(eval::integer_overflow))]
+#[diagnostic(help(
+    "Convert the operands to floats, or use ':arith wrapping' or ':arith saturating' \
+    to let integers wrap around or stay at the bounds"
+))]
+pub(crate) struct IntegerOverflowError(pub(crate) &'static str);
+
+/// Pick the result of an integer operation according to the arithmetic mode of the query.
+fn int_arith(
+    what: &'static str,
+    checked: Option<i64>,
+    wrapping: i64,
+    saturating: i64,
+) -> Result<i64> {
+    match arith_mode() {
+        ArithMode::Checked => checked.ok_or_else(|| IntegerOverflowError(what).into()),
+        ArithMode::Wrapping => Ok(wrapping),
+        ArithMode::Saturating => Ok(saturating),
+    }
+}
+
+pub(crate) fn int_add(a: i64, b: i64) -> Result<i64> {
+    int_arith(
+        "addition",
+        a.checked_add(b),
+        a.wrapping_add(b),
+        a.saturating_add(b),
+    )
+}
+
+pub(crate) fn int_mul(a: i64, b: i64) -> Result<i64> {
+    int_arith(
+        "multiplication",
+        a.checked_mul(b),
+        a.wrapping_mul(b),
+        a.saturating_mul(b),
+    )
+}
+
+/// The integer `v`, computed exactly, brought into range according to the arithmetic mode.
+pub(crate) fn int_from_i128(what: &'static str, v: i128) -> Result<i64> {
+    int_arith(
+        what,
+        i64::try_from(v).ok(),
+        v as i64,
+        v.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+    )
+}
+
+fn is_decimal(n: &Num) -> bool {
+    matches!(n, Num::Decimal(_))
+}
+
+/// Arithmetic involving a decimal: exact if the other operand is an integer or a decimal,
+/// in floats otherwise.
+fn decimal_arith(
+    l: &Num,
+    r: &Num,
+    exact: fn(Decimal, Decimal) -> Result<Decimal>,
+    approx: fn(f64, f64) -> f64,
+) -> Result<DataValue> {
+    Ok(DataValue::Num(match (l.get_decimal(), r.get_decimal()) {
+        (Some(l), Some(r)) => Num::Decimal(exact(l, r)?),
+        _ => Num::Float(approx(l.get_float(), r.get_float())),
+    }))
+}
+
 define_op!(OP_ADD, 0, true);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
+    let mut d_accum: Option<Decimal> = None;
     for arg in args {
         match arg {
-            DataValue::Num(Num::Int(i)) => i_accum += i,
+            DataValue::Num(Num::Int(i)) => i_accum = int_add(i_accum, *i)?,
             DataValue::Num(Num::Float(f)) => f_accum += f,
+            DataValue::Num(Num::Decimal(d)) => {
+                d_accum = Some(match d_accum {
+                    None => *d,
+                    Some(accum) => accum.checked_add(*d)?,
+                })
+            }
             _ => bail!("addition requires numbers"),
         }
     }
     if f_accum == 0.0f64 {
-        Ok(DataValue::Num(Num::Int(i_accum)))
+        Ok(DataValue::Num(match d_accum {
+            None => Num::Int(i_accum),
+            Some(d) => Num::Decimal(d.checked_add(Decimal::from(i_accum))?),
+        }))
     } else {
-        Ok(DataValue::Num(Num::Float(i_accum as f64 + f_accum)))
+        let d = d_accum.map(Decimal::to_f64).unwrap_or(0.);
+        Ok(DataValue::Num(Num::Float(i_accum as f64 + d + f_accum)))
     }
 }
 
@@ -202,7 +308,15 @@ define_op!(OP_SUB, 2, false);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Int(*a - *b))
+            DataValue::Num(Num::Int(int_arith(
+                "subtraction",
+                a.checked_sub(*b),
+                a.wrapping_sub(*b),
+                a.saturating_sub(*b),
+            )?))
+        }
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            decimal_arith(l, r, Decimal::checked_sub, |a, b| a - b)?
         }
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
             DataValue::Num(Num::Float(*a - *b))
@@ -221,17 +335,28 @@ define_op!(OP_MUL, 0, true);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
+    let mut d_accum: Option<Decimal> = None;
     for arg in args {
         match arg {
-            DataValue::Num(Num::Int(i)) => i_accum *= i,
+            DataValue::Num(Num::Int(i)) => i_accum = int_mul(i_accum, *i)?,
             DataValue::Num(Num::Float(f)) => f_accum *= f,
+            DataValue::Num(Num::Decimal(d)) => {
+                d_accum = Some(match d_accum {
+                    None => *d,
+                    Some(accum) => accum.checked_mul(*d)?,
+                })
+            }
             _ => bail!("multiplication requires numbers"),
         }
     }
     if f_accum == 1.0f64 {
-        Ok(DataValue::Num(Num::Int(i_accum)))
+        Ok(DataValue::Num(match d_accum {
+            None => Num::Int(i_accum),
+            Some(d) => Num::Decimal(d.checked_mul(Decimal::from(i_accum))?),
+        }))
     } else {
-        Ok(DataValue::Num(Num::Float(i_accum as f64 * f_accum)))
+        let d = d_accum.map(Decimal::to_f64).unwrap_or(1.);
+        Ok(DataValue::Num(Num::Float(i_accum as f64 * d * f_accum)))
     }
 }
 
@@ -250,6 +375,9 @@ pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float(a / (*b as f64)))
         }
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            decimal_arith(l, r, Decimal::checked_div, |a, b| a / b)?
+        }
         _ => bail!("division requires numbers"),
     })
 }
@@ -257,8 +385,14 @@ pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_MINUS, 1, false);
 pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
-        DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
+        DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(int_arith(
+            "negation",
+            i.checked_neg(),
+            i.wrapping_neg(),
+            i.saturating_neg(),
+        )?)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(-(*f))),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.checked_neg()?)),
         _ => bail!("minus can only be applied to numbers"),
     })
 }
@@ -266,8 +400,18 @@ pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ABS, 1, false);
 pub(crate) fn op_abs(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
-        DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.abs())),
+        DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(int_arith(
+            "'abs'",
+            i.checked_abs(),
+            i.wrapping_abs(),
+            i.saturating_abs(),
+        )?)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.abs())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(if d.signum() < 0 {
+            d.checked_neg()?
+        } else {
+            *d
+        })),
         _ => bail!("'abs' requires numbers"),
     })
 }
@@ -287,6 +431,7 @@ pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
                 DataValue::from(f64::NAN)
             }
         }
+        DataValue::Num(Num::Decimal(d)) => DataValue::from(d.signum()),
         _ => bail!("'signum' requires numbers"),
     })
 }
@@ -296,6 +441,7 @@ pub(crate) fn op_floor(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.floor())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.floor())),
         _ => bail!("'floor' requires numbers"),
     })
 }
@@ -305,15 +451,47 @@ pub(crate) fn op_ceil(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.ceil())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.ceil())),
         _ => bail!("'ceil' requires numbers"),
     })
 }
 
+define_op!(OP_TO_DECIMAL, 1, true);
+pub(crate) fn op_to_decimal(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(args.len() <= 2, "'to_decimal' takes at most two arguments");
+    let d = match &args[0] {
+        DataValue::Num(Num::Int(i)) => Decimal::from(*i),
+        DataValue::Num(Num::Float(f)) => Decimal::from_f64(*f)?,
+        DataValue::Num(Num::Decimal(d)) => *d,
+        DataValue::Str(s) => Decimal::from_str(s.trim())?,
+        v => bail!("'to_decimal' does not recognize {:?}", v),
+    };
+    let d = match args.get(1) {
+        None => d,
+        Some(scale) => {
+            let scale = scale
+                .get_non_neg_int()
+                .ok_or_else(|| miette!("the scale given to 'to_decimal' must be an integer"))?;
+            d.rescale(u8::try_from(scale).unwrap_or(u8::MAX))?
+        }
+    };
+    Ok(DataValue::Num(Num::Decimal(d)))
+}
+
+define_op!(OP_IS_DECIMAL, 1, false);
+pub(crate) fn op_is_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(
+        args[0],
+        DataValue::Num(Num::Decimal(_))
+    )))
+}
+
 define_op!(OP_ROUND, 1, false);
 pub(crate) fn op_round(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.round())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.rescale(0)?)),
         _ => bail!("'round' requires numbers"),
     })
 }
@@ -321,8 +499,7 @@ pub(crate) fn op_round(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_EXP, 1, false);
 pub(crate) fn op_exp(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'exp' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.exp())))
@@ -331,8 +508,7 @@ pub(crate) fn op_exp(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_EXP2, 1, false);
 pub(crate) fn op_exp2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'exp2' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.exp2())))
@@ -341,8 +517,7 @@ pub(crate) fn op_exp2(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LN, 1, false);
 pub(crate) fn op_ln(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'ln' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.ln())))
@@ -351,8 +526,7 @@ pub(crate) fn op_ln(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LOG2, 1, false);
 pub(crate) fn op_log2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'log2' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.log2())))
@@ -361,8 +535,7 @@ pub(crate) fn op_log2(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LOG10, 1, false);
 pub(crate) fn op_log10(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'log10' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.log10())))
@@ -371,8 +544,7 @@ pub(crate) fn op_log10(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_SIN, 1, false);
 pub(crate) fn op_sin(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'sin' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.sin())))
@@ -381,8 +553,7 @@ pub(crate) fn op_sin(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_COS, 1, false);
 pub(crate) fn op_cos(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'cos' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.cos())))
@@ -391,8 +562,7 @@ pub(crate) fn op_cos(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_TAN, 1, false);
 pub(crate) fn op_tan(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'tan' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.tan())))
@@ -401,8 +571,7 @@ pub(crate) fn op_tan(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ASIN, 1, false);
 pub(crate) fn op_asin(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'asin' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.asin())))
@@ -411,8 +580,7 @@ pub(crate) fn op_asin(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ACOS, 1, false);
 pub(crate) fn op_acos(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'acos' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.acos())))
@@ -421,8 +589,7 @@ pub(crate) fn op_acos(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ATAN, 1, false);
 pub(crate) fn op_atan(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'atan' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.atan())))
@@ -431,13 +598,11 @@ pub(crate) fn op_atan(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ATAN2, 2, false);
 pub(crate) fn op_atan2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'atan2' requires numbers"),
     };
     let b = match &args[1] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'atan2' requires numbers"),
     };
 
@@ -447,8 +612,7 @@ pub(crate) fn op_atan2(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_SINH, 1, false);
 pub(crate) fn op_sinh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'sinh' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.sinh())))
@@ -457,8 +621,7 @@ pub(crate) fn op_sinh(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_COSH, 1, false);
 pub(crate) fn op_cosh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'cosh' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.cosh())))
@@ -467,8 +630,7 @@ pub(crate) fn op_cosh(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_TANH, 1, false);
 pub(crate) fn op_tanh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'tanh' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.tanh())))
@@ -477,8 +639,7 @@ pub(crate) fn op_tanh(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ASINH, 1, false);
 pub(crate) fn op_asinh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'asinh' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.asinh())))
@@ -487,8 +648,7 @@ pub(crate) fn op_asinh(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ACOSH, 1, false);
 pub(crate) fn op_acosh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'acosh' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.acosh())))
@@ -497,8 +657,7 @@ pub(crate) fn op_acosh(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_ATANH, 1, false);
 pub(crate) fn op_atanh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'atanh' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.atanh())))
@@ -507,13 +666,11 @@ pub(crate) fn op_atanh(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_POW, 2, false);
 pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'pow' requires numbers"),
     };
     let b = match &args[1] {
-        DataValue::Num(Num::Int(i)) => *i as f64,
-        DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(n) => n.get_float(),
         _ => bail!("'pow' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.powf(b))))
@@ -523,7 +680,17 @@ define_op!(OP_MOD, 2, false);
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Int(a.rem(b)))
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Integer modulo by zero")]
+            #[diagnostic(code(eval::modulo_by_zero))]
+            struct ModuloByZeroError;
+
+            // the remainder never overflows, even where the quotient does
+            let rem = a.checked_rem(*b).or((*b == -1).then_some(0));
+            DataValue::Num(Num::Int(rem.ok_or(ModuloByZeroError)?))
+        }
+        (DataValue::Num(l), DataValue::Num(r)) if is_decimal(l) || is_decimal(r) => {
+            decimal_arith(l, r, Decimal::checked_rem, |a, b| a.rem(b))?
         }
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
             DataValue::Num(Num::Float(a.rem(*b)))
@@ -896,14 +1063,16 @@ define_op!(OP_IS_NUM, 1, false);
 pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
-        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Float(_))
+        DataValue::Num(Num::Int(_))
+            | DataValue::Num(Num::Float(_))
+            | DataValue::Num(Num::Decimal(_))
     )))
 }
 
 define_op!(OP_IS_FINITE, 1, false);
 pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
-        DataValue::Num(Num::Int(_)) => true,
+        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Decimal(_)) => true,
         DataValue::Num(Num::Float(f)) => f.is_finite(),
         _ => false,
     }))
@@ -1275,7 +1444,7 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => !l.is_empty(),
         DataValue::Set(s) => !s.is_empty(),
         DataValue::Validity(vld) => vld.is_assert.0,
-        DataValue::Bot => false,
+        DataValue::Bot | DataValue::Param(_) => false,
     }))
 }
 
@@ -1292,7 +1461,7 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => i64::from(!l.is_empty()),
         DataValue::Set(s) => i64::from(!s.is_empty()),
         DataValue::Validity(vld) => i64::from(vld.is_assert.0),
-        DataValue::Bot => 0,
+        DataValue::Bot | DataValue::Param(_) => 0,
     }))
 }
 
@@ -1343,6 +1512,8 @@ define_op!(OP_TO_STRING, 1, false);
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Str(s) => DataValue::Str(s.clone()),
+        DataValue::Num(Num::Decimal(d)) => DataValue::from(d.to_string()),
+        DataValue::Param(name) => bail!("'to_string' found the unbound parameter ${}", name),
         v => {
             let jv = JsonValue::from(v.clone());
             let s = jv.to_string();
@@ -1351,12 +1522,12 @@ pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
-define_op!(OP_RAND_FLOAT, 0, false);
+define_op!(OP_RAND_FLOAT, 0, false, false);
 pub(crate) fn op_rand_float(_args: &[DataValue]) -> Result<DataValue> {
-    Ok(thread_rng().gen::<f64>().into())
+    Ok(with_rng(|rng| rng.gen::<f64>()).into())
 }
 
-define_op!(OP_RAND_BERNOULLI, 1, false);
+define_op!(OP_RAND_BERNOULLI, 1, false, false);
 pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
     let prob = match &args[0] {
         DataValue::Num(n) => {
@@ -1369,10 +1540,10 @@ pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
         }
         _ => bail!("'rand_bernoulli' requires number between 0. and 1."),
     };
-    Ok(DataValue::from(thread_rng().gen_bool(prob)))
+    Ok(DataValue::from(with_rng(|rng| rng.gen_bool(prob))))
 }
 
-define_op!(OP_RAND_INT, 2, false);
+define_op!(OP_RAND_INT, 2, false, false);
 pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let lower = &args[0]
         .get_int()
@@ -1380,23 +1551,17 @@ pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let upper = &args[1]
         .get_int()
         .ok_or_else(|| miette!("'rand_int' requires integers"))?;
-    Ok(thread_rng().gen_range(*lower..=*upper).into())
+    Ok(with_rng(|rng| rng.gen_range(*lower..=*upper)).into())
 }
 
-define_op!(OP_RAND_CHOOSE, 1, false);
+define_op!(OP_RAND_CHOOSE, 1, false, false);
 pub(crate) fn op_rand_choose(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
-        DataValue::List(l) => Ok(l
-            .choose(&mut thread_rng())
-            .cloned()
-            .unwrap_or(DataValue::Null)),
-        DataValue::Set(l) => Ok(l
-            .iter()
-            .collect_vec()
-            .choose(&mut thread_rng())
-            .cloned()
-            .cloned()
-            .unwrap_or(DataValue::Null)),
+        DataValue::List(l) => Ok(with_rng(|rng| l.choose(rng).cloned()).unwrap_or(DataValue::Null)),
+        DataValue::Set(l) => {
+            let l = l.iter().collect_vec();
+            Ok(with_rng(|rng| l.choose(rng).cloned().cloned()).unwrap_or(DataValue::Null))
+        }
         _ => bail!("'rand_choice' requires lists"),
     }
 }
@@ -1487,7 +1652,7 @@ pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_NOW, 0, false);
+define_op!(OP_NOW, 0, false, false);
 #[cfg(target_arch = "wasm32")]
 pub(crate) fn op_now(_args: &[DataValue]) -> Result<DataValue> {
     let d: f64 = Date::now() / 1000.;
@@ -1573,10 +1738,9 @@ pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     Ok(ValidityTs(Reverse(microseconds as i64)))
 }
 
-define_op!(OP_RAND_UUID_V1, 0, false);
+define_op!(OP_RAND_UUID_V1, 0, false, false);
 pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
-    let mut rng = rand::thread_rng();
-    let uuid_ctx = uuid::v1::Context::new(rng.gen());
+    let uuid_ctx = uuid::v1::Context::new(with_rng(|rng| rng.gen()));
     #[cfg(target_arch = "wasm32")]
     let ts = {
         let since_epoch: f64 = Date::now();
@@ -1591,14 +1755,16 @@ pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
         Timestamp::from_unix(uuid_ctx, since_epoch.as_secs(), since_epoch.subsec_nanos())
     };
     let mut rand_vals = [0u8; 6];
-    rng.fill(&mut rand_vals);
+    with_rng(|rng| rng.fill(&mut rand_vals));
     let id = uuid::Uuid::new_v1(ts, &rand_vals);
     Ok(DataValue::uuid(id))
 }
 
-define_op!(OP_RAND_UUID_V4, 0, false);
+define_op!(OP_RAND_UUID_V4, 0, false, false);
 pub(crate) fn op_rand_uuid_v4(_args: &[DataValue]) -> Result<DataValue> {
-    let id = uuid::Uuid::new_v4();
+    let mut random_bytes = [0u8; 16];
+    with_rng(|rng| rng.fill(&mut random_bytes));
+    let id = uuid::Builder::from_random_bytes(random_bytes).into_uuid();
     Ok(DataValue::uuid(id))
 }
 
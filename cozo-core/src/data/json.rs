@@ -67,6 +67,8 @@ impl From<DataValue> for JsonValue {
             DataValue::Null => JsonValue::Null,
             DataValue::Bool(b) => JsonValue::Bool(b),
             DataValue::Num(Num::Int(i)) => JsonValue::Number(i.into()),
+            // as strings, since JSON numbers are read as floats in many places
+            DataValue::Num(Num::Decimal(d)) => JsonValue::String(d.to_string()),
             DataValue::Num(Num::Float(f)) => {
                 if f.is_finite() {
                     json!(f)
@@ -88,6 +90,9 @@ impl From<DataValue> for JsonValue {
                 JsonValue::Array(l.iter().map(|v| JsonValue::from(v.clone())).collect())
             }
             DataValue::Bot => panic!("found bottom"),
+            // plans are only run with all their parameters bound, so placeholders never
+            // reach the results: should one slip through, it is shown as it is written
+            DataValue::Param(name) => JsonValue::String(format!("${name}")),
             DataValue::Set(l) => {
                 JsonValue::Array(l.iter().map(|v| JsonValue::from(v.clone())).collect())
             }
@@ -14,6 +14,9 @@ use std::str::FromStr;
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use regex::Regex;
 
+use crate::data::collation::Collation;
+use crate::data::decimal::Decimal;
+use crate::data::tuple::ENCODED_KEY_MIN_LEN;
 use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
 
 const INIT_TAG: u8 = 0x00;
@@ -28,12 +31,21 @@ const REGEX_TAG: u8 = 0x09;
 const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
 const VLD_TAG: u8 = 0x0C;
+const COLLATED_STR_TAG: u8 = 0x0D;
+const PARAM_TAG: u8 = 0x0E;
 const BOT_TAG: u8 = 0xFF;
 
+const IS_ABOVE_FLOAT: u8 = 0b00100000;
 const IS_FLOAT: u8 = 0b00010000;
+const IS_DECIMAL: u8 = 0b00001000;
 const IS_APPROX_INT: u8 = 0b00000100;
 const IS_EXACT_INT: u8 = 0b00000000;
 const EXACT_INT_BOUND: i64 = 0x20_0000_0000_0000;
+const DECIMAL_NEG: u8 = 0x00;
+const DECIMAL_ZERO: u8 = 0x01;
+const DECIMAL_POS: u8 = 0x02;
+const ABOVE_FLOAT_INT: u8 = 0x00;
+const ABOVE_FLOAT_DECIMAL: u8 = 0x01;
 
 pub(crate) trait MemCmpEncoder: Write {
     fn encode_datavalue(&mut self, v: &DataValue) {
@@ -89,12 +101,36 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
+            DataValue::Param(name) => {
+                self.write_u8(PARAM_TAG).unwrap();
+                self.encode_bytes(name.as_bytes());
+            }
         }
     }
+    /// Encode a string in a collated key column by its sort key. The string itself cannot be
+    /// decoded back, and is stored with the value instead.
+    fn encode_collated_str(&mut self, collation: Collation, s: &str) {
+        self.write_u8(COLLATED_STR_TAG).unwrap();
+        self.write_u8(collation.tag()).unwrap();
+        self.encode_bytes(&collation.sort_key(s));
+    }
+    /// Numbers are encoded by the largest floats not greater than them. Integers and decimals
+    /// lying strictly between two floats follow all numbers equal to the lower float, and are
+    /// ordered by their exact values.
     fn encode_num(&mut self, v: Num) {
-        let f = v.get_float();
+        let (f, above) = v.float_floor();
         let u = order_encode_f64(f);
         self.write_u64::<BigEndian>(u).unwrap();
+        if above {
+            self.write_u8(IS_ABOVE_FLOAT).unwrap();
+            self.encode_decimal(v.get_decimal().unwrap());
+            let kind = match v {
+                Num::Int(_) => ABOVE_FLOAT_INT,
+                _ => ABOVE_FLOAT_DECIMAL,
+            };
+            self.write_u8(kind).unwrap();
+            return;
+        }
         match v {
             Num::Int(i) => {
                 if i > -EXACT_INT_BOUND && i < EXACT_INT_BOUND {
@@ -108,7 +144,36 @@ pub(crate) trait MemCmpEncoder: Write {
             Num::Float(_) => {
                 self.write_u8(IS_FLOAT).unwrap();
             }
+            Num::Decimal(d) => {
+                self.write_u8(IS_DECIMAL).unwrap();
+                self.encode_decimal(d);
+            }
+        }
+    }
+    /// Decimals of the same approximation as floats are ordered by their significant digits,
+    /// and then by their scales.
+    fn encode_decimal(&mut self, d: Decimal) {
+        let (negative, exponent, digits) = d.to_parts();
+        // the exponent is within -38..=39
+        let exponent = (exponent + 128) as u8;
+        if digits.is_empty() {
+            self.write_u8(DECIMAL_ZERO).unwrap();
+        } else if negative {
+            self.write_u8(DECIMAL_NEG).unwrap();
+            self.write_u8(!exponent).unwrap();
+            for digit in digits {
+                self.write_u8(!(digit + 1)).unwrap();
+            }
+            self.write_u8(!0).unwrap();
+        } else {
+            self.write_u8(DECIMAL_POS).unwrap();
+            self.write_u8(exponent).unwrap();
+            for digit in digits {
+                self.write_u8(digit + 1).unwrap();
+            }
+            self.write_u8(0).unwrap();
         }
+        self.write_u8(d.scale()).unwrap();
     }
 
     fn encode_bytes(&mut self, key: &[u8]) {
@@ -190,6 +255,35 @@ const ENC_GROUP_SIZE: usize = 8;
 const ENC_MARKER: u8 = b'\xff';
 const ENC_ASC_PADDING: [u8; ENC_GROUP_SIZE] = [0; ENC_GROUP_SIZE];
 
+/// The inverse of [`MemCmpEncoder::encode_decimal`]
+fn decode_decimal(bs: &[u8]) -> (Decimal, &[u8]) {
+    let (sign, remaining) = bs.split_first().unwrap();
+    match *sign {
+        DECIMAL_ZERO => {
+            let (scale, remaining) = remaining.split_first().unwrap();
+            (Decimal::from_parts(false, 0, &[], *scale), remaining)
+        }
+        sign => {
+            let negative = sign == DECIMAL_NEG;
+            let flip = |b: u8| if negative { !b } else { b };
+            let (exponent, mut remaining) = remaining.split_first().unwrap();
+            let exponent = flip(*exponent) as i16 - 128;
+            let mut digits = vec![];
+            loop {
+                let (b, rest) = remaining.split_first().unwrap();
+                remaining = rest;
+                match flip(*b) {
+                    0 => break,
+                    digit => digits.push(digit - 1),
+                }
+            }
+            let (scale, remaining) = remaining.split_first().unwrap();
+            let d = Decimal::from_parts(negative, exponent, &digits, *scale);
+            (d, remaining)
+        }
+    }
+}
+
 impl Num {
     pub(crate) fn decode_from_key(bs: &[u8]) -> (Self, &[u8]) {
         let (float_part, remaining) = bs.split_at(8);
@@ -198,6 +292,18 @@ impl Num {
         let (tag, remaining) = remaining.split_first().unwrap();
         match *tag {
             IS_FLOAT => (Num::Float(f), remaining),
+            IS_DECIMAL => {
+                let (d, remaining) = decode_decimal(remaining);
+                (Num::Decimal(d), remaining)
+            }
+            IS_ABOVE_FLOAT => {
+                let (d, remaining) = decode_decimal(remaining);
+                let (kind, remaining) = remaining.split_first().unwrap();
+                match *kind {
+                    ABOVE_FLOAT_INT => (Num::Int(d.to_i64().unwrap()), remaining),
+                    _ => (Num::Decimal(d), remaining),
+                }
+            }
             IS_EXACT_INT => (Num::Int(f as i64), remaining),
             IS_APPROX_INT => {
                 let (int_part, remaining) = remaining.split_at(8);
@@ -295,9 +401,42 @@ impl DataValue {
                 )
             }
             BOT_TAG => (DataValue::Bot, remaining),
+            PARAM_TAG => {
+                let (bytes, remaining) = decode_bytes(remaining);
+                let s = unsafe { String::from_utf8_unchecked(bytes) };
+                (DataValue::Param(s.into()), remaining)
+            }
+            COLLATED_STR_TAG => {
+                // the sort key stands in for the string until restored from the value
+                let (bytes, remaining) = decode_bytes(&remaining[1..]);
+                (DataValue::Bytes(bytes), remaining)
+            }
             _ => unreachable!("{:?}", bs),
         }
     }
 }
 
 impl<T: Write> MemCmpEncoder for T {}
+
+/// The key encoded as it is now, if it was stored in an earlier encoding and needs upgrading.
+/// Before storage version 1, integers not exactly representable as floats were placed after
+/// their nearest float, instead of after the float just below them.
+pub(crate) fn upgrade_key_encoding(key: &[u8]) -> Option<Vec<u8>> {
+    let (prefix, mut remaining) = key.split_at(ENCODED_KEY_MIN_LEN);
+    let mut upgraded = prefix.to_vec();
+    let mut changed = false;
+    while !remaining.is_empty() {
+        let (val, next) = DataValue::decode_from_key(remaining);
+        let encoded = &remaining[..remaining.len() - next.len()];
+        // collated strings cannot be encoded again from their sort keys, and never changed
+        if encoded[0] == COLLATED_STR_TAG {
+            upgraded.extend_from_slice(encoded);
+        } else {
+            let start = upgraded.len();
+            upgraded.encode_datavalue(&val);
+            changed |= upgraded[start..] != *encoded;
+        }
+        remaining = next;
+    }
+    changed.then_some(upgraded)
+}
@@ -7,12 +7,15 @@
  */
 
 pub(crate) mod aggr;
+pub(crate) mod collation;
+pub(crate) mod decimal;
 pub(crate) mod expr;
 pub(crate) mod functions;
 pub(crate) mod json;
 pub(crate) mod memcmp;
 pub(crate) mod program;
 pub(crate) mod relation;
+pub(crate) mod sketch;
 pub(crate) mod symb;
 pub(crate) mod tuple;
 pub(crate) mod value;
@@ -11,18 +11,21 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
+use itertools::Itertools;
 use miette::{ensure, Diagnostic, Result};
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::aggr::Aggregation;
+use crate::data::aggr::Aggr;
+use crate::data::collation::Collation;
 use crate::data::expr::Expr;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
 use crate::parse::SourceSpan;
+use crate::query::cursor::CursorPosition;
 use crate::runtime::relation::InputRelationHandle;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
@@ -39,9 +42,21 @@ pub(crate) struct QueryOutOptions {
     pub(crate) offset: Option<usize>,
     pub(crate) timeout: Option<f64>,
     pub(crate) sleep: Option<f64>,
-    pub(crate) sorters: Vec<(Symbol, SortDir)>,
+    pub(crate) sorters: Vec<Sorter>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp)>,
     pub(crate) assertion: Option<QueryAssertion>,
+    pub(crate) cursor: Option<QueryCursor>,
+    /// Set by the `:seed` option, makes the random functions and aggregations reproducible
+    pub(crate) seed: Option<u64>,
+    /// Set by the `:arith` option
+    pub(crate) arith: ArithMode,
+}
+
+/// Set by the `:cursor` option, which paginates the results by keyset.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueryCursor {
+    /// Where the previous page ended, `None` for the first page
+    pub(crate) after: Option<Box<CursorPosition>>,
 }
 
 impl Debug for QueryOutOptions {
@@ -61,12 +76,14 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
-        for (symb, dir) in &self.sorters {
-            write!(f, ":order ")?;
-            if *dir == SortDir::Dsc {
-                write!(f, "-")?;
-            }
-            writeln!(f, "{symb};")?;
+        if let Some(l) = self.seed {
+            writeln!(f, ":seed {l};")?;
+        }
+        if self.arith != ArithMode::Checked {
+            writeln!(f, ":arith {};", self.arith)?;
+        }
+        for sorter in &self.sorters {
+            writeln!(f, ":order {sorter};")?;
         }
         if let Some((
             InputRelationHandle {
@@ -132,6 +149,13 @@ impl Display for QueryOutOptions {
             writeln!(f, "}};")?;
         }
 
+        if let Some(cursor) = &self.cursor {
+            match &cursor.after {
+                None => writeln!(f, ":cursor;")?,
+                Some(pos) => writeln!(f, ":cursor {:?};", pos.encode())?,
+            }
+        }
+
         if let Some(a) = &self.assertion {
             match a {
                 QueryAssertion::AssertNone(_) => {
@@ -157,12 +181,73 @@ impl QueryOutOptions {
     }
 }
 
+/// What integer arithmetic does on overflow
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub(crate) enum ArithMode {
+    /// Raise an error
+    #[default]
+    Checked,
+    /// Wrap around at the boundary of the type
+    Wrapping,
+    /// Stay at the boundary of the type
+    Saturating,
+}
+
+impl Display for ArithMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithMode::Checked => f.write_str("checked"),
+            ArithMode::Wrapping => f.write_str("wrapping"),
+            ArithMode::Saturating => f.write_str("saturating"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum SortDir {
     Asc,
     Dsc,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum NullsOrder {
+    First,
+    Last,
+}
+
+/// A sort key of the `:sort` option
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Sorter {
+    /// An expression over the variables in the head of the entry rule
+    pub(crate) key: Expr,
+    pub(crate) dir: SortDir,
+    /// How strings are compared, by their bytes if not given
+    pub(crate) collation: Option<Collation>,
+    /// Where nulls are placed, as the least values if not given
+    pub(crate) nulls: Option<NullsOrder>,
+}
+
+impl Display for Sorter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.dir == SortDir::Dsc {
+            write!(f, "-")?;
+        }
+        match &self.key {
+            Expr::Binding { .. } => write!(f, "{}", self.key)?,
+            key => write!(f, "({key})")?,
+        }
+        if let Some(c) = self.collation {
+            write!(f, " collate {c}")?;
+        }
+        match self.nulls {
+            None => {}
+            Some(NullsOrder::First) => write!(f, " nulls first")?,
+            Some(NullsOrder::Last) => write!(f, " nulls last")?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum RelationOp {
     Create,
@@ -248,6 +333,7 @@ impl Debug for FixedRuleApply {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct MagicFixedRuleApply {
     pub(crate) fixed_handle: FixedRuleHandle,
     pub(crate) rule_args: Vec<MagicFixedRuleRuleArg>,
@@ -286,7 +372,7 @@ impl MagicFixedRuleApply {
         idx: usize,
         len: usize,
         tx: &SessionTx<'_>,
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<&MagicFixedRuleRuleArg> {
         #[derive(Error, Diagnostic, Debug)]
         #[error("Input relation to fixed rule has insufficient arity")]
@@ -385,7 +471,7 @@ impl Display for FixedRuleArg {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum MagicFixedRuleRuleArg {
     InMem {
         name: MagicSymbol,
@@ -522,6 +608,45 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 pub(crate) struct NoEntryError;
 
 impl InputProgram {
+    /// Replace the parameter placeholders throughout the program.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        for rules_or_fixed in self.prog.values_mut() {
+            match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => {
+                    for rule in rules {
+                        for (_, aggr_args) in rule.aggr.iter_mut().flatten() {
+                            for arg in aggr_args {
+                                arg.bind_params(params)
+                            }
+                        }
+                        for atom in &mut rule.body {
+                            atom.bind_params(params)
+                        }
+                        for subquery in &mut rule.subqueries {
+                            subquery.bind_params(params)
+                        }
+                    }
+                }
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    for opt in Arc::make_mut(&mut fixed.options).values_mut() {
+                        opt.bind_params(params)
+                    }
+                }
+            }
+        }
+        if let Some((handle, _)) = &mut self.out_opts.store_relation {
+            for col in handle
+                .metadata
+                .keys
+                .iter_mut()
+                .chain(handle.metadata.non_keys.iter_mut())
+            {
+                if let Some(expr) = &mut col.default_gen {
+                    expr.bind_params(params)
+                }
+            }
+        }
+    }
     pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
         if let Some((h, _)) = &self.out_opts.store_relation {
             if !h.name.name.starts_with('_') {
@@ -565,14 +690,7 @@ impl InputProgram {
                     for (symb, aggr) in head.iter().zip(aggrs.iter()) {
                         if let Some((aggr, _)) = aggr {
                             ret.push(Symbol::new(
-                                format!(
-                                    "{}({})",
-                                    aggr.name
-                                        .strip_prefix("AGGR_")
-                                        .unwrap()
-                                        .to_ascii_lowercase(),
-                                    symb
-                                ),
+                                format!("{}({})", aggr.query_name(), symb),
                                 symb.span,
                             ))
                         } else {
@@ -598,11 +716,20 @@ impl InputProgram {
         tx: &SessionTx<'_>,
     ) -> Result<(NormalFormProgram, QueryOutOptions)> {
         let mut prog: BTreeMap<Symbol, _> = Default::default();
-        for (k, rules_or_fixed) in self.prog {
+        // rules lifted from subqueries are normalized in turn
+        let mut pending = self.prog.into_iter().collect_vec();
+        let mut lifted = vec![];
+        while let Some((k, rules_or_fixed)) = pending.pop() {
             match rules_or_fixed {
                 InputInlineRulesOrFixed::Rules { rules } => {
                     let mut collected_rules = vec![];
                     for rule in rules {
+                        let has_subqueries = !rule.subqueries.is_empty();
+                        let rule = if has_subqueries {
+                            rule.lift_subqueries(&k, &mut lifted)?
+                        } else {
+                            rule
+                        };
                         let mut counter = -1;
                         let mut gen_symb = |span| {
                             counter += 1;
@@ -648,9 +775,14 @@ impl InputProgram {
                                 aggr: rule.aggr.clone(),
                                 body,
                             };
-                            collected_rules.push(normalized_rule.convert_to_well_ordered_rule()?);
+                            collected_rules.push(
+                                normalized_rule.convert_to_well_ordered_rule(has_subqueries)?,
+                            );
                         }
                     }
+                    pending.extend(lifted.drain(..).map(|(name, rule)| {
+                        (name, InputInlineRulesOrFixed::Rules { rules: vec![rule] })
+                    }));
                     prog.insert(
                         k.clone(),
                         NormalFormRulesOrFixed::Rules {
@@ -841,22 +973,69 @@ impl MagicSymbol {
 #[derive(Debug, Clone)]
 pub(crate) struct InputInlineRule {
     pub(crate) head: Vec<Symbol>,
-    pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
+    pub(crate) aggr: Vec<Option<(Aggr, Vec<DataValue>)>>,
     pub(crate) body: Vec<InputAtom>,
+    /// The subqueries within the expressions of the body
+    pub(crate) subqueries: Vec<InputSubquery>,
     pub(crate) span: SourceSpan,
 }
 
+/// An `exists { ... }` or scalar `{ ?[x] := ... }` subquery within an expression, which is
+/// lifted into rules of its own when the program is normalized.
+#[derive(Debug, Clone)]
+pub(crate) struct InputSubquery {
+    /// The variable standing in for the value of the subquery in the rule containing it
+    pub(crate) var: Symbol,
+    /// The output of a scalar subquery with its aggregation, `None` for `exists`
+    pub(crate) value: Option<(Symbol, Option<(Aggr, Vec<DataValue>)>)>,
+    pub(crate) body: Vec<InputAtom>,
+    /// The subqueries within the expressions of `body`
+    pub(crate) subqueries: Vec<InputSubquery>,
+    pub(crate) span: SourceSpan,
+}
+
+impl InputSubquery {
+    /// Collect the variables occurring in the subquery, including those in nested subqueries
+    /// other than their outputs
+    pub(crate) fn collect_bindings(&self, coll: &mut BTreeSet<Symbol>) {
+        for atom in &self.body {
+            atom.collect_bindings(coll)
+        }
+        for nested in &self.subqueries {
+            let mut nested_vars = BTreeSet::new();
+            nested.collect_bindings(&mut nested_vars);
+            if let Some((output, _)) = &nested.value {
+                nested_vars.remove(output);
+            }
+            coll.extend(nested_vars)
+        }
+    }
+    fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        if let Some((_, Some((_, aggr_args)))) = &mut self.value {
+            for arg in aggr_args {
+                arg.bind_params(params)
+            }
+        }
+        for atom in &mut self.body {
+            atom.bind_params(params)
+        }
+        for nested in &mut self.subqueries {
+            nested.bind_params(params)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct NormalFormInlineRule {
     pub(crate) head: Vec<Symbol>,
-    pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
+    pub(crate) aggr: Vec<Option<(Aggr, Vec<DataValue>)>>,
     pub(crate) body: Vec<NormalFormAtom>,
 }
 
 #[derive(Debug)]
 pub(crate) struct MagicInlineRule {
     pub(crate) head: Vec<Symbol>,
-    pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
+    pub(crate) aggr: Vec<Option<(Aggr, Vec<DataValue>)>>,
     pub(crate) body: Vec<MagicAtom>,
 }
 
@@ -865,7 +1044,9 @@ impl MagicInlineRule {
         let mut coll = BTreeSet::new();
         for atom in self.body.iter() {
             match atom {
-                MagicAtom::Rule(rule) | MagicAtom::NegatedRule(rule) => {
+                MagicAtom::Rule(rule)
+                | MagicAtom::NegatedRule(rule)
+                | MagicAtom::OptionalRule(rule) => {
                     coll.insert(rule.name.clone());
                 }
                 _ => {}
@@ -893,6 +1074,12 @@ pub(crate) enum InputAtom {
         inner: Box<InputAtom>,
         span: SourceSpan,
     },
+    /// A rule or relation application binding its unbound variables to null
+    /// when nothing matches
+    Optional {
+        inner: Box<InputAtom>,
+        span: SourceSpan,
+    },
     Conjunction {
         inner: Vec<InputAtom>,
         span: SourceSpan,
@@ -943,6 +1130,9 @@ impl Display for InputAtom {
             InputAtom::Negation { inner, .. } => {
                 write!(f, "not {inner}")?;
             }
+            InputAtom::Optional { inner, .. } => {
+                write!(f, "optional {inner}")?;
+            }
             InputAtom::Conjunction { inner, .. } => {
                 for (i, a) in inner.iter().enumerate() {
                     if i > 0 {
@@ -995,6 +1185,7 @@ impl InputAtom {
     pub(crate) fn span(&self) -> SourceSpan {
         match self {
             InputAtom::Negation { span, .. }
+            | InputAtom::Optional { span, .. }
             | InputAtom::Conjunction { span, .. }
             | InputAtom::Disjunction { span, .. } => *span,
             InputAtom::Rule { inner, .. } => inner.span,
@@ -1004,6 +1195,69 @@ impl InputAtom {
             InputAtom::Unification { inner, .. } => inner.span,
         }
     }
+    /// Collect the variables occurring in the atom
+    pub(crate) fn collect_bindings(&self, coll: &mut BTreeSet<Symbol>) {
+        match self {
+            InputAtom::Rule { inner } => {
+                for arg in &inner.args {
+                    arg.collect_bindings(coll)
+                }
+            }
+            InputAtom::NamedFieldRelation { inner } => {
+                for arg in inner.args.values() {
+                    arg.collect_bindings(coll)
+                }
+            }
+            InputAtom::Relation { inner } => {
+                for arg in &inner.args {
+                    arg.collect_bindings(coll)
+                }
+            }
+            InputAtom::Predicate { inner } => inner.collect_bindings(coll),
+            InputAtom::Negation { inner, .. } | InputAtom::Optional { inner, .. } => {
+                inner.collect_bindings(coll)
+            }
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_bindings(coll)
+                }
+            }
+            InputAtom::Unification { inner } => {
+                coll.insert(inner.binding.clone());
+                inner.expr.collect_bindings(coll)
+            }
+        }
+    }
+    /// Replace the parameter placeholders within the atom.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        match self {
+            InputAtom::Rule { inner } => {
+                for arg in &mut inner.args {
+                    arg.bind_params(params)
+                }
+            }
+            InputAtom::NamedFieldRelation { inner } => {
+                for arg in inner.args.values_mut() {
+                    arg.bind_params(params)
+                }
+            }
+            InputAtom::Relation { inner } => {
+                for arg in &mut inner.args {
+                    arg.bind_params(params)
+                }
+            }
+            InputAtom::Predicate { inner } => inner.bind_params(params),
+            InputAtom::Negation { inner, .. } | InputAtom::Optional { inner, .. } => {
+                inner.bind_params(params)
+            }
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.bind_params(params)
+                }
+            }
+            InputAtom::Unification { inner } => inner.expr.bind_params(params),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1012,6 +1266,8 @@ pub(crate) enum NormalFormAtom {
     Relation(NormalFormRelationApplyAtom),
     NegatedRule(NormalFormRuleApplyAtom),
     NegatedRelation(NormalFormRelationApplyAtom),
+    OptionalRule(NormalFormRuleApplyAtom),
+    OptionalRelation(NormalFormRelationApplyAtom),
     Predicate(Expr),
     Unification(Unification),
 }
@@ -1023,6 +1279,8 @@ pub(crate) enum MagicAtom {
     Predicate(Expr),
     NegatedRule(MagicRuleApplyAtom),
     NegatedRelation(MagicRelationApplyAtom),
+    OptionalRule(MagicRuleApplyAtom),
+    OptionalRelation(MagicRelationApplyAtom),
     Unification(Unification),
 }
 
@@ -8,6 +8,7 @@
 
 use std::cmp::Reverse;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::STANDARD;
@@ -18,8 +19,10 @@ use miette::{bail, ensure, Diagnostic, Result};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::collation::Collation;
+use crate::data::decimal::Decimal;
 use crate::data::expr::Expr;
-use crate::data::value::{DataValue, UuidWrapper, Validity, ValidityTs};
+use crate::data::value::{DataValue, Num, UuidWrapper, Validity, ValidityTs};
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub(crate) struct NullableColType {
@@ -34,7 +37,10 @@ impl Display for NullableColType {
             ColType::Bool => f.write_str("Bool")?,
             ColType::Int => f.write_str("Int")?,
             ColType::Float => f.write_str("Float")?,
+            ColType::Decimal(None) => f.write_str("Decimal")?,
+            ColType::Decimal(Some(scale)) => write!(f, "Decimal({scale})")?,
             ColType::String => f.write_str("String")?,
+            ColType::CollatedString(c) => write!(f, "String collate {c}")?,
             ColType::Bytes => f.write_str("Bytes")?,
             ColType::Uuid => f.write_str("Uuid")?,
             ColType::Validity => f.write_str("Validity")?,
@@ -80,6 +86,11 @@ pub(crate) enum ColType {
     },
     Tuple(Vec<NullableColType>),
     Validity,
+    // new variants go last, as the metadata of stored relations refers to them by position
+    /// Strings compared by the collation when used as keys
+    CollatedString(Collation),
+    /// Decimals, rescaled to the scale if given
+    Decimal(Option<u8>),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
@@ -187,7 +198,23 @@ impl NullableColType {
             ColType::Bool => DataValue::from(data.get_bool().ok_or_else(make_err)?),
             ColType::Int => DataValue::from(data.get_int().ok_or_else(make_err)?),
             ColType::Float => DataValue::from(data.get_float().ok_or_else(make_err)?),
-            ColType::String => {
+            ColType::Decimal(scale) => {
+                let d = match &data {
+                    DataValue::Num(Num::Int(i)) => Decimal::from(*i),
+                    DataValue::Num(Num::Float(f)) => {
+                        Decimal::from_f64(*f).map_err(|_| make_err())?
+                    }
+                    DataValue::Num(Num::Decimal(d)) => *d,
+                    DataValue::Str(s) => Decimal::from_str(s.trim()).map_err(|_| make_err())?,
+                    _ => bail!(make_err()),
+                };
+                let d = match scale {
+                    None => d,
+                    Some(scale) => d.rescale(*scale)?,
+                };
+                DataValue::Num(Num::Decimal(d))
+            }
+            ColType::String | ColType::CollatedString(_) => {
                 if matches!(data, DataValue::Str(_)) {
                     data
                 } else {
@@ -0,0 +1,149 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Merging t-digest for estimating quantiles.
+#[derive(Clone)]
+pub(crate) struct TDigest {
+    compression: f64,
+    /// (mean, weight), sorted by mean
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+const TDIGEST_BUFFER_SIZE: usize = 512;
+
+impl Default for TDigest {
+    fn default() -> Self {
+        Self {
+            compression: 100.,
+            centroids: vec![],
+            buffer: vec![],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl TDigest {
+    pub(crate) fn add(&mut self, x: f64) {
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.buffer.push(x);
+        if self.buffer.len() >= TDIGEST_BUFFER_SIZE {
+            self.centroids = self.merged();
+            self.buffer.clear();
+        }
+    }
+    /// The centroids with the buffered values merged in.
+    fn merged(&self) -> Vec<(f64, f64)> {
+        let mut points = self.centroids.clone();
+        points.extend(self.buffer.iter().map(|x| (*x, 1.)));
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total: f64 = points.iter().map(|(_, w)| w).sum();
+        let mut ret = Vec::with_capacity(points.len());
+        let mut points = points.into_iter();
+        let mut current = match points.next() {
+            None => return ret,
+            Some(p) => p,
+        };
+        let mut weight_so_far = 0.;
+        for (mean, weight) in points {
+            let proposed = current.1 + weight;
+            let q0 = weight_so_far / total;
+            let q2 = (weight_so_far + proposed) / total;
+            let limit = 4. * total * (q0 * (1. - q0)).min(q2 * (1. - q2)) / self.compression;
+            if proposed <= limit {
+                current.0 += (mean - current.0) * weight / proposed;
+                current.1 = proposed;
+            } else {
+                weight_so_far += current.1;
+                ret.push(current);
+                current = (mean, weight);
+            }
+        }
+        ret.push(current);
+        ret
+    }
+    /// Estimate the `q`-quantile, `q` being between 0 and 1.
+    pub(crate) fn quantile(&self, q: f64) -> Option<f64> {
+        let centroids = self.merged();
+        if centroids.is_empty() {
+            return None;
+        }
+        if q <= 0. {
+            return Some(self.min);
+        }
+        if q >= 1. {
+            return Some(self.max);
+        }
+        let total: f64 = centroids.iter().map(|(_, w)| w).sum();
+        let target = q * total;
+        // interpolate between the centres of the centroids, starting from the minimum
+        let mut prev = (0., self.min);
+        let mut cumulative = 0.;
+        for (mean, weight) in centroids {
+            let center = cumulative + weight / 2.;
+            if target < center {
+                return Some(prev.1 + (mean - prev.1) * (target - prev.0) / (center - prev.0));
+            }
+            prev = (center, mean);
+            cumulative += weight;
+        }
+        if total <= prev.0 {
+            return Some(self.max);
+        }
+        Some(prev.1 + (self.max - prev.1) * (target - prev.0) / (total - prev.0))
+    }
+}
+
+/// HyperLogLog for estimating the number of distinct values.
+pub(crate) struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+const HLL_PRECISION: u32 = 14;
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub(crate) fn add(&mut self, value: &impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let idx = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if self.registers[idx] < rank {
+            self.registers[idx] = rank;
+        }
+    }
+    pub(crate) fn count(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1. + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // linear counting is more accurate for small cardinalities
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
@@ -296,7 +296,7 @@ fn test_sum() {
     sum_aggr.set(&DataValue::from(3)).unwrap();
     sum_aggr.set(&DataValue::from(4)).unwrap();
     sum_aggr.set(&DataValue::from(5)).unwrap();
-    assert_eq!(sum_aggr.get().unwrap(), DataValue::from(15.));
+    assert_eq!(sum_aggr.get().unwrap(), DataValue::from(15));
 }
 
 #[test]
@@ -310,7 +310,7 @@ fn test_product() {
     product_aggr.set(&DataValue::from(3)).unwrap();
     product_aggr.set(&DataValue::from(4)).unwrap();
     product_aggr.set(&DataValue::from(5)).unwrap();
-    assert_eq!(product_aggr.get().unwrap(), DataValue::from(120.));
+    assert_eq!(product_aggr.get().unwrap(), DataValue::from(120));
 }
 
 #[test]
@@ -572,3 +572,229 @@ fn test_bit_xor() {
     bit_xor_aggr.set(&DataValue::Bytes(vec![0b01011])).unwrap();
     assert_eq!(bit_xor_aggr.get().unwrap(), DataValue::Bytes(vec![0b10111]));
 }
+
+#[test]
+fn test_median() {
+    let mut aggr = parse_aggr("median").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut median_aggr = aggr.normal_op.unwrap();
+    assert_eq!(median_aggr.get().unwrap(), DataValue::Null);
+    for i in [4, 1, 3, 2] {
+        median_aggr.set(&DataValue::from(i)).unwrap();
+    }
+    assert_eq!(median_aggr.get().unwrap(), DataValue::from(2.5));
+    median_aggr.set(&DataValue::from(100)).unwrap();
+    assert_eq!(median_aggr.get().unwrap(), DataValue::from(3.));
+    assert!(median_aggr.set(&DataValue::from("a")).is_err());
+}
+
+#[test]
+fn test_percentile() {
+    let mut aggr = parse_aggr("percentile").unwrap().clone();
+    assert!(aggr.clone().normal_init(&[]).is_err());
+    assert!(aggr.clone().normal_init(&[DataValue::from(1.5)]).is_err());
+    aggr.normal_init(&[DataValue::from(0.25)]).unwrap();
+
+    let mut percentile_aggr = aggr.normal_op.unwrap();
+    for i in 0..=100 {
+        percentile_aggr.set(&DataValue::from(i)).unwrap();
+    }
+    assert_eq!(percentile_aggr.get().unwrap(), DataValue::from(25.));
+}
+
+#[test]
+fn test_approx_percentile() {
+    let mut aggr = parse_aggr("approx_percentile").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(0.9)]).unwrap();
+
+    let mut percentile_aggr = aggr.normal_op.unwrap();
+    assert_eq!(percentile_aggr.get().unwrap(), DataValue::Null);
+    for i in 0..10000 {
+        percentile_aggr
+            .set(&DataValue::from((i * 7919) % 10000))
+            .unwrap();
+    }
+    let estimate = percentile_aggr.get().unwrap().get_float().unwrap();
+    assert!(estimate.abs_diff_eq(&9000., 50.), "{}", estimate);
+}
+
+#[test]
+fn test_approx_count_distinct() {
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut count_aggr = aggr.normal_op.unwrap();
+    assert_eq!(count_aggr.get().unwrap(), DataValue::from(0));
+    for _ in 0..3 {
+        for i in 0..10000 {
+            count_aggr.set(&DataValue::from(i)).unwrap();
+        }
+    }
+    let estimate = count_aggr.get().unwrap().get_int().unwrap();
+    assert!((9800..=10200).contains(&estimate), "{}", estimate);
+}
+
+#[test]
+fn test_mode() {
+    let mut aggr = parse_aggr("mode").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut mode_aggr = aggr.normal_op.unwrap();
+    assert_eq!(mode_aggr.get().unwrap(), DataValue::Null);
+    for v in ["b", "a", "c", "b", "a"] {
+        mode_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    assert_eq!(mode_aggr.get().unwrap(), DataValue::from("a"));
+    mode_aggr.set(&DataValue::from("c")).unwrap();
+    mode_aggr.set(&DataValue::from("c")).unwrap();
+    assert_eq!(mode_aggr.get().unwrap(), DataValue::from("c"));
+}
+
+#[test]
+fn test_skewness_kurtosis() {
+    let values = [2, 8, 0, 4, 1, 9, 9, 0];
+
+    let mut aggr = parse_aggr("skewness").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut skewness_aggr = aggr.normal_op.unwrap();
+    for v in values {
+        skewness_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    let skewness = skewness_aggr.get().unwrap().get_float().unwrap();
+    assert!(skewness.abs_diff_eq(&0.2650554122698573, 1e-10));
+
+    let mut aggr = parse_aggr("kurtosis").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut kurtosis_aggr = aggr.normal_op.unwrap();
+    for v in values {
+        kurtosis_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    let kurtosis = kurtosis_aggr.get().unwrap().get_float().unwrap();
+    assert!(kurtosis.abs_diff_eq(&-1.6660010752838508, 1e-10));
+
+    // shifting the values far from zero changes nothing
+    for (name, expected) in [
+        ("skewness", 0.2650554122698573),
+        ("kurtosis", -1.6660010752838508),
+    ] {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(&[]).unwrap();
+        let mut aggr = aggr.normal_op.unwrap();
+        for v in values {
+            aggr.set(&DataValue::from(v as f64 + 1e9)).unwrap();
+        }
+        let res = aggr.get().unwrap().get_float().unwrap();
+        assert!(res.abs_diff_eq(&expected, 1e-6));
+    }
+
+    // undefined for fewer than two values, or values all the same
+    for name in ["skewness", "kurtosis"] {
+        for values in [vec![], vec![3], vec![3, 3, 3]] {
+            let mut aggr = parse_aggr(name).unwrap().clone();
+            aggr.normal_init(&[]).unwrap();
+            let mut aggr = aggr.normal_op.unwrap();
+            for v in values {
+                aggr.set(&DataValue::from(v)).unwrap();
+            }
+            assert_eq!(aggr.get().unwrap(), DataValue::Null);
+        }
+    }
+}
+
+#[test]
+fn test_covariance_corr() {
+    let pairs = [(1, 2), (2, 4), (3, 5), (4, 4), (5, 5)];
+
+    let mut aggr = parse_aggr("covariance").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut covariance_aggr = aggr.normal_op.unwrap();
+    for (x, y) in pairs {
+        covariance_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(x),
+                DataValue::from(y),
+            ]))
+            .unwrap();
+    }
+    let covariance = covariance_aggr.get().unwrap().get_float().unwrap();
+    assert!(covariance.abs_diff_eq(&1.5, 1e-10));
+    assert!(covariance_aggr.set(&DataValue::from(1)).is_err());
+
+    let mut aggr = parse_aggr("corr").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut corr_aggr = aggr.normal_op.unwrap();
+    for (x, y) in pairs {
+        corr_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(x),
+                DataValue::from(y),
+            ]))
+            .unwrap();
+    }
+    let corr = corr_aggr.get().unwrap().get_float().unwrap();
+    assert!(corr.abs_diff_eq(&0.7745966692414834, 1e-10));
+
+    // undefined for a single pair, and the correlation also unless both sides vary
+    for (name, pairs, expected) in [
+        ("covariance", vec![], DataValue::Null),
+        ("covariance", vec![(1, 2)], DataValue::Null),
+        ("covariance", vec![(1, 2), (1, 3)], DataValue::from(0.)),
+        ("corr", vec![], DataValue::Null),
+        ("corr", vec![(1, 2)], DataValue::Null),
+        ("corr", vec![(1, 2), (1, 3)], DataValue::Null),
+        ("corr", vec![(1, 2), (2, 2)], DataValue::Null),
+    ] {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(&[]).unwrap();
+        let mut aggr = aggr.normal_op.unwrap();
+        for (x, y) in pairs {
+            aggr.set(&DataValue::List(vec![
+                DataValue::from(x),
+                DataValue::from(y),
+            ]))
+            .unwrap();
+        }
+        assert_eq!(aggr.get().unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_string_agg() {
+    let mut aggr = parse_aggr("string_agg").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(", ")]).unwrap();
+
+    let mut string_agg_aggr = aggr.normal_op.unwrap();
+    string_agg_aggr.set(&DataValue::from("a")).unwrap();
+    string_agg_aggr.set(&DataValue::from("b")).unwrap();
+    string_agg_aggr.set(&DataValue::from("c")).unwrap();
+    assert_eq!(string_agg_aggr.get().unwrap(), DataValue::from("a, b, c"));
+    assert!(string_agg_aggr.set(&DataValue::from(1)).is_err());
+
+    let mut aggr = parse_aggr("string_agg").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut string_agg_aggr = aggr.normal_op.unwrap();
+    string_agg_aggr.set(&DataValue::from("a")).unwrap();
+    string_agg_aggr.set(&DataValue::from("b")).unwrap();
+    assert_eq!(string_agg_aggr.get().unwrap(), DataValue::from("a,b"));
+}
+
+#[test]
+fn test_top_k() {
+    let mut aggr = parse_aggr("top_k").unwrap().clone();
+    assert!(aggr.clone().normal_init(&[DataValue::from(0)]).is_err());
+    aggr.normal_init(&[DataValue::from(3)]).unwrap();
+
+    let mut top_k_aggr = aggr.normal_op.unwrap();
+    for i in [5, 1, 9, 3, 7, 9, 2] {
+        top_k_aggr.set(&DataValue::from(i)).unwrap();
+    }
+    assert_eq!(
+        top_k_aggr.get().unwrap(),
+        DataValue::List(vec![
+            DataValue::from(9),
+            DataValue::from(9),
+            DataValue::from(7)
+        ])
+    );
+}
@@ -19,3 +19,13 @@ fn bad_values() {
     println!("{}", JsonValue::from(DataValue::from(f64::NEG_INFINITY)));
     println!("{}", JsonValue::from(DataValue::from(f64::NAN)));
 }
+
+#[test]
+fn param_placeholder() {
+    let placeholder = DataValue::param_placeholder("x");
+    assert_eq!(JsonValue::from(placeholder.clone()), json!("$x"));
+    assert_eq!(
+        JsonValue::from(DataValue::List(vec![DataValue::from(1), placeholder])),
+        json!([1, "$x"])
+    );
+}
@@ -9,7 +9,9 @@
 
 use uuid::Uuid;
 
-use crate::data::memcmp::{decode_bytes, MemCmpEncoder};
+use crate::data::collation::Collation;
+use crate::data::decimal::Decimal;
+use crate::data::memcmp::{decode_bytes, upgrade_key_encoding, MemCmpEncoder};
 use crate::data::value::{DataValue, Num, UuidWrapper};
 
 #[test]
@@ -49,6 +51,75 @@ fn encode_decode_num() {
     assert_eq!(collected, collected_copy);
 }
 
+#[test]
+fn encode_decode_decimal() {
+    use std::str::FromStr;
+
+    let mut nums = vec![Num::Int(0), Num::Int(1), Num::Int(-3), Num::Int(i64::MAX)];
+    nums.extend([0.0, -0.0, 0.1, 1.0, -2.5, 1e30].map(Num::Float));
+    for s in [
+        "0",
+        "0.00",
+        "1",
+        "1.0",
+        "1.00",
+        "0.1",
+        "0.10",
+        "-0.1",
+        "-0.12",
+        "-0.123",
+        "0.12",
+        "0.123",
+        "12.5",
+        "-12.50",
+        "100",
+        "-100",
+        "9223372036854775807.5",
+        "9223372036854775807.25",
+        "123456789012345678901234567890.12345678",
+        "-123456789012345678901234567890.12345678",
+        "0.00000000000000000000000000000000000001",
+        // around 2^53, where not every integer is a float
+        "9007199254740992",
+        "9007199254740992.5",
+        "9007199254740993",
+        "9007199254740993.00",
+        "-9007199254740993",
+        // the float 0.1 is slightly greater
+        "0.1000000000000000055511151231257827",
+    ] {
+        nums.push(Num::Decimal(Decimal::from_str(s).unwrap()));
+    }
+    let p53 = 1i64 << 53;
+    nums.extend([p53 - 1, p53, p53 + 1, p53 + 2, p53 + 3, -p53 - 1].map(Num::Int));
+    nums.extend([p53 as f64, (p53 + 2) as f64, -p53 as f64].map(Num::Float));
+    let mut encoded = vec![];
+    for n in &nums {
+        let mut encoder = vec![];
+        encoder.encode_num(*n);
+        let (decoded, rest) = Num::decode_from_key(&encoder);
+        assert_eq!(decoded, *n);
+        assert_eq!(format!("{decoded:?}"), format!("{n:?}"));
+        assert!(rest.is_empty());
+        encoded.push(encoder);
+    }
+    // the order of the encodings is the order of the numbers
+    let mut by_encoding = nums.iter().zip(encoded).collect::<Vec<_>>();
+    by_encoding.sort_by(|(_, a), (_, b)| a.cmp(b));
+    let by_encoding = by_encoding.into_iter().map(|(n, _)| *n).collect::<Vec<_>>();
+    nums.sort();
+    assert_eq!(by_encoding, nums);
+
+    // numbers of the same approximation as floats are still compared exactly
+    let dec = |s: &str| Num::Decimal(Decimal::from_str(s).unwrap());
+    assert!(Num::Int(p53 + 1) > dec("9007199254740992"));
+    assert!(dec("9007199254740992.5") > Num::Float(p53 as f64));
+    assert!(Num::Int(p53 + 1) > Num::Float(p53 as f64));
+    assert!(Num::Int(-p53 - 1) < Num::Float(-p53 as f64));
+    assert!(dec("0.1") < Num::Float(0.1));
+    assert!(dec("0.1000000000000000055511151231257827") < Num::Float(0.1));
+}
+
 #[test]
 fn test_encode_decode_uuid() {
     let uuid = DataValue::Uuid(UuidWrapper(
@@ -135,3 +206,64 @@ fn encode_decode_datavalues() {
     assert!(remaining.is_empty());
     assert_eq!(decoded, v);
 }
+
+#[test]
+fn encode_collated_strings() {
+    let encode = |collation: Collation, s: &str| {
+        let mut encoder = vec![];
+        encoder.encode_collated_str(collation, s);
+        encoder.encode_datavalue(&DataValue::from(1));
+        encoder
+    };
+    assert_eq!(
+        encode(Collation::NoCase, "Alice"),
+        encode(Collation::NoCase, "aLICE")
+    );
+    assert!(encode(Collation::NoCase, "alice") < encode(Collation::NoCase, "Bob"));
+
+    // the value following a collated string can still be decoded
+    let encoded = encode(Collation::NoCase, "Á");
+    let (_, remaining) = DataValue::decode_from_key(&encoded);
+    let (next, remaining) = DataValue::decode_from_key(remaining);
+    assert!(remaining.is_empty());
+    assert_eq!(next, DataValue::from(1));
+}
+
+#[test]
+fn upgrade_inexact_int_keys() {
+    let key = |vals: &[DataValue]| {
+        let mut key = vec![0; 8];
+        for val in vals {
+            key.encode_datavalue(val);
+        }
+        key
+    };
+    // before storage version 1, integers were placed after their nearest float
+    let old_int = |i: i64| {
+        let mut encoded = key(&[DataValue::from(i as f64)]);
+        encoded.truncate(encoded.len() - 1);
+        encoded.push(0b00000100);
+        encoded.extend((i as u64 ^ 0x8000000000000000).to_be_bytes());
+        encoded
+    };
+    let big = (1i64 << 53) + 3;
+    assert_eq!(big as f64, (big + 1) as f64);
+    for i in [big, -big] {
+        let mut old = old_int(i);
+        old.extend(&key(&[DataValue::from("x")])[8..]);
+        assert_eq!(
+            upgrade_key_encoding(&old),
+            Some(key(&[DataValue::from(i), DataValue::from("x")]))
+        );
+    }
+    let upgraded = upgrade_key_encoding(&old_int(big)).unwrap();
+    assert!(upgraded < key(&[DataValue::from((big + 1) as f64)]));
+    assert!(upgraded > key(&[DataValue::from((big - 1) as f64)]));
+
+    // keys whose encoding has not changed are left alone
+    assert_eq!(upgrade_key_encoding(&old_int(1 << 60)), None);
+    let mut collated = vec![0; 8];
+    collated.encode_collated_str(Collation::NoCase, "Alice");
+    collated.encode_datavalue(&DataValue::from(5));
+    assert_eq!(upgrade_key_encoding(&collated), None);
+}
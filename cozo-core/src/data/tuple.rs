@@ -7,8 +7,11 @@
  */
 
 use crate::data::functions::TERMINAL_VALIDITY;
+use itertools::Itertools;
 use miette::Result;
 use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::iter;
 
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::value::{DataValue, Validity, ValidityTs};
@@ -18,6 +21,143 @@ pub type Tuple = Vec<DataValue>;
 
 pub(crate) type TupleIter<'a> = Box<dyn Iterator<Item = Result<Tuple>> + 'a>;
 
+/// Maximum number of rows collected into a single [TupleBatch].
+pub(crate) const BATCH_SIZE: usize = 1024;
+
+/// A chunk of tuples stored column by column.
+///
+/// `len` is tracked separately since zero-width tuples are legal.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct TupleBatch {
+    pub(crate) columns: Vec<Vec<DataValue>>,
+    pub(crate) len: usize,
+}
+
+pub(crate) type BatchIter<'a> = Box<dyn Iterator<Item = Result<TupleBatch>> + 'a>;
+
+impl TupleBatch {
+    pub(crate) fn with_width(width: usize) -> Self {
+        Self {
+            columns: (0..width).map(|_| Vec::with_capacity(BATCH_SIZE)).collect(),
+            len: 0,
+        }
+    }
+    pub(crate) fn width(&self) -> usize {
+        self.columns.len()
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub(crate) fn push_row(&mut self, row: Tuple) {
+        debug_assert_eq!(row.len(), self.width());
+        for (col, val) in self.columns.iter_mut().zip(row) {
+            col.push(val);
+        }
+        self.len += 1;
+    }
+    /// Copy the `idx`-th row into `buf`, reusing its allocation.
+    pub(crate) fn fill_row(&self, idx: usize, buf: &mut Tuple) {
+        buf.clear();
+        buf.extend(self.columns.iter().map(|col| col[idx].clone()));
+    }
+    pub(crate) fn into_rows(self) -> Vec<Tuple> {
+        let mut rows = (0..self.len)
+            .map(|_| Vec::with_capacity(self.columns.len()))
+            .collect::<Vec<_>>();
+        for col in self.columns {
+            for (row, val) in rows.iter_mut().zip(col) {
+                row.push(val);
+            }
+        }
+        rows
+    }
+    /// Keep only the rows whose entry in `mask` is `true`.
+    pub(crate) fn retain_rows(&mut self, mask: &[bool]) {
+        debug_assert_eq!(mask.len(), self.len);
+        for col in self.columns.iter_mut() {
+            let mut it = mask.iter();
+            col.retain(|_| *it.next().unwrap());
+        }
+        self.len = mask.iter().filter(|keep| **keep).count();
+    }
+    /// Rearrange the columns so that the `i`-th column of the result is the
+    /// `indices[i]`-th column of the original.
+    pub(crate) fn project(self, indices: &[usize]) -> Self {
+        let mut old = self.columns.into_iter().map(Some).collect::<Vec<_>>();
+        let columns = indices
+            .iter()
+            .enumerate()
+            .map(|(pos, i)| {
+                if indices[pos + 1..].contains(i) {
+                    old[*i].clone().unwrap()
+                } else {
+                    old[*i].take().unwrap()
+                }
+            })
+            .collect();
+        Self {
+            columns,
+            len: self.len,
+        }
+    }
+    pub(crate) fn remove_columns(&mut self, indices: &BTreeSet<usize>) {
+        if indices.is_empty() {
+            return;
+        }
+        let mut i = 0;
+        self.columns.retain(|_| {
+            let keep = !indices.contains(&i);
+            i += 1;
+            keep
+        });
+    }
+}
+
+/// Collect a tuple iterator into chunks of at most [BATCH_SIZE] rows.
+pub(crate) fn batch_tuples(it: TupleIter<'_>) -> BatchIter<'_> {
+    let mut it = it.peekable();
+    Box::new(iter::from_fn(move || {
+        let first = match it.next()? {
+            Ok(t) => t,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut batch = TupleBatch::with_width(first.len());
+        batch.push_row(first);
+        while batch.len < BATCH_SIZE {
+            match it.peek() {
+                Some(Ok(_)) => batch.push_row(it.next().unwrap().unwrap()),
+                _ => break,
+            }
+        }
+        Some(Ok(batch))
+    }))
+}
+
+/// Collect a tuple iterator into vectors of at most [BATCH_SIZE] rows.
+pub(crate) fn chunk_tuples(it: TupleIter<'_>) -> impl Iterator<Item = Result<Vec<Tuple>>> + '_ {
+    let mut it = it.peekable();
+    iter::from_fn(move || {
+        let first = match it.next()? {
+            Ok(t) => t,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut chunk = Vec::with_capacity(BATCH_SIZE);
+        chunk.push(first);
+        while chunk.len() < BATCH_SIZE {
+            match it.peek() {
+                Some(Ok(_)) => chunk.push(it.next().unwrap().unwrap()),
+                _ => break,
+            }
+        }
+        Some(Ok(chunk))
+    })
+}
+
+/// The inverse of [batch_tuples].
+pub(crate) fn unbatch_tuples(it: BatchIter<'_>) -> TupleIter<'_> {
+    Box::new(it.map_ok(|batch| batch.into_rows()).flatten_ok())
+}
+
 pub(crate) trait TupleT {
     fn encode_as_key(&self, prefix: RelationId) -> Vec<u8>;
 }
@@ -56,29 +196,31 @@ pub fn decode_tuple_from_key(key: &[u8]) -> Tuple {
 /// the second element gives the next binary key for the seek to be used as an inclusive
 /// lower bound.
 pub fn check_key_for_validity(key: &[u8], valid_at: ValidityTs) -> (Option<Tuple>, Vec<u8>) {
-    let mut decoded = decode_tuple_from_key(key);
-    let rel_id = RelationId::raw_decode(key);
+    let decoded = decode_tuple_from_key(key);
     let vld = match decoded.last().unwrap() {
-        DataValue::Validity(vld) => vld,
+        DataValue::Validity(vld) => *vld,
         _ => unreachable!(),
     };
+    // the seek keys are made from the raw bytes, as keys in collated columns cannot be decoded
+    let seek_at = |vld: Validity| {
+        let mut nxt_seek = key[..key.len() - ENCODED_VALIDITY_LEN].to_vec();
+        nxt_seek.encode_datavalue(&DataValue::Validity(vld));
+        nxt_seek
+    };
     if vld.timestamp < valid_at {
-        *decoded.last_mut().unwrap() = DataValue::Validity(Validity {
+        let nxt_seek = seek_at(Validity {
             timestamp: valid_at,
             is_assert: Reverse(true),
         });
-        let nxt_seek = decoded.encode_as_key(rel_id);
         (None, nxt_seek)
     } else if !vld.is_assert.0 {
-        *decoded.last_mut().unwrap() = DataValue::Validity(TERMINAL_VALIDITY);
-        let nxt_seek = decoded.encode_as_key(rel_id);
-        (None, nxt_seek)
+        (None, seek_at(TERMINAL_VALIDITY))
     } else {
-        let ret = decoded.clone();
-        *decoded.last_mut().unwrap() = DataValue::Validity(TERMINAL_VALIDITY);
-        let nxt_seek = decoded.encode_as_key(rel_id);
-        (Some(ret), nxt_seek)
+        (Some(decoded), seek_at(TERMINAL_VALIDITY))
     }
 }
 
+/// The length of a validity in the memcmp encoding
+const ENCODED_VALIDITY_LEN: usize = 10;
+
 pub(crate) const ENCODED_KEY_MIN_LEN: usize = 8;
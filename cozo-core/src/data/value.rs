@@ -9,7 +9,7 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use std::cmp::{Ordering, Reverse};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 
@@ -19,6 +19,8 @@ use serde::{Deserialize, Deserializer, Serialize};
 use smartstring::{LazyCompact, SmartString};
 use uuid::Uuid;
 
+use crate::data::decimal::Decimal;
+
 /// UUID value in the database
 #[derive(Clone, Hash, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub struct UuidWrapper(pub Uuid);
@@ -150,6 +152,9 @@ pub enum DataValue {
     Validity(Validity),
     /// bottom type, used internally only
     Bot,
+    /// stands in for a parameter in a prepared query, used internally only
+    #[doc(hidden)]
+    Param(SmartString<LazyCompact>),
 }
 
 impl From<i64> for DataValue {
@@ -189,6 +194,8 @@ pub enum Num {
     Int(i64),
     /// float number
     Float(f64),
+    /// decimal number with a fixed scale
+    Decimal(Decimal),
 }
 
 impl Hash for Num {
@@ -196,6 +203,7 @@ impl Hash for Num {
         match self {
             Num::Int(i) => i.hash(state),
             Num::Float(f) => OrderedFloat(*f).hash(state),
+            Num::Decimal(d) => d.hash(state),
         }
     }
 }
@@ -211,12 +219,58 @@ impl Num {
                     None
                 }
             }
+            Num::Decimal(d) => d.to_i64(),
         }
     }
     pub(crate) fn get_float(&self) -> f64 {
         match self {
             Num::Int(i) => *i as f64,
             Num::Float(f) => *f,
+            Num::Decimal(d) => d.to_f64(),
+        }
+    }
+    /// The exact value as a decimal, for integers and decimals
+    pub(crate) fn get_decimal(&self) -> Option<Decimal> {
+        match self {
+            Num::Int(i) => Some(Decimal::from(*i)),
+            Num::Float(_) => None,
+            Num::Decimal(d) => Some(*d),
+        }
+    }
+    /// Compare the values of numbers, exactly unless floats are involved.
+    /// Unlike the total order of numbers, `1`, `1.0` and `to_decimal("1.00")` compare equal.
+    pub(crate) fn numeric_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.get_decimal(), other.get_decimal()) {
+            (Some(l), Some(r)) => Some(l.value_cmp(&r)),
+            _ => self.get_float().partial_cmp(&other.get_float()),
+        }
+    }
+    /// The largest float not greater than the number, and whether the number is greater than
+    /// it. In the total order, numbers are compared by these floats first.
+    pub(crate) fn float_floor(&self) -> (f64, bool) {
+        let (f, exact) = match self {
+            Num::Float(f) => return (*f, false),
+            Num::Int(i) => {
+                let f = *i as f64;
+                (f, (*i as i128).cmp(&(f as i128)))
+            }
+            Num::Decimal(d) => {
+                let f = d.to_f64();
+                (f, d.cmp_f64(f).unwrap())
+            }
+        };
+        match exact {
+            Ordering::Equal => (f, false),
+            Ordering::Greater => (f, true),
+            Ordering::Less => (f.next_down(), true),
+        }
+    }
+    /// Where numbers of equal values are placed in the total order
+    fn kind_rank(&self) -> u8 {
+        match self {
+            Num::Int(_) => 0,
+            Num::Decimal(_) => 1,
+            Num::Float(_) => 2,
         }
     }
 }
@@ -246,6 +300,7 @@ impl Display for Num {
                     write!(f, "{n}")
                 }
             }
+            Num::Decimal(d) => write!(f, r#"to_decimal("{d}")"#),
         }
     }
 }
@@ -255,6 +310,7 @@ impl Debug for Num {
         match self {
             Num::Int(i) => write!(f, "{i}"),
             Num::Float(n) => write!(f, "{n}"),
+            Num::Decimal(d) => write!(f, "{d}"),
         }
     }
 }
@@ -268,24 +324,23 @@ impl PartialOrd for Num {
 impl Ord for Num {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Num::Int(i), Num::Float(r)) => {
-                let l = *i as f64;
-                match l.total_cmp(r) {
-                    Ordering::Less => Ordering::Less,
-                    Ordering::Equal => Ordering::Less,
-                    Ordering::Greater => Ordering::Greater,
-                }
-            }
-            (Num::Float(l), Num::Int(i)) => {
-                let r = *i as f64;
-                match l.total_cmp(&r) {
-                    Ordering::Less => Ordering::Less,
-                    Ordering::Equal => Ordering::Greater,
-                    Ordering::Greater => Ordering::Greater,
-                }
-            }
             (Num::Int(l), Num::Int(r)) => l.cmp(r),
             (Num::Float(l), Num::Float(r)) => l.total_cmp(r),
+            (Num::Decimal(l), Num::Decimal(r)) => l.cmp(r),
+            // as in the memcmp encoding, by the floors as floats first, then exactly
+            (l, r) => {
+                let (l_floor, l_above) = l.float_floor();
+                let (r_floor, r_above) = r.float_floor();
+                l_floor
+                    .total_cmp(&r_floor)
+                    .then_with(|| l_above.cmp(&r_above))
+                    .then_with(|| match (l_above, l.get_decimal(), r.get_decimal()) {
+                        // only integers and decimals lie strictly between floats
+                        (true, Some(l), Some(r)) => l.value_cmp(&r),
+                        _ => Ordering::Equal,
+                    })
+                    .then_with(|| l.kind_rank().cmp(&r.kind_rank()))
+            }
         }
     }
 }
@@ -317,6 +372,7 @@ impl Display for DataValue {
             DataValue::List(ls) => f.debug_list().entries(ls).finish(),
             DataValue::Set(s) => f.debug_list().entries(s).finish(),
             DataValue::Bot => write!(f, "null"),
+            DataValue::Param(name) => write!(f, "${name}"),
             DataValue::Validity(v) => f
                 .debug_struct("Validity")
                 .field("timestamp", &v.timestamp.0)
@@ -380,6 +436,36 @@ impl DataValue {
             _ => None,
         }
     }
+    /// Stands in for the parameter `name` in a query plan prepared before the parameters
+    /// are known.
+    pub(crate) fn param_placeholder(name: &str) -> Self {
+        DataValue::Param(SmartString::from(name))
+    }
+    fn get_placeholder_name(&self) -> Option<&str> {
+        match self {
+            DataValue::Param(name) => Some(name),
+            _ => None,
+        }
+    }
+    pub(crate) fn contains_placeholder(&self) -> bool {
+        match self {
+            DataValue::List(l) => l.iter().any(|v| v.contains_placeholder()),
+            v => v.get_placeholder_name().is_some(),
+        }
+    }
+    /// Replace the placeholders within the value, including those nested in lists.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        if let Some(val) = self
+            .get_placeholder_name()
+            .and_then(|name| params.get(name))
+        {
+            *self = val.clone();
+        } else if let DataValue::List(l) = self {
+            for v in l {
+                v.bind_params(params)
+            }
+        }
+    }
 }
 
 pub(crate) const LARGEST_UTF_CHAR: char = '\u{10ffff}';
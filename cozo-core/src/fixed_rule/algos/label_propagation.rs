@@ -34,8 +34,9 @@ impl FixedRule for LabelPropagation {
         let edges = payload.get_input(0)?;
         let undirected = payload.bool_option("undirected", Some(false))?;
         let max_iter = payload.pos_integer_option("max_iter", Some(10))?;
+        let mut rng = payload.seeded_rng()?;
         let (graph, indices, _inv_indices) = edges.as_directed_weighted_graph(undirected, true)?;
-        let labels = label_propagation(&graph, max_iter, poison)?;
+        let labels = label_propagation(&graph, max_iter, &mut rng, poison)?;
         for (idx, label) in labels.into_iter().enumerate() {
             let node = indices[idx].clone();
             out.put(vec![DataValue::from(label as i64), node]);
@@ -56,14 +57,14 @@ impl FixedRule for LabelPropagation {
 fn label_propagation(
     graph: &DirectedCsrGraph<u32, (), f32>,
     max_iter: usize,
+    rng: &mut StdRng,
     poison: Poison,
 ) -> Result<Vec<u32>> {
     let n_nodes = graph.node_count();
     let mut labels = (0..n_nodes).collect_vec();
-    let mut rng = thread_rng();
     let mut iter_order = (0..n_nodes).collect_vec();
     for _ in 0..max_iter {
-        iter_order.shuffle(&mut rng);
+        iter_order.shuffle(rng);
         let mut changed = false;
         for node in &iter_order {
             let mut labels_for_node: BTreeMap<u32, f32> = BTreeMap::new();
@@ -82,7 +83,7 @@ fn label_propagation(
                 .take_while(|(_, score)| *score == max_score)
                 .map(|(l, _)| l)
                 .collect_vec();
-            let new_label = *candidate_labels.choose(&mut rng).unwrap();
+            let new_label = *candidate_labels.choose(rng).unwrap();
             if new_label != labels[*node as usize] {
                 changed = true;
                 labels[*node as usize] = new_label;
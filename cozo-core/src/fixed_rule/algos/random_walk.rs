@@ -36,6 +36,7 @@ impl FixedRule for RandomWalk {
         let starting = payload.get_input(2)?;
         let iterations = payload.pos_integer_option("iterations", Some(1))?;
         let steps = payload.pos_integer_option("steps", None)?;
+        let mut rng = payload.seeded_rng()?;
 
         let mut maybe_weight = payload.expr_option("weight", None).ok();
         let mut maybe_weight_bytecode = None;
@@ -51,7 +52,6 @@ impl FixedRule for RandomWalk {
         let mut stack = vec![];
 
         let mut counter = 0i64;
-        let mut rng = thread_rng();
         for start_node in starting.iter()? {
             let start_node = start_node?;
             let start_node_key = &start_node[0];
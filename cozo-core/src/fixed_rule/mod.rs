@@ -19,6 +19,8 @@ use lazy_static::lazy_static;
 use miette::IntoDiagnostic;
 #[allow(unused_imports)]
 use miette::{bail, ensure, Diagnostic, Report, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
@@ -37,6 +39,7 @@ use crate::parse::SourceSpan;
 use crate::runtime::db::Poison;
 use crate::runtime::temp_store::{EpochStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
+use crate::utils::with_rng;
 use crate::NamedRows;
 
 #[cfg(feature = "graph-algo")]
@@ -46,7 +49,7 @@ pub(crate) mod utilities;
 /// Passed into implementation of fixed rule, can be used to obtain relation inputs and options
 pub struct FixedRulePayload<'a, 'b> {
     pub(crate) manifest: &'a MagicFixedRuleApply,
-    pub(crate) stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    pub(crate) stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     pub(crate) tx: &'a SessionTx<'b>,
 }
 
@@ -54,7 +57,7 @@ pub struct FixedRulePayload<'a, 'b> {
 #[derive(Copy, Clone)]
 pub struct FixedRuleInputRelation<'a, 'b> {
     arg_manifest: &'a MagicFixedRuleRuleArg,
-    stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     tx: &'a SessionTx<'b>,
 }
 
@@ -463,6 +466,16 @@ impl<'a, 'b> FixedRulePayload<'a, 'b> {
         );
         Ok(i as usize)
     }
+    /// The RNG of a randomized fixed rule: seeded by the `seed` option if given, otherwise
+    /// drawn from the RNG of the query, so that it follows the `:seed` of the query.
+    pub(crate) fn seeded_rng(&self) -> Result<StdRng> {
+        let seed = if self.manifest.options.contains_key("seed") {
+            self.non_neg_integer_option("seed", None)? as u64
+        } else {
+            with_rng(|rng| rng.gen())
+        };
+        Ok(StdRng::seed_from_u64(seed))
+    }
     /// Extract a floating point option
     pub fn float_option(&self, name: &str, default: Option<f64>) -> Result<f64> {
         match self.manifest.options.get(name) {
@@ -815,6 +828,10 @@ lazy_static! {
                 "ReorderSort".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(ReorderSort)),
             ),
+            (
+                "Window".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Window)),
+            ),
             (
                 "JsonReader".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(JsonReader)),
@@ -898,7 +915,7 @@ impl MagicFixedRuleRuleArg {
     pub(crate) fn arity(
         &self,
         tx: &SessionTx<'_>,
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<usize> {
         Ok(match self {
             MagicFixedRuleRuleArg::InMem { name, .. } => {
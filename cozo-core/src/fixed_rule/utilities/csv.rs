@@ -13,7 +13,7 @@ use miette::{bail, ensure, IntoDiagnostic, Result};
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
-use crate::data::functions::{op_to_float, op_to_uuid, TERMINAL_VALIDITY};
+use crate::data::functions::{op_to_decimal, op_to_float, op_to_uuid, TERMINAL_VALIDITY};
 use crate::data::program::{FixedRuleOptionNotFoundError, WrongFixedRuleOptionError};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
@@ -104,7 +104,9 @@ impl FixedRule for CsvReader {
                     Some(s) => {
                         let dv = DataValue::from(s);
                         match &typ.coltype {
-                            ColType::Any | ColType::String => out_tuple.push(dv),
+                            ColType::Any | ColType::String | ColType::CollatedString(_) => {
+                                out_tuple.push(dv)
+                            }
                             ColType::Uuid => out_tuple.push(match op_to_uuid(&[dv]) {
                                 Ok(uuid) => uuid,
                                 Err(err) => {
@@ -115,6 +117,22 @@ impl FixedRule for CsvReader {
                                     }
                                 }
                             }),
+                            ColType::Decimal(scale) => {
+                                let mut args = vec![dv];
+                                if let Some(scale) = scale {
+                                    args.push(DataValue::from(*scale as i64));
+                                }
+                                out_tuple.push(match op_to_decimal(&args) {
+                                    Ok(data) => data,
+                                    Err(err) => {
+                                        if typ.nullable {
+                                            DataValue::Null
+                                        } else {
+                                            bail!(err)
+                                        }
+                                    }
+                                })
+                            }
                             ColType::Float => out_tuple.push(match op_to_float(&[dv]) {
                                 Ok(data) => data,
                                 Err(err) => {
@@ -14,11 +14,12 @@ use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::{eval_bytecode, Expr};
 use crate::data::functions::OP_LIST;
-use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::program::{NullsOrder, SortDir, Sorter, WrongFixedRuleOptionError};
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
 use crate::parse::SourceSpan;
+use crate::query::sort::{compare_key, SortKey};
 use crate::runtime::db::Poison;
 use crate::runtime::temp_store::RegularTempStore;
 
@@ -63,6 +64,17 @@ impl FixedRule for ReorderSort {
             }),
         )?;
         let sort_descending = payload.bool_option("descending", Some(false))?;
+        let nulls = match &payload.string_option("nulls", Some("least"))? as &str {
+            "least" => None,
+            "first" => Some(NullsOrder::First),
+            "last" => Some(NullsOrder::Last),
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "nulls".to_string(),
+                span: payload.option_span("nulls")?,
+                rule_name: payload.name().to_string(),
+                help: "This option must be one of 'first', 'last' or 'least'".to_string()
+            }),
+        };
         let break_ties = payload.bool_option("break_ties", Some(false))?;
         let skip = payload.non_neg_integer_option("skip", Some(0))?;
         let take = payload.non_neg_integer_option("take", Some(0))?;
@@ -80,26 +92,31 @@ impl FixedRule for ReorderSort {
         for tuple in in_rel.iter()? {
             let tuple = tuple?;
             let sorter = eval_bytecode(&sort_by_bytecodes, &tuple, &mut stack)?;
-            let mut s_tuple: Vec<_> = out_bytecods
+            let s_tuple: Vec<_> = out_bytecods
                 .iter()
                 .map(|ex| eval_bytecode(ex, &tuple, &mut stack))
                 .try_collect()?;
-            s_tuple.push(sorter);
-            buffer.push(s_tuple);
+            buffer.push((SortKey::new(sorter, None), s_tuple));
             poison.check()?;
         }
-        if sort_descending {
-            buffer.sort_by(|l, r| r.last().cmp(&l.last()));
-        } else {
-            buffer.sort_by(|l, r| l.last().cmp(&r.last()));
-        }
+        let sorter = Sorter {
+            key: sort_by,
+            dir: if sort_descending {
+                SortDir::Dsc
+            } else {
+                SortDir::Asc
+            },
+            collation: None,
+            nulls,
+        };
+        buffer.sort_by(|(l, _), (r, _)| compare_key(&sorter, l, r));
 
         let mut count = 0usize;
         let mut rank = 0usize;
         let mut last = &DataValue::Bot;
         let take_plus_skip = take.saturating_add(skip);
-        for val in &buffer {
-            let sorter = val.last().unwrap();
+        for (sorter, val) in &buffer {
+            let sorter = &sorter.value;
 
             if sorter == last {
                 count += 1;
@@ -117,7 +134,7 @@ impl FixedRule for ReorderSort {
                 continue;
             }
             let mut out_t = vec![DataValue::from(if break_ties { count } else { rank } as i64)];
-            out_t.extend_from_slice(&val[0..val.len() - 1]);
+            out_t.extend_from_slice(val);
             out.put(out_t);
             poison.check()?;
         }
@@ -0,0 +1,362 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::aggr::{parse_aggr, Aggr};
+use crate::data::expr::{eval_bytecode, Bytecode, Expr};
+use crate::data::functions::OP_LIST;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Computes window functions over the rows of a relation,
+/// partitioned by `partition_by` and sorted by `order_by` within each partition.
+///
+/// Each element of the option `functions` is a list whose first element names the function:
+///
+/// * `['row_number']`, `['rank']`, `['dense_rank']`
+/// * `['lag', expr, offset?, default?]`, `['lead', expr, offset?, default?]`
+/// * `['cumulative_sum', expr]`
+/// * `[aggr, expr, start, end, aggr_args...]`, applying the aggregation `aggr` to the rows
+///   between the offsets `start` and `end` relative to the current row, where `null`
+///   means unbounded
+///
+/// Each output row consists of the values of `out` followed by the values of the functions.
+pub(crate) struct Window;
+
+enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Shift {
+        expr: Vec<Bytecode>,
+        offset: i64,
+        default: DataValue,
+    },
+    Frame {
+        aggr: Aggr,
+        aggr_args: Vec<DataValue>,
+        expr: Vec<Bytecode>,
+        start: Option<i64>,
+        end: Option<i64>,
+    },
+}
+
+fn list_option(expr: &Expr) -> Option<Vec<Expr>> {
+    match expr {
+        Expr::Const {
+            val: DataValue::List(l),
+            span,
+        } => Some(
+            l.iter()
+                .map(|d| Expr::Const {
+                    val: d.clone(),
+                    span: *span,
+                })
+                .collect_vec(),
+        ),
+        Expr::Apply { op, args, .. } if **op == OP_LIST => Some(args.to_vec()),
+        _ => None,
+    }
+}
+
+impl Window {
+    fn list_option(payload: &FixedRulePayload<'_, '_>, name: &str) -> Result<Vec<Expr>> {
+        let expr = payload.expr_option(
+            name,
+            Some(Expr::Const {
+                val: DataValue::List(vec![]),
+                span: SourceSpan(0, 0),
+            }),
+        )?;
+        match list_option(&expr) {
+            Some(l) => Ok(l),
+            None => bail!(WrongFixedRuleOptionError {
+                name: name.to_string(),
+                span: expr.span(),
+                rule_name: payload.name().to_string(),
+                help: "This option must evaluate to a list".to_string()
+            }),
+        }
+    }
+
+    fn parse_function(
+        payload: &FixedRulePayload<'_, '_>,
+        spec: Expr,
+        binding_map: &BTreeMap<Symbol, usize>,
+    ) -> Result<WindowFunction> {
+        let span = spec.span();
+        let bad_spec = |help: String| WrongFixedRuleOptionError {
+            name: "functions".to_string(),
+            span,
+            rule_name: payload.name().to_string(),
+            help,
+        };
+        let mut parts = match list_option(&spec) {
+            Some(l) => l.into_iter(),
+            None => bail!(bad_spec(
+                "Each window function must be given as a list".to_string()
+            )),
+        };
+        let name = match parts.next().map(|e| e.eval_to_const()) {
+            Some(Ok(DataValue::Str(s))) => s,
+            _ => bail!(bad_spec(
+                "A window function must start with its name as a string".to_string()
+            )),
+        };
+        let mut expr = match name.as_str() {
+            "row_number" => return Ok(WindowFunction::RowNumber),
+            "rank" => return Ok(WindowFunction::Rank),
+            "dense_rank" => return Ok(WindowFunction::DenseRank),
+            _ => match parts.next() {
+                Some(e) => e,
+                None => bail!(bad_spec(format!(
+                    "The window function '{name}' requires an expression to operate on"
+                ))),
+            },
+        };
+        expr.fill_binding_indices(binding_map)?;
+        let expr = expr.compile();
+        let mut consts = vec![];
+        for part in parts {
+            consts.push(part.eval_to_const()?);
+        }
+        let mut consts = consts.into_iter();
+        let mut frame_bound = |default: Option<i64>| -> Result<Option<i64>> {
+            Ok(match consts.next() {
+                None => default,
+                Some(DataValue::Null) => None,
+                Some(v) => match v.get_int() {
+                    Some(i) => Some(i),
+                    None => bail!(bad_spec(format!(
+                        "Frame bounds must be integers or null, got {v:?}"
+                    ))),
+                },
+            })
+        };
+        Ok(match name.as_str() {
+            "lag" | "lead" => {
+                let offset = frame_bound(Some(1))?.unwrap_or(1);
+                let offset = if name == "lag" { -offset } else { offset };
+                let default = consts.next().unwrap_or(DataValue::Null);
+                WindowFunction::Shift {
+                    expr,
+                    offset,
+                    default,
+                }
+            }
+            "cumulative_sum" => WindowFunction::Frame {
+                aggr: parse_aggr("sum").unwrap().clone(),
+                aggr_args: vec![],
+                expr,
+                start: None,
+                end: Some(0),
+            },
+            aggr_name => {
+                let aggr = match parse_aggr(aggr_name) {
+                    Some(aggr) => aggr.clone(),
+                    None => bail!(bad_spec(format!(
+                        "'{aggr_name}' is neither a window function nor an aggregation"
+                    ))),
+                };
+                let start = frame_bound(None)?;
+                let end = frame_bound(Some(0))?;
+                WindowFunction::Frame {
+                    aggr,
+                    aggr_args: consts.collect_vec(),
+                    expr,
+                    start,
+                    end,
+                }
+            }
+        })
+    }
+}
+
+impl FixedRule for Window {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let binding_map = in_rel.get_binding_map(0);
+
+        let mut partition_by = Self::list_option(&payload, "partition_by")?;
+        let mut out_list = Self::list_option(&payload, "out")?;
+        let mut order_by = payload.expr_option(
+            "order_by",
+            Some(Expr::Const {
+                val: DataValue::Null,
+                span: SourceSpan(0, 0),
+            }),
+        )?;
+        let descending = payload.bool_option("descending", Some(false))?;
+        let functions: Vec<_> = Self::list_option(&payload, "functions")?
+            .into_iter()
+            .map(|spec| Self::parse_function(&payload, spec, &binding_map))
+            .try_collect()?;
+
+        order_by.fill_binding_indices(&binding_map)?;
+        let order_by = order_by.compile();
+        for expr in partition_by.iter_mut().chain(out_list.iter_mut()) {
+            expr.fill_binding_indices(&binding_map)?;
+        }
+        let partition_by = partition_by.iter().map(|e| e.compile()).collect_vec();
+        let out_list = out_list.iter().map(|e| e.compile()).collect_vec();
+        let mut stack = vec![];
+
+        // (partition key, sort key, input tuple)
+        let mut rows = vec![];
+        for tuple in in_rel.iter()? {
+            let tuple = tuple?;
+            let partition: Vec<_> = partition_by
+                .iter()
+                .map(|ex| eval_bytecode(ex, &tuple, &mut stack))
+                .try_collect()?;
+            let sorter = eval_bytecode(&order_by, &tuple, &mut stack)?;
+            rows.push((partition, sorter, tuple));
+            poison.check()?;
+        }
+        rows.sort_by(|(lp, ls, _), (rp, rs, _)| {
+            lp.cmp(rp)
+                .then_with(|| if descending { rs.cmp(ls) } else { ls.cmp(rs) })
+        });
+
+        for (_, group) in &rows.iter().group_by(|(partition, _, _)| partition) {
+            let group = group.collect_vec();
+            let n = group.len();
+            let mut results: Vec<Vec<DataValue>> = group
+                .iter()
+                .map(|(_, _, tuple)| -> Result<Vec<DataValue>> {
+                    out_list
+                        .iter()
+                        .map(|ex| eval_bytecode(ex, tuple, &mut stack))
+                        .try_collect()
+                })
+                .try_collect()?;
+            for function in &functions {
+                match function {
+                    WindowFunction::RowNumber => {
+                        for (i, row) in results.iter_mut().enumerate() {
+                            row.push(DataValue::from(i as i64 + 1));
+                        }
+                    }
+                    WindowFunction::Rank | WindowFunction::DenseRank => {
+                        let dense = matches!(function, WindowFunction::DenseRank);
+                        let mut rank = 0;
+                        for (i, row) in results.iter_mut().enumerate() {
+                            if i == 0 || group[i].1 != group[i - 1].1 {
+                                rank = if dense { rank + 1 } else { i as i64 + 1 };
+                            }
+                            row.push(DataValue::from(rank));
+                        }
+                    }
+                    WindowFunction::Shift {
+                        expr,
+                        offset,
+                        default,
+                    } => {
+                        for (i, row) in results.iter_mut().enumerate() {
+                            let target = i as i64 + offset;
+                            let val = if target < 0 || target >= n as i64 {
+                                default.clone()
+                            } else {
+                                eval_bytecode(expr, &group[target as usize].2, &mut stack)?
+                            };
+                            row.push(val);
+                        }
+                    }
+                    WindowFunction::Frame {
+                        aggr,
+                        aggr_args,
+                        expr,
+                        start,
+                        end,
+                    } => {
+                        let values: Vec<_> = group
+                            .iter()
+                            .map(|(_, _, tuple)| eval_bytecode(expr, tuple, &mut stack))
+                            .try_collect()?;
+                        let bounds = |i: usize| {
+                            let lo = start.map_or(0, |s| i as i64 + s).max(0);
+                            let hi = end.map_or(n as i64 - 1, |e| i as i64 + e).min(n as i64 - 1);
+                            (lo, hi)
+                        };
+                        let new_op = || -> Result<_> {
+                            let mut aggr = aggr.clone();
+                            aggr.normal_init(aggr_args)?;
+                            Ok(aggr.normal_op.unwrap())
+                        };
+                        if start.is_none() {
+                            // the frame only grows, so a single aggregation suffices
+                            let mut op = new_op()?;
+                            let mut fed = 0;
+                            for (i, row) in results.iter_mut().enumerate() {
+                                let (_, hi) = bounds(i);
+                                while fed <= hi {
+                                    op.set(&values[fed as usize])?;
+                                    fed += 1;
+                                }
+                                row.push(op.get()?);
+                            }
+                        } else {
+                            for (i, row) in results.iter_mut().enumerate() {
+                                let (lo, hi) = bounds(i);
+                                let mut op = new_op()?;
+                                for j in lo..=hi {
+                                    op.set(&values[j as usize])?;
+                                }
+                                row.push(op.get()?);
+                                poison.check()?;
+                            }
+                        }
+                    }
+                }
+                poison.check()?;
+            }
+            for row in results {
+                out.put(row);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        opts: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let mut arity = 0;
+        for name in ["out", "functions"] {
+            arity += match opts.get(name) {
+                None => 0,
+                Some(opt) => match list_option(opt) {
+                    Some(l) => l.len(),
+                    None => bail!(CannotDetermineArity(
+                        "Window".to_string(),
+                        format!("invalid option '{name}' given, expect a list"),
+                        span
+                    )),
+                },
+            };
+        }
+        Ok(arity)
+    }
+}
@@ -48,10 +48,15 @@ use miette::{
 };
 use serde_json::json;
 
+pub use data::aggr::{Aggregation, MeetAggrObj, NormalAggrObj};
+pub use data::decimal::Decimal;
 pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
+pub use runtime::prepared::PreparedQuery;
+#[cfg(not(target_arch = "wasm32"))]
+pub use runtime::stream::NamedRowsIter;
 pub use runtime::relation::decode_tuple_from_kv;
 pub use runtime::temp_store::RegularTempStore;
 pub use storage::mem::{new_cozo_mem, MemStorage};
@@ -178,6 +183,57 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_script(payload, params),
         }
     }
+    /// Dispatcher method. See [crate::Db::call_procedure].
+    pub fn call_procedure(
+        &self,
+        name: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.call_procedure(name, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.call_procedure(name, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.call_procedure(name, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.call_procedure(name, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.call_procedure(name, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_iter].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_script_iter(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRowsIter> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_iter(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_iter(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_iter(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_iter(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_iter(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_plan_cache_capacity].
+    pub fn set_plan_cache_capacity(&self, capacity: usize) {
+        match self {
+            DbInstance::Mem(db) => db.set_plan_cache_capacity(capacity),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_plan_cache_capacity(capacity),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_plan_cache_capacity(capacity),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_plan_cache_capacity(capacity),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_plan_cache_capacity(capacity),
+        }
+    }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
     /// Fold any error into the return JSON itself.
     /// See [crate::Db::run_script].
@@ -223,6 +279,20 @@ impl DbInstance {
         };
         self.run_script_fold_err(payload, params_json).to_string()
     }
+    /// Dispatcher method. See [crate::Db::check_script].
+    pub fn check_script(&self, payload: &str, params: BTreeMap<String, DataValue>) -> JsonValue {
+        match self {
+            DbInstance::Mem(db) => db.check_script(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.check_script(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.check_script(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.check_script(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.check_script(payload, params),
+        }
+    }
     /// Dispatcher method. See [crate::Db::export_relations].
     pub fn export_relations<'a, I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>>
     where
@@ -446,6 +516,68 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_fixed_rule(name),
         }
     }
+    /// Dispatcher method. See [crate::Db::register_aggregation].
+    pub fn register_aggregation<A>(&self, name: String, aggr_impl: A) -> Result<()>
+    where
+        A: Aggregation + 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_aggregation(name, aggr_impl),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_aggregation]
+    pub fn unregister_aggregation(&self, name: &str) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_aggregation(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::register_function].
+    pub fn register_function<F>(&self, name: String, arity: usize, func: F) -> Result<()>
+    where
+        F: Fn(&[DataValue]) -> Result<DataValue> + Send + Sync + 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_function(name, arity, func),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_function]
+    pub fn unregister_function(&self, name: &str) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_function(name),
+        }
+    }
 
     /// Dispatcher method. See [crate::Db::run_multi_transaction]
     pub fn run_multi_transaction(
@@ -527,6 +659,27 @@ impl MultiTransaction {
             Err(err) => bail!(err),
         }
     }
+    /// Sets a named savepoint in the multi-transaction
+    pub fn savepoint(&self, name: &str) -> Result<()> {
+        self.run_savepoint_op(TransactionPayload::Savepoint(name.to_string()))
+    }
+    /// Undoes the changes made since the named savepoint, which stays active
+    pub fn rollback_to(&self, name: &str) -> Result<()> {
+        self.run_savepoint_op(TransactionPayload::RollbackTo(name.to_string()))
+    }
+    /// Removes the named savepoint, keeping the changes made since
+    pub fn release(&self, name: &str) -> Result<()> {
+        self.run_savepoint_op(TransactionPayload::Release(name.to_string()))
+    }
+    fn run_savepoint_op(&self, payload: TransactionPayload) -> Result<()> {
+        if let Err(err) = self.sender.send(payload) {
+            bail!(err);
+        }
+        match self.receiver.recv() {
+            Ok(r) => r.map(|_| ()),
+            Err(err) => bail!(err),
+        }
+    }
 }
 
 /// Convert error raised by the database into friendly JSON format
@@ -6,8 +6,6 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
-
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use miette::{bail, ensure, Diagnostic, Result};
@@ -22,7 +20,8 @@ use crate::data::functions::{
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::parse::query::subquery_var;
+use crate::parse::{ExtractSpan, Pair, ParseContext, Rule, SourceSpan};
 
 lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
@@ -74,6 +73,17 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
                 span: *span,
             })
         }
+        Expr::ApplyCustom { func, args, span } => {
+            let arity = args.len();
+            for arg in args.iter() {
+                expr2bytecode(arg, collector);
+            }
+            collector.push(Bytecode::ApplyCustom {
+                func: func.clone(),
+                arity,
+                span: *span,
+            })
+        }
         Expr::Cond { clauses, span } => {
             let mut return_jump_pos = vec![];
             for (cond, val) in clauses {
@@ -108,14 +118,14 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
     }
 }
 
-pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+pub(crate) fn build_expr(pair: Pair<'_>, ctx: &ParseContext<'_>) -> Result<Expr> {
     ensure!(
         pair.as_rule() == Rule::expr,
         InvalidExpression(pair.extract_span())
     );
 
     PRATT_PARSER
-        .map_primary(|v| build_term(v, param_pool))
+        .map_primary(|v| build_term(v, ctx))
         .map_infix(build_expr_infix)
         .map_prefix(|op, rhs| {
             let rhs = rhs?;
@@ -168,7 +178,7 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
     })
 }
 
-fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+fn build_term(pair: Pair<'_>, ctx: &ParseContext<'_>) -> Result<Expr> {
     let span = pair.extract_span();
     let op = pair.as_rule();
     Ok(match op {
@@ -176,6 +186,19 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             var: Symbol::new(pair.as_str(), pair.extract_span()),
             tuple_pos: None,
         },
+        // bound by the rule containing the subquery once it is lifted, see `lift_subqueries`
+        Rule::exists_subquery | Rule::scalar_subquery => {
+            #[derive(Error, Diagnostic, Debug)]
+            #[error("Subqueries can only be used within the bodies of rules")]
+            #[diagnostic(code(parser::subquery_outside_rule_body))]
+            struct SubqueryOutsideRuleBody(#[label] SourceSpan);
+
+            ensure!(ctx.in_rule_body, SubqueryOutsideRuleBody(span));
+            Expr::Binding {
+                var: subquery_var(span),
+                tuple_pos: None,
+            }
+        }
         Rule::param => {
             #[derive(Error, Diagnostic, Debug)]
             #[error("Required parameter {0} not found")]
@@ -184,7 +207,8 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
 
             let param_str = pair.as_str().strip_prefix('$').unwrap();
             Expr::Const {
-                val: param_pool
+                val: ctx
+                    .params
                     .get(param_str)
                     .ok_or_else(|| ParamNotFoundError(param_str.to_string(), span))?
                     .clone(),
@@ -262,7 +286,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
         Rule::list => {
             let mut collected = vec![];
             for p in pair.into_inner() {
-                collected.push(build_expr(p, param_pool)?)
+                collected.push(build_expr(p, ctx)?)
             }
             Expr::Apply {
                 op: &OP_LIST,
@@ -278,7 +302,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|v| build_expr(v, param_pool))
+                .map(|v| build_expr(v, ctx))
                 .try_collect()?;
             #[derive(Error, Diagnostic, Debug)]
             #[error("Named function '{0}' not found")]
@@ -355,16 +379,34 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                     Expr::Cond { clauses, span }
                 }
                 _ => {
-                    let op = get_op(ident).ok_or_else(|| {
-                        FuncNotFoundError(ident.to_string(), ident_p.extract_span())
-                    })?;
-                    op.post_process_args(&mut args);
-
                     #[derive(Error, Diagnostic, Debug)]
                     #[error("Wrong number of arguments for function '{0}'")]
                     #[diagnostic(code(parser::func_wrong_num_args))]
                     struct WrongNumArgsError(String, #[label] SourceSpan, #[help] String);
 
+                    let op = match get_op(ident) {
+                        Some(op) => op,
+                        None => {
+                            let func = ctx.custom_fns.get(ident).ok_or_else(|| {
+                                FuncNotFoundError(ident.to_string(), ident_p.extract_span())
+                            })?;
+                            ensure!(
+                                func.arity == args.len(),
+                                WrongNumArgsError(
+                                    ident.to_string(),
+                                    span,
+                                    format!("Need exactly {} argument(s)", func.arity)
+                                )
+                            );
+                            return Ok(Expr::ApplyCustom {
+                                func: func.clone(),
+                                args: args.into(),
+                                span,
+                            });
+                        }
+                    };
+                    op.post_process_args(&mut args);
+
                     if op.vararg {
                         ensure!(
                             op.min_arity <= args.len(),
@@ -392,7 +434,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 }
             }
         }
-        Rule::grouping => build_expr(pair.into_inner().next().unwrap(), param_pool)?,
+        Rule::grouping => build_expr(pair.into_inner().next().unwrap(), ctx)?,
         r => unreachable!("Encountered unknown op {:?}", r),
     })
 }
@@ -7,24 +7,62 @@
  *
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
 use miette::{Diagnostic, Result};
-use smartstring::SmartString;
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::program::InputProgram;
+use crate::data::value::DataValue;
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, ImperativeProgram, ImperativeStmt, Pair, Rule, SourceSpan};
-use crate::{DataValue, FixedRule, ValidityTs};
+use crate::parse::{
+    parse_script_pairs, ExtractSpan, ImperativeProgram, ImperativeQuery, ImperativeStmt, Pair,
+    ParseContext, Rule, SourceSpan,
+};
+use crate::{FixedRule, ValidityTs};
 
 pub(crate) fn parse_imperative_block(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+) -> Result<ImperativeProgram> {
+    let runtime_params = RuntimeParams {
+        let_bound: src
+            .clone()
+            .into_inner()
+            .flatten()
+            .filter(|p| p.as_rule() == Rule::let_stmt)
+            .map(|p| param_name(p.into_inner().next().unwrap()))
+            .collect(),
+        in_for: false,
+    };
+    parse_imperative_stmts(src, ctx, fixed_rules, cur_vld, &runtime_params)
+}
+
+/// Parameters bound while the script runs. Queries using them are parsed only when they are run.
+#[derive(Clone)]
+struct RuntimeParams {
+    /// Parameters bound by `%let`
+    let_bound: BTreeSet<String>,
+    /// Whether inside a `%for` loop, whose rows may bind any parameter
+    in_for: bool,
+}
+
+fn param_name(pair: Pair<'_>) -> String {
+    pair.as_str().strip_prefix('$').unwrap().to_string()
+}
+
+fn parse_imperative_stmts(
+    src: Pair<'_>,
+    ctx: &ParseContext<'_>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+    runtime_params: &RuntimeParams,
 ) -> Result<ImperativeProgram> {
     let mut collected = vec![];
 
@@ -34,15 +72,84 @@ pub(crate) fn parse_imperative_block(
         }
         collected.push(parse_imperative_stmt(
             pair,
-            param_pool,
+            ctx,
             fixed_rules,
             cur_vld,
+            runtime_params,
         )?);
     }
 
     Ok(collected)
 }
 
+fn parse_imperative_query(
+    pair: Pair<'_>,
+    ctx: &ParseContext<'_>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+    runtime_params: &RuntimeParams,
+) -> Result<ImperativeQuery> {
+    let deferred: BTreeSet<_> = pair
+        .clone()
+        .into_inner()
+        .flatten()
+        .filter(|p| p.as_rule() == Rule::param)
+        .map(param_name)
+        .filter(|name| runtime_params.in_for || runtime_params.let_bound.contains(name))
+        .collect();
+    if deferred.is_empty() {
+        return Ok(ImperativeQuery::Parsed(Box::new(parse_query(
+            pair.into_inner(),
+            ctx,
+            fixed_rules,
+            cur_vld,
+        )?)));
+    }
+    let write_lock = pair
+        .clone()
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::relation_option)
+        .map(|p| SmartString::from(p.into_inner().nth(1).unwrap().as_str()))
+        .filter(|name: &SmartString<LazyCompact>| !name.starts_with('_'));
+    let mut param_pool = ctx.params.clone();
+    for name in &deferred {
+        param_pool.insert(name.clone(), DataValue::param_placeholder(name));
+    }
+    let placeholder_ctx = ParseContext {
+        params: &param_pool,
+        ..*ctx
+    };
+    let span = pair.as_span();
+    // failing with placeholders, the query is parsed again with the values when run,
+    // and any error is reported then
+    let prog = parse_query(pair.into_inner(), &placeholder_ctx, fixed_rules, cur_vld)
+        .ok()
+        .map(Box::new);
+    Ok(ImperativeQuery::Deferred {
+        prog,
+        params: deferred,
+        pos: span.start(),
+        end: span.end(),
+        write_lock,
+    })
+}
+
+/// Parse the query between the braces at `pos` and `end` of the imperative script, with the
+/// values of the parameters it uses now bound. The rest of the script is blanked out, so that
+/// the spans in errors still point into the script.
+pub(crate) fn parse_deferred_query(
+    src: &str,
+    pos: usize,
+    end: usize,
+    ctx: &ParseContext<'_>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+) -> Result<InputProgram> {
+    let blanked = format!("{}{}", " ".repeat(pos + 1), &src[pos + 1..end - 1]);
+    let pair = parse_script_pairs(&blanked)?;
+    parse_query(pair.into_inner(), ctx, fixed_rules, cur_vld)
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("cannot manipulate permanent relation in imperative script")]
 #[diagnostic(code(parser::manipulate_perm_rel_in_script))]
@@ -55,9 +162,10 @@ struct DuplicateMarker(#[label] SourceSpan);
 
 fn parse_imperative_stmt(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+    runtime_params: &RuntimeParams,
 ) -> Result<ImperativeStmt> {
     Ok(match pair.as_rule() {
         Rule::break_stmt => {
@@ -86,7 +194,8 @@ fn parse_imperative_stmt(
                         rets.push(Right(rel));
                     }
                     Rule::query_script_inner => {
-                        let prog = parse_query(p.into_inner(), param_pool, fixed_rules, cur_vld)?;
+                        let prog =
+                            parse_imperative_query(p, ctx, fixed_rules, cur_vld, runtime_params)?;
                         rets.push(Left(prog))
                     }
                     _ => unreachable!(),
@@ -101,11 +210,12 @@ fn parse_imperative_stmt(
             let condition = inner.next().unwrap();
             let cond = match condition.as_rule() {
                 Rule::underscore_ident => Left(SmartString::from(condition.as_str())),
-                Rule::query_script_inner => Right(parse_query(
-                    condition.into_inner(),
-                    param_pool,
+                Rule::query_script_inner => Right(parse_imperative_query(
+                    condition,
+                    ctx,
                     fixed_rules,
                     cur_vld,
+                    runtime_params,
                 )?),
                 _ => unreachable!(),
             };
@@ -113,13 +223,13 @@ fn parse_imperative_stmt(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|p| parse_imperative_stmt(p, param_pool, fixed_rules, cur_vld))
+                .map(|p| parse_imperative_stmt(p, ctx, fixed_rules, cur_vld, runtime_params))
                 .try_collect()?;
             let else_body = match inner.next() {
                 None => vec![],
                 Some(rest) => rest
                     .into_inner()
-                    .map(|p| parse_imperative_stmt(p, param_pool, fixed_rules, cur_vld))
+                    .map(|p| parse_imperative_stmt(p, ctx, fixed_rules, cur_vld, runtime_params))
                     .try_collect()?,
             };
             ImperativeStmt::If {
@@ -138,9 +248,81 @@ fn parse_imperative_stmt(
                 mark = Some(SmartString::from(nxt.as_str()));
                 nxt = inner.next().unwrap();
             }
-            let body = parse_imperative_block(nxt, param_pool, fixed_rules, cur_vld)?;
+            let body = parse_imperative_stmts(nxt, ctx, fixed_rules, cur_vld, runtime_params)?;
             ImperativeStmt::Loop { label: mark, body }
         }
+        Rule::for_block => {
+            let mut inner = pair.into_inner();
+            let label = SmartString::from(inner.next().unwrap().as_str());
+            let source = inner.next().unwrap();
+            let source = match source.as_rule() {
+                Rule::underscore_ident => Left(SmartString::from(source.as_str())),
+                Rule::query_script_inner => Right(parse_imperative_query(
+                    source,
+                    ctx,
+                    fixed_rules,
+                    cur_vld,
+                    runtime_params,
+                )?),
+                _ => unreachable!(),
+            };
+            let body = parse_imperative_stmts(
+                inner.next().unwrap(),
+                ctx,
+                fixed_rules,
+                cur_vld,
+                &RuntimeParams {
+                    in_for: true,
+                    ..runtime_params.clone()
+                },
+            )?;
+            ImperativeStmt::For {
+                label,
+                source,
+                body,
+            }
+        }
+        Rule::try_block => {
+            let mut inner = pair.into_inner();
+            let body = parse_imperative_stmts(
+                inner.next().unwrap(),
+                ctx,
+                fixed_rules,
+                cur_vld,
+                runtime_params,
+            )?;
+            let mut nxt = inner.next();
+            let catch = match &nxt {
+                Some(p) if p.as_rule() == Rule::underscore_ident => {
+                    let name = SmartString::from(p.as_str());
+                    nxt = inner.next();
+                    Some(name)
+                }
+                _ => None,
+            };
+            let handler = match nxt {
+                None => vec![],
+                Some(p) => parse_imperative_stmts(p, ctx, fixed_rules, cur_vld, runtime_params)?,
+            };
+            ImperativeStmt::Try {
+                body,
+                catch,
+                handler,
+            }
+        }
+        Rule::let_stmt => {
+            let span = pair.extract_span();
+            let mut inner = pair.into_inner();
+            let param = param_name(inner.next().unwrap());
+            let prog = parse_imperative_query(
+                inner.next().unwrap(),
+                ctx,
+                fixed_rules,
+                cur_vld,
+                runtime_params,
+            )?;
+            ImperativeStmt::Let { param, prog, span }
+        }
         Rule::temp_swap => {
             // let span = pair.extract_span();
             let mut pairs = pair.into_inner();
@@ -164,12 +346,12 @@ fn parse_imperative_stmt(
             }
         }
         Rule::query_script_inner => {
-            let prog = parse_query(pair.into_inner(), param_pool, fixed_rules, cur_vld)?;
+            let prog = parse_imperative_query(pair, ctx, fixed_rules, cur_vld, runtime_params)?;
             ImperativeStmt::Program { prog }
         }
         Rule::ignore_error_script => {
             let pair = pair.into_inner().next().unwrap();
-            let prog = parse_query(pair.into_inner(), param_pool, fixed_rules, cur_vld)?;
+            let prog = parse_imperative_query(pair, ctx, fixed_rules, cur_vld, runtime_params)?;
             ImperativeStmt::IgnoreErrorProgram { prog }
         }
         r => unreachable!("{r:?}"),
@@ -18,6 +18,8 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::aggr::Aggregation;
+use crate::data::expr::{get_op, CustomFunction};
 use crate::data::program::InputProgram;
 use crate::data::relation::NullableColType;
 use crate::data::value::{DataValue, ValidityTs};
@@ -40,6 +42,7 @@ pub(crate) struct CozoScriptParser;
 pub(crate) type Pair<'a> = pest::iterators::Pair<'a, Rule>;
 pub(crate) type Pairs<'a> = pest::iterators::Pairs<'a, Rule>;
 
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum CozoScript {
     Single(InputProgram),
     Imperative(ImperativeProgram),
@@ -57,13 +60,18 @@ pub(crate) enum ImperativeStmt {
         span: SourceSpan,
     },
     Return {
-        returns: Vec<Either<InputProgram, SmartString<LazyCompact>>>,
+        returns: Vec<Either<ImperativeQuery, SmartString<LazyCompact>>>,
     },
     Program {
-        prog: InputProgram,
+        prog: ImperativeQuery,
     },
     IgnoreErrorProgram {
-        prog: InputProgram,
+        prog: ImperativeQuery,
+    },
+    Let {
+        param: String,
+        prog: ImperativeQuery,
+        span: SourceSpan,
     },
     If {
         condition: ImperativeCondition,
@@ -76,6 +84,17 @@ pub(crate) enum ImperativeStmt {
         label: Option<SmartString<LazyCompact>>,
         body: ImperativeProgram,
     },
+    For {
+        label: SmartString<LazyCompact>,
+        source: ImperativeCondition,
+        body: ImperativeProgram,
+    },
+    Try {
+        body: ImperativeProgram,
+        /// The temp relation receiving the error caught
+        catch: Option<SmartString<LazyCompact>>,
+        handler: ImperativeProgram,
+    },
     TempSwap {
         left: SmartString<LazyCompact>,
         right: SmartString<LazyCompact>,
@@ -86,7 +105,33 @@ pub(crate) enum ImperativeStmt {
     },
 }
 
-pub(crate) type ImperativeCondition = Either<SmartString<LazyCompact>, InputProgram>;
+/// A query in an imperative script
+#[derive(Debug)]
+pub(crate) enum ImperativeQuery {
+    Parsed(Box<InputProgram>),
+    /// A query using parameters bound by `%let` or `%for`, whose values are only known when it
+    /// is run. It is parsed once with placeholders for `params`, which are bound at each run.
+    /// If the parameters shape the query itself, as in `:limit $n`, there is no `prog`, and the
+    /// query alone is parsed at each run from the source between `pos` and `end`.
+    Deferred {
+        prog: Option<Box<InputProgram>>,
+        params: BTreeSet<String>,
+        pos: usize,
+        end: usize,
+        write_lock: Option<SmartString<LazyCompact>>,
+    },
+}
+
+impl ImperativeQuery {
+    fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
+        match self {
+            ImperativeQuery::Parsed(prog) => prog.needs_write_lock(),
+            ImperativeQuery::Deferred { write_lock, .. } => write_lock.clone(),
+        }
+    }
+}
+
+pub(crate) type ImperativeCondition = Either<SmartString<LazyCompact>, ImperativeQuery>;
 
 pub(crate) type ImperativeProgram = Vec<ImperativeStmt>;
 
@@ -94,7 +139,8 @@ impl ImperativeStmt {
     pub(crate) fn needs_write_locks(&self, collector: &mut BTreeSet<SmartString<LazyCompact>>) {
         match self {
             ImperativeStmt::Program { prog, .. }
-            | ImperativeStmt::IgnoreErrorProgram { prog, .. } => {
+            | ImperativeStmt::IgnoreErrorProgram { prog, .. }
+            | ImperativeStmt::Let { prog, .. } => {
                 if let Some(name) = prog.needs_write_lock() {
                     collector.insert(name);
                 }
@@ -108,6 +154,16 @@ impl ImperativeStmt {
                     }
                 }
             }
+            ImperativeStmt::For { source, body, .. } => {
+                if let ImperativeCondition::Right(prog) = source {
+                    if let Some(name) = prog.needs_write_lock() {
+                        collector.insert(name);
+                    }
+                }
+                for prog in body {
+                    prog.needs_write_locks(collector);
+                }
+            }
             ImperativeStmt::If {
                 condition,
                 then_branch,
@@ -128,6 +184,11 @@ impl ImperativeStmt {
                     prog.needs_write_locks(collector);
                 }
             }
+            ImperativeStmt::Try { body, handler, .. } => {
+                for prog in body.iter().chain(handler.iter()) {
+                    prog.needs_write_locks(collector);
+                }
+            }
             ImperativeStmt::TempDebug { .. }
             | ImperativeStmt::Break { .. }
             | ImperativeStmt::Continue { .. }
@@ -195,6 +256,42 @@ pub(crate) struct ParseError {
     pub(crate) span: SourceSpan,
 }
 
+/// The parameters and the registered functions and aggregations that a script is parsed with.
+#[derive(Copy, Clone)]
+pub(crate) struct ParseContext<'a> {
+    pub(crate) params: &'a BTreeMap<String, DataValue>,
+    pub(crate) custom_fns: &'a BTreeMap<String, CustomFunction>,
+    pub(crate) custom_aggrs: &'a BTreeMap<String, Arc<dyn Aggregation>>,
+    /// Whether the expressions parsed are in the body of a rule, the only place for subqueries
+    pub(crate) in_rule_body: bool,
+}
+
+static NO_PARAMS: BTreeMap<String, DataValue> = BTreeMap::new();
+static NO_CUSTOM_FNS: BTreeMap<String, CustomFunction> = BTreeMap::new();
+static NO_CUSTOM_AGGRS: BTreeMap<String, Arc<dyn Aggregation>> = BTreeMap::new();
+
+/// No parameters and nothing registered, for expressions that must be constant.
+impl Default for ParseContext<'_> {
+    fn default() -> Self {
+        Self {
+            params: &NO_PARAMS,
+            custom_fns: &NO_CUSTOM_FNS,
+            custom_aggrs: &NO_CUSTOM_AGGRS,
+            in_rule_body: false,
+        }
+    }
+}
+
+impl ParseContext<'_> {
+    /// The same context without parameters, for scripts stored to be run later.
+    pub(crate) fn without_params(&self) -> Self {
+        Self {
+            params: &NO_PARAMS,
+            ..*self
+        }
+    }
+}
+
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     let parsed = CozoScriptParser::parse(Rule::col_type_with_term, src)
         .into_diagnostic()?
@@ -205,40 +302,72 @@ pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
 
 pub(crate) fn parse_script(
     src: &str,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<CozoScript> {
-    let parsed = CozoScriptParser::parse(Rule::script, src)
-        .map_err(|err| {
-            let span = match err.location {
-                InputLocation::Pos(p) => SourceSpan(p, 0),
-                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
-            };
-            ParseError { span }
-        })?
-        .next()
-        .unwrap();
+    let parsed = parse_script_pairs(src)?;
     Ok(match parsed.as_rule() {
         Rule::query_script => {
-            let q = parse_query(parsed.into_inner(), param_pool, fixed_rules, cur_vld)?;
+            let q = parse_query(parsed.into_inner(), ctx, fixed_rules, cur_vld)?;
             CozoScript::Single(q)
         }
         Rule::imperative_script => {
-            let p = parse_imperative_block(parsed, param_pool, fixed_rules, cur_vld)?;
+            let p = parse_imperative_block(parsed, ctx, fixed_rules, cur_vld)?;
             CozoScript::Imperative(p)
         }
 
-        Rule::sys_script => CozoScript::Sys(parse_sys(
-            parsed.into_inner(),
-            param_pool,
-            fixed_rules,
-            cur_vld,
-        )?),
+        Rule::sys_script => {
+            CozoScript::Sys(parse_sys(parsed.into_inner(), ctx, fixed_rules, cur_vld)?)
+        }
         _ => unreachable!(),
     })
 }
 
+pub(crate) fn parse_script_pairs(src: &str) -> Result<Pair<'_>> {
+    Ok(CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap())
+}
+
+/// What a script references, as far as preparing it ahead of its parameters is concerned.
+pub(crate) struct ScriptScan {
+    /// Names of the parameters used, without the leading `$`
+    pub(crate) params: BTreeSet<String>,
+    /// Whether the script calls any function whose results differ between runs
+    pub(crate) is_deterministic: bool,
+}
+
+pub(crate) fn scan_script(src: &str) -> Result<ScriptScan> {
+    let mut ret = ScriptScan {
+        params: Default::default(),
+        is_deterministic: true,
+    };
+    for pair in parse_script_pairs(src)?.into_inner().flatten() {
+        match pair.as_rule() {
+            Rule::param => {
+                ret.params
+                    .insert(pair.as_str().strip_prefix('$').unwrap().to_string());
+            }
+            Rule::apply => {
+                let name = pair.into_inner().next().unwrap().as_str();
+                if get_op(name).is_some_and(|op| !op.deterministic) {
+                    ret.is_deterministic = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
 trait ExtractSpan {
     fn extract_span(&self) -> SourceSpan;
 }
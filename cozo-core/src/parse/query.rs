@@ -19,13 +19,15 @@ use miette::{bail, ensure, Diagnostic, LabeledSpan, Report, Result};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::aggr::{parse_aggr, Aggregation};
+use crate::data::aggr::{parse_aggr, Aggr};
+use crate::data::collation::Collation;
 use crate::data::expr::Expr;
 use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
+    ArithMode, FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, SortDir, Unification,
+    InputSubquery, NullsOrder, QueryAssertion, QueryCursor, QueryOutOptions, RelationOp, SortDir,
+    Sorter, Unification,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
@@ -34,8 +36,10 @@ use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
 use crate::parse::expr::build_expr;
 use crate::parse::schema::parse_schema;
-use crate::parse::{ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pair, Pairs, ParseContext, Rule, SourceSpan};
+use crate::query::cursor::{CursorMismatchError, CursorPosition, InvalidCursorError};
 use crate::runtime::relation::InputRelationHandle;
+use crate::utils::{with_seeded_rng, ArithModeGuard};
 use crate::FixedRule;
 
 #[derive(Error, Diagnostic, Debug)]
@@ -99,18 +103,60 @@ fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
 
 pub(crate) fn parse_query(
     src: Pairs<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+) -> Result<InputProgram> {
+    // constants are evaluated while parsing, so the arithmetic mode must be known beforehand
+    let arith = match src.clone().find(|p| p.as_rule() == Rule::arith_option) {
+        None => ArithMode::Checked,
+        Some(pair) => match pair.into_inner().next().unwrap().as_rule() {
+            Rule::arith_checked => ArithMode::Checked,
+            Rule::arith_wrapping => ArithMode::Wrapping,
+            Rule::arith_saturating => ArithMode::Saturating,
+            _ => unreachable!(),
+        },
+    };
+    let _arith = ArithModeGuard::set(arith);
+    // so are the random draws, such as those in the data of constant rules
+    let seed = match src.clone().find(|p| p.as_rule() == Rule::seed_option) {
+        None => None,
+        Some(pair) => {
+            let pair = pair.into_inner().next().unwrap();
+            let span = pair.extract_span();
+            let seed = build_expr(pair, ctx)?
+                .eval_to_const()
+                .map_err(|err| OptionNotConstantError("seed", span, [err]))?
+                .get_non_neg_int()
+                .ok_or(OptionNotNonNegIntError("seed", span))?;
+            Some(seed)
+        }
+    };
+    let out_opts = QueryOutOptions {
+        arith,
+        seed,
+        ..Default::default()
+    };
+    with_seeded_rng(seed, || {
+        parse_query_body(src, ctx, fixed_rules, cur_vld, out_opts)
+    })
+}
+
+fn parse_query_body(
+    src: Pairs<'_>,
+    ctx: &ParseContext<'_>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+    mut out_opts: QueryOutOptions,
 ) -> Result<InputProgram> {
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrFixed> = Default::default();
-    let mut out_opts: QueryOutOptions = Default::default();
     let mut stored_relation = None;
+    let mut cursor = None;
 
     for pair in src {
         match pair.as_rule() {
             Rule::rule => {
-                let (name, rule) = parse_rule(pair, param_pool, cur_vld)?;
+                let (name, rule) = parse_rule(pair, ctx, cur_vld)?;
 
                 match progs.entry(name) {
                     Entry::Vacant(e) => {
@@ -154,7 +200,7 @@ pub(crate) fn parse_query(
             }
             Rule::fixed_rule => {
                 let rule_span = pair.extract_span();
-                let (name, apply) = parse_fixed_rule(pair, param_pool, fixed_rules, cur_vld)?;
+                let (name, apply) = parse_fixed_rule(pair, ctx, fixed_rules, cur_vld)?;
 
                 match progs.entry(name) {
                     Entry::Vacant(e) => {
@@ -176,7 +222,7 @@ pub(crate) fn parse_query(
             Rule::const_rule => {
                 let span = pair.extract_span();
                 let mut src = pair.into_inner();
-                let (name, head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+                let (name, head, aggr) = parse_rule_head(src.next().unwrap(), ctx)?;
 
                 if let Some(found) = progs.get(&name) {
                     let mut found_span = match found {
@@ -203,7 +249,7 @@ pub(crate) fn parse_query(
                     ensure!(a.is_none(), AggrInConstRuleError(v.span));
                 }
 
-                let data = build_expr(src.next().unwrap(), param_pool)?;
+                let data = build_expr(src.next().unwrap(), ctx)?;
                 let mut options = BTreeMap::new();
                 options.insert(SmartString::from("data"), data);
                 let handle = FixedRuleHandle {
@@ -236,7 +282,7 @@ pub(crate) fn parse_query(
             Rule::timeout_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let timeout = build_expr(pair, param_pool)?
+                let timeout = build_expr(pair, ctx)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("timeout", span, [err]))?
                     .get_float()
@@ -252,7 +298,7 @@ pub(crate) fn parse_query(
                 {
                     let pair = pair.into_inner().next().unwrap();
                     let span = pair.extract_span();
-                    let sleep = build_expr(pair, param_pool)?
+                    let sleep = build_expr(pair, ctx)?
                         .eval_to_const()
                         .map_err(|err| OptionNotConstantError("sleep", span, [err]))?
                         .get_float()
@@ -261,10 +307,11 @@ pub(crate) fn parse_query(
                     out_opts.sleep = Some(sleep);
                 }
             }
+            Rule::arith_option | Rule::seed_option => {}
             Rule::limit_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let limit = build_expr(pair, param_pool)?
+                let limit = build_expr(pair, ctx)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("limit", span, [err]))?
                     .get_non_neg_int()
@@ -274,7 +321,7 @@ pub(crate) fn parse_query(
             Rule::offset_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let offset = build_expr(pair, param_pool)?
+                let offset = build_expr(pair, ctx)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("offset", span, [err]))?
                     .get_non_neg_int()
@@ -283,21 +330,36 @@ pub(crate) fn parse_query(
             }
             Rule::sort_option => {
                 for part in pair.into_inner() {
-                    let mut var = "";
+                    let mut key = None;
                     let mut dir = SortDir::Asc;
-                    let mut span = part.extract_span();
+                    let mut collation = None;
+                    let mut nulls = None;
                     for a in part.into_inner() {
                         match a.as_rule() {
                             Rule::out_arg => {
-                                var = a.as_str();
-                                span = a.extract_span();
+                                key = Some(Expr::Binding {
+                                    var: Symbol::new(a.as_str(), a.extract_span()),
+                                    tuple_pos: None,
+                                })
                             }
+                            Rule::expr => key = Some(build_expr(a, ctx)?),
                             Rule::sort_asc => dir = SortDir::Asc,
                             Rule::sort_desc => dir = SortDir::Dsc,
+                            Rule::ident => {
+                                collation =
+                                    Some(Collation::from_name(a.as_str(), a.extract_span())?)
+                            }
+                            Rule::nulls_first => nulls = Some(NullsOrder::First),
+                            Rule::nulls_last => nulls = Some(NullsOrder::Last),
                             _ => unreachable!(),
                         }
                     }
-                    out_opts.sorters.push((Symbol::new(var, span), dir));
+                    out_opts.sorters.push(Sorter {
+                        key: key.unwrap(),
+                        dir,
+                        collation,
+                        nulls,
+                    });
                 }
             }
             Rule::relation_option => {
@@ -346,6 +408,23 @@ pub(crate) fn parse_query(
                 );
                 out_opts.assertion = Some(QueryAssertion::AssertSome(pair.extract_span()))
             }
+            Rule::cursor_option => {
+                let after = match pair.into_inner().next() {
+                    None => None,
+                    Some(pair) => {
+                        let span = pair.extract_span();
+                        match build_expr(pair, ctx)?
+                            .eval_to_const()
+                            .map_err(|err| OptionNotConstantError("cursor", span, [err]))?
+                        {
+                            DataValue::Null => None,
+                            DataValue::Str(token) => Some((token, span)),
+                            _ => bail!(InvalidCursorError(span)),
+                        }
+                    }
+                };
+                cursor = Some(after);
+            }
             Rule::EOI => break,
             r => unreachable!("{:?}", r),
         }
@@ -417,6 +496,28 @@ pub(crate) fn parse_query(
         }
     }
 
+    if let Some(after) = cursor {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Query option ':cursor' cannot be used when storing the results")]
+        #[diagnostic(code(parser::cursor_with_store))]
+        struct CursorWithStoreError;
+
+        ensure!(prog.out_opts.store_relation.is_none(), CursorWithStoreError);
+        let after = match after {
+            None => None,
+            Some((token, span)) => {
+                let pos = CursorPosition::decode(&token, span)?;
+                let head = prog.get_entry_out_head_or_default()?;
+                ensure!(
+                    pos.matches(&head, &prog.out_opts.sorters),
+                    CursorMismatchError(span)
+                );
+                Some(Box::new(pos))
+            }
+        };
+        prog.out_opts.cursor = Some(QueryCursor { after });
+    }
+
     if !prog.out_opts.sorters.is_empty() {
         #[derive(Debug, Error, Diagnostic)]
         #[error("Sort key '{0}' not found")]
@@ -425,11 +526,13 @@ pub(crate) fn parse_query(
 
         let head_args = prog.get_entry_out_head()?;
 
-        for (sorter, _) in &prog.out_opts.sorters {
-            ensure!(
-                head_args.contains(sorter),
-                SortKeyNotFound(sorter.to_string(), sorter.span)
-            )
+        for sorter in &prog.out_opts.sorters {
+            for var in sorter.key.bindings() {
+                ensure!(
+                    head_args.contains(&var),
+                    SortKeyNotFound(var.to_string(), var.span)
+                )
+            }
         }
     }
 
@@ -438,14 +541,14 @@ pub(crate) fn parse_query(
 
 fn parse_rule(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     cur_vld: ValidityTs,
 ) -> Result<(Symbol, InputInlineRule)> {
     let span = src.extract_span();
     let mut src = src.into_inner();
     let head = src.next().unwrap();
     let head_span = head.extract_span();
-    let (name, head, aggr) = parse_rule_head(head, param_pool)?;
+    let (name, head, aggr) = parse_rule_head(head, ctx)?;
 
     #[derive(Debug, Error, Diagnostic)]
     #[error("Horn-clause rule cannot have empty rule head")]
@@ -453,39 +556,110 @@ fn parse_rule(
     struct EmptyRuleHead(#[label] SourceSpan);
 
     ensure!(!head.is_empty(), EmptyRuleHead(head_span));
-    let body = src.next().unwrap();
-    let mut body_clauses = vec![];
-    let mut ignored_counter = 0;
-    for atom_src in body.into_inner() {
-        body_clauses.push(parse_disjunction(
-            atom_src,
-            param_pool,
-            cur_vld,
-            &mut ignored_counter,
-        )?)
-    }
+    let (body, subqueries) = parse_rule_body(src.next().unwrap(), ctx, cur_vld)?;
 
     Ok((
         name,
         InputInlineRule {
             head,
             aggr,
-            body: body_clauses,
+            body,
+            subqueries,
             span,
         },
     ))
 }
 
+fn parse_rule_body(
+    src: Pair<'_>,
+    ctx: &ParseContext<'_>,
+    cur_vld: ValidityTs,
+) -> Result<(Vec<InputAtom>, Vec<InputSubquery>)> {
+    let body_ctx = ParseContext {
+        in_rule_body: true,
+        ..*ctx
+    };
+    let mut body = vec![];
+    let mut ignored_counter = 0;
+    for atom_src in src.clone().into_inner() {
+        body.push(parse_disjunction(
+            atom_src,
+            &body_ctx,
+            cur_vld,
+            &mut ignored_counter,
+        )?)
+    }
+    let mut found = vec![];
+    find_subqueries(src, &mut found);
+    let subqueries = found
+        .into_iter()
+        .map(|pair| parse_subquery(pair, ctx, cur_vld))
+        .try_collect()?;
+    Ok((body, subqueries))
+}
+
+/// The variable standing in for the value of the subquery at `span` in the rule containing it.
+pub(crate) fn subquery_var(span: SourceSpan) -> Symbol {
+    Symbol::new(format!("*subquery@{}", span.0), span)
+}
+
+fn find_subqueries<'a>(src: Pair<'a>, found: &mut Vec<Pair<'a>>) {
+    for pair in src.into_inner() {
+        match pair.as_rule() {
+            Rule::exists_subquery | Rule::scalar_subquery => found.push(pair),
+            _ => find_subqueries(pair, found),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("A scalar subquery must define the entry rule '?' with a single column")]
+#[diagnostic(code(parser::bad_scalar_subquery))]
+struct BadScalarSubquery(#[label] SourceSpan);
+
+/// Parse the subquery, which is decorrelated from the rule containing it during normalization
+fn parse_subquery(
+    pair: Pair<'_>,
+    ctx: &ParseContext<'_>,
+    cur_vld: ValidityTs,
+) -> Result<InputSubquery> {
+    let span = pair.extract_span();
+    let var = subquery_var(span);
+    Ok(if pair.as_rule() == Rule::exists_subquery {
+        let (body, subqueries) = parse_rule_body(pair.into_inner().next().unwrap(), ctx, cur_vld)?;
+        InputSubquery {
+            var,
+            value: None,
+            body,
+            subqueries,
+            span,
+        }
+    } else {
+        let (name, mut rule) = parse_rule(pair.into_inner().next().unwrap(), ctx, cur_vld)?;
+        ensure!(
+            name.is_prog_entry() && rule.head.len() == 1,
+            BadScalarSubquery(span)
+        );
+        InputSubquery {
+            var,
+            value: Some((rule.head.pop().unwrap(), rule.aggr.pop().unwrap())),
+            body: rule.body,
+            subqueries: rule.subqueries,
+            span,
+        }
+    })
+}
+
 fn parse_disjunction(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     cur_vld: ValidityTs,
     ignored_counter: &mut u32,
 ) -> Result<InputAtom> {
     let span = pair.extract_span();
     let res: Vec<_> = pair
         .into_inner()
-        .map(|v| parse_atom(v, param_pool, cur_vld, ignored_counter))
+        .map(|v| parse_atom(v, ctx, cur_vld, ignored_counter))
         .try_collect()?;
     Ok(if res.len() == 1 {
         res.into_iter().next().unwrap()
@@ -496,7 +670,7 @@ fn parse_disjunction(
 
 fn parse_atom(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     cur_vld: ValidityTs,
     ignored_counter: &mut u32,
 ) -> Result<InputAtom> {
@@ -505,19 +679,19 @@ fn parse_atom(
             let span = src.extract_span();
             let grouped: Vec<_> = src
                 .into_inner()
-                .map(|v| parse_disjunction(v, param_pool, cur_vld, ignored_counter))
+                .map(|v| parse_disjunction(v, ctx, cur_vld, ignored_counter))
                 .try_collect()?;
             InputAtom::Conjunction {
                 inner: grouped,
                 span,
             }
         }
-        Rule::disjunction => parse_disjunction(src, param_pool, cur_vld, ignored_counter)?,
+        Rule::disjunction => parse_disjunction(src, ctx, cur_vld, ignored_counter)?,
         Rule::negation => {
             let span = src.extract_span();
             let inner = parse_atom(
                 src.into_inner().next().unwrap(),
-                param_pool,
+                ctx,
                 cur_vld,
                 ignored_counter,
             )?;
@@ -526,8 +700,21 @@ fn parse_atom(
                 span,
             }
         }
+        Rule::optional => {
+            let span = src.extract_span();
+            let inner = parse_atom(
+                src.into_inner().next().unwrap(),
+                ctx,
+                cur_vld,
+                ignored_counter,
+            )?;
+            InputAtom::Optional {
+                inner: inner.into(),
+                span,
+            }
+        }
         Rule::expr => {
-            let expr = build_expr(src, param_pool)?;
+            let expr = build_expr(src, ctx)?;
             InputAtom::Predicate { inner: expr }
         }
         Rule::unify => {
@@ -539,7 +726,7 @@ fn parse_atom(
                 symb.name = format!("*^*{}", *ignored_counter).into();
                 *ignored_counter += 1;
             }
-            let expr = build_expr(src.next().unwrap(), param_pool)?;
+            let expr = build_expr(src.next().unwrap(), ctx)?;
             InputAtom::Unification {
                 inner: Unification {
                     binding: symb,
@@ -558,7 +745,7 @@ fn parse_atom(
                 symb.name = format!("*^*{}", *ignored_counter).into();
                 *ignored_counter += 1;
             }
-            let expr = build_expr(src.next().unwrap(), param_pool)?;
+            let expr = build_expr(src.next().unwrap(), ctx)?;
             InputAtom::Unification {
                 inner: Unification {
                     binding: symb,
@@ -576,7 +763,7 @@ fn parse_atom(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|v| build_expr(v, param_pool))
+                .map(|v| build_expr(v, ctx))
                 .try_collect()?;
             InputAtom::Rule {
                 inner: InputRuleApplyAtom {
@@ -594,12 +781,12 @@ fn parse_atom(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|v| build_expr(v, param_pool))
+                .map(|v| build_expr(v, ctx))
                 .try_collect()?;
             let valid_at = match src.next() {
                 None => None,
                 Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
+                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), ctx)?;
                     Some(expr2vld_spec(vld_expr, cur_vld)?)
                 }
             };
@@ -626,7 +813,7 @@ fn parse_atom(
                     let name_p = inner.next().unwrap();
                     let name = SmartString::from(name_p.as_str());
                     let arg = match inner.next() {
-                        Some(a) => build_expr(a, param_pool)?,
+                        Some(a) => build_expr(a, ctx)?,
                         None => Expr::Binding {
                             var: Symbol::new(name.clone(), name_p.extract_span()),
                             tuple_pos: None,
@@ -638,7 +825,7 @@ fn parse_atom(
             let valid_at = match src.next() {
                 None => None,
                 Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
+                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), ctx)?;
                     Some(expr2vld_spec(vld_expr, cur_vld)?)
                 }
             };
@@ -657,18 +844,14 @@ fn parse_atom(
 
 fn parse_rule_head(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-) -> Result<(
-    Symbol,
-    Vec<Symbol>,
-    Vec<Option<(Aggregation, Vec<DataValue>)>>,
-)> {
+    ctx: &ParseContext<'_>,
+) -> Result<(Symbol, Vec<Symbol>, Vec<Option<(Aggr, Vec<DataValue>)>>)> {
     let mut src = src.into_inner();
     let name = src.next().unwrap();
     let mut args = vec![];
     let mut aggrs = vec![];
     for p in src {
-        let (arg, aggr) = parse_rule_head_arg(p, param_pool)?;
+        let (arg, aggr) = parse_rule_head_arg(p, ctx)?;
         args.push(arg);
         aggrs.push(aggr);
     }
@@ -682,8 +865,8 @@ struct AggrNotFound(String, #[label] SourceSpan);
 
 fn parse_rule_head_arg(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-) -> Result<(Symbol, Option<(Aggregation, Vec<DataValue>)>)> {
+    ctx: &ParseContext<'_>,
+) -> Result<(Symbol, Option<(Aggr, Vec<DataValue>)>)> {
     let src = src.into_inner().next().unwrap();
     Ok(match src.as_rule() {
         Rule::var => (Symbol::new(src.as_str(), src.extract_span()), None),
@@ -693,14 +876,20 @@ fn parse_rule_head_arg(
             let aggr_name = aggr_p.as_str();
             let var = inner.next().unwrap();
             let args: Vec<_> = inner
-                .map(|v| -> Result<DataValue> { build_expr(v, param_pool)?.eval_to_const() })
+                .map(|v| -> Result<DataValue> { build_expr(v, ctx)?.eval_to_const() })
                 .try_collect()?;
             (
                 Symbol::new(var.as_str(), var.extract_span()),
                 Some((
-                    parse_aggr(aggr_name)
-                        .ok_or_else(|| AggrNotFound(aggr_name.to_string(), aggr_p.extract_span()))?
-                        .clone(),
+                    match parse_aggr(aggr_name) {
+                        Some(aggr) => aggr.clone(),
+                        None => match ctx.custom_aggrs.get(aggr_name) {
+                            Some(aggr) => Aggr::new_custom(aggr_name, aggr.clone()),
+                            None => {
+                                bail!(AggrNotFound(aggr_name.to_string(), aggr_p.extract_span()))
+                            }
+                        },
+                    },
                     args,
                 )),
             )
@@ -716,12 +905,12 @@ struct BadValiditySpecification(#[label] SourceSpan);
 
 fn parse_fixed_rule(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<(Symbol, FixedRuleApply)> {
     let mut src = src.into_inner();
-    let (out_symbol, head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+    let (out_symbol, head, aggr) = parse_rule_head(src.next().unwrap(), ctx)?;
 
     #[derive(Debug, Error, Diagnostic)]
     #[error("fixed rule cannot be combined with aggregation")]
@@ -803,7 +992,7 @@ fn parse_fixed_rule(
                                 }
                                 Rule::validity_clause => {
                                     let vld_inner = v.into_inner().next().unwrap();
-                                    let vld_expr = build_expr(vld_inner, param_pool)?;
+                                    let vld_expr = build_expr(vld_inner, ctx)?;
                                     valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?)
                                 }
                                 _ => unreachable!(),
@@ -848,7 +1037,7 @@ fn parse_fixed_rule(
                                 }
                                 Rule::validity_clause => {
                                     let vld_inner = p.into_inner().next().unwrap();
-                                    let vld_expr = build_expr(vld_inner, param_pool)?;
+                                    let vld_expr = build_expr(vld_inner, ctx)?;
                                     valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?)
                                 }
                                 _ => unreachable!(),
@@ -872,7 +1061,7 @@ fn parse_fixed_rule(
                 let mut inner = nxt.into_inner();
                 let name = inner.next().unwrap().as_str();
                 let val = inner.next().unwrap();
-                let val = build_expr(val, param_pool)?;
+                let val = build_expr(val, ctx)?;
                 options.insert(SmartString::from(name), val);
             }
             _ => unreachable!(),
@@ -13,6 +13,8 @@ use miette::{bail, ensure, Diagnostic, Result};
 use smartstring::SmartString;
 use thiserror::Error;
 
+use crate::data::collation::Collation;
+use crate::data::decimal::MAX_SCALE;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
@@ -113,19 +115,58 @@ pub(crate) fn parse_nullable_type(pair: Pair<'_>) -> Result<NullableColType> {
     Ok(NullableColType { coltype, nullable })
 }
 
+/// Collations only apply to whole columns, as they change the encoding of the keys
+fn parse_nested_type(pair: Pair<'_>) -> Result<NullableColType> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Collations cannot be given to the elements of lists and tuples")]
+    #[diagnostic(code(parser::nested_collation))]
+    struct NestedCollation(#[label] SourceSpan);
+
+    let span = pair.extract_span();
+    let typ = parse_nullable_type(pair)?;
+    ensure!(
+        !matches!(typ.coltype, ColType::CollatedString(_)),
+        NestedCollation(span)
+    );
+    Ok(typ)
+}
+
 fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
     Ok(match pair.as_rule() {
         Rule::any_type => ColType::Any,
         Rule::bool_type => ColType::Bool,
         Rule::int_type => ColType::Int,
         Rule::float_type => ColType::Float,
-        Rule::string_type => ColType::String,
+        Rule::decimal_type => match pair.into_inner().next() {
+            None => ColType::Decimal(None),
+            Some(scale) => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("The scale of a decimal must be at most {MAX_SCALE}")]
+                #[diagnostic(code(parser::bad_decimal_scale))]
+                struct BadDecimalScale(#[label] SourceSpan);
+
+                let span = scale.extract_span();
+                let scale = scale
+                    .as_str()
+                    .replace('_', "")
+                    .parse::<u8>()
+                    .map_err(|_| BadDecimalScale(span))?;
+                ensure!(scale <= MAX_SCALE, BadDecimalScale(span));
+                ColType::Decimal(Some(scale))
+            }
+        },
+        Rule::string_type => match pair.into_inner().next() {
+            None => ColType::String,
+            Some(name) => {
+                ColType::CollatedString(Collation::from_name(name.as_str(), name.extract_span())?)
+            }
+        },
         Rule::bytes_type => ColType::Bytes,
         Rule::uuid_type => ColType::Uuid,
         Rule::validity_type => ColType::Validity,
         Rule::list_type => {
             let mut inner = pair.into_inner();
-            let eltype = parse_nullable_type(inner.next().unwrap())?;
+            let eltype = parse_nested_type(inner.next().unwrap())?;
             let len = match inner.next() {
                 None => None,
                 Some(len_p) => {
@@ -148,9 +189,7 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
                 len,
             }
         }
-        Rule::tuple_type => {
-            ColType::Tuple(pair.into_inner().map(parse_nullable_type).try_collect()?)
-        }
+        Rule::tuple_type => ColType::Tuple(pair.into_inner().map(parse_nested_type).try_collect()?),
         _ => unreachable!(),
     })
 }
@@ -18,7 +18,7 @@ use crate::data::symb::Symbol;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::expr::build_expr;
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pairs, ParseContext, Rule, SourceSpan};
 use crate::runtime::relation::AccessLevel;
 use crate::FixedRule;
 
@@ -37,6 +37,13 @@ pub(crate) enum SysOp {
     SetAccessLevel(Vec<Symbol>, AccessLevel),
     CreateIndex(Symbol, Symbol, Vec<Symbol>),
     RemoveIndex(Symbol, Symbol),
+    CreateView(Symbol, String, bool),
+    RemoveView(Symbol),
+    ListViews,
+    CreateProcedure(Symbol, Vec<Symbol>, String),
+    RemoveProcedure(Symbol),
+    ListProcedures,
+    CallProcedure(Symbol, Vec<DataValue>),
 }
 
 #[derive(Debug, Diagnostic, Error)]
@@ -46,7 +53,7 @@ struct ProcessIdError(String, #[label] SourceSpan);
 
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    ctx: &ParseContext<'_>,
     algorithms: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<SysOp> {
@@ -56,7 +63,7 @@ pub(crate) fn parse_sys(
         Rule::running_op => SysOp::ListRunning,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
-            let i_val = build_expr(i_expr, param_pool)?;
+            let i_val = build_expr(i_expr, ctx)?;
             let i_val = i_val.eval_to_const()?;
             let i_val = i_val
                 .get_int()
@@ -66,7 +73,7 @@ pub(crate) fn parse_sys(
         Rule::explain_op => {
             let prog = parse_query(
                 inner.into_inner().next().unwrap().into_inner(),
-                param_pool,
+                ctx,
                 algorithms,
                 cur_vld,
             )?;
@@ -135,7 +142,7 @@ pub(crate) fn parse_sys(
                 let script_str = script.as_str();
                 parse_query(
                     script.into_inner(),
-                    &Default::default(),
+                    &ctx.without_params(),
                     algorithms,
                     cur_vld,
                 )?;
@@ -185,6 +192,121 @@ pub(crate) fn parse_sys(
             }
         }
         Rule::list_fixed_rules => SysOp::ListFixedRules,
+        Rule::list_views_op => SysOp::ListViews,
+        Rule::view_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::view_create | Rule::view_materialize => {
+                    let materialized = inner.as_rule() == Rule::view_materialize;
+                    let mut inner = inner.into_inner();
+                    let name = inner.next().unwrap();
+                    let name = Symbol::new(name.as_str(), name.extract_span());
+                    let rules = inner.next().unwrap();
+                    let definition = rules.as_str().to_string();
+                    let span = rules.extract_span();
+                    let prog = parse_query(
+                        rules.into_inner(),
+                        &ctx.without_params(),
+                        algorithms,
+                        cur_vld,
+                    )?;
+
+                    #[derive(Debug, Diagnostic, Error)]
+                    #[error("views cannot have query options")]
+                    #[diagnostic(code(parser::view_with_options))]
+                    #[diagnostic(help("Options should be given in the queries using the view"))]
+                    struct ViewWithOptions(#[label] SourceSpan);
+
+                    let opts = &prog.out_opts;
+                    ensure!(
+                        opts.limit.is_none()
+                            && opts.offset.is_none()
+                            && opts.timeout.is_none()
+                            && opts.sleep.is_none()
+                            && opts.sorters.is_empty()
+                            && opts.store_relation.is_none()
+                            && opts.assertion.is_none()
+                            && opts.cursor.is_none(),
+                        ViewWithOptions(span)
+                    );
+                    prog.get_entry_arity()?;
+                    SysOp::CreateView(name, definition, materialized)
+                }
+                Rule::view_drop => {
+                    let name = inner.into_inner().next().unwrap();
+                    SysOp::RemoveView(Symbol::new(name.as_str(), name.extract_span()))
+                }
+                _ => unreachable!(),
+            }
+        }
+        Rule::list_procedures_op => SysOp::ListProcedures,
+        Rule::procedure_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::procedure_create => {
+                    let mut src = inner.into_inner();
+                    let name = src.next().unwrap();
+                    let name = Symbol::new(name.as_str(), name.extract_span());
+                    let mut params: Vec<Symbol> = vec![];
+                    let mut body = None;
+                    for pair in src {
+                        match pair.as_rule() {
+                            Rule::param => {
+                                let param = Symbol::new(
+                                    pair.as_str().strip_prefix('$').unwrap(),
+                                    pair.extract_span(),
+                                );
+
+                                #[derive(Debug, Diagnostic, Error)]
+                                #[error("Parameter ${0} is declared more than once")]
+                                #[diagnostic(code(parser::duplicate_procedure_param))]
+                                struct DuplicateProcedureParam(String, #[label] SourceSpan);
+
+                                ensure!(
+                                    !params.contains(&param),
+                                    DuplicateProcedureParam(param.to_string(), param.span)
+                                );
+                                params.push(param);
+                            }
+                            Rule::procedure_body => body = Some(pair),
+                            _ => unreachable!(),
+                        }
+                    }
+                    let body = body.unwrap();
+                    for used in body.clone().into_inner().flatten() {
+                        if used.as_rule() != Rule::param {
+                            continue;
+                        }
+                        let used_name = used.as_str().strip_prefix('$').unwrap();
+
+                        #[derive(Debug, Diagnostic, Error)]
+                        #[error("Parameter ${0} is used but not declared by the procedure")]
+                        #[diagnostic(code(parser::undeclared_procedure_param))]
+                        struct UndeclaredProcedureParam(String, #[label] SourceSpan);
+
+                        ensure!(
+                            params.iter().any(|p| p.name == used_name),
+                            UndeclaredProcedureParam(used_name.to_string(), used.extract_span())
+                        );
+                    }
+                    SysOp::CreateProcedure(name, params, body.as_str().to_string())
+                }
+                Rule::procedure_drop => {
+                    let name = inner.into_inner().next().unwrap();
+                    SysOp::RemoveProcedure(Symbol::new(name.as_str(), name.extract_span()))
+                }
+                _ => unreachable!(),
+            }
+        }
+        Rule::call_op => {
+            let mut src = inner.into_inner();
+            let name = src.next().unwrap();
+            let name = Symbol::new(name.as_str(), name.extract_span());
+            let args = src
+                .map(|arg| build_expr(arg, ctx)?.eval_to_const())
+                .try_collect()?;
+            SysOp::CallProcedure(name, args)
+        }
         rule => unreachable!("{:?}", rule),
     })
 }
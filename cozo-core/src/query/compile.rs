@@ -7,16 +7,21 @@
  */
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
 use miette::{bail, ensure, Context, Diagnostic, Result};
 use thiserror::Error;
 
-use crate::data::aggr::Aggregation;
+use crate::data::aggr::Aggr;
 use crate::data::expr::Expr;
 use crate::data::program::{
-    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRulesOrFixed, MagicSymbol,
-    StratifiedMagicProgram,
+    MagicAtom, MagicFixedRuleApply, MagicFixedRuleRuleArg, MagicInlineRule, MagicRulesOrFixed,
+    MagicSymbol, StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
@@ -24,10 +29,12 @@ use crate::parse::SourceSpan;
 use crate::query::ra::RelAlgebra;
 use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
 use crate::runtime::transact::SessionTx;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::Storage;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum CompiledRuleSet {
     Rules(Vec<CompiledRule>),
     Fixed(MagicFixedRuleApply),
@@ -41,6 +48,20 @@ pub(crate) enum AggrKind {
 }
 
 impl CompiledRuleSet {
+    /// The stores read during the evaluation of this rule set.
+    pub(crate) fn dependencies(&self) -> Vec<&MagicSymbol> {
+        match self {
+            CompiledRuleSet::Rules(rs) => rs.iter().flat_map(|r| &r.contained_rules).collect(),
+            CompiledRuleSet::Fixed(fixed) => fixed
+                .rule_args
+                .iter()
+                .filter_map(|arg| match arg {
+                    MagicFixedRuleRuleArg::InMem { name, .. } => Some(name),
+                    MagicFixedRuleRuleArg::Stored { .. } => None,
+                })
+                .collect(),
+        }
+    }
     pub(crate) fn arity(&self) -> usize {
         match self {
             CompiledRuleSet::Rules(rs) => rs[0].aggr.len(),
@@ -75,13 +96,132 @@ impl CompiledRuleSet {
             CompiledRuleSet::Fixed(_) => AggrKind::None,
         }
     }
+    /// Replace the parameter placeholders throughout the rule set.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        match self {
+            CompiledRuleSet::Rules(rules) => {
+                for rule in rules {
+                    for (_, aggr_args) in rule.aggr.iter_mut().flatten() {
+                        for arg in aggr_args {
+                            arg.bind_params(params)
+                        }
+                    }
+                    rule.relation.bind_params(params);
+                }
+            }
+            CompiledRuleSet::Fixed(fixed) => {
+                let mut options = (*fixed.options).clone();
+                for expr in options.values_mut() {
+                    expr.bind_params(params)
+                }
+                fixed.options = Arc::new(options);
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct CompiledRule {
-    pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
+    pub(crate) aggr: Vec<Option<(Aggr, Vec<DataValue>)>>,
     pub(crate) relation: RelAlgebra,
     pub(crate) contained_rules: BTreeSet<MagicSymbol>,
+    /// Copies of `relation` scanning disjoint key ranges of the stored relation driving it,
+    /// which may be evaluated in parallel instead. Empty if the scan is not split.
+    pub(crate) partitions: Vec<RelAlgebra>,
+}
+
+/// Scans expected to see fewer rows than this are not split.
+const PARTITION_MIN_ROWS: usize = 10000;
+
+/// Key samples older than this are taken again, as the relations change in the meantime.
+#[cfg(not(target_arch = "wasm32"))]
+const KEY_SAMPLES_TTL: Duration = Duration::from_secs(10);
+
+/// The key samples taken from the stored relations, by the lower bounds of their key ranges.
+/// Kept for a while, so that the storage is not sampled for every query.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub(crate) struct KeySampleCache(BTreeMap<Vec<u8>, (Instant, Vec<Vec<u8>>)>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl KeySampleCache {
+    fn get(&self, lower: &[u8], now: Instant) -> Option<Vec<Vec<u8>>> {
+        match self.0.get(lower) {
+            Some((taken, samples)) if now.duration_since(*taken) < KEY_SAMPLES_TTL => {
+                Some(samples.clone())
+            }
+            _ => None,
+        }
+    }
+    fn insert(&mut self, lower: Vec<u8>, now: Instant, samples: Vec<Vec<u8>>) {
+        // also drops the samples of relations no longer queried, or no longer existing
+        self.0
+            .retain(|_, (taken, _)| now.duration_since(*taken) < KEY_SAMPLES_TTL);
+        self.0.insert(lower, (now, samples));
+    }
+}
+
+/// Split the full scans of stored relations driving the rules into key-range partitions,
+/// with the boundaries sampled from the storage, or taken from `cache` if sampled recently.
+/// Scans already restricted to a key range are left alone.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn partition_driving_scans<'s, S: Storage<'s>>(
+    storage: &'s S,
+    cache: &Mutex<KeySampleCache>,
+    strata: &mut [CompiledProgram],
+) -> Result<()> {
+    let max_partitions = rayon::current_num_threads() * 2;
+    if max_partitions < 2 {
+        return Ok(());
+    }
+    for rule in strata
+        .iter_mut()
+        .flat_map(|prog| prog.values_mut())
+        .filter_map(|rule_set| match rule_set {
+            CompiledRuleSet::Rules(rules) => Some(rules),
+            CompiledRuleSet::Fixed(_) => None,
+        })
+        .flatten()
+    {
+        let (lower, upper) = match rule.relation.driving_scan() {
+            Some(scan)
+                if !scan.storage.is_temp
+                    && !scan.storage.metadata.keys.is_empty()
+                    && scan.key_range.is_none() =>
+            {
+                scan.storage.key_range()
+            }
+            _ => continue,
+        };
+        let now = Instant::now();
+        let cached = cache.lock().unwrap().get(&lower, now);
+        let mut samples = match cached {
+            Some(samples) => samples,
+            None => {
+                let samples = storage.sample_keys(&lower, &upper, PARTITION_MIN_ROWS)?;
+                cache
+                    .lock()
+                    .unwrap()
+                    .insert(lower.clone(), now, samples.clone());
+                samples
+            }
+        };
+        if samples.is_empty() {
+            continue;
+        }
+        if samples.len() >= max_partitions {
+            let n = samples.len();
+            samples = (1..max_partitions)
+                .map(|i| samples[i * n / max_partitions].clone())
+                .collect();
+        }
+        let mut bounds = Vec::with_capacity(samples.len() + 2);
+        bounds.push(lower);
+        bounds.extend(samples);
+        bounds.push(upper);
+        rule.partitions = rule.relation.partition(&bounds);
+    }
+    Ok(())
 }
 
 #[derive(Debug, Error, Diagnostic)]
@@ -140,6 +280,7 @@ impl<'a> SessionTx<'a> {
                                         aggr: rule.aggr.clone(),
                                         relation,
                                         contained_rules: rule.contained_rules(),
+                                        partitions: vec![],
                                     })
                                 }
                                 Ok((k, CompiledRuleSet::Rules(collected)))
@@ -468,6 +609,109 @@ impl<'a> SessionTx<'a> {
                         }
                     }
                 }
+                MagicAtom::OptionalRule(rule_app) => {
+                    let store_arity = store_arities.get(&rule_app.name).ok_or_else(|| {
+                        RuleNotFound(
+                            rule_app.name.symbol().to_string(),
+                            rule_app.name.symbol().span,
+                        )
+                    })?;
+                    ensure!(
+                        *store_arity == rule_app.args.len(),
+                        ArityMismatch(
+                            rule_app.name.symbol().to_string(),
+                            *store_arity,
+                            rule_app.args.len(),
+                            rule_app.span
+                        )
+                    );
+
+                    let mut prev_joiner_vars = vec![];
+                    let mut right_joiner_vars = vec![];
+                    let mut right_vars = vec![];
+
+                    for var in &rule_app.args {
+                        if seen_variables.contains(var) {
+                            prev_joiner_vars.push(var.clone());
+                            let rk = gen_symb(var.span);
+                            right_vars.push(rk.clone());
+                            right_joiner_vars.push(rk);
+                        } else {
+                            seen_variables.insert(var.clone());
+                            right_vars.push(var.clone());
+                        }
+                    }
+
+                    let right =
+                        RelAlgebra::derived(right_vars, rule_app.name.clone(), rule_app.span);
+                    debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
+                    ret = ret.outer_join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
+                }
+                MagicAtom::OptionalRelation(rel_app) => {
+                    let store = self.get_relation(&rel_app.name, false)?;
+                    if store.access_level < AccessLevel::ReadOnly {
+                        bail!(InsufficientAccessLevel(
+                            store.name.to_string(),
+                            "reading rows".to_string(),
+                            store.access_level
+                        ));
+                    }
+                    ensure!(
+                        store.arity() == rel_app.args.len(),
+                        ArityMismatch(
+                            rel_app.name.to_string(),
+                            store.arity(),
+                            rel_app.args.len(),
+                            rel_app.span
+                        )
+                    );
+
+                    let mut prev_joiner_vars = vec![];
+                    let mut right_joiner_vars = vec![];
+                    let mut right_vars = vec![];
+                    let mut join_indices = vec![];
+
+                    for var in rel_app.args.iter() {
+                        if seen_variables.contains(var) {
+                            prev_joiner_vars.push(var.clone());
+                            let rk = gen_symb(var.span);
+                            right_vars.push(rk.clone());
+                            right_joiner_vars.push(rk);
+                            join_indices.push(IndexPositionUse::Join)
+                        } else {
+                            seen_variables.insert(var.clone());
+                            right_vars.push(var.clone());
+                            if var.is_generated_ignored_symbol() {
+                                join_indices.push(IndexPositionUse::Ignored)
+                            } else {
+                                join_indices.push(IndexPositionUse::BindForLater)
+                            }
+                        }
+                    }
+
+                    // an index is only used if it covers all the columns needed,
+                    // as going back to the relation would require another outer join
+                    let right = match store.choose_index(&join_indices, rel_app.valid_at.is_some())
+                    {
+                        None | Some((_, _, true)) => {
+                            RelAlgebra::relation(right_vars, store, rel_app.span, rel_app.valid_at)?
+                        }
+                        Some((chosen_index, mapper, false)) => {
+                            let new_right_vars = mapper
+                                .into_iter()
+                                .map(|i| right_vars[i].clone())
+                                .collect_vec();
+                            RelAlgebra::relation(
+                                new_right_vars,
+                                chosen_index,
+                                rel_app.span,
+                                rel_app.valid_at,
+                            )?
+                        }
+                    };
+                    debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
+                    ret = ret.outer_join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                }
                 MagicAtom::Predicate(p) => {
                     ret = ret.filter(p.clone());
                 }
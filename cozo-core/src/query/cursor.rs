@@ -0,0 +1,148 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::program::{MagicSymbol, QueryOutOptions, SortDir, Sorter};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+
+/// The position in the results of a paginated query after which the next page starts.
+/// Handed out to users as an opaque token.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct CursorPosition {
+    /// The headers and the sort keys of the query the position belongs to
+    head: Vec<String>,
+    sorters: Vec<(String, bool)>,
+    /// The last row of the previous page
+    pub(crate) last: Tuple,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid cursor token")]
+#[diagnostic(code(parser::invalid_cursor))]
+#[diagnostic(help(
+    "Cursor tokens must be taken unchanged from the results of a query with ':cursor'"
+))]
+pub(crate) struct InvalidCursorError(#[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The cursor token belongs to a different query")]
+#[diagnostic(code(parser::cursor_mismatch))]
+#[diagnostic(help(
+    "A cursor token can only be used with a query having the same headers and sort keys"
+))]
+pub(crate) struct CursorMismatchError(#[label] pub(crate) SourceSpan);
+
+impl CursorPosition {
+    fn new(head: &[Symbol], sorters: &[Sorter], last: Tuple) -> Self {
+        Self {
+            head: head_names(head),
+            sorters: sorter_names(sorters),
+            last,
+        }
+    }
+    pub(crate) fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(rmp_serde::to_vec(self).unwrap())
+    }
+    pub(crate) fn decode(token: &str, span: SourceSpan) -> Result<Self> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| InvalidCursorError(span))?;
+        Ok(rmp_serde::from_slice(&bytes).map_err(|_| InvalidCursorError(span))?)
+    }
+    /// Whether the position belongs to a query with the given headers and sort keys.
+    pub(crate) fn matches(&self, head: &[Symbol], sorters: &[Sorter]) -> bool {
+        self.head == head_names(head) && self.sorters == sorter_names(sorters)
+    }
+}
+
+fn head_names(head: &[Symbol]) -> Vec<String> {
+    head.iter().map(|s| s.to_string()).collect()
+}
+
+fn sorter_names(sorters: &[Sorter]) -> Vec<(String, bool)> {
+    sorters
+        .iter()
+        .map(|s| {
+            let name = Sorter {
+                dir: SortDir::Asc,
+                ..s.clone()
+            };
+            (name.to_string(), s.dir == SortDir::Dsc)
+        })
+        .collect()
+}
+
+/// The token for the page following `rows`, or `None` if there are no more pages.
+pub(crate) fn next_page_token(
+    out_opts: &QueryOutOptions,
+    head: &[Symbol],
+    rows: &[Tuple],
+) -> Option<String> {
+    out_opts.cursor.as_ref()?;
+    match (out_opts.limit, rows.last()) {
+        (Some(limit), Some(last)) if rows.len() == limit => {
+            Some(CursorPosition::new(head, &out_opts.sorters, last.clone()).encode())
+        }
+        _ => None,
+    }
+}
+
+/// Check if the entry rule produces its rows in the key order of the stored relation
+/// driving it, with the key columns leading the output and at most one row per key.
+/// If so, the rows come out in order, and the scan is made to start after `after`,
+/// if given.
+pub(crate) fn push_down_cursor(strata: &mut [CompiledProgram], after: Option<&Tuple>) -> bool {
+    let entry_symbol = MagicSymbol::Muggle {
+        inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+    };
+    let rule = match strata
+        .last_mut()
+        .and_then(|prog| prog.get_mut(&entry_symbol))
+    {
+        Some(CompiledRuleSet::Rules(rules)) if rules.len() == 1 => &mut rules[0],
+        _ => return false,
+    };
+    if rule.aggr.iter().any(|aggr| aggr.is_some()) || rule.contained_rules.contains(&entry_symbol) {
+        return false;
+    }
+    let bindings = rule.relation.bindings_after_eliminate();
+    let unique_key = match rule.relation.unique_key() {
+        Some(key) => key,
+        None => return false,
+    };
+    let scan = match rule.relation.driving_scan() {
+        Some(scan) => scan,
+        None => return false,
+    };
+    let n_keys = scan.storage.metadata.keys.len();
+    let key_bindings = &scan.bindings[..n_keys];
+    // the keys of collated columns are not in the order of the strings
+    if n_keys == 0
+        || scan.storage.has_collated_keys()
+        || !bindings.starts_with(key_bindings)
+        || !unique_key.iter().all(|k| key_bindings.contains(k))
+    {
+        return false;
+    }
+    if let Some(after) = after {
+        let mut lower = after[..n_keys].to_vec();
+        lower.push(DataValue::Bot);
+        let lower = lower.encode_as_key(scan.storage.id);
+        let (_, upper) = scan.storage.key_range();
+        scan.key_range = Some((lower, upper));
+    }
+    true
+}
@@ -0,0 +1,272 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeSet;
+use std::mem;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::aggr::parse_aggr;
+use crate::data::expr::Expr;
+use crate::data::functions::{OP_ASSERT, OP_COALESCE, OP_EQ, OP_FIRST, OP_IS_NULL, OP_LENGTH};
+use crate::data::program::{
+    InputAtom, InputInlineRule, InputRuleApplyAtom, InputSubquery, Unification,
+};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+
+/// The variables bound by the applications and unifications at the top level of the body
+fn bound_by_body(body: &[InputAtom], coll: &mut BTreeSet<Symbol>) {
+    for atom in body {
+        match atom {
+            InputAtom::Rule { inner } => {
+                coll.extend(inner.args.iter().filter_map(|a| a.get_binding().cloned()))
+            }
+            InputAtom::Relation { inner } => {
+                coll.extend(inner.args.iter().filter_map(|a| a.get_binding().cloned()))
+            }
+            InputAtom::NamedFieldRelation { inner } => {
+                coll.extend(inner.args.values().filter_map(|a| a.get_binding().cloned()))
+            }
+            InputAtom::Unification { inner } => {
+                coll.insert(inner.binding.clone());
+            }
+            InputAtom::Conjunction { inner, .. } => bound_by_body(inner, coll),
+            _ => {}
+        }
+    }
+}
+
+impl InputInlineRule {
+    /// Replace the subqueries within the expressions of the body by their values,
+    /// lifting each of them into a rule of its own, named after `rule_name`.
+    ///
+    /// The lifted rule is grouped by the variables the subquery shares with the rest of the body,
+    /// and joined back with an optional atom. If the subquery does not bind all of the shared
+    /// variables itself, it is restricted to the values they take in the rest of the body,
+    /// leaving out the atoms depending on the values of the subqueries.
+    /// Subqueries nested in the lifted rules are left in them, to be lifted in turn.
+    pub(crate) fn lift_subqueries(
+        mut self,
+        rule_name: &Symbol,
+        lifted: &mut Vec<(Symbol, InputInlineRule)>,
+    ) -> Result<Self> {
+        let subqueries = mem::take(&mut self.subqueries);
+        // the values of the subqueries, and the variables computed from them
+        let mut dependent: BTreeSet<_> = subqueries.iter().map(|sq| sq.var.clone()).collect();
+        loop {
+            let n_dependent = dependent.len();
+            for atom in self.body.iter() {
+                if let InputAtom::Unification { inner } = atom {
+                    if !inner.expr.bindings().is_disjoint(&dependent) {
+                        dependent.insert(inner.binding.clone());
+                    }
+                }
+            }
+            if dependent.len() == n_dependent {
+                break;
+            }
+        }
+        let mut outer_vars = BTreeSet::new();
+        let mut independent = vec![];
+        for atom in self.body.iter() {
+            let mut vars = BTreeSet::new();
+            atom.collect_bindings(&mut vars);
+            if vars.is_disjoint(&dependent) {
+                independent.push(atom.clone());
+            }
+            outer_vars.extend(vars);
+        }
+
+        for subquery in subqueries {
+            let mut sub_vars = BTreeSet::new();
+            subquery.collect_bindings(&mut sub_vars);
+            let InputSubquery {
+                var,
+                value,
+                body: mut sub_body,
+                subqueries: nested,
+                span,
+            } = subquery;
+            let name = Symbol::new(format!("{}{}", rule_name.name, var.name), span);
+            // the output of a scalar subquery and generated variables are local to their rules
+            let output = value.as_ref().map(|(h, _)| h);
+            let correlated = sub_vars
+                .intersection(&outer_vars)
+                .filter(|v| !v.name.starts_with('*') && Some(*v) != output)
+                .cloned()
+                .collect_vec();
+            let as_args = |vars: &[Symbol]| {
+                vars.iter()
+                    .map(|v| Expr::Binding {
+                        var: v.clone(),
+                        tuple_pos: None,
+                    })
+                    .collect_vec()
+            };
+
+            let mut bound = BTreeSet::new();
+            bound_by_body(&sub_body, &mut bound);
+            if correlated.iter().any(|v| !bound.contains(v)) {
+                let domain = Symbol::new(format!("{}.domain", name.name), span);
+                lifted.push((
+                    domain.clone(),
+                    InputInlineRule {
+                        head: correlated.clone(),
+                        aggr: vec![None; correlated.len()],
+                        body: independent.clone(),
+                        subqueries: vec![],
+                        span,
+                    },
+                ));
+                sub_body.push(InputAtom::Rule {
+                    inner: InputRuleApplyAtom {
+                        name: domain,
+                        args: as_args(&correlated),
+                        span,
+                    },
+                });
+            }
+
+            let found = Symbol::new(format!("{}.value", var.name), span);
+            let found_expr = Expr::Binding {
+                var: found.clone(),
+                tuple_pos: None,
+            };
+            let mut head = correlated.clone();
+            let mut aggr = vec![None; correlated.len()];
+            let value_expr = match value {
+                None => {
+                    // whether a row is found for the shared variables
+                    let flag = Symbol::new("*found", span);
+                    sub_body.push(InputAtom::Unification {
+                        inner: Unification {
+                            binding: flag.clone(),
+                            expr: Expr::Const {
+                                val: DataValue::from(true),
+                                span,
+                            },
+                            one_many_unif: false,
+                            span,
+                        },
+                    });
+                    head.push(flag);
+                    aggr.push(None);
+                    Expr::Apply {
+                        op: &OP_COALESCE,
+                        args: Box::new([
+                            found_expr,
+                            Expr::Const {
+                                val: DataValue::from(false),
+                                span,
+                            },
+                        ]),
+                        span,
+                    }
+                }
+                Some((var, Some((aggregation, args)))) => {
+                    // the value of the aggregation over no rows at all
+                    let mut empty = aggregation.clone();
+                    empty.normal_init(&args)?;
+                    let empty_val = empty
+                        .normal_op
+                        .as_ref()
+                        .and_then(|op| op.get().ok())
+                        .unwrap_or(DataValue::Null);
+                    head.push(var);
+                    aggr.push(Some((aggregation, args)));
+                    Expr::Apply {
+                        op: &OP_COALESCE,
+                        args: Box::new([
+                            found_expr,
+                            Expr::Const {
+                                val: empty_val,
+                                span,
+                            },
+                        ]),
+                        span,
+                    }
+                }
+                Some((var, None)) => {
+                    // collect the values to check that there is at most one of them
+                    head.push(var);
+                    aggr.push(Some((parse_aggr("collect").unwrap().clone(), vec![])));
+                    let apply = |op, args: Vec<Expr>| Expr::Apply {
+                        op,
+                        args: args.into(),
+                        span,
+                    };
+                    let const_expr = |val| Expr::Const { val, span };
+                    Expr::Cond {
+                        clauses: vec![
+                            (
+                                apply(&OP_IS_NULL, vec![found_expr.clone()]),
+                                const_expr(DataValue::Null),
+                            ),
+                            (
+                                apply(
+                                    &OP_EQ,
+                                    vec![
+                                        apply(&OP_LENGTH, vec![found_expr.clone()]),
+                                        const_expr(DataValue::from(1)),
+                                    ],
+                                ),
+                                apply(&OP_FIRST, vec![found_expr]),
+                            ),
+                            (
+                                const_expr(DataValue::from(true)),
+                                apply(
+                                    &OP_ASSERT,
+                                    vec![
+                                        const_expr(DataValue::from(false)),
+                                        const_expr(DataValue::from(
+                                            "scalar subquery produced more than one row",
+                                        )),
+                                    ],
+                                ),
+                            ),
+                        ],
+                        span,
+                    }
+                }
+            };
+
+            lifted.push((
+                name.clone(),
+                InputInlineRule {
+                    head,
+                    aggr,
+                    body: sub_body,
+                    subqueries: nested,
+                    span,
+                },
+            ));
+            let mut args = as_args(&correlated);
+            args.push(Expr::Binding {
+                var: found,
+                tuple_pos: None,
+            });
+            self.body.push(InputAtom::Optional {
+                inner: Box::new(InputAtom::Rule {
+                    inner: InputRuleApplyAtom { name, args, span },
+                }),
+                span,
+            });
+            self.body.push(InputAtom::Unification {
+                inner: Unification {
+                    binding: var,
+                    expr: value_expr,
+                    one_many_unif: false,
+                    span,
+                },
+            });
+        }
+        Ok(self)
+    }
+}
@@ -6,27 +6,211 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-
 use itertools::Itertools;
 use log::{debug, trace};
 use miette::Result;
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-use crate::data::aggr::Aggregation;
+use crate::data::aggr::Aggr;
 use crate::data::program::{MagicSymbol, NoEntryError};
 use crate::data::symb::{Symbol, PROG_ENTRY};
-use crate::data::tuple::Tuple;
+use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::parse::SourceSpan;
 use crate::query::compile::{AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::graph::{strongly_connected_components, Graph};
 use crate::runtime::db::Poison;
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
+use crate::utils::{arith_mode, ArithModeGuard};
+
+/// The states of normal aggregations, by the values of the grouping keys
+type AggrWork = BTreeMap<Vec<DataValue>, Vec<Aggr>>;
+
+/// Combine the groups of `part`, aggregated from another part of the same rows, into `work`.
+#[allow(clippy::mutable_key_type)]
+fn merge_aggr_work(work: &mut AggrWork, part: AggrWork) -> Result<()> {
+    for (keys, part_aggrs) in part {
+        match work.entry(keys) {
+            Entry::Vacant(ent) => {
+                ent.insert(part_aggrs);
+            }
+            Entry::Occupied(mut ent) => {
+                for (aggr, part_aggr) in ent.get_mut().iter_mut().zip(part_aggrs) {
+                    let partial = part_aggr.normal_op.unwrap().partial()?.unwrap();
+                    aggr.normal_op.as_mut().unwrap().merge(&partial)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A unit of semi-naive evaluation: a strongly connected set of rules, to be evaluated
+/// once all the stores that it reads from are complete.
+struct EvalTask {
+    prog: CompiledProgram,
+    /// stores defined by other tasks that are read by this task
+    reads: BTreeSet<MagicSymbol>,
+}
+
+/// Split the stratified program into tasks along the strongly connected components of
+/// the rules in each stratum.
+///
+/// Unlike the strata, tasks only wait for what they actually depend on, so that independent
+/// parts of the program, including fixed rules whose inputs are ready, are evaluated concurrently.
+fn make_eval_tasks(strata: Vec<CompiledProgram>) -> Result<Vec<EvalTask>> {
+    let mut tasks = vec![];
+    for mut prog in strata {
+        let graph: Graph<MagicSymbol> = prog
+            .iter()
+            .map(|(k, rule_set)| {
+                let deps = rule_set
+                    .dependencies()
+                    .into_iter()
+                    .filter(|dep| prog.contains_key(dep))
+                    .cloned()
+                    .collect_vec();
+                (k.clone(), deps)
+            })
+            .collect();
+        for scc in strongly_connected_components(&graph)? {
+            let mut task_prog = CompiledProgram::new();
+            for name in scc {
+                let rule_set = prog.remove(name).unwrap();
+                task_prog.insert(name.clone(), rule_set);
+            }
+            let reads = task_prog
+                .values()
+                .flat_map(|rule_set| rule_set.dependencies())
+                .filter(|dep| !task_prog.contains_key(dep))
+                .cloned()
+                .collect();
+            tasks.push(EvalTask {
+                prog: task_prog,
+                reads,
+            });
+        }
+    }
+    Ok(tasks)
+}
+
+/// Book-keeping for running the tasks in dependency order.
+struct TaskScheduler {
+    tasks: Vec<Option<EvalTask>>,
+    /// for each task, the number of tasks it still waits for
+    waiting_for: Vec<usize>,
+    dependents: Vec<Vec<usize>>,
+    /// for each store, the number of tasks that still need to read it
+    remaining_readers: BTreeMap<MagicSymbol, usize>,
+    completed: BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    early_return: bool,
+    error: Option<miette::Report>,
+}
+
+impl TaskScheduler {
+    /// The stores in `keep` are kept once complete, as if read by a task that never runs.
+    fn new(tasks: Vec<EvalTask>, keep: &BTreeSet<MagicSymbol>) -> Self {
+        let mut owners = BTreeMap::new();
+        for (idx, task) in tasks.iter().enumerate() {
+            for name in task.prog.keys() {
+                owners.insert(name.clone(), idx);
+            }
+        }
+        let mut waiting_for = vec![0; tasks.len()];
+        let mut dependents = vec![vec![]; tasks.len()];
+        let mut remaining_readers: BTreeMap<MagicSymbol, usize> =
+            keep.iter().map(|name| (name.clone(), 1)).collect();
+        for (idx, task) in tasks.iter().enumerate() {
+            let mut deps = BTreeSet::new();
+            for name in &task.reads {
+                *remaining_readers.entry(name.clone()).or_default() += 1;
+                if let Some(owner) = owners.get(name) {
+                    deps.insert(*owner);
+                }
+            }
+            waiting_for[idx] = deps.len();
+            for dep in deps {
+                dependents[dep].push(idx);
+            }
+        }
+        Self {
+            tasks: tasks.into_iter().map(Some).collect(),
+            waiting_for,
+            dependents,
+            remaining_readers,
+            completed: Default::default(),
+            early_return: false,
+            error: None,
+        }
+    }
+    fn initially_ready(&self) -> Vec<usize> {
+        (0..self.tasks.len())
+            .filter(|idx| self.waiting_for[*idx] == 0)
+            .collect()
+    }
+    /// Take the task out together with the completed stores it reads from.
+    fn start(&mut self, idx: usize) -> Option<(EvalTask, BTreeMap<MagicSymbol, Arc<EpochStore>>)> {
+        if self.error.is_some() {
+            return None;
+        }
+        let task = self.tasks[idx].take().unwrap();
+        let inputs = task
+            .reads
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.completed.get(name)?.clone())))
+            .collect();
+        Some((task, inputs))
+    }
+    /// Record the result of a task, returning the tasks that become ready.
+    fn finish(
+        &mut self,
+        idx: usize,
+        reads: BTreeSet<MagicSymbol>,
+        result: Result<(BTreeMap<MagicSymbol, Arc<EpochStore>>, bool)>,
+    ) -> Vec<usize> {
+        let (stores, early_return) = match result {
+            Ok(r) => r,
+            Err(err) => {
+                if self.error.is_none() {
+                    self.error = Some(err);
+                }
+                return vec![];
+            }
+        };
+        for name in reads {
+            if let Some(n) = self.remaining_readers.get_mut(&name) {
+                *n -= 1;
+                if *n == 0 {
+                    // remove stores that have outlived their usefulness!
+                    self.completed.remove(&name);
+                }
+            }
+        }
+        for (name, store) in stores {
+            if name.is_prog_entry() {
+                self.early_return = early_return;
+                self.completed.insert(name, store);
+            } else if self.remaining_readers.get(&name).cloned().unwrap_or(0) > 0 {
+                self.completed.insert(name, store);
+            }
+        }
+        let mut ready = vec![];
+        for dependent in &self.dependents[idx] {
+            self.waiting_for[*dependent] -= 1;
+            if self.waiting_for[*dependent] == 0 {
+                ready.push(*dependent);
+            }
+        }
+        ready
+    }
+}
 
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
@@ -62,56 +246,145 @@ impl QueryLimiter {
 impl<'a> SessionTx<'a> {
     pub(crate) fn stratified_magic_evaluate(
         &self,
-        strata: &[CompiledProgram],
-        store_lifetimes: BTreeMap<MagicSymbol, usize>,
+        strata: Vec<CompiledProgram>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
     ) -> Result<(EpochStore, bool)> {
-        let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
-        let mut early_return = false;
-        for (stratum, cur_prog) in strata.iter().enumerate() {
-            if stratum > 0 {
-                // remove stores that have outlived their usefulness!
-                stores.retain(|name, _| match store_lifetimes.get(name) {
-                    None => false,
-                    Some(n) => *n >= stratum,
-                });
-                trace!("{:?}", stores);
-            }
-            for (rule_name, rule_set) in cur_prog {
-                let store = match rule_set.aggr_kind() {
-                    AggrKind::None | AggrKind::Normal => EpochStore::new_normal(rule_set.arity()),
-                    AggrKind::Meet => {
-                        let rs = match rule_set {
-                            CompiledRuleSet::Rules(rs) => rs,
-                            _ => unreachable!(),
-                        };
-                        EpochStore::new_meet(&rs[0].aggr)?
-                    }
-                };
-                stores.insert(rule_name.clone(), store);
-            }
-            debug!("stratum {}", stratum);
-            early_return = self.semi_naive_magic_evaluate(
-                cur_prog,
-                &mut stores,
-                total_num_to_take,
-                num_to_skip,
-                poison.clone(),
-            )?;
-        }
+        let mut scheduler = self.run_eval_tasks(
+            strata,
+            &Default::default(),
+            total_num_to_take,
+            num_to_skip,
+            poison,
+        )?;
         let entry_symbol = MagicSymbol::Muggle {
             inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
         };
-        let ret_area = stores.remove(&entry_symbol).ok_or(NoEntryError)?;
-        Ok((ret_area, early_return))
+        let ret_area = scheduler
+            .completed
+            .remove(&entry_symbol)
+            .ok_or(NoEntryError)?;
+        let ret_area =
+            Arc::try_unwrap(ret_area).expect("program logic error: entry store still in use");
+        Ok((ret_area, scheduler.early_return))
+    }
+    /// Evaluate a program without an entry rule, returning the completed stores in `keep`.
+    pub(crate) fn evaluate_stores(
+        &self,
+        strata: Vec<CompiledProgram>,
+        keep: &BTreeSet<MagicSymbol>,
+        poison: Poison,
+    ) -> Result<BTreeMap<MagicSymbol, Arc<EpochStore>>> {
+        let scheduler = self.run_eval_tasks(strata, keep, None, None, poison)?;
+        let mut completed = scheduler.completed;
+        completed.retain(|name, _| keep.contains(name));
+        Ok(completed)
+    }
+    fn run_eval_tasks(
+        &self,
+        strata: Vec<CompiledProgram>,
+        keep: &BTreeSet<MagicSymbol>,
+        total_num_to_take: Option<usize>,
+        num_to_skip: Option<usize>,
+        poison: Poison,
+    ) -> Result<TaskScheduler> {
+        let tasks = make_eval_tasks(strata)?;
+        debug!("{} evaluation tasks", tasks.len());
+        let scheduler = Mutex::new(TaskScheduler::new(tasks, keep));
+        let ready = scheduler.lock().unwrap().initially_ready();
+        // tasks may run on other threads
+        let arith = arith_mode();
+        let run_task = |idx: usize| -> Vec<usize> {
+            let _arith = ArithModeGuard::set(arith);
+            let started = scheduler.lock().unwrap().start(idx);
+            let (task, inputs) = match started {
+                None => return vec![],
+                Some(s) => s,
+            };
+            let result = poison.check().and_then(|_| {
+                self.evaluate_task(
+                    &task.prog,
+                    inputs,
+                    total_num_to_take,
+                    num_to_skip,
+                    poison.clone(),
+                )
+            });
+            let failed = result.is_err();
+            let mut scheduler = scheduler.lock().unwrap();
+            let ready = scheduler.finish(idx, task.reads, result);
+            if failed {
+                // no point in letting the other tasks continue. The error is recorded first,
+                // so that it is not masked by the tasks killed here.
+                poison.0.store(true, Ordering::Relaxed);
+            }
+            ready
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            fn spawn_all<'s, F>(scope: &rayon::Scope<'s>, run_task: &'s F, ready: Vec<usize>)
+            where
+                F: Fn(usize) -> Vec<usize> + Sync,
+            {
+                for idx in ready {
+                    scope.spawn(move |s| spawn_all(s, run_task, run_task(idx)));
+                }
+            }
+            rayon::scope(|s| spawn_all(s, &run_task, ready));
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let mut ready = ready;
+            while let Some(idx) = ready.pop() {
+                ready.extend(run_task(idx));
+            }
+        }
+
+        let mut scheduler = scheduler.into_inner().unwrap();
+        if let Some(err) = scheduler.error.take() {
+            return Err(err);
+        }
+        Ok(scheduler)
+    }
+    fn evaluate_task(
+        &self,
+        prog: &CompiledProgram,
+        mut stores: BTreeMap<MagicSymbol, Arc<EpochStore>>,
+        total_num_to_take: Option<usize>,
+        num_to_skip: Option<usize>,
+        poison: Poison,
+    ) -> Result<(BTreeMap<MagicSymbol, Arc<EpochStore>>, bool)> {
+        for (rule_name, rule_set) in prog {
+            let store = match rule_set.aggr_kind() {
+                AggrKind::None | AggrKind::Normal => EpochStore::new_normal(rule_set.arity()),
+                AggrKind::Meet => {
+                    let rs = match rule_set {
+                        CompiledRuleSet::Rules(rs) => rs,
+                        _ => unreachable!(),
+                    };
+                    EpochStore::new_meet(&rs[0].aggr)?
+                }
+            };
+            stores.insert(rule_name.clone(), Arc::new(store));
+        }
+        let early_return = self.semi_naive_magic_evaluate(
+            prog,
+            &mut stores,
+            total_num_to_take,
+            num_to_skip,
+            poison,
+        )?;
+        trace!("{:?}", stores);
+        stores.retain(|name, _| prog.contains_key(name));
+        Ok((stores, early_return))
     }
     /// returns true if early return is activated
     fn semi_naive_magic_evaluate(
         &self,
         prog: &CompiledProgram,
-        stores: &mut BTreeMap<MagicSymbol, EpochStore>,
+        stores: &mut BTreeMap<MagicSymbol, Arc<EpochStore>>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
@@ -128,9 +401,12 @@ impl<'a> SessionTx<'a> {
             debug!("epoch {}", epoch);
             let mut to_merge = BTreeMap::new();
             let borrowed_stores = stores as &BTreeMap<_, _>;
+            // rules may be evaluated on other threads
+            let arith = arith_mode();
             if epoch == 0 {
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
+                    let _arith = ArithModeGuard::set(arith);
                     let new_store = match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => match compiled_ruleset.aggr_kind() {
                             AggrKind::None => {
@@ -214,6 +490,7 @@ impl<'a> SessionTx<'a> {
             } else {
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
+                    let _arith = ArithModeGuard::set(arith);
                     let new_store = match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => {
                             match compiled_ruleset.aggr_kind() {
@@ -287,7 +564,8 @@ impl<'a> SessionTx<'a> {
             }
             let mut changed = false;
             for (k, new_store) in to_merge {
-                let old_store = stores.get_mut(k).unwrap();
+                let old_store = Arc::get_mut(stores.get_mut(k).unwrap())
+                    .expect("program logic error: store shared during evaluation");
                 old_store.merge_in(new_store)?;
                 trace!("delta for {}: {}", k, old_store.has_delta());
                 changed |= old_store.has_delta();
@@ -298,12 +576,76 @@ impl<'a> SessionTx<'a> {
         }
         Ok(used_limiter.load(Ordering::Acquire))
     }
+    /// Feed the results of the rule body in the initial epoch to `consume`, stopping early
+    /// once it returns false.
+    /// If the planner has split the scan driving the rule, the partitions are evaluated in
+    /// parallel before their rows are fed in order, unless `sequential` is set.
+    fn initial_rule_for_each(
+        &self,
+        rule: &CompiledRule,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
+        sequential: bool,
+        poison: &Poison,
+        mut consume: impl FnMut(Tuple) -> Result<bool>,
+    ) -> Result<()> {
+        if !sequential {
+            if let Some(parts) = self.on_partitions(rule, stores, poison, |rows| {
+                rows.collect::<Result<Vec<_>>>()
+            }) {
+                for tuple in parts?.into_iter().flatten() {
+                    if !consume(tuple)? {
+                        break;
+                    }
+                }
+                return Ok(());
+            }
+        }
+        for tuple in rule.relation.iter(self, None, stores)? {
+            if !consume(tuple?)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+    /// If the planner has split the scan driving the rule, evaluate `f` on the partitions
+    /// in parallel on the thread pool. Used to aggregate each partition before combining
+    /// the results.
+    fn on_partitions<T: Send>(
+        &self,
+        rule: &CompiledRule,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
+        poison: &Poison,
+        f: impl Fn(TupleIter<'_>) -> Result<T> + Sync,
+    ) -> Option<Result<Vec<T>>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if !rule.partitions.is_empty() {
+            let arith = arith_mode();
+            return Some(
+                rule.partitions
+                    .par_iter()
+                    .map(|part| {
+                        let _arith = ArithModeGuard::set(arith);
+                        let poison = poison.clone();
+                        poison.check()?;
+                        let rows = part.iter(self, None, stores)?.map(move |tuple| {
+                            poison.check()?;
+                            tuple
+                        });
+                        f(Box::new(rows))
+                    })
+                    .collect(),
+            );
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = (rule, stores, poison, f);
+        None
+    }
     /// returns true is early return is activated
     fn initial_rule_non_aggr_eval(
         &self,
         rule_symb: &MagicSymbol,
         ruleset: &[CompiledRule],
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
         limiter: &QueryLimiter,
         poison: Poison,
     ) -> Result<(bool, RegularTempStore)> {
@@ -312,8 +654,9 @@ impl<'a> SessionTx<'a> {
 
         for (rule_n, rule) in ruleset.iter().enumerate() {
             debug!("initial calculation for rule {:?}.{}", rule_symb, rule_n);
-            for item_res in rule.relation.iter(self, None, stores)? {
-                let item = item_res?;
+            let mut stopped = false;
+            // with a limit, the rows must come in order
+            self.initial_rule_for_each(rule, stores, should_check_limit, &poison, |item| {
                 trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
                 if should_check_limit {
                     if !out_store.exists(&item) {
@@ -324,12 +667,17 @@ impl<'a> SessionTx<'a> {
                         }
                         if limiter.incr_and_should_stop() {
                             trace!("early stopping due to result count limit exceeded");
-                            return Ok((true, out_store));
+                            stopped = true;
+                            return Ok(false);
                         }
                     }
                 } else {
                     out_store.put(item);
                 }
+                Ok(true)
+            })?;
+            if stopped {
+                return Ok((true, out_store));
             }
             poison.check()?;
         }
@@ -340,7 +688,7 @@ impl<'a> SessionTx<'a> {
         &self,
         rule_symb: &MagicSymbol,
         ruleset: &[CompiledRule],
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
         poison: Poison,
     ) -> Result<MeetAggrStore> {
         let mut out_store = MeetAggrStore::new(ruleset[0].aggr.clone())?;
@@ -351,10 +699,28 @@ impl<'a> SessionTx<'a> {
             for (aggr, args) in aggr.iter_mut().flatten() {
                 aggr.meet_init(args)?;
             }
-            for item_res in rule.relation.iter(self, None, stores)? {
-                let item = item_res?;
-                trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
-                out_store.meet_put(item)?;
+            // the meet operation is associative and commutative: partitions are aggregated
+            // separately and combined
+            let parts = self.on_partitions(rule, stores, &poison, |rows| {
+                let mut part_store = MeetAggrStore::new(ruleset[0].aggr.clone())?;
+                for item in rows {
+                    part_store.meet_put(item?)?;
+                }
+                Ok(part_store)
+            });
+            match parts {
+                Some(parts) => {
+                    for part_store in parts? {
+                        out_store.meet_merge(part_store)?;
+                    }
+                }
+                None => {
+                    self.initial_rule_for_each(rule, stores, false, &poison, |item| {
+                        trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
+                        out_store.meet_put(item)?;
+                        Ok(true)
+                    })?;
+                }
             }
             poison.check()?;
         }
@@ -375,17 +741,18 @@ impl<'a> SessionTx<'a> {
         }
         Ok(out_store)
     }
+    #[allow(clippy::mutable_key_type)]
     fn initial_rule_aggr_eval(
         &self,
         rule_symb: &MagicSymbol,
         ruleset: &[CompiledRule],
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
         limiter: &QueryLimiter,
         poison: Poison,
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
-        let mut aggr_work: BTreeMap<Vec<DataValue>, Vec<Aggregation>> = BTreeMap::new();
+        let mut aggr_work = AggrWork::new();
 
         for (rule_n, rule) in ruleset.iter().enumerate() {
             debug!(
@@ -411,10 +778,7 @@ impl<'a> SessionTx<'a> {
                 .filter_map(|(i, a)| a.as_ref().map(|aggr| (i, aggr.clone())))
                 .collect_vec();
 
-            for item_res in rule.relation.iter(self, None, stores)? {
-                let item = item_res?;
-                trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
-
+            let aggregate = |aggr_work: &mut AggrWork, item: Tuple| -> Result<()> {
                 let keys = extract_keys(&item);
 
                 match aggr_work.entry(keys) {
@@ -439,6 +803,40 @@ impl<'a> SessionTx<'a> {
                         ent.insert(aggr_ops);
                     }
                 }
+                Ok(())
+            };
+
+            // partitions are aggregated separately if the aggregations can be combined
+            let mut mergeable = true;
+            for (_, (aggr, params)) in &val_indices_and_aggrs {
+                let mut aggr = aggr.clone();
+                aggr.normal_init(params)?;
+                mergeable &= aggr.normal_op.unwrap().partial()?.is_some();
+            }
+            let parts = if mergeable {
+                self.on_partitions(rule, stores, &poison, |rows| {
+                    let mut part_work = AggrWork::new();
+                    for item in rows {
+                        aggregate(&mut part_work, item?)?;
+                    }
+                    Ok(part_work)
+                })
+            } else {
+                None
+            };
+            match parts {
+                Some(parts) => {
+                    for part_work in parts? {
+                        merge_aggr_work(&mut aggr_work, part_work)?;
+                    }
+                }
+                None => {
+                    self.initial_rule_for_each(rule, stores, false, &poison, |item| {
+                        trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
+                        aggregate(&mut aggr_work, item)?;
+                        Ok(true)
+                    })?;
+                }
             }
             poison.check()?;
         }
@@ -506,7 +904,7 @@ impl<'a> SessionTx<'a> {
         rule_symb: &MagicSymbol,
         ruleset: &[CompiledRule],
         epoch: u32,
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
         limiter: &QueryLimiter,
         poison: Poison,
     ) -> Result<(bool, RegularTempStore)> {
@@ -570,7 +968,7 @@ impl<'a> SessionTx<'a> {
         &self,
         rule_symb: &MagicSymbol,
         ruleset: &[CompiledRule],
-        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        stores: &BTreeMap<MagicSymbol, Arc<EpochStore>>,
         poison: Poison,
     ) -> Result<MeetAggrStore> {
         let mut out_store = MeetAggrStore::new(ruleset[0].aggr.clone())?;
@@ -606,3 +1004,189 @@ impl<'a> SessionTx<'a> {
         Ok(out_store)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::data::functions::current_validity;
+    use crate::data::program::MagicSymbol;
+    use crate::data::symb::{Symbol, PROG_ENTRY};
+    use crate::data::tuple::TupleT;
+    use crate::data::value::DataValue;
+    use crate::new_cozo_mem;
+    use crate::parse::{parse_script, CozoScript};
+    use crate::query::compile::{partition_driving_scans, CompiledRuleSet};
+    use crate::runtime::relation::RelationId;
+    use crate::storage::Storage;
+
+    #[test]
+    fn test_independent_strata() {
+        let db = new_cozo_mem().unwrap();
+        let res = db
+            .run_script(
+                r#"
+        a[x] <- [[1], [2], [3]]
+        b[x] <- [[2], [10], [20]]
+        ca[count(x)] := a[x]
+        cb[max(x)] := b[x]
+        d[x] := a[x], not b[x]
+        cd[count(x)] := d[x]
+        ?[p, q, r] := ca[p], cb[q], cd[r]
+        "#,
+                Default::default(),
+            )
+            .unwrap()
+            .rows;
+        assert_eq!(
+            res,
+            vec![vec![
+                DataValue::from(3),
+                DataValue::from(20),
+                DataValue::from(2)
+            ]]
+        )
+    }
+
+    #[test]
+    fn test_error_in_independent_task() {
+        let db = new_cozo_mem().unwrap();
+        let res = db.run_script(
+            r#"
+        a[x] <- [[1], [2], [3]]
+        good[count(x)] := a[x]
+        bad[count(y)] := a[x], y = x + 'not a number'
+        ?[p, q] := good[p], bad[q]
+        "#,
+            Default::default(),
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_partitioned_scan() {
+        let db = new_cozo_mem().unwrap();
+        let n = 30000i64;
+        let data = (0..n)
+            .map(|x| DataValue::List(vec![DataValue::from(x), DataValue::from(x % 7)]))
+            .collect();
+        db.run_script(
+            r#"
+        ?[k, v] <- $data
+        :create r {k => v}
+        "#,
+            BTreeMap::from([("data".to_string(), DataValue::List(data))]),
+        )
+        .unwrap();
+
+        let lower = vec![].encode_as_key(RelationId(0));
+        let upper = vec![].encode_as_key(RelationId(u64::MAX));
+        assert!(!db.db.sample_keys(&lower, &upper, 10000).unwrap().is_empty());
+
+        // the number of partitions of the entry rule
+        let num_partitions = |script: &str| {
+            let program = match parse_script(
+                script,
+                &Default::default(),
+                &db.fixed_rules.read().unwrap(),
+                current_validity(),
+            )
+            .unwrap()
+            {
+                CozoScript::Single(p) => p,
+                _ => unreachable!(),
+            };
+            let mut tx = db.transact().unwrap();
+            let mut strata = db.compile_query(&mut tx, program).unwrap().strata;
+            partition_driving_scans(&db.db, &db.key_samples, &mut strata).unwrap();
+            match strata.last().unwrap().get(&MagicSymbol::Muggle {
+                inner: Symbol::new(PROG_ENTRY, Default::default()),
+            }) {
+                Some(CompiledRuleSet::Rules(rules)) => rules[0].partitions.len(),
+                _ => unreachable!(),
+            }
+        };
+        if rayon::current_num_threads() > 1 {
+            assert!(num_partitions("?[count(k)] := *r[k, v]") > 1);
+            assert!(num_partitions("?[k] := *r[k, v], v > 2") > 1);
+        }
+        assert_eq!(num_partitions("?[v] := *r[5, v]"), 0);
+
+        let res = db
+            .run_script(
+                r#"
+        ?[count(k), sum(v)] := *r[k, v], v > 2
+        "#,
+                Default::default(),
+            )
+            .unwrap()
+            .rows;
+        let expected = (0..n).filter(|x| x % 7 > 2);
+        assert_eq!(
+            res,
+            vec![vec![
+                DataValue::from(expected.clone().count() as i64),
+                DataValue::from(expected.map(|x| x % 7).sum::<i64>())
+            ]]
+        );
+
+        let res = db
+            .run_script(
+                r#"
+        ?[k, w] := *r[k, v], w = v * 2, k % 1000 == 999
+        "#,
+                Default::default(),
+            )
+            .unwrap()
+            .rows;
+        let expected = (0..n)
+            .filter(|x| x % 1000 == 999)
+            .map(|x| vec![DataValue::from(x), DataValue::from(x % 7 * 2)])
+            .collect::<Vec<_>>();
+        assert_eq!(res, expected);
+
+        // partitions aggregated separately, for meet aggregations and mergeable ones
+        let res = db
+            .run_script(
+                "?[v, min(k), max(k), count(k), mean(k), unique(w)] := *r[k, v], w = k % 3",
+                Default::default(),
+            )
+            .unwrap()
+            .rows;
+        assert_eq!(res.len(), 7);
+        assert_eq!(
+            res[3],
+            vec![
+                DataValue::from(3),
+                DataValue::from(3),
+                DataValue::from(n - 2),
+                DataValue::from((n - 2 - 3) / 7 + 1),
+                DataValue::from((3 + n - 2) as f64 / 2.),
+                DataValue::List(vec![0, 1, 2].into_iter().map(DataValue::from).collect()),
+            ]
+        );
+        let res = db
+            .run_script("?[min_cost(l)] := *r[k, v], l = [k, v]", Default::default())
+            .unwrap()
+            .rows;
+        assert_eq!(
+            res,
+            vec![vec![DataValue::List(vec![
+                DataValue::from(0),
+                DataValue::from(0)
+            ])]]
+        );
+        // aggregations that cannot be merged see all the rows
+        let res = db
+            .run_script("?[median(k)] := *r[k, v]", Default::default())
+            .unwrap()
+            .rows;
+        assert_eq!(res, vec![vec![DataValue::from((n - 1) as f64 / 2.)]]);
+        // with a limit, the rows are not streamed from partitions
+        let res = db
+            .run_script("?[k] := *r[k, v] :limit 3", Default::default())
+            .unwrap()
+            .rows;
+        assert_eq!(res.len(), 3);
+    }
+}
@@ -78,7 +78,7 @@ impl InputAtom {
                     .try_collect()?,
                 span,
             },
-            InputAtom::Unification { inner: unif } => InputAtom::Unification { inner: unif },
+            a @ (InputAtom::Unification { .. } | InputAtom::Optional { .. }) => a,
             InputAtom::Negation { inner: arg, span } => match *arg {
                 a @ (InputAtom::Rule { .. }
                 | InputAtom::NamedFieldRelation { .. }
@@ -121,6 +121,9 @@ impl InputAtom {
                 InputAtom::Unification { inner } => {
                     bail!(UnsafeNegation(inner.span))
                 }
+                InputAtom::Optional { span, .. } => {
+                    bail!(NegatedOptional(span))
+                }
             },
         })
     }
@@ -222,6 +225,33 @@ impl InputAtom {
                 }
                 _ => unreachable!(),
             },
+            InputAtom::Optional { inner: o, .. } => {
+                let mut normalized = match *o {
+                    InputAtom::Rule { inner: r } => {
+                        check_optional_args(r.args.iter())?;
+                        r.normalize(false, gen)
+                    }
+                    InputAtom::Relation { inner: v } => {
+                        check_optional_args(v.args.iter())?;
+                        v.normalize(false, gen)
+                    }
+                    InputAtom::NamedFieldRelation { inner } => {
+                        check_optional_args(inner.args.values())?;
+                        let r = Self::convert_named_field_relation(inner, gen, tx)?;
+                        r.normalize(false, gen)
+                    }
+                    _ => unreachable!(),
+                };
+                // the application comes last, after the unifications of its arguments
+                let atoms = &mut normalized.inner[0].0;
+                let app = match atoms.pop() {
+                    Some(NormalFormAtom::Rule(r)) => NormalFormAtom::OptionalRule(r),
+                    Some(NormalFormAtom::Relation(v)) => NormalFormAtom::OptionalRelation(v),
+                    _ => unreachable!(),
+                };
+                atoms.push(app);
+                normalized
+            }
             InputAtom::Unification { inner: u } => {
                 Disjunction::singlet(NormalFormAtom::Unification(u))
             }
@@ -229,6 +259,21 @@ impl InputAtom {
     }
 }
 
+/// Variables bound by an optional atom may be null, so they cannot be required to be
+/// equal to each other within the atom.
+fn check_optional_args<'a>(args: impl Iterator<Item = &'a Expr>) -> Result<()> {
+    let mut seen_variables = BTreeSet::new();
+    for arg in args {
+        if let Expr::Binding { var, .. } = arg {
+            ensure!(
+                var.is_ignored_symbol() || seen_variables.insert(var),
+                RepeatedVariableInOptional(var.to_string(), var.span)
+            );
+        }
+    }
+    Ok(())
+}
+
 impl InputRuleApplyAtom {
     fn normalize(self, is_negated: bool, gen: &mut TempSymbGen) -> Disjunction {
         let mut ret = Vec::with_capacity(self.args.len() + 1);
@@ -359,3 +404,14 @@ pub(crate) struct NamedFieldNotFound(
     pub(crate) String,
     #[label] pub(crate) SourceSpan,
 );
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Optional atoms cannot be negated")]
+#[diagnostic(code(eval::negated_optional))]
+pub(crate) struct NegatedOptional(#[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Variable '{0}' occurs more than once in an optional atom")]
+#[diagnostic(code(eval::repeated_var_in_optional))]
+#[diagnostic(help("Use a different variable and compare the two in a later atom"))]
+pub(crate) struct RepeatedVariableInOptional(String, #[label] SourceSpan);
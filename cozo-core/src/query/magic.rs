@@ -168,6 +168,14 @@ fn magic_rewrite_ruleset(
                     seen_bindings.extend(v.args.iter().cloned());
                     collected_atoms.push(MagicAtom::Relation(v));
                 }
+                MagicAtom::OptionalRule(r) => {
+                    seen_bindings.extend(r.args.iter().cloned());
+                    collected_atoms.push(MagicAtom::OptionalRule(r));
+                }
+                MagicAtom::OptionalRelation(v) => {
+                    seen_bindings.extend(v.args.iter().cloned());
+                    collected_atoms.push(MagicAtom::OptionalRelation(v));
+                }
                 MagicAtom::Unification(u) => {
                     seen_bindings.insert(u.binding.clone());
                     collected_atoms.push(MagicAtom::Unification(u));
@@ -264,7 +272,8 @@ impl NormalFormProgram {
                         for atom in rule.body.iter() {
                             match atom {
                                 NormalFormAtom::Rule(r_app)
-                                | NormalFormAtom::NegatedRule(r_app) => {
+                                | NormalFormAtom::NegatedRule(r_app)
+                                | NormalFormAtom::OptionalRule(r_app) => {
                                     if !own_rules.contains(&r_app.name) {
                                         downstream_rules.insert(r_app.name.clone());
                                     }
@@ -564,6 +573,26 @@ impl NormalFormAtom {
                     span: nv.span,
                 })
             }
+            NormalFormAtom::OptionalRule(r) => {
+                // like negation, the optional rule is computed in full in an earlier stratum
+                seen_bindings.extend(r.args.iter().cloned());
+                MagicAtom::OptionalRule(MagicRuleApplyAtom {
+                    name: MagicSymbol::Muggle {
+                        inner: r.name.clone(),
+                    },
+                    args: r.args.clone(),
+                    span: r.span,
+                })
+            }
+            NormalFormAtom::OptionalRelation(v) => {
+                seen_bindings.extend(v.args.iter().cloned());
+                MagicAtom::OptionalRelation(MagicRelationApplyAtom {
+                    name: v.name.clone(),
+                    args: v.args.clone(),
+                    valid_at: v.valid_at,
+                    span: v.span,
+                })
+            }
             NormalFormAtom::Unification(u) => {
                 seen_bindings.insert(u.binding.clone());
                 MagicAtom::Unification(u.clone())
@@ -7,6 +7,8 @@
  */
 
 pub(crate) mod compile;
+pub(crate) mod cursor;
+pub(crate) mod decorrelate;
 pub(crate) mod eval;
 pub(crate) mod graph;
 pub(crate) mod logical;
@@ -9,6 +9,7 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
 use std::iter;
+use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
@@ -16,11 +17,16 @@ use log::{debug, error};
 use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
-use crate::data::expr::{compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr};
+use crate::data::expr::{
+    bind_params_in_bytecode, compute_bounds, eval_bytecode, eval_bytecode_pred,
+    eval_bytecode_pred_batch, is_vectorizable, Bytecode, Expr,
+};
 use crate::data::program::MagicSymbol;
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
-use crate::data::tuple::{Tuple, TupleIter};
+use crate::data::tuple::{
+    batch_tuples, chunk_tuples, unbatch_tuples, BatchIter, Tuple, TupleBatch, TupleIter,
+};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
 use crate::runtime::relation::RelationHandle;
@@ -28,6 +34,7 @@ use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
 use crate::utils::swap_option_result;
 
+#[derive(Clone)]
 pub(crate) enum RelAlgebra {
     Fixed(InlineFixedRA),
     TempStore(TempStoreRA),
@@ -35,6 +42,7 @@ pub(crate) enum RelAlgebra {
     StoredWithValidity(StoredWithValidityRA),
     Join(Box<InnerJoin>),
     NegJoin(Box<NegJoin>),
+    OuterJoin(Box<OuterJoin>),
     Reorder(ReorderRA),
     Filter(FilteredRA),
     Unification(UnificationRA),
@@ -48,6 +56,7 @@ impl RelAlgebra {
             RelAlgebra::Stored(i) => i.span,
             RelAlgebra::Join(i) => i.span,
             RelAlgebra::NegJoin(i) => i.span,
+            RelAlgebra::OuterJoin(i) => i.span,
             RelAlgebra::Reorder(i) => i.relation.span(),
             RelAlgebra::Filter(i) => i.span,
             RelAlgebra::Unification(i) => i.span,
@@ -56,6 +65,7 @@ impl RelAlgebra {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct UnificationRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) binding: Symbol,
@@ -117,7 +127,7 @@ impl UnificationRA {
         &'a self,
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         let mut bindings = self.parent.bindings_after_eliminate();
         bindings.push(self.binding.clone());
@@ -169,6 +179,7 @@ impl UnificationRA {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct FilteredRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) filters: Vec<Expr>,
@@ -210,7 +221,7 @@ impl FilteredRA {
         &'a self,
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         let bindings = self.parent.bindings_after_eliminate();
         let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
@@ -234,6 +245,40 @@ impl FilteredRA {
                 }),
         ))
     }
+    fn batch_iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<BatchIter<'a>> {
+        let bindings = self.parent.bindings_after_eliminate();
+        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        let mut stack = vec![];
+        Ok(Box::new(
+            self.parent
+                .batch_iter(tx, delta_rule, stores)?
+                .filter_map(move |batch| {
+                    let mut batch = match batch {
+                        Ok(b) => b,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    for (p, span) in self.filters_bytecodes.iter() {
+                        if batch.is_empty() {
+                            return None;
+                        }
+                        match eval_bytecode_pred_batch(p, &batch, &mut stack, *span) {
+                            Ok(mask) => batch.retain_rows(&mask),
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                    if batch.is_empty() {
+                        return None;
+                    }
+                    batch.remove_columns(&eliminate_indices);
+                    Some(Ok(batch))
+                }),
+        ))
+    }
 }
 
 struct BindingFormatter(Vec<Symbol>);
@@ -302,6 +347,13 @@ impl Debug for RelAlgebra {
                 .field(&r.left)
                 .field(&r.right)
                 .finish(),
+            RelAlgebra::OuterJoin(r) => f
+                .debug_tuple("OuterJoin")
+                .field(&bindings)
+                .field(&r.joiner)
+                .field(&r.left)
+                .field(&r.right)
+                .finish(),
             RelAlgebra::Reorder(r) => f
                 .debug_tuple("Reorder")
                 .field(&r.new_order)
@@ -363,6 +415,10 @@ impl RelAlgebra {
                 r.left.fill_binding_indices_and_compile()?;
                 r.right.fill_binding_indices_and_compile()?;
             }
+            RelAlgebra::OuterJoin(r) => {
+                r.left.fill_binding_indices_and_compile()?;
+                r.right.fill_binding_indices_and_compile()?;
+            }
         }
         Ok(())
     }
@@ -376,6 +432,153 @@ impl RelAlgebra {
             false
         }
     }
+    /// The stored relation whose full scan drives the evaluation, if any.
+    /// Every result is derived from exactly one tuple of this scan,
+    /// so results over a partition of its keys partition the results.
+    pub(crate) fn driving_scan(&mut self) -> Option<&mut StoredRA> {
+        match self {
+            RelAlgebra::Stored(s) => Some(s),
+            RelAlgebra::Filter(r) => r.parent.driving_scan(),
+            RelAlgebra::Reorder(r) => r.relation.driving_scan(),
+            RelAlgebra::Unification(r) => r.parent.driving_scan(),
+            RelAlgebra::NegJoin(j) => j.left.driving_scan(),
+            RelAlgebra::OuterJoin(j) => j.left.driving_scan(),
+            RelAlgebra::Join(j) => {
+                if j.left.is_unit() {
+                    if let RelAlgebra::Stored(s) = &mut j.right {
+                        return Some(s);
+                    }
+                }
+                j.left.driving_scan()
+            }
+            _ => None,
+        }
+    }
+    /// Copies of the relation, each restricting its driving scan to the keys between
+    /// consecutive `bounds`.
+    pub(crate) fn partition(&self, bounds: &[Vec<u8>]) -> Vec<RelAlgebra> {
+        bounds
+            .iter()
+            .tuple_windows()
+            .map(|(lower, upper)| {
+                let mut part = self.clone();
+                if let Some(scan) = part.driving_scan() {
+                    scan.key_range = Some((lower.clone(), upper.clone()));
+                }
+                part
+            })
+            .collect()
+    }
+    /// Replace the parameter placeholders in the expressions of the tree.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) {
+        fn bind_filters(
+            filters: &mut [Expr],
+            filters_bytecodes: &mut [(Vec<Bytecode>, SourceSpan)],
+            params: &BTreeMap<String, DataValue>,
+        ) {
+            for filter in filters {
+                filter.bind_params(params);
+            }
+            for (bytecodes, _) in filters_bytecodes {
+                bind_params_in_bytecode(bytecodes, params);
+            }
+        }
+
+        match self {
+            RelAlgebra::Fixed(_) => {}
+            RelAlgebra::TempStore(r) => {
+                bind_filters(&mut r.filters, &mut r.filters_bytecodes, params)
+            }
+            RelAlgebra::Stored(r) => bind_filters(&mut r.filters, &mut r.filters_bytecodes, params),
+            RelAlgebra::StoredWithValidity(r) => {
+                bind_filters(&mut r.filters, &mut r.filters_bytecodes, params)
+            }
+            RelAlgebra::Join(j) => {
+                j.left.bind_params(params);
+                j.right.bind_params(params);
+            }
+            RelAlgebra::NegJoin(j) => {
+                j.left.bind_params(params);
+                j.right.bind_params(params);
+            }
+            RelAlgebra::OuterJoin(j) => {
+                j.left.bind_params(params);
+                j.right.bind_params(params);
+            }
+            RelAlgebra::Reorder(r) => r.relation.bind_params(params),
+            RelAlgebra::Filter(r) => {
+                r.parent.bind_params(params);
+                bind_filters(&mut r.filters, &mut r.filters_bytecodes, params)
+            }
+            RelAlgebra::Unification(r) => {
+                r.parent.bind_params(params);
+                r.expr.bind_params(params);
+                bind_params_in_bytecode(&mut r.expr_bytecode, params);
+            }
+        }
+    }
+    /// Collect the stored relations scanned by the tree, each with whether the scan
+    /// is time travelling.
+    pub(crate) fn collect_stored_scans<'a>(&'a self, coll: &mut Vec<(&'a RelationHandle, bool)>) {
+        match self {
+            RelAlgebra::Fixed(_) | RelAlgebra::TempStore(_) => {}
+            RelAlgebra::Stored(r) => coll.push((&r.storage, false)),
+            RelAlgebra::StoredWithValidity(r) => coll.push((&r.storage, true)),
+            RelAlgebra::Join(j) => {
+                j.left.collect_stored_scans(coll);
+                j.right.collect_stored_scans(coll);
+            }
+            RelAlgebra::NegJoin(j) => {
+                j.left.collect_stored_scans(coll);
+                j.right.collect_stored_scans(coll);
+            }
+            RelAlgebra::OuterJoin(j) => {
+                j.left.collect_stored_scans(coll);
+                j.right.collect_stored_scans(coll);
+            }
+            RelAlgebra::Reorder(r) => r.relation.collect_stored_scans(coll),
+            RelAlgebra::Filter(r) => r.parent.collect_stored_scans(coll),
+            RelAlgebra::Unification(r) => r.parent.collect_stored_scans(coll),
+        }
+    }
+    /// A set of output bindings whose values determine the whole output tuple, or `None`
+    /// if the tree cannot be shown to produce distinct tuples.
+    pub(crate) fn unique_key(&self) -> Option<BTreeSet<Symbol>> {
+        let key: BTreeSet<Symbol> = match self {
+            RelAlgebra::Fixed(r) => {
+                if r.data.len() > 1 {
+                    return None;
+                }
+                BTreeSet::new()
+            }
+            RelAlgebra::TempStore(r) => r.bindings.iter().cloned().collect(),
+            RelAlgebra::Stored(r) => r.bindings[..r.storage.metadata.keys.len()]
+                .iter()
+                .cloned()
+                .collect(),
+            RelAlgebra::StoredWithValidity(r) => r.bindings[..r.storage.metadata.keys.len()]
+                .iter()
+                .cloned()
+                .collect(),
+            RelAlgebra::Join(j) => j.joiner.joined_unique_key(&j.left, &j.right)?,
+            // a row padded with nulls is only produced for a left row without matches
+            RelAlgebra::OuterJoin(j) => j.joiner.joined_unique_key(&j.left, &j.right)?,
+            RelAlgebra::NegJoin(j) => j.left.unique_key()?,
+            RelAlgebra::Reorder(r) => r.relation.unique_key()?,
+            RelAlgebra::Filter(r) => r.parent.unique_key()?,
+            RelAlgebra::Unification(u) => {
+                // a list may contain duplicates
+                if u.is_multi {
+                    return None;
+                }
+                u.parent.unique_key()?
+            }
+        };
+        match self.eliminate_set() {
+            Some(eliminated) if !key.is_disjoint(eliminated) => None,
+            _ => Some(key),
+        }
+    }
     pub(crate) fn cartesian_join(self, right: RelAlgebra, span: SourceSpan) -> Self {
         self.join(right, vec![], vec![], span)
     }
@@ -404,6 +607,7 @@ impl RelAlgebra {
                 storage,
                 filters: vec![],
                 filters_bytecodes: vec![],
+                key_range: None,
                 span,
             })),
             Some(vld) => {
@@ -437,6 +641,7 @@ impl RelAlgebra {
             s @ (RelAlgebra::Fixed(_)
             | RelAlgebra::Reorder(_)
             | RelAlgebra::NegJoin(_)
+            | RelAlgebra::OuterJoin(_)
             | RelAlgebra::Unification(_)) => {
                 let span = filter.span();
                 RelAlgebra::Filter(FilteredRA {
@@ -484,6 +689,7 @@ impl RelAlgebra {
                 storage,
                 mut filters,
                 filters_bytecodes,
+                key_range,
                 span,
             }) => {
                 filters.push(filter);
@@ -492,6 +698,7 @@ impl RelAlgebra {
                     storage,
                     filters,
                     filters_bytecodes,
+                    key_range,
                     span,
                 })
             }
@@ -614,9 +821,27 @@ impl RelAlgebra {
             span,
         }))
     }
+    pub(crate) fn outer_join(
+        self,
+        right: RelAlgebra,
+        left_keys: Vec<Symbol>,
+        right_keys: Vec<Symbol>,
+        span: SourceSpan,
+    ) -> Self {
+        RelAlgebra::OuterJoin(Box::new(OuterJoin {
+            left: self,
+            right,
+            joiner: Joiner {
+                left_keys,
+                right_keys,
+            },
+            to_eliminate: Default::default(),
+            span,
+        }))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct ReorderRA {
     pub(crate) relation: Box<RelAlgebra>,
     pub(crate) new_order: Vec<Symbol>,
@@ -626,27 +851,29 @@ impl ReorderRA {
     fn bindings(&self) -> Vec<Symbol> {
         self.new_order.clone()
     }
-    fn iter<'a>(
-        &'a self,
-        tx: &'a SessionTx<'_>,
-        delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
-    ) -> Result<TupleIter<'a>> {
+    fn reorder_indices(&self) -> Vec<usize> {
         let old_order = self.relation.bindings_after_eliminate();
         let old_order_indices: BTreeMap<_, _> = old_order
             .into_iter()
             .enumerate()
             .map(|(k, v)| (v, k))
             .collect();
-        let reorder_indices = self
-            .new_order
+        self.new_order
             .iter()
             .map(|k| {
                 *old_order_indices
                     .get(k)
                     .expect("program logic error: reorder indices mismatch")
             })
-            .collect_vec();
+            .collect_vec()
+    }
+    fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<TupleIter<'a>> {
+        let reorder_indices = self.reorder_indices();
         Ok(Box::new(
             self.relation
                 .iter(tx, delta_rule, stores)?
@@ -660,9 +887,22 @@ impl ReorderRA {
                 }),
         ))
     }
+    fn batch_iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<BatchIter<'a>> {
+        let reorder_indices = self.reorder_indices();
+        Ok(Box::new(
+            self.relation
+                .batch_iter(tx, delta_rule, stores)?
+                .map_ok(move |batch| batch.project(&reorder_indices)),
+        ))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct InlineFixedRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) data: Vec<Vec<DataValue>>,
@@ -759,6 +999,56 @@ impl InlineFixedRA {
     }
 }
 
+impl InlineFixedRA {
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn batch_join<'a>(
+        &'a self,
+        left_iter: BatchIter<'a>,
+        (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
+        eliminate_indices: BTreeSet<usize>,
+    ) -> Result<BatchIter<'a>> {
+        if self.data.is_empty() {
+            return Ok(Box::new(iter::empty()));
+        }
+        let right_width = self.data[0].len();
+        let mut right_mapping: BTreeMap<Vec<&DataValue>, Vec<&Tuple>> = BTreeMap::new();
+        for data in &self.data {
+            let right_join_values = right_join_indices.iter().map(|v| &data[*v]).collect_vec();
+            right_mapping
+                .entry(right_join_values)
+                .or_default()
+                .push(data);
+        }
+        Ok(Box::new(
+            left_iter
+                .map_ok(move |batch| {
+                    let left_width = batch.width();
+                    let mut ret = TupleBatch::with_width(left_width + right_width);
+                    let mut left_join_values = Vec::with_capacity(left_join_indices.len());
+                    for i in 0..batch.len {
+                        left_join_values.clear();
+                        left_join_values
+                            .extend(left_join_indices.iter().map(|c| &batch.columns[*c][i]));
+                        if let Some(found) = right_mapping.get(&left_join_values) {
+                            for right_values in found {
+                                for (c, col) in batch.columns.iter().enumerate() {
+                                    ret.columns[c].push(col[i].clone());
+                                }
+                                for (c, val) in right_values.iter().enumerate() {
+                                    ret.columns[left_width + c].push(val.clone());
+                                }
+                                ret.len += 1;
+                            }
+                        }
+                    }
+                    ret.remove_columns(&eliminate_indices);
+                    ret
+                })
+                .filter(|batch| !matches!(batch, Ok(b) if b.is_empty())),
+        ))
+    }
+}
+
 pub(crate) fn flatten_err<T, E1: Into<miette::Error>, E2: Into<miette::Error>>(
     v: std::result::Result<std::result::Result<T, E2>, E1>,
 ) -> Result<T> {
@@ -812,16 +1102,18 @@ fn get_eliminate_indices(bindings: &[Symbol], eliminate: &BTreeSet<Symbol>) -> B
         .collect::<BTreeSet<_>>()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct StoredRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage: RelationHandle,
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
+    /// Restricts a full scan to part of the relation, set when the scan is partitioned.
+    pub(crate) key_range: Option<(Vec<u8>, Vec<u8>)>,
     pub(crate) span: SourceSpan,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct StoredWithValidityRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage: RelationHandle,
@@ -968,68 +1260,53 @@ impl StoredRA {
         let val_len = self.storage.metadata.non_keys.len();
         let all_right_val_indices: BTreeSet<usize> =
             (0..val_len).map(|i| left_tuple_len + key_len + i).collect();
+        let only_check_existence =
+            self.filters.is_empty() && eliminate_indices.is_superset(&all_right_val_indices);
         let mut stack = vec![];
-        if self.filters.is_empty() && eliminate_indices.is_superset(&all_right_val_indices) {
-            let it = left_iter
-                .map_ok(move |tuple| -> Result<Option<Tuple>> {
-                    let prefix = left_to_prefix_indices
-                        .iter()
-                        .map(|i| tuple[*i].clone())
-                        .collect_vec();
-                    let key = &prefix[0..key_len];
-                    for (p, span) in self.filters_bytecodes.iter() {
-                        if !eval_bytecode_pred(p, key, &mut stack, *span)? {
-                            return Ok(None);
-                        }
-                    }
-                    if self.storage.exists(tx, key)? {
-                        let mut ret = tuple;
-                        ret.extend_from_slice(key);
+        // the left tuples are looked up a chunk at a time, to save round trips to the storage
+        let it = chunk_tuples(left_iter)
+            .map(move |chunk| -> Result<Vec<Tuple>> {
+                let chunk = chunk?;
+                let keys = chunk
+                    .iter()
+                    .map(|tuple| {
+                        left_to_prefix_indices[0..key_len]
+                            .iter()
+                            .map(|i| tuple[*i].clone())
+                            .collect_vec()
+                    })
+                    .collect_vec();
+                let found = self.storage.multi_get(tx, &keys)?;
+                let mut ret = Vec::with_capacity(chunk.len());
+                'outer: for ((tuple, key), found) in chunk.into_iter().zip(keys).zip(found) {
+                    let found = match found {
+                        None => continue,
+                        Some(found) => found,
+                    };
+                    let mut joined = tuple;
+                    if only_check_existence {
+                        joined.extend(key);
                         for _ in 0..val_len {
-                            ret.push(DataValue::Bot);
+                            joined.push(DataValue::Bot);
                         }
-                        Ok(Some(ret))
                     } else {
-                        Ok(None)
-                    }
-                })
-                .flatten_ok()
-                .filter_map(invert_option_err);
-            Ok(if eliminate_indices.is_empty() {
-                Box::new(it)
-            } else {
-                Box::new(it.map_ok(move |t| eliminate_from_tuple(t, &eliminate_indices)))
-            })
-        } else {
-            let it = left_iter
-                .map_ok(move |tuple| -> Result<Option<Tuple>> {
-                    let prefix = left_to_prefix_indices
-                        .iter()
-                        .map(|i| tuple[*i].clone())
-                        .collect_vec();
-                    let key = &prefix[0..key_len];
-                    match self.storage.get(tx, key)? {
-                        None => Ok(None),
-                        Some(found) => {
-                            for (p, span) in self.filters_bytecodes.iter() {
-                                if !eval_bytecode_pred(p, &found, &mut stack, *span)? {
-                                    return Ok(None);
-                                }
+                        for (p, span) in self.filters_bytecodes.iter() {
+                            if !eval_bytecode_pred(p, &found, &mut stack, *span)? {
+                                continue 'outer;
                             }
-                            let mut ret = tuple;
-                            ret.extend(found);
-                            Ok(Some(ret))
                         }
+                        joined.extend(found);
                     }
-                })
-                .flatten_ok()
-                .filter_map(invert_option_err);
-            Ok(if eliminate_indices.is_empty() {
-                Box::new(it)
-            } else {
-                Box::new(it.map_ok(move |t| eliminate_from_tuple(t, &eliminate_indices)))
+                    ret.push(joined);
+                }
+                Ok(ret)
             })
-        }
+            .flatten_ok();
+        Ok(if eliminate_indices.is_empty() {
+            Box::new(it)
+        } else {
+            Box::new(it.map_ok(move |t| eliminate_from_tuple(t, &eliminate_indices)))
+        })
     }
 
     fn prefix_join<'a>(
@@ -1078,9 +1355,11 @@ impl StoredRA {
                     if !l_bound.iter().all(|v| *v == DataValue::Null)
                         || !u_bound.iter().all(|v| *v == DataValue::Bot)
                     {
+                        let range = self
+                            .storage
+                            .bounded_prefix_key_range(&prefix, &l_bound, &u_bound);
                         return Left(
-                            self.storage
-                                .scan_bounded_prefix(tx, &prefix, &l_bound, &u_bound)
+                            self.scan_clamped(tx, range)
                                 .map(move |res_found| -> Result<Option<Tuple>> {
                                     let found = res_found?;
                                     for (p, span) in self.filters_bytecodes.iter() {
@@ -1098,8 +1377,7 @@ impl StoredRA {
                 }
                 skip_range_check = true;
                 Right(
-                    self.storage
-                        .scan_prefix(tx, &prefix)
+                    self.scan_clamped(tx, self.storage.prefix_key_range(&prefix))
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for (p, span) in self.filters_bytecodes.iter() {
@@ -1225,8 +1503,28 @@ impl StoredRA {
         }
     }
 
+    /// Scan the raw key range, restricted to `key_range` if it is set.
+    fn scan_clamped<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        (mut lower, mut upper): (Vec<u8>, Vec<u8>),
+    ) -> TupleIter<'a> {
+        if let Some((part_lower, part_upper)) = &self.key_range {
+            if *part_lower > lower {
+                lower = part_lower.clone();
+            }
+            if *part_upper < upper {
+                upper = part_upper.clone();
+            }
+            if lower >= upper {
+                return Box::new(iter::empty());
+            }
+        }
+        Box::new(self.storage.scan_range(tx, &lower, &upper))
+    }
+
     fn iter<'a>(&'a self, tx: &'a SessionTx<'_>) -> Result<TupleIter<'a>> {
-        let it = self.storage.scan_all(tx);
+        let it = self.scan_clamped(tx, self.storage.key_range());
         Ok(if self.filters.is_empty() {
             Box::new(it)
         } else {
@@ -1242,7 +1540,7 @@ fn join_is_prefix(right_join_indices: &[usize]) -> bool {
     indices.into_iter().eq(0..l)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct TempStoreRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage_key: MagicSymbol,
@@ -1270,7 +1568,7 @@ impl TempStoreRA {
     fn iter<'a>(
         &'a self,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         let storage = stores.get(&self.storage_key).unwrap();
 
@@ -1294,7 +1592,7 @@ impl TempStoreRA {
         left_iter: TupleIter<'a>,
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         let storage = stores.get(&self.storage_key).unwrap();
         debug_assert!(!right_join_indices.is_empty());
@@ -1393,7 +1691,7 @@ impl TempStoreRA {
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         let storage = stores.get(&self.storage_key).unwrap();
 
@@ -1495,6 +1793,7 @@ impl TempStoreRA {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Joiner {
     // invariant: these are of the same lengths
     pub(crate) left_keys: Vec<Symbol>,
@@ -1542,6 +1841,19 @@ impl Joiner {
         }
         Ok((ret_l, ret_r))
     }
+    /// The unique key of the join of `left` and `right` on this joiner, if both sides have one
+    fn joined_unique_key(&self, left: &RelAlgebra, right: &RelAlgebra) -> Option<BTreeSet<Symbol>> {
+        let mut key = left.unique_key()?;
+        let right_key = right.unique_key()?;
+        // the joined columns of the right are equal to those of the left
+        let equiv: BTreeMap<_, _> = self.right_keys.iter().zip(self.left_keys.iter()).collect();
+        key.extend(
+            right_key
+                .into_iter()
+                .map(|k| equiv.get(&k).map(|l| (*l).clone()).unwrap_or(k)),
+        );
+        Some(key)
+    }
 }
 
 impl RelAlgebra {
@@ -1555,6 +1867,7 @@ impl RelAlgebra {
             RelAlgebra::Reorder(r) => r.relation.eliminate_temp_vars(used),
             RelAlgebra::Filter(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::NegJoin(r) => r.do_eliminate_temp_vars(used),
+            RelAlgebra::OuterJoin(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::Unification(r) => r.do_eliminate_temp_vars(used),
         }
     }
@@ -1569,6 +1882,7 @@ impl RelAlgebra {
             RelAlgebra::Reorder(_) => None,
             RelAlgebra::Filter(r) => Some(&r.to_eliminate),
             RelAlgebra::NegJoin(r) => Some(&r.to_eliminate),
+            RelAlgebra::OuterJoin(r) => Some(&r.to_eliminate),
             RelAlgebra::Unification(u) => Some(&u.to_eliminate),
         }
     }
@@ -1594,6 +1908,7 @@ impl RelAlgebra {
             RelAlgebra::Reorder(r) => r.bindings(),
             RelAlgebra::Filter(r) => r.parent.bindings_after_eliminate(),
             RelAlgebra::NegJoin(j) => j.left.bindings_after_eliminate(),
+            RelAlgebra::OuterJoin(j) => j.bindings(),
             RelAlgebra::Unification(u) => {
                 let mut bindings = u.parent.bindings_after_eliminate();
                 bindings.push(u.binding.clone());
@@ -1601,12 +1916,45 @@ impl RelAlgebra {
             }
         }
     }
+    /// Whether this operator benefits from batch-at-a-time execution.
+    ///
+    /// Operators without a batch path are still usable inside a batched tree,
+    /// their tuples are simply chunked after being produced.
+    fn has_batch_path(&self) -> bool {
+        match self {
+            RelAlgebra::Filter(r) => r
+                .filters_bytecodes
+                .iter()
+                .any(|(bytecodes, _)| is_vectorizable(bytecodes)),
+            RelAlgebra::Reorder(r) => r.relation.has_batch_path(),
+            // joins against anything else are driven tuple by tuple, though their left
+            // side may still take its own batch path
+            RelAlgebra::Join(j) => matches!(j.right, RelAlgebra::Fixed(_)),
+            _ => false,
+        }
+    }
+    pub(crate) fn batch_iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<BatchIter<'a>> {
+        match self {
+            RelAlgebra::Join(j) => j.batch_iter(tx, delta_rule, stores),
+            RelAlgebra::Reorder(r) => r.batch_iter(tx, delta_rule, stores),
+            RelAlgebra::Filter(r) => r.batch_iter(tx, delta_rule, stores),
+            _ => Ok(batch_tuples(self.iter(tx, delta_rule, stores)?)),
+        }
+    }
     pub(crate) fn iter<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
+        if self.has_batch_path() {
+            return Ok(unbatch_tuples(self.batch_iter(tx, delta_rule, stores)?));
+        }
         match self {
             RelAlgebra::Fixed(f) => Ok(Box::new(f.data.iter().map(|t| Ok(t.clone())))),
             RelAlgebra::TempStore(r) => r.iter(delta_rule, stores),
@@ -1616,12 +1964,13 @@ impl RelAlgebra {
             RelAlgebra::Reorder(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::Filter(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::NegJoin(r) => r.iter(tx, delta_rule, stores),
+            RelAlgebra::OuterJoin(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::Unification(r) => r.iter(tx, delta_rule, stores),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct NegJoin {
     pub(crate) left: RelAlgebra,
     pub(crate) right: RelAlgebra,
@@ -1684,7 +2033,7 @@ impl NegJoin {
         &'a self,
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         let bindings = self.left.bindings_after_eliminate();
         let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
@@ -1726,7 +2075,182 @@ impl NegJoin {
     }
 }
 
-#[derive(Debug)]
+/// Joins every row of the left to the matching rows of the right,
+/// or to a row of nulls if there are none
+#[derive(Debug, Clone)]
+pub(crate) struct OuterJoin {
+    pub(crate) left: RelAlgebra,
+    pub(crate) right: RelAlgebra,
+    pub(crate) joiner: Joiner,
+    pub(crate) to_eliminate: BTreeSet<Symbol>,
+    pub(crate) span: SourceSpan,
+}
+
+impl OuterJoin {
+    pub(crate) fn do_eliminate_temp_vars(&mut self, used: &BTreeSet<Symbol>) -> Result<()> {
+        for binding in self.bindings() {
+            if !used.contains(&binding) {
+                self.to_eliminate.insert(binding.clone());
+            }
+        }
+        let mut left = used.clone();
+        left.extend(self.joiner.left_keys.clone());
+        self.left.eliminate_temp_vars(&left)?;
+        let mut right = used.clone();
+        right.extend(self.joiner.right_keys.clone());
+        self.right.eliminate_temp_vars(&right)?;
+        Ok(())
+    }
+
+    pub(crate) fn bindings(&self) -> Vec<Symbol> {
+        let mut ret = self.left.bindings_after_eliminate();
+        ret.extend(self.right.bindings_after_eliminate());
+        ret
+    }
+
+    pub(crate) fn join_type(&self) -> &str {
+        let join_indices = self
+            .joiner
+            .join_indices(
+                &self.left.bindings_after_eliminate(),
+                &self.right.bindings_after_eliminate(),
+            )
+            .unwrap();
+        match &self.right {
+            RelAlgebra::TempStore(_) if join_is_prefix(&join_indices.1) => "mem_outer_prefix_join",
+            RelAlgebra::TempStore(_) => "mem_outer_mat_join",
+            RelAlgebra::Stored(_) | RelAlgebra::StoredWithValidity(_)
+                if join_is_prefix(&join_indices.1) =>
+            {
+                "stored_outer_prefix_join"
+            }
+            RelAlgebra::Stored(_) | RelAlgebra::StoredWithValidity(_) => "stored_outer_mat_join",
+            _ => {
+                unreachable!()
+            }
+        }
+    }
+
+    pub(crate) fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<TupleIter<'a>> {
+        let bindings = self.bindings();
+        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        let left_bindings = self.left.bindings_after_eliminate();
+        let right_bindings = self.right.bindings_after_eliminate();
+        let right_len = right_bindings.len();
+        let join_indices = self
+            .joiner
+            .join_indices(&left_bindings, &right_bindings)
+            .unwrap();
+        let left_iter = self.left.iter(tx, delta_rule, stores)?;
+
+        let pad = move |mut tuple: Tuple| {
+            tuple.extend(vec![DataValue::Null; right_len]);
+            tuple
+        };
+
+        let it: TupleIter<'a> = if join_is_prefix(&join_indices.1) {
+            // probe the right for each row of the left; the right is never the delta
+            // since it is computed in full in an earlier stratum
+            let left_len = left_bindings.len();
+            Box::new(
+                left_iter
+                    .map(move |res| -> Result<Vec<Tuple>> {
+                        let tuple = res?;
+                        let probe: TupleIter<'a> = Box::new(iter::once(Ok(tuple.clone())));
+                        let found: Vec<Tuple> = match &self.right {
+                            RelAlgebra::TempStore(r) => r.prefix_join(
+                                probe,
+                                join_indices.clone(),
+                                Default::default(),
+                                None,
+                                stores,
+                            )?,
+                            RelAlgebra::Stored(r) => r.prefix_join(
+                                tx,
+                                probe,
+                                join_indices.clone(),
+                                Default::default(),
+                                left_len,
+                            )?,
+                            RelAlgebra::StoredWithValidity(r) => {
+                                r.prefix_join(tx, probe, join_indices.clone(), Default::default())?
+                            }
+                            _ => unreachable!(),
+                        }
+                        .try_collect()?;
+                        Ok(if found.is_empty() {
+                            vec![pad(tuple)]
+                        } else {
+                            found
+                        })
+                    })
+                    .flatten_ok(),
+            )
+        } else {
+            debug!("using materialized outer join");
+            let (left_join_indices, right_join_indices) = join_indices;
+            let right_join_indices_set = BTreeSet::from_iter(right_join_indices.iter().cloned());
+            let mut right_store_indices = right_join_indices;
+            for i in 0..right_len {
+                if !right_join_indices_set.contains(&i) {
+                    right_store_indices.push(i)
+                }
+            }
+            let right_invert_indices = right_store_indices
+                .iter()
+                .enumerate()
+                .sorted_by_key(|(_, b)| **b)
+                .map(|(a, _)| a)
+                .collect_vec();
+            let mut materialized = vec![];
+            for item in self.right.iter(tx, None, stores)? {
+                let tuple = item?;
+                materialized.push(
+                    right_store_indices
+                        .iter()
+                        .map(|i| tuple[*i].clone())
+                        .collect_vec(),
+                );
+            }
+            materialized.sort();
+            materialized.dedup();
+            Box::new(
+                left_iter
+                    .map_ok(move |tuple| {
+                        let (prefix, idx) =
+                            build_mat_range_iter(&materialized, &left_join_indices, &tuple);
+                        let found = materialized[idx..]
+                            .iter()
+                            .take_while(|data| data.starts_with(&prefix))
+                            .map(|data| {
+                                let mut ret = tuple.clone();
+                                ret.extend(right_invert_indices.iter().map(|i| data[*i].clone()));
+                                ret
+                            })
+                            .collect_vec();
+                        if found.is_empty() {
+                            vec![pad(tuple)]
+                        } else {
+                            found
+                        }
+                    })
+                    .flatten_ok(),
+            )
+        };
+        Ok(if eliminate_indices.is_empty() {
+            it
+        } else {
+            Box::new(it.map_ok(move |t| eliminate_from_tuple(t, &eliminate_indices)))
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct InnerJoin {
     pub(crate) left: RelAlgebra,
     pub(crate) right: RelAlgebra,
@@ -1819,18 +2343,30 @@ impl InnerJoin {
             RelAlgebra::NegJoin(_) => {
                 panic!("joining on NegJoin")
             }
+            RelAlgebra::OuterJoin(_) => {
+                panic!("joining on OuterJoin")
+            }
         }
     }
     pub(crate) fn iter<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
-        let bindings = self.bindings();
-        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        let left_iter = self.left.iter(tx, delta_rule, stores)?;
+        self.join_with_left(tx, left_iter, delta_rule, stores)
+    }
+    fn batch_iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<BatchIter<'a>> {
         match &self.right {
             RelAlgebra::Fixed(f) => {
+                let bindings = self.bindings();
+                let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
                 let join_indices = self
                     .joiner
                     .join_indices(
@@ -1838,12 +2374,35 @@ impl InnerJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                f.join(
-                    self.left.iter(tx, delta_rule, stores)?,
+                f.batch_join(
+                    self.left.batch_iter(tx, delta_rule, stores)?,
                     join_indices,
                     eliminate_indices,
                 )
             }
+            _ => Ok(batch_tuples(self.iter(tx, delta_rule, stores)?)),
+        }
+    }
+    fn join_with_left<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        left_iter: TupleIter<'a>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
+    ) -> Result<TupleIter<'a>> {
+        let bindings = self.bindings();
+        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        match &self.right {
+            RelAlgebra::Fixed(f) => {
+                let join_indices = self
+                    .joiner
+                    .join_indices(
+                        &self.left.bindings_after_eliminate(),
+                        &self.right.bindings_after_eliminate(),
+                    )
+                    .unwrap();
+                f.join(left_iter, join_indices, eliminate_indices)
+            }
             RelAlgebra::TempStore(r) => {
                 let join_indices = self
                     .joiner
@@ -1854,14 +2413,14 @@ impl InnerJoin {
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
                     r.prefix_join(
-                        self.left.iter(tx, delta_rule, stores)?,
+                        left_iter,
                         join_indices,
                         eliminate_indices,
                         delta_rule,
                         stores,
                     )
                 } else {
-                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                    self.materialized_join(tx, left_iter, eliminate_indices, delta_rule, stores)
                 }
             }
             RelAlgebra::Stored(r) => {
@@ -1874,15 +2433,9 @@ impl InnerJoin {
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
                     let left_len = self.left.bindings_after_eliminate().len();
-                    r.prefix_join(
-                        tx,
-                        self.left.iter(tx, delta_rule, stores)?,
-                        join_indices,
-                        eliminate_indices,
-                        left_len,
-                    )
+                    r.prefix_join(tx, left_iter, join_indices, eliminate_indices, left_len)
                 } else {
-                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                    self.materialized_join(tx, left_iter, eliminate_indices, delta_rule, stores)
                 }
             }
             RelAlgebra::StoredWithValidity(r) => {
@@ -1894,18 +2447,13 @@ impl InnerJoin {
                     )
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
-                    r.prefix_join(
-                        tx,
-                        self.left.iter(tx, delta_rule, stores)?,
-                        join_indices,
-                        eliminate_indices,
-                    )
+                    r.prefix_join(tx, left_iter, join_indices, eliminate_indices)
                 } else {
-                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                    self.materialized_join(tx, left_iter, eliminate_indices, delta_rule, stores)
                 }
             }
             RelAlgebra::Join(_) | RelAlgebra::Filter(_) | RelAlgebra::Unification(_) => {
-                self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                self.materialized_join(tx, left_iter, eliminate_indices, delta_rule, stores)
             }
             RelAlgebra::Reorder(_) => {
                 panic!("joining on reordered")
@@ -1913,14 +2461,18 @@ impl InnerJoin {
             RelAlgebra::NegJoin(_) => {
                 panic!("joining on NegJoin")
             }
+            RelAlgebra::OuterJoin(_) => {
+                panic!("joining on OuterJoin")
+            }
         }
     }
     fn materialized_join<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
+        mut left_iter: TupleIter<'a>,
         eliminate_indices: BTreeSet<usize>,
         delta_rule: Option<&MagicSymbol>,
-        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        stores: &'a BTreeMap<MagicSymbol, Arc<EpochStore>>,
     ) -> Result<TupleIter<'a>> {
         debug!("using materialized join");
         let right_bindings = self.right.bindings_after_eliminate();
@@ -1929,7 +2481,6 @@ impl InnerJoin {
             .join_indices(&self.left.bindings_after_eliminate(), &right_bindings)
             .unwrap();
 
-        let mut left_iter = self.left.iter(tx, delta_rule, stores)?;
         let left_cache = match left_iter.next() {
             None => return Ok(Box::new(iter::empty())),
             Some(Err(err)) => return Err(err),
@@ -2071,8 +2622,11 @@ impl<'a> Iterator for CachedMaterializedIterator<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use crate::data::value::DataValue;
-    use crate::new_cozo_mem;
+    use crate::storage::Storage;
+    use crate::{new_cozo_mem, new_cozo_sqlite, Db};
 
     #[test]
     fn test_mat_join() {
@@ -2092,4 +2646,118 @@ mod tests {
             vec![vec![DataValue::from(1)], vec![DataValue::from(2)]]
         )
     }
+
+    #[test]
+    fn test_batch_filter() {
+        let db = new_cozo_mem().unwrap();
+        let data = (0..5000i64)
+            .map(|x| DataValue::List(vec![DataValue::from(x)]))
+            .collect::<Vec<_>>();
+        let res = db
+            .run_script(
+                r#"
+        data[x] <- $data
+        ?[y, x] := data[x], y = x * 2, y % 7 == 0, y > 100,
+                   if(y > 8000, y % 4 == 0, true)
+        "#,
+                BTreeMap::from([("data".to_string(), DataValue::List(data.clone()))]),
+            )
+            .unwrap()
+            .rows;
+        let expected = (0..5000i64)
+            .filter(|x| (x * 2) % 7 == 0 && x * 2 > 100 && (x * 2 <= 8000 || x % 2 == 0))
+            .map(|x| vec![DataValue::from(x * 2), DataValue::from(x)])
+            .collect::<Vec<_>>();
+        assert_eq!(res, expected);
+
+        // filters wrapped in `if` cannot be vectorized, and take the tuple path
+        db.run_script(
+            r#"
+        data[k] <- $data
+        ?[k, v] := data[k], k < 3000, v = k % 7
+        :create r {k => v}
+        "#,
+            BTreeMap::from([("data".to_string(), DataValue::List(data.clone()))]),
+        )
+        .unwrap();
+        let run = |filter: &str| {
+            let script = format!(
+                r#"
+            data[x] <- $data
+            pair[x, y] := data[x], y = x % 11
+            ?[x, y, z] := data[x], *r{{k: x, v: y}}, pair[x, z], {filter}
+            "#
+            );
+            db.run_script(
+                &script,
+                BTreeMap::from([("data".to_string(), DataValue::List(data.clone()))]),
+            )
+            .unwrap()
+            .rows
+        };
+        let batched = run("y > 2, z % 3 == 0");
+        let by_tuple = run("if(y > 2, z % 3 == 0, false)");
+        let expected = (0..3000i64)
+            .filter(|x| x % 7 > 2 && x % 11 % 3 == 0)
+            .count();
+        assert_eq!(batched.len(), expected);
+        assert_eq!(batched, by_tuple);
+    }
+
+    fn check_point_lookup_join<'s, S: Storage<'s>>(db: &'s Db<S>) {
+        let data = (0..3000i64)
+            .map(|x| DataValue::List(vec![DataValue::from(x), DataValue::from(x % 5)]))
+            .collect();
+        db.run_script(
+            r#"
+        ?[k, v] <- $data
+        :create r {k => v}
+        "#,
+            BTreeMap::from([("data".to_string(), DataValue::List(data))]),
+        )
+        .unwrap();
+        let probes = DataValue::List(
+            (0..2500i64)
+                .map(|x| DataValue::List(vec![DataValue::from(x * 2)]))
+                .collect(),
+        );
+        let res = db
+            .run_script(
+                r#"
+        probe[k] <- $probes
+        ?[count(k)] := probe[k], *r{k}
+        "#,
+                BTreeMap::from([("probes".to_string(), probes.clone())]),
+            )
+            .unwrap()
+            .rows;
+        assert_eq!(res, vec![vec![DataValue::from(1500)]]);
+        let res = db
+            .run_script(
+                r#"
+        probe[k] <- $probes
+        ?[k, v] := probe[k], *r[k, v], v > 2
+        "#,
+                BTreeMap::from([("probes".to_string(), probes)]),
+            )
+            .unwrap()
+            .rows;
+        let expected = (0..1500i64)
+            .map(|x| x * 2)
+            .filter(|x| x % 5 > 2)
+            .map(|x| vec![DataValue::from(x), DataValue::from(x % 5)])
+            .collect::<Vec<_>>();
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn test_point_lookup_join() {
+        check_point_lookup_join(&new_cozo_mem().unwrap());
+
+        let path = std::env::temp_dir().join(format!("cozo-lookup-{}.db", std::process::id()));
+        let db = new_cozo_sqlite(&path).unwrap();
+        check_point_lookup_join(&db);
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
 }
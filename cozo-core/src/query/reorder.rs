@@ -31,10 +31,17 @@ pub(crate) struct UnsafeNegation(#[label] pub(crate) SourceSpan);
 pub(crate) struct UnboundVariable(#[label] pub(crate) SourceSpan);
 
 impl NormalFormInlineRule {
-    pub(crate) fn convert_to_well_ordered_rule(self) -> Result<Self> {
+    /// Order the atoms of the body so that every atom comes after those binding its variables.
+    /// With `settle_pending`, atoms waiting for the bindings of other waiting unifications
+    /// are collected as well, as in rules that subqueries are lifted from: their values are
+    /// unified only once the lifted rules are joined.
+    pub(crate) fn convert_to_well_ordered_rule(self, settle_pending: bool) -> Result<Self> {
         let mut seen_variables = BTreeSet::default();
         let mut round_1_collected = vec![];
         let mut pending = vec![];
+        // optional atoms are joined only after all the other applications,
+        // so that everything bound elsewhere in the body is used as the join keys
+        let mut optionals = vec![];
 
         for atom in self.body {
             match atom {
@@ -68,11 +75,15 @@ impl NormalFormInlineRule {
                 NormalFormAtom::NegatedRelation(v) => {
                     pending.push(NormalFormAtom::NegatedRelation(v))
                 }
+                a @ (NormalFormAtom::OptionalRule(_) | NormalFormAtom::OptionalRelation(_)) => {
+                    optionals.push(a)
+                }
                 NormalFormAtom::Predicate(p) => {
                     pending.push(NormalFormAtom::Predicate(p));
                 }
             }
         }
+        round_1_collected.extend(optionals);
 
         let mut collected = vec![];
         seen_variables.clear();
@@ -89,6 +100,14 @@ impl NormalFormInlineRule {
                     seen_variables.extend(v.args.iter().cloned());
                     collected.push(NormalFormAtom::Relation(v))
                 }
+                NormalFormAtom::OptionalRule(r) => {
+                    seen_variables.extend(r.args.iter().cloned());
+                    collected.push(NormalFormAtom::OptionalRule(r))
+                }
+                NormalFormAtom::OptionalRelation(v) => {
+                    seen_variables.extend(v.args.iter().cloned());
+                    collected.push(NormalFormAtom::OptionalRelation(v))
+                }
                 NormalFormAtom::NegatedRule(_)
                 | NormalFormAtom::NegatedRelation(_)
                 | NormalFormAtom::Predicate(_) => {
@@ -101,7 +120,10 @@ impl NormalFormInlineRule {
             }
             for atom in last_pending.iter() {
                 match atom {
-                    NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) => unreachable!(),
+                    NormalFormAtom::Rule(_)
+                    | NormalFormAtom::Relation(_)
+                    | NormalFormAtom::OptionalRule(_)
+                    | NormalFormAtom::OptionalRelation(_) => unreachable!(),
                     NormalFormAtom::NegatedRule(r) => {
                         if r.args.iter().all(|a| seen_variables.contains(a)) {
                             collected.push(NormalFormAtom::NegatedRule(r.clone()));
@@ -134,10 +156,53 @@ impl NormalFormInlineRule {
             }
         }
 
+        if settle_pending {
+            for atom in &collected {
+                if let NormalFormAtom::Unification(u) = atom {
+                    seen_variables.insert(u.binding.clone());
+                }
+            }
+            loop {
+                let n_collected = collected.len();
+                for atom in mem::take(&mut pending) {
+                    let ready = match &atom {
+                        NormalFormAtom::Rule(_)
+                        | NormalFormAtom::Relation(_)
+                        | NormalFormAtom::OptionalRule(_)
+                        | NormalFormAtom::OptionalRelation(_) => unreachable!(),
+                        NormalFormAtom::NegatedRule(r) => {
+                            r.args.iter().all(|a| seen_variables.contains(a))
+                        }
+                        NormalFormAtom::NegatedRelation(v) => {
+                            v.args.iter().all(|a| seen_variables.contains(a))
+                        }
+                        NormalFormAtom::Predicate(p) => p.bindings().is_subset(&seen_variables),
+                        NormalFormAtom::Unification(u) => {
+                            u.bindings_in_expr().is_subset(&seen_variables)
+                        }
+                    };
+                    if !ready {
+                        pending.push(atom);
+                        continue;
+                    }
+                    if let NormalFormAtom::Unification(u) = &atom {
+                        seen_variables.insert(u.binding.clone());
+                    }
+                    collected.push(atom);
+                }
+                if collected.len() == n_collected {
+                    break;
+                }
+            }
+        }
+
         if !pending.is_empty() {
             for atom in pending {
                 match atom {
-                    NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) => unreachable!(),
+                    NormalFormAtom::Rule(_)
+                    | NormalFormAtom::Relation(_)
+                    | NormalFormAtom::OptionalRule(_)
+                    | NormalFormAtom::OptionalRelation(_) => unreachable!(),
                     NormalFormAtom::NegatedRule(r) => {
                         if r.args.iter().any(|a| seen_variables.contains(a)) {
                             collected.push(NormalFormAtom::NegatedRule(r.clone()));
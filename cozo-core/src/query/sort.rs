@@ -12,41 +12,120 @@ use std::collections::BTreeMap;
 use itertools::Itertools;
 use miette::Result;
 
-use crate::data::program::SortDir;
+use crate::data::collation::Collation;
+use crate::data::expr::{eval_bytecode, Bytecode};
+use crate::data::program::{NullsOrder, SortDir, Sorter};
 use crate::data::symb::Symbol;
 use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
 
+/// A row together with the values of its sort keys
+pub(crate) type SortedRow = (Vec<SortKey>, Tuple);
+
+/// The value of a sort key, with the sort key of the collation computed once for strings.
+#[derive(Debug, Clone)]
+pub(crate) struct SortKey {
+    pub(crate) value: DataValue,
+    collated: Option<Vec<u8>>,
+}
+
+impl SortKey {
+    pub(crate) fn new(value: DataValue, collation: Option<Collation>) -> Self {
+        let collated = match (&value, collation) {
+            (DataValue::Str(s), Some(c)) => Some(c.sort_key(s)),
+            _ => None,
+        };
+        Self { value, collated }
+    }
+}
+
 impl<'a> SessionTx<'a> {
     pub(crate) fn sort_and_collect(
         &mut self,
         original: EpochStore,
-        sorters: &[(Symbol, SortDir)],
-        head: &[Symbol],
-    ) -> Result<Vec<Tuple>> {
-        let head_indices: BTreeMap<_, _> = head.iter().enumerate().map(|(i, k)| (k, i)).collect();
-        let idx_sorters = sorters
+        sorter: &RowSorter<'_>,
+    ) -> Result<Vec<SortedRow>> {
+        let mut all_data: Vec<_> = original
+            .all_iter()
+            .map(|v| sorter.with_keys(v.into_tuple()))
+            .try_collect()?;
+        all_data.sort_by(|a, b| sorter.compare(a, b));
+
+        Ok(all_data)
+    }
+}
+
+/// The sort keys of the `:sort` option, evaluated on the rows of the entry rule.
+pub(crate) struct RowSorter<'a> {
+    sorters: &'a [Sorter],
+    bytecodes: Vec<Vec<Bytecode>>,
+}
+
+impl<'a> RowSorter<'a> {
+    pub(crate) fn new(sorters: &'a [Sorter], head: &[Symbol]) -> Result<Self> {
+        let binding_map: BTreeMap<_, _> = head
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.clone(), i))
+            .collect();
+        let bytecodes = sorters
+            .iter()
+            .map(|sorter| -> Result<Vec<Bytecode>> {
+                let mut key = sorter.key.clone();
+                key.fill_binding_indices(&binding_map)?;
+                Ok(key.compile())
+            })
+            .try_collect()?;
+        Ok(Self { sorters, bytecodes })
+    }
+    pub(crate) fn with_keys(&self, tuple: Tuple) -> Result<SortedRow> {
+        let mut stack = vec![];
+        let keys = self
+            .sorters
             .iter()
-            .map(|(k, dir)| (head_indices[k], *dir))
-            .collect_vec();
-
-        let mut all_data: Vec<_> = original.all_iter().map(|v| v.into_tuple()).collect_vec();
-        all_data.sort_by(|a, b| {
-            for (idx, dir) in &idx_sorters {
-                match a[*idx].cmp(&b[*idx]) {
-                    Ordering::Equal => {}
-                    o => {
-                        return match dir {
-                            SortDir::Asc => o,
-                            SortDir::Dsc => o.reverse(),
-                        }
-                    }
-                }
+            .zip(&self.bytecodes)
+            .map(|(sorter, bytecode)| -> Result<SortKey> {
+                let value = eval_bytecode(bytecode, &tuple, &mut stack)?;
+                Ok(SortKey::new(value, sorter.collation))
+            })
+            .try_collect()?;
+        Ok((keys, tuple))
+    }
+    /// The order of sorted results: by the sort keys, with ties broken by the whole tuple.
+    pub(crate) fn compare(&self, (a_keys, a): &SortedRow, (b_keys, b): &SortedRow) -> Ordering {
+        for ((sorter, x), y) in self.sorters.iter().zip(a_keys).zip(b_keys) {
+            match compare_key(sorter, x, y) {
+                Ordering::Equal => {}
+                o => return o,
             }
-            Ordering::Equal
-        });
+        }
+        a.cmp(b)
+    }
+}
 
-        Ok(all_data)
+/// Nulls are placed as asked regardless of the direction, and are otherwise the least values.
+/// Strings under a collation are compared by their sort keys, with ties broken by their bytes.
+pub(crate) fn compare_key(sorter: &Sorter, x: &SortKey, y: &SortKey) -> Ordering {
+    if let Some(nulls) = sorter.nulls {
+        let null_order = match nulls {
+            NullsOrder::First => Ordering::Less,
+            NullsOrder::Last => Ordering::Greater,
+        };
+        match (x.value == DataValue::Null, y.value == DataValue::Null) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return null_order,
+            (false, true) => return null_order.reverse(),
+            (false, false) => {}
+        }
+    }
+    let o = match (&x.collated, &y.collated) {
+        (Some(a), Some(b)) => a.cmp(b).then_with(|| x.value.cmp(&y.value)),
+        _ => x.value.cmp(&y.value),
+    };
+    match sorter.dir {
+        SortDir::Asc => o,
+        SortDir::Dsc => o.reverse(),
     }
 }
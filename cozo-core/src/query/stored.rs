@@ -22,7 +22,7 @@ use crate::data::tuple::Tuple;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
-use crate::parse::parse_script;
+use crate::parse::{parse_script, ParseContext};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel,
@@ -39,7 +39,7 @@ struct RelationArityMismatch(String, usize, usize);
 impl<'a> SessionTx<'a> {
     pub(crate) fn execute_relation<'s, S: Storage<'s>>(
         &mut self,
-        db: &Db<S>,
+        db: &'s Db<S>,
         res_iter: impl Iterator<Item = Tuple>,
         op: RelationOp,
         meta: &InputRelationHandle,
@@ -51,6 +51,7 @@ impl<'a> SessionTx<'a> {
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
+        let mut cleared_in_place = false;
         if op == RelationOp::Replace {
             if !propagate_triggers {
                 #[derive(Debug, Error, Diagnostic)]
@@ -75,12 +76,20 @@ impl<'a> SessionTx<'a> {
                     ));
                 }
                 if old_handle.has_triggers() {
-                    replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
+                    replaced_old_triggers = Some((
+                        old_handle.put_triggers.clone(),
+                        old_handle.rm_triggers.clone(),
+                    ))
                 }
                 for trigger in &old_handle.replace_triggers {
                     let program = parse_script(
                         trigger,
-                        &Default::default(),
+                        &ParseContext {
+                            params: &Default::default(),
+                            custom_fns: &db.custom_functions.read().unwrap(),
+                            custom_aggrs: &db.custom_aggregations.read().unwrap(),
+                            in_rule_body: false,
+                        },
                         &db.fixed_rules.read().unwrap(),
                         cur_vld,
                     )?
@@ -105,10 +114,38 @@ impl<'a> SessionTx<'a> {
                     to_clear.extend(cleanups);
                 }
 
-                to_clear.push(self.destroy_relation(&meta.name)?);
+                if old_handle.materialized_views.is_empty() {
+                    to_clear.push(self.destroy_relation(&meta.name)?);
+                } else {
+                    // the relation is kept for the views computed from it: its rows are
+                    // removed in place, and the views maintained as for any removal
+                    if old_handle.metadata != meta.metadata {
+                        #[derive(Debug, Error, Diagnostic)]
+                        #[error("cannot change the columns of relation {0} as materialized views are computed from it")]
+                        #[diagnostic(code(eval::replace_rel_with_views))]
+                        struct ReplaceRelationWithViews(String);
+                        bail!(ReplaceRelationWithViews(old_handle.name.to_string()))
+                    }
+                    let old_rows: Vec<Tuple> = old_handle.scan_all(self).try_collect()?;
+                    for row in &old_rows {
+                        let key = old_handle.encode_key_for_store(row, Default::default())?;
+                        self.store_tx.del(&key)?;
+                    }
+                    self.maintain_materialized_views(
+                        db,
+                        &old_handle,
+                        vec![],
+                        old_rows,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                    )?;
+                    cleared_in_place = true;
+                }
             }
         }
-        let mut relation_store = if op == RelationOp::Replace || op == RelationOp::Create {
+        let create = op == RelationOp::Create || (op == RelationOp::Replace && !cleared_in_place);
+        let mut relation_store = if create {
             self.create_relation(meta.clone())?
         } else {
             self.get_relation(&meta.name, false)?
@@ -143,8 +180,10 @@ impl<'a> SessionTx<'a> {
                     headers,
                 )?;
 
+                let has_views = !relation_store.materialized_views.is_empty();
                 let need_to_collect = !relation_store.is_temp
                     && (is_callback_target
+                        || has_views
                         || (propagate_triggers && !relation_store.rm_triggers.is_empty()));
                 let has_indices = !relation_store.indices.is_empty();
                 let mut new_tuples: Vec<DataValue> = vec![];
@@ -160,6 +199,7 @@ impl<'a> SessionTx<'a> {
                         if let Some(existing) = self.store_tx.get(&key, false)? {
                             let mut tup = extracted.clone();
                             extend_tuple_from_v(&mut tup, &existing);
+                            let tup = relation_store.restore_collated_keys(tup);
                             if has_indices {
                                 for (idx_rel, extractor) in relation_store.indices.values() {
                                     let idx_tup =
@@ -184,6 +224,25 @@ impl<'a> SessionTx<'a> {
                     }
                 }
 
+                if has_views && !old_tuples.is_empty() {
+                    let deleted = old_tuples
+                        .iter()
+                        .map(|v| match v {
+                            DataValue::List(l) => l.clone(),
+                            _ => unreachable!(),
+                        })
+                        .collect_vec();
+                    self.maintain_materialized_views(
+                        db,
+                        &relation_store,
+                        vec![],
+                        deleted,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                    )?;
+                }
+
                 // triggers and callbacks
                 if need_to_collect && !new_tuples.is_empty() {
                     let k_bindings = relation_store
@@ -206,7 +265,12 @@ impl<'a> SessionTx<'a> {
                         for trigger in &relation_store.rm_triggers {
                             let mut program = parse_script(
                                 trigger,
-                                &Default::default(),
+                                &ParseContext {
+                                    params: &Default::default(),
+                                    custom_fns: &db.custom_functions.read().unwrap(),
+                                    custom_aggrs: &db.custom_aggregations.read().unwrap(),
+                                    in_rule_body: false,
+                                },
                                 &db.fixed_rules.read().unwrap(),
                                 cur_vld,
                             )?
@@ -393,8 +457,10 @@ impl<'a> SessionTx<'a> {
                     headers,
                 )?;
 
+                let has_views = !relation_store.materialized_views.is_empty();
                 let need_to_collect = !relation_store.is_temp
                     && (is_callback_target
+                        || has_views
                         || (propagate_triggers && !relation_store.put_triggers.is_empty()));
                 let has_indices = !relation_store.indices.is_empty();
                 let mut new_tuples: Vec<DataValue> = vec![];
@@ -421,6 +487,7 @@ impl<'a> SessionTx<'a> {
                         if let Some(existing) = self.store_tx.get(&key, false)? {
                             let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
                             extend_tuple_from_v(&mut tup, &existing);
+                            let tup = relation_store.restore_collated_keys(tup);
                             if has_indices && extracted != tup {
                                 for (idx_rel, extractor) in relation_store.indices.values() {
                                     let idx_tup_old =
@@ -435,7 +502,10 @@ impl<'a> SessionTx<'a> {
                                         .collect_vec();
                                     let encoded_new = idx_rel
                                         .encode_key_for_store(&idx_tup_new, Default::default())?;
-                                    self.store_tx.put(&encoded_new, &[])?;
+                                    self.store_tx.put(
+                                        &encoded_new,
+                                        &idx_rel.encode_index_val(&idx_tup_new)?,
+                                    )?;
                                 }
                             }
 
@@ -450,7 +520,8 @@ impl<'a> SessionTx<'a> {
                                     .collect_vec();
                                 let encoded_new = idx_rel
                                     .encode_key_for_store(&idx_tup_new, Default::default())?;
-                                self.store_tx.put(&encoded_new, &[])?;
+                                self.store_tx
+                                    .put(&encoded_new, &idx_rel.encode_index_val(&idx_tup_new)?)?;
                             }
                         }
 
@@ -466,6 +537,20 @@ impl<'a> SessionTx<'a> {
                     }
                 }
 
+                if has_views && !new_tuples.is_empty() {
+                    let (inserted, deleted) =
+                        net_changes(relation_store.metadata.keys.len(), &new_tuples, &old_tuples);
+                    self.maintain_materialized_views(
+                        db,
+                        &relation_store,
+                        inserted,
+                        deleted,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                    )?;
+                }
+
                 if need_to_collect && !new_tuples.is_empty() {
                     let mut bindings = relation_store
                         .metadata
@@ -485,7 +570,12 @@ impl<'a> SessionTx<'a> {
                         for trigger in &relation_store.put_triggers {
                             let mut program = parse_script(
                                 trigger,
-                                &Default::default(),
+                                &ParseContext {
+                                    params: &Default::default(),
+                                    custom_fns: &db.custom_functions.read().unwrap(),
+                                    custom_aggrs: &db.custom_aggregations.read().unwrap(),
+                                    in_rule_body: false,
+                                },
                                 &db.fixed_rules.read().unwrap(),
                                 cur_vld,
                             )?
@@ -631,7 +721,53 @@ fn make_extractor(
     }
 }
 
-fn make_const_rule(
+/// The rows added to and removed from a relation by a batch of puts, given the rows put
+/// and the existing rows they overwrote, both in the order of processing
+#[allow(clippy::mutable_key_type)]
+pub(crate) fn net_changes(
+    key_len: usize,
+    new_tuples: &[DataValue],
+    old_tuples: &[DataValue],
+) -> (Vec<Tuple>, Vec<Tuple>) {
+    let as_tuple = |v: &DataValue| match v {
+        DataValue::List(l) => l.clone(),
+        _ => unreachable!(),
+    };
+    // for each key, the number of puts and the last row put
+    let mut put: BTreeMap<Tuple, (usize, Tuple)> = BTreeMap::new();
+    for v in new_tuples {
+        let tuple = as_tuple(v);
+        let entry = put.entry(tuple[..key_len].to_vec()).or_default();
+        entry.0 += 1;
+        entry.1 = tuple;
+    }
+    // for each key, the number of rows overwritten and the first of them
+    let mut overwritten: BTreeMap<Tuple, (usize, Tuple)> = BTreeMap::new();
+    for v in old_tuples {
+        let tuple = as_tuple(v);
+        overwritten
+            .entry(tuple[..key_len].to_vec())
+            .or_insert_with(|| (0, tuple))
+            .0 += 1;
+    }
+    let mut inserted = vec![];
+    let mut deleted = vec![];
+    for (key, (n_put, last)) in put {
+        match overwritten.remove(&key) {
+            // the key existed before the batch only if every put overwrote a row
+            Some((n_overwritten, original)) if n_overwritten == n_put => {
+                if original != last {
+                    deleted.push(original);
+                    inserted.push(last);
+                }
+            }
+            _ => inserted.push(last),
+        }
+    }
+    (inserted, deleted)
+}
+
+pub(crate) fn make_const_rule(
     program: &mut InputProgram,
     rule_name: &str,
     bindings: Vec<Symbol>,
@@ -14,7 +14,7 @@ use miette::{ensure, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::program::{
-    FixedRuleArg, MagicSymbol, NormalFormAtom, NormalFormProgram, NormalFormRulesOrFixed,
+    FixedRuleArg, NormalFormAtom, NormalFormProgram, NormalFormRulesOrFixed,
     StratifiedNormalFormProgram,
 };
 use crate::data::symb::{Symbol, PROG_ENTRY};
@@ -28,10 +28,13 @@ impl NormalFormAtom {
         match self {
             NormalFormAtom::Relation(_)
             | NormalFormAtom::NegatedRelation(_)
+            | NormalFormAtom::OptionalRelation(_)
             | NormalFormAtom::Predicate(_)
             | NormalFormAtom::Unification(_) => Default::default(),
             NormalFormAtom::Rule(r) => BTreeMap::from([(&r.name, false)]),
-            NormalFormAtom::NegatedRule(r) => BTreeMap::from([(&r.name, true)]),
+            NormalFormAtom::NegatedRule(r) | NormalFormAtom::OptionalRule(r) => {
+                BTreeMap::from([(&r.name, true)])
+            }
         }
     }
 }
@@ -164,7 +167,7 @@ fn verify_no_cycle(g: &StratifiedGraph<&'_ Symbol>, sccs: &[BTreeSet<&Symbol>])
                     #[diagnostic(help(
                         "The rule '{0}' is in the strongly connected component {1:?},\n\
                     and is involved in at least one forbidden dependency \n\
-                    (negation, non-meet aggregation, or algorithm-application)."
+                    (negation, optional application, non-meet aggregation, or algorithm-application)."
                     ))]
                     struct UnStratifiableProgram(String, Vec<String>);
 
@@ -219,9 +222,7 @@ fn make_scc_reduced_graph(
 
 impl NormalFormProgram {
     /// returns the stratified program and the store lifetimes of the intermediate relations
-    pub(crate) fn into_stratified_program(
-        self,
-    ) -> Result<(StratifiedNormalFormProgram, BTreeMap<MagicSymbol, usize>)> {
+    pub(crate) fn into_stratified_program(self) -> Result<StratifiedNormalFormProgram> {
         // prerequisite: the program is already in disjunctive normal form
         // 0. build a graph of the program
         let prog_entry: &Symbol = &Symbol::new(PROG_ENTRY, SourceSpan(0, 0));
@@ -263,31 +264,6 @@ impl NormalFormProgram {
         let mut ret: Vec<NormalFormProgram> =
             (0..n_strata).map(|_| Default::default()).collect_vec();
 
-        let mut store_lifetimes = BTreeMap::new();
-        for (fr, tos) in &stratified_graph {
-            if let Some(fr_idx) = invert_indices.get(fr) {
-                if let Some(fr_stratum) = invert_sort_result.get(fr_idx) {
-                    for to in tos.keys() {
-                        let used_in = n_strata - 1 - *fr_stratum;
-                        let magic_to = MagicSymbol::Muggle {
-                            inner: (*to).clone(),
-                        };
-                        match store_lifetimes.entry(magic_to) {
-                            Entry::Vacant(e) => {
-                                e.insert(used_in);
-                            }
-                            Entry::Occupied(mut o) => {
-                                let existing = *o.get();
-                                if used_in > existing {
-                                    o.insert(used_in);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
         for (name, ruleset) in self.prog {
             if let Some(scc_idx) = invert_indices.get(&name) {
                 if let Some(rev_stratum_idx) = invert_sort_result.get(scc_idx) {
@@ -297,7 +273,7 @@ impl NormalFormProgram {
             }
         }
 
-        Ok((StratifiedNormalFormProgram(ret), store_lifetimes))
+        Ok(StratifiedNormalFormProgram(ret))
     }
 }
 
@@ -27,36 +27,49 @@ use either::{Left, Right};
 use itertools::Itertools;
 #[allow(unused_imports)]
 use miette::{bail, Diagnostic, ensure, IntoDiagnostic, miette, Result, WrapErr};
-use miette::Report;
+use miette::{Report, Severity};
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::{decode_tuple_from_kv, FixedRule};
+use crate::{decode_tuple_from_kv, format_error_as_json, FixedRule};
+use crate::data::aggr::{parse_aggr, Aggregation};
+use crate::data::expr::{get_op, CustomFunction};
 use crate::data::functions::current_validity;
 use crate::data::json::JsonValue;
-use crate::data::program::{InputProgram, QueryAssertion, RelationOp};
+use crate::data::program::{InputProgram, QueryAssertion, QueryOutOptions, RelationOp};
 use crate::data::relation::ColumnDef;
+use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::{DataValue, LARGEST_UTF_CHAR, ValidityTs};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
-use crate::parse::{CozoScript, parse_script, SourceSpan};
+use crate::parse::{CozoScript, parse_script, ParseContext, SourceSpan};
 use crate::parse::sys::SysOp;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::query::compile::{partition_driving_scans, KeySampleCache};
 use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::cursor::{next_page_token, push_down_cursor};
 use crate::query::ra::{
-    FilteredRA, InnerJoin, NegJoin, RelAlgebra, ReorderRA, StoredRA, StoredWithValidityRA,
-    TempStoreRA, UnificationRA,
+    FilteredRA, InnerJoin, NegJoin, OuterJoin, RelAlgebra, ReorderRA, StoredRA,
+    StoredWithValidityRA, TempStoreRA, UnificationRA,
 };
+use crate::query::sort::RowSorter;
+use crate::query::stored::net_changes;
 #[allow(unused_imports)]
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::prepared::PlanCache;
+use crate::runtime::procedure::CallDepthGuard;
 use crate::runtime::relation::{
     AccessLevel, extend_tuple_from_v, InsufficientAccessLevel, RelationHandle, RelationId,
 };
 use crate::runtime::transact::SessionTx;
+use crate::runtime::view::ViewNameConflictError;
+use crate::storage::overlay::CatalogOverlayTx;
 use crate::storage::{Storage, StoreTx};
 use crate::storage::temp::TempStorage;
+use crate::utils::{with_seeded_rng, ArithModeGuard};
 
 pub(crate) struct RunningQueryHandle {
     pub(crate) started_at: f64,
@@ -91,11 +104,19 @@ pub struct Db<S> {
     pub(crate) queries_count: Arc<AtomicU64>,
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
+    pub(crate) custom_functions: Arc<ShardedLock<BTreeMap<String, CustomFunction>>>,
+    pub(crate) custom_aggregations: Arc<ShardedLock<BTreeMap<String, Arc<dyn Aggregation>>>>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    pub(crate) plan_cache: Arc<Mutex<PlanCache>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) key_samples: Arc<Mutex<KeySampleCache>>,
+    /// Incremented whenever registered functions, aggregations, fixed rules or views change,
+    /// which makes the plans compiled before out of date
+    pub(crate) plan_generation: Arc<AtomicU64>,
 }
 
 impl<S> Debug for Db<S> {
@@ -123,6 +144,10 @@ pub struct NamedRows {
     pub rows: Vec<Tuple>,
     /// Contains the next named rows, if exists
     pub next: Option<Box<NamedRows>>,
+    /// For queries paginated with `:cursor`, the token to pass to `:cursor` for the next page,
+    /// if there may be more rows
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 impl NamedRows {
@@ -132,6 +157,7 @@ impl NamedRows {
             headers,
             rows,
             next: None,
+            cursor: None,
         }
     }
 
@@ -167,11 +193,15 @@ impl NamedRows {
             .into_iter()
             .map(|row| row.into_iter().map(JsonValue::from).collect::<JsonValue>())
             .collect::<JsonValue>();
-        json!({
+        let mut ret = json!({
             "headers": self.headers,
             "rows": rows,
             "next": nxt,
-        })
+        });
+        if let Some(cursor) = self.cursor {
+            ret["cursor"] = json!(cursor);
+        }
+        ret
     }
     /// Make named rows from JSON
     pub fn from_json(value: &JsonValue) -> Result<Self> {
@@ -200,11 +230,7 @@ impl NamedRows {
                 Ok(row.iter().map(|el| DataValue::from(el)).collect_vec())
             })
             .try_collect()?;
-        Ok(Self {
-            headers,
-            rows,
-            next: None,
-        })
+        Ok(Self::new(headers, rows))
     }
 }
 
@@ -220,6 +246,72 @@ pub enum TransactionPayload {
     Abort,
     /// Run a query inside the transaction
     Query((String, BTreeMap<String, DataValue>)),
+    /// Set a savepoint with the given name. Savepoints may be nested, and names may be reused:
+    /// a name then refers to the most recent savepoint set with it.
+    Savepoint(String),
+    /// Undo the changes made since the named savepoint was set. The savepoint stays active,
+    /// whereas the savepoints set after it are removed.
+    RollbackTo(String),
+    /// Remove the named savepoint and those set after it, keeping the changes made since.
+    Release(String),
+}
+
+/// A savepoint set in a multi-transaction, with the lengths of the collected cleanups
+/// and callbacks at the time, to which they are truncated when the savepoint is rolled back to
+struct MultiTxSavepoint {
+    name: String,
+    n_cleanups: usize,
+    n_callbacks: BTreeMap<SmartString<LazyCompact>, usize>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No savepoint named '{0}' is active in the transaction")]
+#[diagnostic(code(tx::savepoint_not_found))]
+struct SavepointNotFound(String);
+
+fn find_savepoint(savepoints: &[MultiTxSavepoint], name: &str) -> Result<usize> {
+    match savepoints.iter().rposition(|sp| sp.name == name) {
+        None => bail!(SavepointNotFound(name.to_string())),
+        Some(idx) => Ok(idx),
+    }
+}
+
+fn rollback_to_savepoint(
+    tx: &mut SessionTx<'_>,
+    savepoints: &mut Vec<MultiTxSavepoint>,
+    name: &str,
+    cleanups: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    callback_collector: &mut CallbackCollector,
+) -> Result<()> {
+    let idx = find_savepoint(savepoints, name)?;
+    for _ in idx..savepoints.len() {
+        tx.rollback_to_savepoint()?;
+    }
+    tx.set_savepoint()?;
+    savepoints.truncate(idx + 1);
+    let savepoint = &savepoints[idx];
+    cleanups.truncate(savepoint.n_cleanups);
+    callback_collector.retain(|k, v| match savepoint.n_callbacks.get(k) {
+        None => false,
+        Some(n) => {
+            v.truncate(*n);
+            true
+        }
+    });
+    Ok(())
+}
+
+fn release_savepoint(
+    tx: &mut SessionTx<'_>,
+    savepoints: &mut Vec<MultiTxSavepoint>,
+    name: &str,
+) -> Result<()> {
+    let idx = find_savepoint(savepoints, name)?;
+    for _ in idx..savepoints.len() {
+        tx.pop_savepoint()?;
+    }
+    savepoints.truncate(idx);
+    Ok(())
 }
 
 impl<'s, S: Storage<'s>> Db<S> {
@@ -234,12 +326,18 @@ impl<'s, S: Storage<'s>> Db<S> {
             queries_count: Default::default(),
             running_queries: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
+            custom_functions: Default::default(),
+            custom_aggregations: Default::default(),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
             // callback_receiver: Arc::new(receiver),
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            plan_cache: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            key_samples: Default::default(),
+            plan_generation: Default::default(),
         };
         Ok(ret)
     }
@@ -281,6 +379,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         let callback_targets = self.current_callback_targets();
         let mut callback_collector = BTreeMap::new();
         let mut write_locks = BTreeMap::new();
+        let mut savepoints = vec![];
 
         for payload in payloads {
             match payload {
@@ -303,19 +402,61 @@ impl<'s, S: Storage<'s>> Db<S> {
                     let _ = results.send(Ok(NamedRows::default()));
                     break;
                 }
+                TransactionPayload::Savepoint(name) => {
+                    let res = tx.set_savepoint().map(|_| {
+                        savepoints.push(MultiTxSavepoint {
+                            name,
+                            n_cleanups: cleanups.len(),
+                            n_callbacks: callback_collector
+                                .iter()
+                                .map(|(k, v)| (k.clone(), v.len()))
+                                .collect(),
+                        });
+                        NamedRows::default()
+                    });
+                    if results.send(res).is_err() {
+                        break;
+                    }
+                }
+                TransactionPayload::RollbackTo(name) => {
+                    let res = rollback_to_savepoint(
+                        &mut tx,
+                        &mut savepoints,
+                        &name,
+                        &mut cleanups,
+                        &mut callback_collector,
+                    );
+                    if results.send(res.map(|_| NamedRows::default())).is_err() {
+                        break;
+                    }
+                }
+                TransactionPayload::Release(name) => {
+                    let res = release_savepoint(&mut tx, &mut savepoints, &name);
+                    if results.send(res.map(|_| NamedRows::default())).is_err() {
+                        break;
+                    }
+                }
                 TransactionPayload::Query((script, params)) => {
-                    let p =
-                        match parse_script(&script, &params, &self.fixed_rules.read().unwrap(), ts)
-                        {
-                            Ok(p) => p,
-                            Err(err) => {
-                                if results.send(Err(err)).is_err() {
-                                    break;
-                                } else {
-                                    continue;
-                                }
+                    let p = match parse_script(
+                        &script,
+                        &ParseContext {
+                            params: &params,
+                            custom_fns: &self.custom_functions.read().unwrap(),
+                            custom_aggrs: &self.custom_aggregations.read().unwrap(),
+                            in_rule_body: false,
+                        },
+                        &self.fixed_rules.read().unwrap(),
+                        ts,
+                    ) {
+                        Ok(p) => p,
+                        Err(err) => {
+                            if results.send(Err(err)).is_err() {
+                                break;
+                            } else {
+                                continue;
                             }
-                        };
+                        }
+                    };
 
                     let p = match p.get_single_program() {
                         Ok(p) => p,
@@ -363,8 +504,72 @@ impl<'s, S: Storage<'s>> Db<S> {
         params: BTreeMap<String, DataValue>,
     ) -> Result<NamedRows> {
         let cur_vld = current_validity();
+        if self.plan_cache.lock().unwrap().is_enabled() {
+            return self.run_script_cached(payload, &params, cur_vld);
+        }
         self.do_run_script(payload, &params, cur_vld)
     }
+    /// Check the CozoScript passed in without running it: parse it, resolve the relations and
+    /// fixed rules it uses, and compile its queries, all within a read transaction.
+    /// The result is a JSON object with the field `ok`, and the field `diagnostics` listing
+    /// every error found, each formatted as by [crate::format_error_as_json].
+    ///
+    /// Changes the script makes to the catalog, such as the relations it creates, are kept in
+    /// memory and never written, so that the statements after them are checked against them.
+    ///
+    /// In imperative scripts, queries using parameters bound by `%let` or `%for` are checked
+    /// with the parameters left unknown. Where the parameters shape the query itself, as in
+    /// `:limit $n`, the query is not checked, and a diagnostic of severity `warning` says so.
+    /// The field `ok` is true if there are no diagnostics other than warnings.
+    pub fn check_script(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> JsonValue {
+        let mut errors = vec![];
+        if let Err(err) = self.do_check_script(payload, &params, &mut errors) {
+            errors.push(err);
+        }
+        let ok = errors
+            .iter()
+            .all(|err| err.severity() == Some(Severity::Warning));
+        let diagnostics = errors
+            .into_iter()
+            .map(|err| {
+                let mut json = format_error_as_json(err, Some(payload));
+                json.as_object_mut().unwrap().remove("ok");
+                json
+            })
+            .collect_vec();
+        json!({"ok": ok, "diagnostics": diagnostics})
+    }
+    /// Run the procedure stored by `::procedure create`.
+    /// The `params` argument gives the values of all its parameters by name.
+    pub fn call_procedure(
+        &'s self,
+        name: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let name = Symbol::new(name, Default::default());
+        let procedure = self.transact()?.get_procedure(&name)?;
+        procedure.check_params(&name, &params)?;
+        self.run_procedure(&name, &procedure.definition, params)
+    }
+    fn run_procedure(
+        &'s self,
+        name: &Symbol,
+        definition: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let _depth = CallDepthGuard::enter(name)?;
+        self.run_script(definition, params).map_err(|err| {
+            if err.source_code().is_some() {
+                err
+            } else {
+                err.with_source_code(definition.to_string())
+            }
+        })
+    }
     /// Export relations to JSON data.
     ///
     /// `relations` contains names of the stored relations to export.
@@ -408,7 +613,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             for data in tx.store_tx.range_scan(&start, &end) {
                 let (k, v) = data?;
                 let tuple = decode_tuple_from_kv(&k, &v);
-                rows.push(tuple);
+                rows.push(handle.restore_collated_keys(tuple));
             }
             let headers = cols.iter().map(|col| col.to_string()).collect_vec();
             ret.insert(rel.as_ref().to_string(), NamedRows::new(headers, rows));
@@ -422,6 +627,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     ///
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
+    /// Materialized views computed from the relations are kept up to date.
     pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
         #[derive(Debug, Diagnostic, Error)]
         #[error("cannot import data for relation '{0}': {1}")]
@@ -453,6 +659,9 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
             let handle = tx.get_relation(relation, false)?;
             let has_indices = !handle.indices.is_empty();
+            let has_views = !handle.materialized_views.is_empty();
+            let mut new_tuples: Vec<DataValue> = vec![];
+            let mut old_tuples: Vec<DataValue> = vec![];
 
             if handle.access_level < AccessLevel::Protected {
                 bail!(InsufficientAccessLevel(
@@ -516,11 +725,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                     })
                     .try_collect()?;
                 let k_store = handle.encode_key_for_store(&keys, Default::default())?;
-                if has_indices {
+                if has_indices || has_views {
                     if let Some(existing) = tx.store_tx.get(&k_store, false)? {
                         let mut old = keys.clone();
                         extend_tuple_from_v(&mut old, &existing);
-                        if is_delete || old != row {
+                        let old = handle.restore_collated_keys(old);
+                        if has_indices && (is_delete || old != row) {
                             for (idx_rel, extractor) in handle.indices.values() {
                                 let idx_tup =
                                     extractor.iter().map(|i| old[*i].clone()).collect_vec();
@@ -529,6 +739,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                                 tx.store_tx.del(&encoded)?;
                             }
                         }
+                        if has_views {
+                            old_tuples.push(DataValue::List(old));
+                        }
                     }
                 }
                 if is_delete {
@@ -545,18 +758,45 @@ impl<'s, S: Storage<'s>> Db<S> {
                         .try_collect()?;
                     let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
                     tx.store_tx.put(&k_store, &v_store)?;
+                    let mut kv = keys;
+                    kv.extend(vals);
                     if has_indices {
-                        let mut kv = keys;
-                        kv.extend(vals);
                         for (idx_rel, extractor) in handle.indices.values() {
                             let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
                             let encoded =
                                 idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                            tx.store_tx.put(&encoded, &[])?;
+                            tx.store_tx
+                                .put(&encoded, &idx_rel.encode_index_val(&idx_tup)?)?;
                         }
                     }
+                    if has_views {
+                        new_tuples.push(DataValue::List(kv));
+                    }
                 }
             }
+            if has_views {
+                let (inserted, deleted) = if is_delete {
+                    let deleted = old_tuples
+                        .into_iter()
+                        .map(|v| match v {
+                            DataValue::List(l) => l,
+                            _ => unreachable!(),
+                        })
+                        .collect_vec();
+                    (vec![], deleted)
+                } else {
+                    net_changes(handle.metadata.keys.len(), &new_tuples, &old_tuples)
+                };
+                tx.maintain_materialized_views(
+                    self,
+                    &handle,
+                    inserted,
+                    deleted,
+                    cur_vld,
+                    &Default::default(),
+                    &mut Default::default(),
+                )?;
+            }
         }
         tx.commit_tx()?;
         Ok(())
@@ -613,6 +853,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     ///
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
+    /// Materialized views computed from the relations are kept up to date.
     #[allow(unused_variables)]
     pub fn import_from_backup(
         &'s self,
@@ -668,10 +909,34 @@ impl<'s, S: Storage<'s>> Db<S> {
                         Ok((src_k, src_v))
                     },
                 );
+                let has_views = !dst_handle.materialized_views.is_empty();
+                let mut new_tuples: Vec<DataValue> = vec![];
+                let mut old_tuples: Vec<DataValue> = vec![];
                 for result in data_it {
                     let (key, val) = result?;
+                    if has_views {
+                        if let Some(existing) = dst_tx.store_tx.get(&key, false)? {
+                            let old = crate::decode_tuple_from_kv(&key, &existing);
+                            old_tuples.push(DataValue::List(dst_handle.restore_collated_keys(old)));
+                        }
+                        let new = crate::decode_tuple_from_kv(&key, &val);
+                        new_tuples.push(DataValue::List(dst_handle.restore_collated_keys(new)));
+                    }
                     dst_tx.store_tx.put(&key, &val)?;
                 }
+                if has_views {
+                    let (inserted, deleted) =
+                        net_changes(dst_handle.metadata.keys.len(), &new_tuples, &old_tuples);
+                    dst_tx.maintain_materialized_views(
+                        self,
+                        &dst_handle,
+                        inserted,
+                        deleted,
+                        current_validity(),
+                        &Default::default(),
+                        &mut Default::default(),
+                    )?;
+                }
             }
 
             src_tx.commit_tx()?;
@@ -686,6 +951,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         match self.fixed_rules.write().unwrap().entry(name) {
             Entry::Vacant(ent) => {
                 ent.insert(Arc::new(Box::new(rule_impl)));
+                self.invalidate_plans();
                 Ok(())
             }
             Entry::Occupied(ent) => {
@@ -702,7 +968,83 @@ impl<'s, S: Storage<'s>> Db<S> {
         if DEFAULT_FIXED_RULES.contains_key(name) {
             bail!("Cannot unregister builtin fixed rule {}", name);
         }
-        Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
+        let removed = self.fixed_rules.write().unwrap().remove(name).is_some();
+        self.invalidate_plans();
+        Ok(removed)
+    }
+
+    /// Register a custom function taking exactly `arity` arguments, callable in expressions
+    /// by `name`. Custom functions are only called during evaluation, so they cannot be used
+    /// where a constant is required, such as in the default values of stored relations.
+    pub fn register_function<F>(&self, name: String, arity: usize, func: F) -> Result<()>
+    where
+        F: Fn(&[DataValue]) -> Result<DataValue> + Send + Sync + 'static,
+    {
+        if get_op(&name).is_some() || name == "cond" || name == "if" {
+            bail!("Cannot register builtin function {}", name);
+        }
+        match self.custom_functions.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                let func = CustomFunction {
+                    name: SmartString::from(ent.key().as_str()),
+                    arity,
+                    inner: Arc::new(func),
+                };
+                ent.insert(func);
+                self.invalidate_plans();
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!("A function with the name {} is already registered", ent.key())
+            }
+        }
+    }
+
+    /// Unregister a custom function.
+    pub fn unregister_function(&self, name: &str) -> Result<bool> {
+        let removed = self
+            .custom_functions
+            .write()
+            .unwrap()
+            .remove(name)
+            .is_some();
+        self.invalidate_plans();
+        Ok(removed)
+    }
+
+    /// Register a custom aggregation, usable in rule heads by `name`.
+    pub fn register_aggregation<A>(&self, name: String, aggr_impl: A) -> Result<()>
+    where
+        A: Aggregation + 'static,
+    {
+        if parse_aggr(&name).is_some() {
+            bail!("Cannot register builtin aggregation {}", name);
+        }
+        match self.custom_aggregations.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                ent.insert(Arc::new(aggr_impl));
+                self.invalidate_plans();
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "An aggregation with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister a custom aggregation.
+    pub fn unregister_aggregation(&self, name: &str) -> Result<bool> {
+        let removed = self
+            .custom_aggregations
+            .write()
+            .unwrap()
+            .remove(name)
+            .is_some();
+        self.invalidate_plans();
+        Ok(removed)
     }
 
     /// Register callback channel to receive changes when the requested relation are successfully committed.
@@ -831,29 +1173,186 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(q_res)
     }
 
-    fn do_run_script(
+    pub(crate) fn do_run_script(
         &'s self,
         payload: &str,
         param_pool: &BTreeMap<String, DataValue>,
         cur_vld: ValidityTs,
     ) -> Result<NamedRows> {
-        match parse_script(
+        let script = parse_script(
             payload,
-            param_pool,
+            &ParseContext {
+                params: param_pool,
+                custom_fns: &self.custom_functions.read().unwrap(),
+                custom_aggrs: &self.custom_aggregations.read().unwrap(),
+                in_rule_body: false,
+            },
             &self.fixed_rules.read().unwrap(),
             cur_vld,
-        )? {
+        )?;
+        match script {
             CozoScript::Single(p) => self.execute_single(cur_vld, p),
-            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps),
+            CozoScript::Imperative(ps) => {
+                self.execute_imperative(cur_vld, &ps, payload, param_pool)
+            }
             CozoScript::Sys(op) => self.run_sys_op(op),
         }
     }
 
+    fn do_check_script(
+        &'s self,
+        payload: &str,
+        param_pool: &BTreeMap<String, DataValue>,
+        errors: &mut Vec<Report>,
+    ) -> Result<()> {
+        let script = parse_script(
+            payload,
+            &ParseContext {
+                params: param_pool,
+                custom_fns: &self.custom_functions.read().unwrap(),
+                custom_aggrs: &self.custom_aggregations.read().unwrap(),
+                in_rule_body: false,
+            },
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        // the relations, indices and views declared by the script are kept in a catalog of
+        // its own over the read transaction, so that the statements using them can be checked
+        let tx = self.transact()?;
+        let mut tx = SessionTx {
+            store_tx: Box::new(CatalogOverlayTx::new(tx.store_tx)),
+            temp_store_tx: tx.temp_store_tx,
+            // the relations declared must not use up the ids of the database
+            relation_store_id: Arc::new(AtomicU64::new(
+                self.relation_store_id.load(Ordering::SeqCst),
+            )),
+            temp_store_id: Default::default(),
+        };
+        match script {
+            CozoScript::Single(p) => errors.extend(self.check_program(&mut tx, p).err()),
+            CozoScript::Imperative(ps) => self.check_imperative_stmts(&ps, &mut tx, errors)?,
+            CozoScript::Sys(op) => errors.extend(self.check_sys_op(&mut tx, op).err()),
+        }
+        Ok(())
+    }
+
+    /// Check the program as [`Db::run_query`] would before evaluating it. The relation it
+    /// creates or replaces is recorded in the catalog of `tx`, which is never committed.
+    pub(crate) fn check_program(&'s self, tx: &mut SessionTx<'_>, p: InputProgram) -> Result<()> {
+        check_store_relation(tx, &p.out_opts)?;
+        let store_relation = p.out_opts.store_relation.clone();
+        self.compile_query(tx, p)?;
+        match store_relation {
+            Some((meta, RelationOp::Create)) => {
+                tx.create_relation(meta)?;
+            }
+            Some((meta, RelationOp::Replace)) => {
+                if !tx.relation_exists(&meta.name)? {
+                    tx.create_relation(meta)?;
+                } else if tx
+                    .get_relation(&meta.name, false)?
+                    .materialized_views
+                    .is_empty()
+                {
+                    // relations with materialized views are cleared in place instead
+                    tx.destroy_relation(&meta.name)?;
+                    tx.create_relation(meta)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check the system op as [`Db::run_sys_op`] would run it, against the catalog in `tx`.
+    /// Its changes are recorded in that catalog, except that new indices are left empty, and
+    /// that views are compiled but not computed.
+    fn check_sys_op(&'s self, tx: &mut SessionTx<'_>, op: SysOp) -> Result<()> {
+        match op {
+            SysOp::Explain(prog) => {
+                self.compile_query(tx, *prog)?;
+            }
+            SysOp::ListRelation(name) | SysOp::ShowTrigger(name) => {
+                tx.get_relation(&name, false)?;
+            }
+            SysOp::RemoveRelation(names) => {
+                for name in names {
+                    tx.destroy_relation(&name)?;
+                }
+            }
+            SysOp::RenameRelation(rename_pairs) => {
+                for (old, new) in rename_pairs {
+                    tx.rename_relation(old, new)?;
+                }
+            }
+            SysOp::SetTriggers(name, puts, rms, replaces) => {
+                tx.set_relation_triggers(name, puts, rms, replaces)?
+            }
+            SysOp::SetAccessLevel(names, level) => {
+                for name in names {
+                    tx.set_access_level(name, level)?;
+                }
+            }
+            SysOp::CreateIndex(rel_name, idx_name, cols) => {
+                tx.create_index(&rel_name, &idx_name, cols, false)?
+            }
+            SysOp::RemoveIndex(rel_name, idx_name) => tx.remove_index(&rel_name, &idx_name)?,
+            SysOp::CreateView(name, definition, materialized) => {
+                tx.check_create_view(self, &name, &definition, materialized)?
+            }
+            SysOp::RemoveView(name) => {
+                tx.remove_view(&name)?;
+            }
+            SysOp::CreateProcedure(name, params, definition) => {
+                tx.create_procedure(&name, &params, &definition)?
+            }
+            SysOp::RemoveProcedure(name) => tx.remove_procedure(&name)?,
+            SysOp::CallProcedure(name, args) => {
+                tx.get_procedure(&name)?.positional_params(&name, args)?;
+            }
+            SysOp::Compact
+            | SysOp::ListRelations
+            | SysOp::ListRunning
+            | SysOp::ListFixedRules
+            | SysOp::KillRunning(_)
+            | SysOp::ListViews
+            | SysOp::ListProcedures => {}
+        }
+        Ok(())
+    }
+
     fn execute_single(&'s self, cur_vld: ValidityTs, p: InputProgram) -> Result<NamedRows, Report> {
+        let write_lock_name = p.needs_write_lock();
+        self.run_in_transaction(
+            write_lock_name,
+            |tx, cleanups, callback_targets, callback_collector| {
+                self.execute_single_program(
+                    p,
+                    tx,
+                    cleanups,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                )
+            },
+        )
+    }
+    /// Run `f` in a transaction of its own, which is a write transaction holding the lock of
+    /// the relation if `write_lock_name` is given.
+    /// Callbacks and cleanups collected by `f` are dealt with after the transaction commits.
+    pub(crate) fn run_in_transaction<T>(
+        &'s self,
+        write_lock_name: Option<SmartString<LazyCompact>>,
+        f: impl FnOnce(
+            &mut SessionTx<'_>,
+            &mut Vec<(Vec<u8>, Vec<u8>)>,
+            &BTreeSet<SmartString<LazyCompact>>,
+            &mut CallbackCollector,
+        ) -> Result<T>,
+    ) -> Result<T> {
         let mut callback_collector = BTreeMap::new();
-        let write_lock_names = p.needs_write_lock();
-        let is_write = write_lock_names.is_some();
-        let write_lock = self.obtain_relation_locks(write_lock_names.iter());
+        let is_write = write_lock_name.is_some();
+        let write_lock = self.obtain_relation_locks(write_lock_name.iter());
         let _write_lock_guards = if is_write {
             Some(write_lock[0].read().unwrap())
         } else {
@@ -873,11 +1372,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 self.transact()?
             };
 
-            res = self.execute_single_program(
-                p,
+            res = f(
                 &mut tx,
                 &mut cleanups,
-                cur_vld,
                 &callback_targets,
                 &mut callback_collector,
             )?;
@@ -1018,6 +1515,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         rel_stack.push(right);
                                         (t, json!(null), json!(joiner.as_map()), json!(null))
                                     }
+                                    RelAlgebra::OuterJoin(inner) => {
+                                        let t = inner.join_type();
+                                        let OuterJoin {
+                                            left,
+                                            right,
+                                            joiner,
+                                            ..
+                                        } = inner.as_ref();
+                                        rel_stack.push(left);
+                                        rel_stack.push(right);
+                                        (t, json!(null), json!(joiner.as_map()), json!(null))
+                                    }
                                     RelAlgebra::Reorder(ReorderRA { relation, .. }) => {
                                         rel_stack.push(relation);
                                         ("reorder", json!(null), json!(null), json!(null))
@@ -1093,10 +1602,11 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
     fn run_sys_op(&'s self, op: SysOp) -> Result<NamedRows> {
         match op {
-            SysOp::Explain(prog) => {
+            SysOp::Explain(mut prog) => {
                 let mut tx = self.transact()?;
+                self.inline_views(&tx, &mut prog)?;
                 let (normalized_program, _) = prog.into_normalized_program(&tx)?;
-                let (stratified_program, _) = normalized_program.into_stratified_program()?;
+                let stratified_program = normalized_program.into_stratified_program()?;
                 let program = stratified_program.magic_sets_rewrite(&tx)?;
                 let compiled = tx.stratified_magic_compile(program)?;
                 tx.commit_tx()?;
@@ -1148,7 +1658,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     .unwrap();
                 let _guard = lock.write().unwrap();
                 let mut tx = self.transact_write()?;
-                tx.create_index(&rel_name, &idx_name, cols)?;
+                tx.create_index(&rel_name, &idx_name, cols, true)?;
                 tx.commit_tx()?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1233,6 +1743,105 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::CreateView(name, definition, materialized) => {
+                let mut tx = self.transact_write()?;
+                if materialized {
+                    tx.create_materialized_view(self, &name, &definition)?;
+                } else {
+                    tx.create_view(&name, &definition)?;
+                }
+                tx.commit_tx()?;
+                self.invalidate_plans();
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveView(name) => {
+                let mut tx = self.transact_write()?;
+                let bounds = tx.remove_view(&name)?;
+                tx.commit_tx()?;
+                if let Some((lower, upper)) = bounds {
+                    self.db.del_range(&lower, &upper)?;
+                }
+                self.invalidate_plans();
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListViews => {
+                let tx = self.transact()?;
+                let rows = tx
+                    .list_views()?
+                    .into_iter()
+                    .map(|(name, view)| {
+                        vec![
+                            DataValue::from(name),
+                            DataValue::from(view.definition),
+                            DataValue::from(view.materialized),
+                        ]
+                    })
+                    .collect_vec();
+                Ok(NamedRows::new(
+                    vec![
+                        "name".to_string(),
+                        "definition".to_string(),
+                        "materialized".to_string(),
+                    ],
+                    rows,
+                ))
+            }
+            SysOp::CreateProcedure(name, params, definition) => {
+                let mut tx = self.transact_write()?;
+                tx.create_procedure(&name, &params, &definition)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveProcedure(name) => {
+                let mut tx = self.transact_write()?;
+                tx.remove_procedure(&name)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListProcedures => {
+                let tx = self.transact()?;
+                let rows = tx
+                    .list_procedures()?
+                    .into_iter()
+                    .map(|(name, procedure)| {
+                        let params = procedure
+                            .params
+                            .into_iter()
+                            .map(DataValue::Str)
+                            .collect_vec();
+                        vec![
+                            DataValue::from(name),
+                            DataValue::List(params),
+                            DataValue::from(procedure.definition),
+                        ]
+                    })
+                    .collect_vec();
+                Ok(NamedRows::new(
+                    vec![
+                        "name".to_string(),
+                        "params".to_string(),
+                        "definition".to_string(),
+                    ],
+                    rows,
+                ))
+            }
+            SysOp::CallProcedure(name, args) => {
+                let procedure = self.transact()?.get_procedure(&name)?;
+                let params = procedure.positional_params(&name, args)?;
+                self.run_procedure(&name, &procedure.definition, params)
+            }
             SysOp::SetAccessLevel(names, level) => {
                 let mut tx = self.transact_write()?;
                 for name in names {
@@ -1246,9 +1855,29 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
     }
+    pub(crate) fn compile_query(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        mut input_program: InputProgram,
+    ) -> Result<CompiledQuery> {
+        // constants are folded during compilation
+        let _arith = ArithModeGuard::set(input_program.out_opts.arith);
+        let entry_head = input_program.get_entry_out_head_or_default()?;
+        let views = self.inline_views(tx, &mut input_program)?;
+        let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
+        let stratified_program = normalized_program.into_stratified_program()?;
+        let program = stratified_program.magic_sets_rewrite(tx)?;
+        let strata = tx.stratified_magic_compile(program)?;
+        Ok(CompiledQuery {
+            strata,
+            out_opts,
+            entry_head,
+            views,
+        })
+    }
     /// This is the entry to query evaluation
     pub(crate) fn run_query(
-        &self,
+        &'s self,
         tx: &mut SessionTx<'_>,
         input_program: InputProgram,
         cur_vld: ValidityTs,
@@ -1256,51 +1885,20 @@ impl<'s, S: Storage<'s>> Db<S> {
         callback_collector: &mut CallbackCollector,
         top_level: bool,
     ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
-        // cleanups contain stored relations that should be deleted at the end of query
-        let mut clean_ups = vec![];
-
-        // Some checks in case the query specifies mutation
-        if let Some((meta, op)) = &input_program.out_opts.store_relation {
-            if *op == RelationOp::Create {
-                #[derive(Debug, Error, Diagnostic)]
-                #[error("Stored relation {0} conflicts with an existing one")]
-                #[diagnostic(code(eval::stored_relation_conflict))]
-                struct StoreRelationConflict(String);
-
-                ensure!(
-                    !tx.relation_exists(&meta.name)?,
-                    StoreRelationConflict(meta.name.to_string())
-                )
-            } else if *op != RelationOp::Replace {
-                #[derive(Debug, Error, Diagnostic)]
-                #[error("Stored relation {0} not found")]
-                #[diagnostic(code(eval::stored_relation_not_found))]
-                struct StoreRelationNotFoundError(String);
-
-                let existing = tx.get_relation(&meta.name, false)?;
-
-                ensure!(
-                    tx.relation_exists(&meta.name)?,
-                    StoreRelationNotFoundError(meta.name.to_string())
-                );
-
-                existing.ensure_compatible(meta, *op == RelationOp::Rm)?;
-            }
-        };
-
-        // query compilation
-        let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
-        let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
-        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
-        let program = stratified_program.magic_sets_rewrite(tx)?;
-        let compiled = tx.stratified_magic_compile(program)?;
-
-        // poison is used to terminate queries early
-        let poison = Poison::default();
-        if let Some(secs) = out_opts.timeout {
-            poison.set_timeout(secs)?;
-        }
-        // give the query an ID and store it so that it can be queried and cancelled
+        check_store_relation(tx, &input_program.out_opts)?;
+        let query = self.compile_query(tx, input_program)?;
+        self.run_compiled_query(
+            tx,
+            query,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            top_level,
+        )
+    }
+    /// Give the query an ID and store it so that it can be queried and cancelled.
+    /// The query is removed from the running queries when the returned guard is dropped.
+    pub(crate) fn register_running_query(&self, poison: &Poison) -> Result<RunningQueryCleanup> {
         let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
 
         // time the query
@@ -1313,31 +1911,87 @@ impl<'s, S: Storage<'s>> Db<S> {
         self.running_queries.lock().unwrap().insert(id, handle);
 
         // RAII cleanups of running query handle
-        let _guard = RunningQueryCleanup {
+        Ok(RunningQueryCleanup {
             id,
             running_queries: self.running_queries.clone(),
-        };
+        })
+    }
+    /// Evaluate a compiled query. The caller is responsible for [`check_store_relation`].
+    pub(crate) fn run_compiled_query(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        query: CompiledQuery,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        top_level: bool,
+    ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        // cleanups contain stored relations that should be deleted at the end of query
+        let mut clean_ups = vec![];
+
+        let CompiledQuery {
+            mut strata,
+            out_opts,
+            entry_head: entry_head_or_default,
+            ..
+        } = query;
+        let _arith = ArithModeGuard::set(out_opts.arith);
+        // paginated results must come in order, and positions in them are skipped
+        // either by the scan driving the entry rule, or after the evaluation
+        let mut cursor_after = None;
+        let mut in_order = true;
+        if let Some(cursor) = &out_opts.cursor {
+            let after = cursor.after.as_ref().map(|pos| &pos.last);
+            in_order =
+                out_opts.sorters.is_empty() && push_down_cursor(&mut strata, after);
+            if !in_order {
+                cursor_after = after;
+            }
+        }
+        // seeded queries are evaluated sequentially, so that the random draws come in order
+        #[cfg(not(target_arch = "wasm32"))]
+        if out_opts.seed.is_none() {
+            partition_driving_scans(&self.db, &self.key_samples, &mut strata)?;
+        }
+
+        // poison is used to terminate queries early
+        let poison = Poison::default();
+        if let Some(secs) = out_opts.timeout {
+            poison.set_timeout(secs)?;
+        }
+        let _guard = self.register_running_query(&poison)?;
 
-        let total_num_to_take = if out_opts.sorters.is_empty() {
+        let total_num_to_take = if out_opts.sorters.is_empty() && in_order {
             out_opts.num_to_take()
         } else {
             None
         };
 
-        let num_to_skip = if out_opts.sorters.is_empty() {
+        let num_to_skip = if out_opts.sorters.is_empty() && in_order {
             out_opts.offset
         } else {
             None
         };
 
         // the real evaluation
-        let (result_store, early_return) = tx.stratified_magic_evaluate(
-            &compiled,
-            store_lifetimes,
-            total_num_to_take,
-            num_to_skip,
-            poison,
-        )?;
+        let evaluate = || {
+            // set again, as seeded queries are evaluated on a thread of their own
+            let _arith = ArithModeGuard::set(out_opts.arith);
+            with_seeded_rng(out_opts.seed, || {
+                tx.stratified_magic_evaluate(strata, total_num_to_take, num_to_skip, poison)
+            })
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let (result_store, early_return) = match out_opts.seed {
+            None => evaluate()?,
+            Some(_) => rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .into_diagnostic()?
+                .install(evaluate)?,
+        };
+        #[cfg(target_arch = "wasm32")]
+        let (result_store, early_return) = evaluate()?;
 
         // deal with assertions
         if let Some(assertion) = &out_opts.assertion {
@@ -1367,12 +2021,20 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         if !out_opts.sorters.is_empty() {
             // sort outputs if required
-            let sorted_result =
-                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+            let sorter = RowSorter::new(&out_opts.sorters, &entry_head_or_default)?;
+            let mut sorted_result = tx.sort_and_collect(result_store, &sorter)?;
+            if let Some(after) = cursor_after {
+                let after = sorter.with_keys(after.clone())?;
+                let start = sorted_result.partition_point(|row| {
+                    sorter.compare(row, &after) != std::cmp::Ordering::Greater
+                });
+                sorted_result.drain(..start);
+            }
+            let sorted_result = sorted_result.into_iter().map(|(_, tuple)| tuple);
             let sorted_iter = if let Some(offset) = out_opts.offset {
-                Left(sorted_result.into_iter().skip(offset))
+                Left(sorted_result.skip(offset))
             } else {
-                Right(sorted_result.into_iter())
+                Right(sorted_result)
             };
             let sorted_iter = if let Some(limit) = out_opts.limit {
                 Left(sorted_iter.take(limit))
@@ -1404,16 +2066,16 @@ impl<'s, S: Storage<'s>> Db<S> {
             } else {
                 // not sorting outputs
                 let rows: Vec<Tuple> = sorted_iter.collect_vec();
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let cursor = next_page_token(&out_opts, &entry_head_or_default, &rows);
+                let mut res = NamedRows::new(
+                    entry_head_or_default
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect_vec(),
+                    rows,
+                );
+                res.cursor = cursor;
+                Ok((res, clean_ups))
             }
         } else {
             let scan = if early_return {
@@ -1426,12 +2088,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                 Right(Right(
                     result_store
                         .all_iter()
+                        .map(|t| t.into_tuple())
+                        .skip_while(|t| matches!(cursor_after, Some(after) if t <= after))
                         .skip(offset)
-                        .take(limit)
-                        .map(|t| t.into_tuple()),
+                        .take(limit),
                 ))
             } else {
-                Left(result_store.all_iter().map(|t| t.into_tuple()))
+                Left(
+                    result_store
+                        .all_iter()
+                        .map(|t| t.into_tuple())
+                        .skip_while(|t| matches!(cursor_after, Some(after) if t <= after)),
+                )
             };
 
             if let Some((meta, relation_op)) = &out_opts.store_relation {
@@ -1458,17 +2126,16 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             } else {
                 let rows: Vec<Tuple> = scan.collect_vec();
-
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let cursor = next_page_token(&out_opts, &entry_head_or_default, &rows);
+                let mut res = NamedRows::new(
+                    entry_head_or_default
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect_vec(),
+                    rows,
+                );
+                res.cursor = cursor;
+                Ok((res, clean_ups))
             }
         }
     }
@@ -1583,6 +2250,54 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 }
 
+/// A query compiled against the relations seen by a transaction, yet to be evaluated.
+#[derive(Clone)]
+pub(crate) struct CompiledQuery {
+    pub(crate) strata: Vec<CompiledProgram>,
+    pub(crate) out_opts: QueryOutOptions,
+    pub(crate) entry_head: Vec<Symbol>,
+    /// The views inlined into the query
+    pub(crate) views: Vec<SmartString<LazyCompact>>,
+}
+
+/// Some checks in case the query specifies mutation
+pub(crate) fn check_store_relation(tx: &SessionTx<'_>, out_opts: &QueryOutOptions) -> Result<()> {
+    if let Some((meta, op)) = &out_opts.store_relation {
+        if matches!(op, RelationOp::Create | RelationOp::Replace) {
+            ensure!(
+                !tx.view_exists(&meta.name)?,
+                ViewNameConflictError(meta.name.to_string(), meta.name.span)
+            );
+        }
+        if *op == RelationOp::Create {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Stored relation {0} conflicts with an existing one")]
+            #[diagnostic(code(eval::stored_relation_conflict))]
+            struct StoreRelationConflict(String);
+
+            ensure!(
+                !tx.relation_exists(&meta.name)?,
+                StoreRelationConflict(meta.name.to_string())
+            )
+        } else if *op != RelationOp::Replace {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Stored relation {0} not found")]
+            #[diagnostic(code(eval::stored_relation_not_found))]
+            struct StoreRelationNotFoundError(String);
+
+            let existing = tx.get_relation(&meta.name, false)?;
+
+            ensure!(
+                tx.relation_exists(&meta.name)?,
+                StoreRelationNotFoundError(meta.name.to_string())
+            );
+
+            existing.ensure_compatible(meta, *op == RelationOp::Rm)?;
+        }
+    };
+    Ok(())
+}
+
 /// Used for user-initiated termination of running queries
 #[derive(Clone, Default)]
 pub struct Poison(pub(crate) Arc<AtomicBool>);
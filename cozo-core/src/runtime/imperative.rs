@@ -17,11 +17,19 @@ use thiserror::Error;
 
 use crate::data::expr::PredicateTypeError;
 use crate::data::functions::op_to_bool;
-use crate::data::symb::Symbol;
-use crate::parse::{ImperativeCondition, ImperativeProgram, ImperativeStmt, SourceSpan};
+use crate::data::program::{InputProgram, QueryOutOptions, RelationOp};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::parse::imperative::parse_deferred_query;
+use crate::parse::{
+    ImperativeCondition, ImperativeProgram, ImperativeQuery, ImperativeStmt, ParseContext,
+    SourceSpan,
+};
+use crate::query::stored::make_const_rule;
 use crate::runtime::callback::CallbackCollector;
+use crate::runtime::relation::InputRelationHandle;
 use crate::runtime::transact::SessionTx;
-use crate::{DataValue, Db, NamedRows, Poison, Storage, ValidityTs};
+use crate::{format_error_as_json, DataValue, Db, NamedRows, Poison, Storage, ValidityTs};
 use crate::runtime::db::{RunningQueryCleanup, RunningQueryHandle, seconds_since_the_epoch};
 
 enum ControlCode {
@@ -30,31 +38,166 @@ enum ControlCode {
     Continue(Option<SmartString<LazyCompact>>, SourceSpan),
 }
 
+/// The source of the script and the parameters, including those bound by `%let` and `%for`
+struct ImperativeEnv<'e> {
+    src: &'e str,
+    params: BTreeMap<String, DataValue>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'%let' requires its query to return a single value, but got {0} rows of {1} columns")]
+#[diagnostic(code(eval::let_not_single_value))]
+struct LetNotSingleValue(usize, usize, #[label] SourceSpan);
+
+/// The program replacing the temp relation `name` with the code, message and span of `err`,
+/// as found by [format_error_as_json]
+fn caught_error_program(name: &str, err: Report, src: &str) -> InputProgram {
+    let json = format_error_as_json(err, Some(src));
+    let span = match &json["labels"][0]["span"] {
+        serde_json::Value::Null => DataValue::Null,
+        span => DataValue::List(vec![
+            DataValue::from(span["offset"].as_i64().unwrap_or_default()),
+            DataValue::from(span["length"].as_i64().unwrap_or_default()),
+        ]),
+    };
+    let row = vec![
+        json["code"]
+            .as_str()
+            .map(DataValue::from)
+            .unwrap_or(DataValue::Null),
+        DataValue::from(json["message"].as_str().unwrap_or_default()),
+        span,
+    ];
+    let bindings = caught_error_bindings();
+    let mut program = InputProgram {
+        prog: Default::default(),
+        out_opts: QueryOutOptions {
+            store_relation: Some((caught_error_relation(name), RelationOp::Replace)),
+            ..Default::default()
+        },
+    };
+    make_const_rule(
+        &mut program,
+        PROG_ENTRY,
+        bindings,
+        vec![DataValue::List(row)],
+    );
+    program
+}
+
+fn caught_error_bindings() -> Vec<Symbol> {
+    ["code", "message", "span"]
+        .map(|col| Symbol::new(col, Default::default()))
+        .to_vec()
+}
+
+/// The temp relation `name` receiving the error caught by `%catch`
+fn caught_error_relation(name: &str) -> InputRelationHandle {
+    let bindings = caught_error_bindings();
+    InputRelationHandle {
+        name: Symbol::new(name, Default::default()),
+        metadata: StoredRelationMetadata {
+            keys: bindings
+                .iter()
+                .map(|col| ColumnDef {
+                    name: col.name.clone(),
+                    typing: NullableColType {
+                        coltype: ColType::Any,
+                        nullable: true,
+                    },
+                    default_gen: None,
+                })
+                .collect_vec(),
+            non_keys: vec![],
+        },
+        key_bindings: bindings,
+        dep_bindings: vec![],
+        span: Default::default(),
+    }
+}
+
 impl<'s, S: Storage<'s>> Db<S> {
-    fn execute_imperative_condition(
+    fn imperative_program(
+        &'s self,
+        q: &ImperativeQuery,
+        env: &ImperativeEnv<'_>,
+        cur_vld: ValidityTs,
+    ) -> Result<InputProgram> {
+        match q {
+            ImperativeQuery::Parsed(p) => Ok((**p).clone()),
+            ImperativeQuery::Deferred {
+                prog: Some(prog),
+                params,
+                ..
+            } if params.iter().all(|name| env.params.contains_key(name)) => {
+                let mut prog = (**prog).clone();
+                prog.bind_params(&env.params);
+                Ok(prog)
+            }
+            ImperativeQuery::Deferred { pos, end, .. } => parse_deferred_query(
+                env.src,
+                *pos,
+                *end,
+                &ParseContext {
+                    params: &env.params,
+                    custom_fns: &self.custom_functions.read().unwrap(),
+                    custom_aggrs: &self.custom_aggregations.read().unwrap(),
+                    in_rule_body: false,
+                },
+                &self.fixed_rules.read().unwrap(),
+                cur_vld,
+            ),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn execute_imperative_rows(
         &'s self,
         p: &ImperativeCondition,
         tx: &mut SessionTx<'_>,
         cleanups: &mut Vec<(Vec<u8>, Vec<u8>)>,
         cur_vld: ValidityTs,
-        span: SourceSpan,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
-    ) -> Result<bool> {
-        let res = match p {
+        env: &ImperativeEnv<'_>,
+    ) -> Result<NamedRows> {
+        Ok(match p {
             Left(rel) => {
                 let relation = tx.get_relation(rel, false)?;
                 relation.as_named_rows(tx)?
             }
             Right(p) => self.execute_single_program(
-                p.clone(),
+                self.imperative_program(p, env, cur_vld)?,
                 tx,
                 cleanups,
                 cur_vld,
                 callback_targets,
                 callback_collector,
             )?,
-        };
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn execute_imperative_condition(
+        &'s self,
+        p: &ImperativeCondition,
+        tx: &mut SessionTx<'_>,
+        cleanups: &mut Vec<(Vec<u8>, Vec<u8>)>,
+        cur_vld: ValidityTs,
+        span: SourceSpan,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        env: &ImperativeEnv<'_>,
+    ) -> Result<bool> {
+        let res = self.execute_imperative_rows(
+            p,
+            tx,
+            cleanups,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            env,
+        )?;
         Ok(match res.rows.first() {
             None => false,
             Some(row) => {
@@ -69,6 +212,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn execute_imperative_stmts(
         &'s self,
         ps: &ImperativeProgram,
@@ -77,7 +221,8 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
-        poison: &Poison
+        poison: &Poison,
+        env: &mut ImperativeEnv<'_>,
     ) -> Result<Either<NamedRows, ControlCode>> {
         let mut ret = NamedRows::default();
         for p in ps {
@@ -97,7 +242,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     for nxt in returns.iter().rev() {
                         let mut nr = match nxt {
                             Left(prog) => self.execute_single_program(
-                                prog.clone(),
+                                self.imperative_program(prog, env, cur_vld)?,
                                 tx,
                                 cleanups,
                                 cur_vld,
@@ -121,7 +266,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }
                 ImperativeStmt::Program { prog, .. } => {
                     ret = self.execute_single_program(
-                        prog.clone(),
+                        self.imperative_program(prog, env, cur_vld)?,
                         tx,
                         cleanups,
                         cur_vld,
@@ -130,14 +275,16 @@ impl<'s, S: Storage<'s>> Db<S> {
                     )?;
                 }
                 ImperativeStmt::IgnoreErrorProgram { prog, .. } => {
-                    match self.execute_single_program(
-                        prog.clone(),
-                        tx,
-                        cleanups,
-                        cur_vld,
-                        callback_targets,
-                        callback_collector,
-                    ) {
+                    match self.imperative_program(prog, env, cur_vld).and_then(|prog| {
+                        self.execute_single_program(
+                            prog,
+                            tx,
+                            cleanups,
+                            cur_vld,
+                            callback_targets,
+                            callback_collector,
+                        )
+                    }) {
                         Ok(res) => ret = res,
                         Err(_) => {
                             ret = NamedRows::new(
@@ -162,6 +309,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         *span,
                         callback_targets,
                         callback_collector,
+                        env,
                     )?;
                     let cond_val = if *negated { !cond_val } else { cond_val };
                     let to_execute = if cond_val { then_branch } else { else_branch };
@@ -172,7 +320,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                         cur_vld,
                         callback_targets,
                         callback_collector,
-                        poison
+                        poison,
+                        env,
                     )? {
                         Left(rows) => {
                             ret = rows;
@@ -192,7 +341,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                             cur_vld,
                             callback_targets,
                             callback_collector,
-                            poison
+                            poison,
+                            env,
                         )? {
                             Left(_) => {}
                             Right(ctrl) => match ctrl {
@@ -217,6 +367,143 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     }
                 }
+                ImperativeStmt::Let { param, prog, span } => {
+                    let res = self.execute_single_program(
+                        self.imperative_program(prog, env, cur_vld)?,
+                        tx,
+                        cleanups,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                    )?;
+                    let val = match &res.rows[..] {
+                        [row] if row.len() == 1 => row[0].clone(),
+                        rows => {
+                            let n_cols = rows.first().map(|row| row.len()).unwrap_or(0);
+                            bail!(LetNotSingleValue(rows.len(), n_cols, *span))
+                        }
+                    };
+                    env.params.insert(param.clone(), val);
+                    ret = NamedRows::default();
+                }
+                ImperativeStmt::For {
+                    label,
+                    source,
+                    body,
+                } => {
+                    ret = Default::default();
+                    let res = self.execute_imperative_rows(
+                        source,
+                        tx,
+                        cleanups,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                        env,
+                    )?;
+                    let shadowed = res
+                        .headers
+                        .iter()
+                        .map(|h| (h.clone(), env.params.get(h).cloned()))
+                        .collect_vec();
+                    let mut ctrl_out = None;
+                    for row in res.rows {
+                        poison.check()?;
+                        for (h, v) in res.headers.iter().zip(row) {
+                            env.params.insert(h.clone(), v);
+                        }
+
+                        match self.execute_imperative_stmts(
+                            body,
+                            tx,
+                            cleanups,
+                            cur_vld,
+                            callback_targets,
+                            callback_collector,
+                            poison,
+                            env,
+                        )? {
+                            Left(_) => {}
+                            Right(ctrl) => match ctrl {
+                                ControlCode::Break(None, _) => break,
+                                ControlCode::Break(Some(l), _) if l == *label => break,
+                                ControlCode::Continue(None, _) => {}
+                                ControlCode::Continue(Some(l), _) if l == *label => {}
+                                ctrl => {
+                                    ctrl_out = Some(ctrl);
+                                    break;
+                                }
+                            },
+                        }
+                    }
+                    for (h, v) in shadowed {
+                        match v {
+                            None => env.params.remove(&h),
+                            Some(v) => env.params.insert(h, v),
+                        };
+                    }
+                    if let Some(ctrl) = ctrl_out {
+                        return Ok(Right(ctrl));
+                    }
+                }
+                ImperativeStmt::Try {
+                    body,
+                    catch,
+                    handler,
+                } => {
+                    tx.set_savepoint()?;
+                    let n_cleanups = cleanups.len();
+                    // callbacks for the writes in the body are only sent if they are kept
+                    let mut body_callbacks = CallbackCollector::new();
+                    let res = match self.execute_imperative_stmts(
+                        body,
+                        tx,
+                        cleanups,
+                        cur_vld,
+                        callback_targets,
+                        &mut body_callbacks,
+                        poison,
+                        env,
+                    ) {
+                        Ok(res) => {
+                            tx.pop_savepoint()?;
+                            for (k, v) in body_callbacks {
+                                callback_collector.entry(k).or_default().extend(v);
+                            }
+                            res
+                        }
+                        Err(err) => {
+                            tx.rollback_to_savepoint()?;
+                            cleanups.truncate(n_cleanups);
+                            // a killed query cannot be caught
+                            poison.check()?;
+                            if let Some(name) = catch {
+                                self.execute_single_program(
+                                    caught_error_program(name, err, env.src),
+                                    tx,
+                                    cleanups,
+                                    cur_vld,
+                                    callback_targets,
+                                    callback_collector,
+                                )?;
+                            }
+                            self.execute_imperative_stmts(
+                                handler,
+                                tx,
+                                cleanups,
+                                cur_vld,
+                                callback_targets,
+                                callback_collector,
+                                poison,
+                                env,
+                            )?
+                        }
+                    };
+                    match res {
+                        Left(rows) => ret = rows,
+                        Right(ctrl) => return Ok(Right(ctrl)),
+                    }
+                }
                 ImperativeStmt::TempSwap { left, right, .. } => {
                     tx.rename_temp_relation(
                         Symbol::new(left.clone(), Default::default()),
@@ -241,6 +528,8 @@ impl<'s, S: Storage<'s>> Db<S> {
         &'s self,
         cur_vld: ValidityTs,
         ps: &ImperativeProgram,
+        src: &str,
+        param_pool: &BTreeMap<String, DataValue>,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let mut write_lock_names = BTreeSet::new();
@@ -286,7 +575,11 @@ impl<'s, S: Storage<'s>> Db<S> {
                 cur_vld,
                 &callback_targets,
                 &mut callback_collector,
-                &poison
+                &poison,
+                &mut ImperativeEnv {
+                    src,
+                    params: param_pool.clone(),
+                },
             )? {
                 Left(res) => ret = res,
                 Right(ctrl) => match ctrl {
@@ -321,4 +614,92 @@ impl<'s, S: Storage<'s>> Db<S> {
         }
         Ok(ret)
     }
+    fn check_imperative_query(
+        &'s self,
+        q: &ImperativeQuery,
+        tx: &mut SessionTx<'_>,
+        errors: &mut Vec<Report>,
+    ) {
+        match q {
+            ImperativeQuery::Parsed(p)
+            | ImperativeQuery::Deferred {
+                prog: Some(p), ..
+            } => errors.extend(self.check_program(tx, (**p).clone()).err()),
+            ImperativeQuery::Deferred {
+                prog: None,
+                pos,
+                end,
+                ..
+            } => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("The query is not checked, as its parameters shape the query itself")]
+                #[diagnostic(code(eval::query_not_checked), severity(Warning))]
+                #[diagnostic(help("It is parsed once the parameters of %let or %for are bound"))]
+                struct QueryNotChecked(#[label] SourceSpan);
+
+                errors.push(QueryNotChecked(SourceSpan(*pos, end - pos)).into())
+            }
+        }
+    }
+    /// Check the statements as [`Db::check_script`] does, collecting the errors of the queries
+    /// into `errors`. Queries deferred until their parameters are bound are checked with the
+    /// parameters unknown, or reported by warnings if they cannot be.
+    pub(crate) fn check_imperative_stmts(
+        &'s self,
+        ps: &ImperativeProgram,
+        tx: &mut SessionTx<'_>,
+        errors: &mut Vec<Report>,
+    ) -> Result<()> {
+        for p in ps {
+            match p {
+                ImperativeStmt::Program { prog }
+                | ImperativeStmt::IgnoreErrorProgram { prog }
+                | ImperativeStmt::Let { prog, .. } => self.check_imperative_query(prog, tx, errors),
+                ImperativeStmt::Return { returns } => {
+                    for q in returns.iter().filter_map(|ret| ret.as_ref().left()) {
+                        self.check_imperative_query(q, tx, errors)
+                    }
+                }
+                ImperativeStmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    if let Right(q) = condition {
+                        self.check_imperative_query(q, tx, errors)
+                    }
+                    self.check_imperative_stmts(then_branch, tx, errors)?;
+                    self.check_imperative_stmts(else_branch, tx, errors)?;
+                }
+                ImperativeStmt::Loop { body, .. } => {
+                    self.check_imperative_stmts(body, tx, errors)?
+                }
+                ImperativeStmt::For { source, body, .. } => {
+                    if let Right(q) = source {
+                        self.check_imperative_query(q, tx, errors)
+                    }
+                    self.check_imperative_stmts(body, tx, errors)?;
+                }
+                ImperativeStmt::Try {
+                    body,
+                    catch,
+                    handler,
+                } => {
+                    self.check_imperative_stmts(body, tx, errors)?;
+                    if let Some(name) = catch {
+                        if !tx.relation_exists(name)? {
+                            tx.create_relation(caught_error_relation(name))?;
+                        }
+                    }
+                    self.check_imperative_stmts(handler, tx, errors)?;
+                }
+                ImperativeStmt::Break { .. }
+                | ImperativeStmt::Continue { .. }
+                | ImperativeStmt::TempSwap { .. }
+                | ImperativeStmt::TempDebug { .. } => {}
+            }
+        }
+        Ok(())
+    }
 }
@@ -9,8 +9,13 @@
 pub(crate) mod callback;
 pub(crate) mod db;
 pub(crate) mod imperative;
+pub(crate) mod prepared;
+pub(crate) mod procedure;
 pub(crate) mod relation;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod stream;
 pub(crate) mod temp_store;
 #[cfg(test)]
 mod tests;
 pub(crate) mod transact;
+pub(crate) mod view;
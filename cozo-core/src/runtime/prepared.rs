@@ -0,0 +1,376 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::functions::current_validity;
+use crate::data::program::{MagicFixedRuleRuleArg, RelationOp};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::{parse_script, scan_script, CozoScript, ParseContext};
+use crate::query::compile::CompiledRuleSet;
+use crate::runtime::db::{check_store_relation, CompiledQuery, Db, NamedRows};
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::storage::Storage;
+
+/// A query compiled before its parameters are known, with placeholders standing in for them.
+pub(crate) struct QueryPlan {
+    /// Names of the parameters the query refers to
+    params: BTreeSet<String>,
+    query: CompiledQuery,
+    /// The stored relations as they were when the query was compiled
+    relations: Vec<RelationHandle>,
+    write_lock: Option<SmartString<LazyCompact>>,
+    /// The generation of the registrations the plan was compiled with
+    generation: u64,
+}
+
+impl QueryPlan {
+    /// Whether the registrations and the stored relations referred to are unchanged since
+    /// the plan was compiled.
+    fn is_current(&self, tx: &SessionTx<'_>, generation: u64) -> bool {
+        self.generation == generation
+            && self.relations.iter().all(|handle| {
+                matches!(
+                    tx.get_relation(&handle.name, false),
+                    Ok(current) if current == *handle
+                )
+            })
+    }
+    fn bind(&self, params: &BTreeMap<String, DataValue>) -> CompiledQuery {
+        let mut query = self.query.clone();
+        for rule_set in query
+            .strata
+            .iter_mut()
+            .flat_map(|stratum| stratum.values_mut())
+        {
+            rule_set.bind_params(params);
+        }
+        if let Some((meta, _)) = &mut query.out_opts.store_relation {
+            for col in meta
+                .metadata
+                .keys
+                .iter_mut()
+                .chain(meta.metadata.non_keys.iter_mut())
+            {
+                if let Some(expr) = &mut col.default_gen {
+                    expr.bind_params(params);
+                }
+            }
+        }
+        query
+    }
+}
+
+/// The outcome of planning a script.
+#[derive(Clone)]
+pub(crate) enum Plan {
+    Compiled(Arc<QueryPlan>),
+    /// The script is not a query that can be run from a plan, and is always run directly
+    Unplannable,
+    /// The query failed to compile against the current stored relations: it is run
+    /// directly, and planned again at a later run
+    Failed,
+}
+
+/// A least-recently-used cache of plans, keyed by the text of the scripts.
+#[derive(Default)]
+pub(crate) struct PlanCache {
+    capacity: usize,
+    tick: u64,
+    plans: BTreeMap<String, (u64, Plan)>,
+    recency: BTreeMap<u64, String>,
+}
+
+impl PlanCache {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+    pub(crate) fn clear(&mut self) {
+        self.plans.clear();
+        self.recency.clear();
+    }
+    fn get(&mut self, script: &str) -> Option<Plan> {
+        self.tick += 1;
+        let (tick, plan) = self.plans.get_mut(script)?;
+        let script = self.recency.remove(tick).unwrap();
+        *tick = self.tick;
+        self.recency.insert(self.tick, script);
+        Some(plan.clone())
+    }
+    fn insert(&mut self, script: &str, plan: Plan) {
+        if let Some((tick, _)) = self.plans.remove(script) {
+            self.recency.remove(&tick);
+        }
+        if matches!(plan, Plan::Failed) {
+            return;
+        }
+        self.tick += 1;
+        self.plans.insert(script.to_string(), (self.tick, plan));
+        self.recency.insert(self.tick, script.to_string());
+        self.evict();
+    }
+    fn evict(&mut self) {
+        while self.plans.len() > self.capacity {
+            let oldest = *self.recency.keys().next().unwrap();
+            let script = self.recency.remove(&oldest).unwrap();
+            self.plans.remove(&script);
+        }
+    }
+}
+
+/// A script prepared by [`Db::prepare`], which can be run many times with different parameters
+/// without being parsed and compiled each time.
+///
+/// The compiled plan is kept up to date with the schemas of the stored relations it refers to.
+/// Scripts that cannot be run from a plan (system ops, imperative scripts, queries creating
+/// or replacing relations, time travelling queries, and queries calling functions such as
+/// `now()` or `rand_float()`) are run as if by [`Db::run_script`].
+pub struct PreparedQuery<S> {
+    db: Db<S>,
+    script: String,
+    plan: Mutex<Plan>,
+}
+
+impl<'s, S: Storage<'s>> PreparedQuery<S> {
+    /// The text of the prepared script.
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+    /// Run the prepared script. The `params` argument is a map of parameters.
+    pub fn run(&'s self, params: BTreeMap<String, DataValue>) -> Result<NamedRows> {
+        let mut plan = self.plan.lock().unwrap().clone();
+        if matches!(plan, Plan::Failed) {
+            plan = self.db.plan_script(&self.script)?;
+            *self.plan.lock().unwrap() = plan.clone();
+        }
+        let (res, replanned) =
+            self.db
+                .run_with_plan(&self.script, &plan, &params, current_validity())?;
+        if let Some(plan) = replanned {
+            *self.plan.lock().unwrap() = plan;
+        }
+        Ok(res)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Parse and compile the script once, so that it can be run many times
+    /// with different parameters. See [`PreparedQuery`].
+    pub fn prepare(&'s self, script: &str) -> Result<PreparedQuery<S>> {
+        let plan = self.plan_script(script)?;
+        Ok(PreparedQuery {
+            db: self.clone(),
+            script: script.to_string(),
+            plan: Mutex::new(plan),
+        })
+    }
+    /// Set the maximal number of plans kept for scripts passed to [`run_script`](Self::run_script),
+    /// so that scripts run again are not parsed and compiled again. Scripts are
+    /// cached by their text, and the least recently used are evicted first.
+    /// The default capacity is zero, in which case no plans are cached.
+    pub fn set_plan_cache_capacity(&self, capacity: usize) {
+        self.plan_cache.lock().unwrap().set_capacity(capacity)
+    }
+    /// Make all the plans compiled so far out of date, including those of prepared queries.
+    pub(crate) fn invalidate_plans(&self) {
+        self.plan_generation.fetch_add(1, Ordering::AcqRel);
+        self.plan_cache.lock().unwrap().clear();
+    }
+    pub(crate) fn run_script_cached(
+        &'s self,
+        script: &str,
+        params: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+    ) -> Result<NamedRows> {
+        let cached = self.plan_cache.lock().unwrap().get(script);
+        let (plan, is_new) = match cached {
+            Some(plan) => (plan, false),
+            None => (self.plan_script(script)?, true),
+        };
+        let (res, replanned) = self.run_with_plan(script, &plan, params, cur_vld)?;
+        match replanned {
+            Some(plan) => self.plan_cache.lock().unwrap().insert(script, plan),
+            None if is_new => self.plan_cache.lock().unwrap().insert(script, plan),
+            None => {}
+        }
+        Ok(res)
+    }
+    /// Parse the script with placeholders for its parameters, and compile it into a plan.
+    pub(crate) fn plan_script(&'s self, script: &str) -> Result<Plan> {
+        // read first, so that registrations changing while planning make the plan out of date
+        let generation = self.plan_generation.load(Ordering::Acquire);
+        let scan = scan_script(script)?;
+        if !scan.is_deterministic {
+            return Ok(Plan::Unplannable);
+        }
+        let param_pool = scan
+            .params
+            .iter()
+            .map(|name| (name.clone(), DataValue::param_placeholder(name)))
+            .collect();
+        let program = match parse_script(
+            script,
+            &ParseContext {
+                params: &param_pool,
+                custom_fns: &self.custom_functions.read().unwrap(),
+                custom_aggrs: &self.custom_aggregations.read().unwrap(),
+                in_rule_body: false,
+            },
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        ) {
+            Ok(CozoScript::Single(p)) => p,
+            _ => return Ok(Plan::Unplannable),
+        };
+        match &program.out_opts.store_relation {
+            Some((_, RelationOp::Create | RelationOp::Replace)) => return Ok(Plan::Unplannable),
+            Some((meta, _)) if meta.name.starts_with('_') => return Ok(Plan::Unplannable),
+            _ => {}
+        }
+        let write_lock = program.needs_write_lock();
+        let mut relation_names = BTreeSet::new();
+        if let Some((meta, _)) = &program.out_opts.store_relation {
+            relation_names.insert(meta.name.name.clone());
+        }
+
+        let mut tx = self.transact()?;
+        let query = match self.compile_query(&mut tx, program) {
+            Ok(query) => query,
+            Err(_) => return Ok(Plan::Failed),
+        };
+        // views can be redefined without the plan noticing
+        if !query.views.is_empty() {
+            return Ok(Plan::Unplannable);
+        }
+        let mut relations = BTreeMap::new();
+        for rule_set in query.strata.iter().flat_map(|stratum| stratum.values()) {
+            match rule_set {
+                CompiledRuleSet::Rules(rules) => {
+                    let mut scans = vec![];
+                    for rule in rules {
+                        rule.relation.collect_stored_scans(&mut scans);
+                    }
+                    for (handle, is_time_travel) in scans {
+                        // the validity is fixed when the script is parsed
+                        if is_time_travel || handle.is_temp {
+                            return Ok(Plan::Unplannable);
+                        }
+                        relations.insert(handle.name.clone(), handle.clone());
+                    }
+                }
+                CompiledRuleSet::Fixed(fixed) => {
+                    for arg in &fixed.rule_args {
+                        if let MagicFixedRuleRuleArg::Stored { name, valid_at, .. } = arg {
+                            if valid_at.is_some() {
+                                return Ok(Plan::Unplannable);
+                            }
+                            relation_names.insert(name.name.clone());
+                        }
+                    }
+                }
+            }
+        }
+        for name in relation_names {
+            if let Entry::Vacant(entry) = relations.entry(name) {
+                match tx.get_relation(entry.key(), false) {
+                    Ok(handle) => {
+                        entry.insert(handle);
+                    }
+                    Err(_) => return Ok(Plan::Failed),
+                }
+            }
+        }
+        tx.commit_tx()?;
+
+        Ok(Plan::Compiled(Arc::new(QueryPlan {
+            params: scan.params,
+            query,
+            relations: relations.into_values().collect(),
+            write_lock,
+            generation,
+        })))
+    }
+    /// Run the script from the plan if possible, and directly otherwise.
+    /// If the plan is found to be out of date, the script is planned again,
+    /// and the new plan is returned alongside the result.
+    pub(crate) fn run_with_plan(
+        &'s self,
+        script: &str,
+        plan: &Plan,
+        params: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+    ) -> Result<(NamedRows, Option<Plan>)> {
+        let mut replanned = None;
+        if let Plan::Compiled(compiled) = plan {
+            // with parameters missing the direct run reports the error
+            if compiled.params.iter().all(|name| params.contains_key(name)) {
+                if let Some(res) = self.run_plan(compiled, params, cur_vld)? {
+                    return Ok((res, None));
+                }
+                let new_plan = self.plan_script(script)?;
+                if let Plan::Compiled(compiled) = &new_plan {
+                    if let Some(res) = self.run_plan(compiled, params, cur_vld)? {
+                        return Ok((res, Some(new_plan)));
+                    }
+                }
+                replanned = Some(new_plan);
+            }
+        }
+        let res = self.do_run_script(script, params, cur_vld)?;
+        Ok((res, replanned))
+    }
+    /// Run the plan with the parameters bound, or return `None` if it is out of date.
+    fn run_plan(
+        &'s self,
+        plan: &QueryPlan,
+        params: &BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+    ) -> Result<Option<NamedRows>> {
+        self.run_in_transaction(
+            plan.write_lock.clone(),
+            |tx, cleanups, callback_targets, callback_collector| {
+                if !plan.is_current(tx, self.plan_generation.load(Ordering::Acquire)) {
+                    return Ok(None);
+                }
+                let query = plan.bind(params);
+                #[allow(unused_variables)]
+                let sleep_opt = query.out_opts.sleep;
+                check_store_relation(tx, &query.out_opts)?;
+                let (res, q_cleanups) = self.run_compiled_query(
+                    tx,
+                    query,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                    true,
+                )?;
+                cleanups.extend(q_cleanups);
+                #[cfg(not(target_arch = "wasm32"))]
+                if let Some(secs) = sleep_opt {
+                    thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
+                }
+                Ok(Some(res))
+            },
+        )
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::{decode_tuple_from_key, TupleT};
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::SourceSpan;
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+
+/// The keys of stored procedures in the system relation start with this, then the procedure name.
+const PROCEDURE_KEY_PREFIX: &str = "PROCEDURE";
+
+/// How deeply procedures may call each other, or themselves, through `::call`
+const MAX_CALL_DEPTH: usize = 64;
+
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+fn procedure_key(name: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from(PROCEDURE_KEY_PREFIX),
+        DataValue::from(name),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+/// A script stored in the database, run with values given for its parameters
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ProcedureHandle {
+    /// The names of the parameters, without the leading `$`
+    pub(crate) params: Vec<SmartString<LazyCompact>>,
+    pub(crate) definition: String,
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Procedure {0} already exists")]
+#[diagnostic(code(eval::procedure_name_conflict))]
+struct ProcedureNameConflict(String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Cannot find requested procedure '{0}'")]
+#[diagnostic(code(query::procedure_not_found))]
+struct ProcedureNotFoundError(String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Procedure '{0}' takes {1} arguments, but {2} are given")]
+#[diagnostic(code(query::procedure_arity_mismatch))]
+struct ProcedureArityMismatch(String, usize, usize, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Procedure '{0}' requires the parameters {1:?}, but {2:?} are given")]
+#[diagnostic(code(query::procedure_params_mismatch))]
+struct ProcedureParamsMismatch(String, Vec<String>, Vec<String>);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Calling procedure '{0}' exceeds the maximal call depth of {1}")]
+#[diagnostic(code(eval::procedure_call_too_deep))]
+#[diagnostic(help("Check whether the procedure calls itself without end"))]
+struct ProcedureCallTooDeep(String, usize, #[label] SourceSpan);
+
+/// Counts a running procedure towards the call depth of the current thread until dropped.
+/// Nested `::call`s run on the thread of their caller, so the count covers the whole chain.
+pub(crate) struct CallDepthGuard;
+
+impl CallDepthGuard {
+    pub(crate) fn enter(name: &Symbol) -> Result<Self> {
+        let depth = CALL_DEPTH.with(|d| d.get());
+        ensure!(
+            depth < MAX_CALL_DEPTH,
+            ProcedureCallTooDeep(name.to_string(), MAX_CALL_DEPTH, name.span)
+        );
+        CALL_DEPTH.with(|d| d.set(depth + 1));
+        Ok(Self)
+    }
+}
+
+impl Drop for CallDepthGuard {
+    fn drop(&mut self) {
+        CALL_DEPTH.with(|d| d.set(d.get() - 1))
+    }
+}
+
+impl ProcedureHandle {
+    /// The parameters for running the procedure with the given positional arguments
+    pub(crate) fn positional_params(
+        &self,
+        name: &Symbol,
+        args: Vec<DataValue>,
+    ) -> Result<BTreeMap<String, DataValue>> {
+        ensure!(
+            args.len() == self.params.len(),
+            ProcedureArityMismatch(name.to_string(), self.params.len(), args.len(), name.span)
+        );
+        Ok(self
+            .params
+            .iter()
+            .map(|p| p.to_string())
+            .zip(args)
+            .collect())
+    }
+    /// Check that the parameters given by name are exactly those of the procedure
+    pub(crate) fn check_params(
+        &self,
+        name: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Result<()> {
+        ensure!(
+            params.len() == self.params.len()
+                && self.params.iter().all(|p| params.contains_key(p.as_str())),
+            ProcedureParamsMismatch(
+                name.to_string(),
+                self.params.iter().map(|p| p.to_string()).collect_vec(),
+                params.keys().cloned().collect_vec()
+            )
+        );
+        Ok(())
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn create_procedure(
+        &mut self,
+        name: &Symbol,
+        params: &[Symbol],
+        definition: &str,
+    ) -> Result<()> {
+        let key = procedure_key(name);
+        ensure!(
+            !self.store_tx.exists(&key, true)?,
+            ProcedureNameConflict(name.to_string(), name.span)
+        );
+        let handle = ProcedureHandle {
+            params: params.iter().map(|p| p.name.clone()).collect_vec(),
+            definition: definition.to_string(),
+        };
+        let mut val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&key, &val)
+    }
+    pub(crate) fn get_procedure(&self, name: &Symbol) -> Result<ProcedureHandle> {
+        match self.store_tx.get(&procedure_key(name), false)? {
+            None => bail!(ProcedureNotFoundError(name.to_string(), name.span)),
+            Some(found) => rmp_serde::from_slice(&found).into_diagnostic(),
+        }
+    }
+    pub(crate) fn remove_procedure(&mut self, name: &Symbol) -> Result<()> {
+        let key = procedure_key(name);
+        ensure!(
+            self.store_tx.exists(&key, true)?,
+            ProcedureNotFoundError(name.to_string(), name.span)
+        );
+        self.store_tx.del(&key)
+    }
+    /// All procedures with their names
+    pub(crate) fn list_procedures(&self) -> Result<Vec<(String, ProcedureHandle)>> {
+        let lower = procedure_key("");
+        let upper = procedure_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            let name = match decode_tuple_from_key(&k_slice).pop() {
+                Some(DataValue::Str(s)) => s.to_string(),
+                _ => continue,
+            };
+            ret.push((name, rmp_serde::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(ret)
+    }
+}
@@ -19,13 +19,14 @@ use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::memcmp::MemCmpEncoder;
-use crate::data::relation::StoredRelationMetadata;
+use crate::data::relation::{ColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
 use crate::query::compile::IndexPositionUse;
 use crate::runtime::transact::SessionTx;
+use crate::runtime::view::ViewNameConflictError;
 use crate::{NamedRows, StoreTx};
 
 #[derive(
@@ -77,6 +78,9 @@ pub(crate) struct RelationHandle {
     pub(crate) is_temp: bool,
     #[serde(default)]
     pub(crate) indices: BTreeMap<SmartString<LazyCompact>, (RelationHandle, Vec<usize>)>,
+    /// Materialized views that must be maintained when this relation changes
+    #[serde(default)]
+    pub(crate) materialized_views: Vec<SmartString<LazyCompact>>,
 }
 
 #[derive(
@@ -147,6 +151,51 @@ impl RelationHandle {
         );
         Ok(NamedRows::new(headers, rows))
     }
+    /// The positions of the key columns having collations
+    fn collated_keys(&self) -> Vec<usize> {
+        self.metadata
+            .keys
+            .iter()
+            .positions(|col| matches!(col.typing.coltype, ColType::CollatedString(_)))
+            .collect()
+    }
+    pub(crate) fn has_collated_keys(&self) -> bool {
+        !self.collated_keys().is_empty()
+    }
+    /// Encode the leading key columns, strings in collated columns by their sort keys.
+    fn encode_key_values(&self, vals: &[DataValue]) -> Vec<u8> {
+        let mut ret = self.encode_key_prefix(vals.len());
+        for (i, val) in vals.iter().enumerate() {
+            match (self.metadata.keys.get(i).map(|col| &col.typing.coltype), val) {
+                (Some(ColType::CollatedString(c)), DataValue::Str(s)) => {
+                    ret.encode_collated_str(*c, s)
+                }
+                _ => ret.encode_datavalue(val),
+            }
+        }
+        ret
+    }
+    /// Put back the strings of the collated key columns, stored after the non-key columns,
+    /// in place of their sort keys.
+    pub(crate) fn restore_collated_keys(&self, tuple: Tuple) -> Tuple {
+        restore_collated(&self.collated_keys(), self.arity(), tuple)
+    }
+    fn restoring_collated<'a>(
+        &self,
+        it: impl Iterator<Item = Result<Tuple>> + 'a,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let collated = self.collated_keys();
+        let arity = self.arity();
+        it.map(move |res| res.map(|tuple| restore_collated(&collated, arity, tuple)))
+    }
+    /// The value stored with an index entry, holding the strings of the collated columns.
+    pub(crate) fn encode_index_val(&self, tuple: &Tuple) -> Result<Vec<u8>> {
+        if self.collated_keys().is_empty() {
+            Ok(vec![])
+        } else {
+            self.encode_val_for_store(tuple, Default::default())
+        }
+    }
     #[allow(dead_code)]
     pub(crate) fn amend_key_prefix(&self, data: &mut [u8]) {
         let prefix_bytes = self.id.0.to_be_bytes();
@@ -214,19 +263,22 @@ impl RelationHandle {
                 span
             }
         );
-        let mut ret = self.encode_key_prefix(len);
-        for val in &tuple[0..len] {
-            ret.encode_datavalue(val);
-        }
-        Ok(ret)
+        Ok(self.encode_key_values(&tuple[0..len]))
     }
     pub(crate) fn encode_val_for_store(&self, tuple: &Tuple, _span: SourceSpan) -> Result<Vec<u8>> {
         let start = self.metadata.keys.len();
         let len = self.metadata.non_keys.len();
         let mut ret = self.encode_key_prefix(len);
-        tuple[start..]
-            .serialize(&mut Serializer::new(&mut ret))
-            .unwrap();
+        let collated = self.collated_keys();
+        if collated.is_empty() {
+            tuple[start..]
+                .serialize(&mut Serializer::new(&mut ret))
+                .unwrap();
+        } else {
+            let mut vals = tuple[start..self.arity()].to_vec();
+            vals.extend(collated.iter().map(|i| tuple[*i].clone()));
+            vals.serialize(&mut Serializer::new(&mut ret)).unwrap();
+        }
         Ok(ret)
     }
     pub(crate) fn encode_val_only_for_store(
@@ -301,17 +353,33 @@ impl RelationHandle {
             RelationDeserError
         })?)
     }
+    /// The range of raw keys spanned by the relation, the upper bound being exclusive.
+    pub(crate) fn key_range(&self) -> (Vec<u8>, Vec<u8>) {
+        (
+            Tuple::default().encode_as_key(self.id),
+            Tuple::default().encode_as_key(self.id.next()),
+        )
+    }
     pub(crate) fn scan_all<'a>(
         &self,
         tx: &'a SessionTx<'_>,
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
-        let lower = Tuple::default().encode_as_key(self.id);
-        let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
-            tx.temp_store_tx.range_scan_tuple(&lower, &upper)
+        let (lower, upper) = self.key_range();
+        self.scan_range(tx, &lower, &upper)
+    }
+    /// Scan the raw key range, which must lie within [key_range](Self::key_range).
+    pub(crate) fn scan_range<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let it = if self.is_temp {
+            tx.temp_store_tx.range_scan_tuple(lower, upper)
         } else {
-            tx.store_tx.range_scan_tuple(&lower, &upper)
-        }
+            tx.store_tx.range_scan_tuple(lower, upper)
+        };
+        self.restoring_collated(it)
     }
 
     pub(crate) fn skip_scan_all<'a>(
@@ -321,56 +389,56 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower, &upper, valid_at)
         } else {
             tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)
-        }
+        };
+        self.restoring_collated(it)
     }
 
-    pub(crate) fn get(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<Option<Tuple>> {
-        let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
-            Ok(tx
-                .temp_store_tx
-                .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data)))
+    /// Look up many keys at once. The results are in the same order as the keys.
+    pub(crate) fn multi_get(
+        &self,
+        tx: &SessionTx<'_>,
+        keys: &[Tuple],
+    ) -> Result<Vec<Option<Tuple>>> {
+        let keys_data = keys
+            .iter()
+            .map(|key| self.encode_key_values(key))
+            .collect_vec();
+        let found = if self.is_temp {
+            tx.temp_store_tx.multi_get(&keys_data, false)?
         } else {
-            Ok(tx
-                .store_tx
-                .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data)))
-        }
+            tx.store_tx.multi_get(&keys_data, false)?
+        };
+        Ok(keys_data
+            .iter()
+            .zip(found)
+            .map(|(key_data, val_data)| {
+                val_data.map(|val_data| {
+                    self.restore_collated_keys(decode_tuple_from_kv(key_data, &val_data))
+                })
+            })
+            .collect())
     }
 
-    pub(crate) fn exists(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<bool> {
-        let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx.exists(&key_data, false)
-        } else {
-            tx.store_tx.exists(&key_data, false)
-        }
+    /// The range of raw keys scanned by [scan_prefix](Self::scan_prefix).
+    pub(crate) fn prefix_key_range(&self, prefix: &Tuple) -> (Vec<u8>, Vec<u8>) {
+        let mut lower = prefix.clone();
+        lower.truncate(self.metadata.keys.len());
+        let mut upper = lower.clone();
+        upper.push(DataValue::Bot);
+        (self.encode_key_values(&lower), self.encode_key_values(&upper))
     }
-
     pub(crate) fn scan_prefix<'a>(
         &self,
         tx: &'a SessionTx<'_>,
         prefix: &Tuple,
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
-        let mut lower = prefix.clone();
-        lower.truncate(self.metadata.keys.len());
-        let mut upper = lower.clone();
-        upper.push(DataValue::Bot);
-        let prefix_encoded = lower.encode_as_key(self.id);
-        let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_scan_tuple(&prefix_encoded, &upper_encoded)
-        } else {
-            tx.store_tx
-                .range_scan_tuple(&prefix_encoded, &upper_encoded)
-        }
+        let (lower, upper) = self.prefix_key_range(prefix);
+        self.scan_range(tx, &lower, &upper)
     }
 
     pub(crate) fn skip_scan_prefix<'a>(
@@ -383,37 +451,49 @@ impl RelationHandle {
         lower.truncate(self.metadata.keys.len());
         let mut upper = lower.clone();
         upper.push(DataValue::Bot);
-        let prefix_encoded = lower.encode_as_key(self.id);
-        let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
+        let prefix_encoded = self.encode_key_values(&lower);
+        let upper_encoded = self.encode_key_values(&upper);
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
         } else {
             tx.store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
-        }
+        };
+        self.restoring_collated(it)
     }
 
-    pub(crate) fn scan_bounded_prefix<'a>(
+    /// The range of raw keys covering the prefix with the following key columns bounded.
+    pub(crate) fn bounded_prefix_key_range(
         &self,
-        tx: &'a SessionTx<'_>,
         prefix: &Tuple,
         lower: &[DataValue],
         upper: &[DataValue],
-    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    ) -> (Vec<u8>, Vec<u8>) {
+        let (lower_t, upper_t) = self.bounded_prefix(prefix, lower, upper);
+        (
+            self.encode_key_values(&lower_t),
+            self.encode_key_values(&upper_t),
+        )
+    }
+    /// The bounds are dropped from the first collated column on, as the order of the
+    /// sort keys is not that of the strings. The range is then wider than asked for.
+    fn bounded_prefix(
+        &self,
+        prefix: &Tuple,
+        lower: &[DataValue],
+        upper: &[DataValue],
+    ) -> (Tuple, Tuple) {
+        let n_bounded = match self.collated_keys().iter().find(|i| **i >= prefix.len()) {
+            Some(i) => i - prefix.len(),
+            None => usize::MAX,
+        };
         let mut lower_t = prefix.clone();
-        lower_t.extend_from_slice(lower);
+        lower_t.extend(lower.iter().take(n_bounded).cloned());
         let mut upper_t = prefix.clone();
-        upper_t.extend_from_slice(upper);
+        upper_t.extend(upper.iter().take(n_bounded).cloned());
         upper_t.push(DataValue::Bot);
-        let lower_encoded = lower_t.encode_as_key(self.id);
-        let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_scan_tuple(&lower_encoded, &upper_encoded)
-        } else {
-            tx.store_tx.range_scan_tuple(&lower_encoded, &upper_encoded)
-        }
+        (lower_t, upper_t)
     }
     pub(crate) fn skip_scan_bounded_prefix<'a>(
         &self,
@@ -423,21 +503,28 @@ impl RelationHandle {
         upper: &[DataValue],
         valid_at: ValidityTs,
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
-        let mut lower_t = prefix.clone();
-        lower_t.extend_from_slice(lower);
-        let mut upper_t = prefix.clone();
-        upper_t.extend_from_slice(upper);
-        upper_t.push(DataValue::Bot);
-        let lower_encoded = lower_t.encode_as_key(self.id);
-        let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
+        let (lower_t, upper_t) = self.bounded_prefix(prefix, lower, upper);
+        let lower_encoded = self.encode_key_values(&lower_t);
+        let upper_encoded = self.encode_key_values(&upper_t);
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
         } else {
             tx.store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
+        };
+        self.restoring_collated(it)
+    }
+}
+
+fn restore_collated(collated: &[usize], arity: usize, mut tuple: Tuple) -> Tuple {
+    if !collated.is_empty() {
+        for (j, i) in collated.iter().enumerate() {
+            tuple.swap(*i, arity + j);
         }
+        tuple.truncate(arity);
     }
+    tuple
 }
 
 /// Decode tuple from key-value pairs. Used for customizing storage
@@ -537,6 +624,7 @@ impl<'a> SessionTx<'a> {
             access_level: AccessLevel::Normal,
             is_temp,
             indices: Default::default(),
+            materialized_views: vec![],
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -587,6 +675,13 @@ impl<'a> SessionTx<'a> {
         if !store.indices.is_empty() {
             bail!("Cannot remove stored relation `{}` with indices attached.", name);
         }
+        if !store.materialized_views.is_empty() {
+            bail!(RelationHasMaterializedViews(
+                store.name.to_string(),
+                "relation removal".to_string(),
+                store.materialized_views
+            ))
+        }
         if store.access_level < AccessLevel::Normal {
             bail!(InsufficientAccessLevel(
                 store.name.to_string(),
@@ -619,12 +714,23 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    pub(crate) fn save_relation_handle(&mut self, meta: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)
+    }
 
+    /// Create the index and fill it with the rows of the relation, unless `populate` is unset,
+    /// as when a script is only checked.
     pub(crate) fn create_index(
         &mut self,
         rel_name: &Symbol,
         idx_name: &Symbol,
         cols: Vec<Symbol>,
+        populate: bool,
     ) -> Result<()> {
         let mut rel_handle = self.get_relation(rel_name, true)?;
         if rel_handle.indices.contains_key(&idx_name.name) {
@@ -716,25 +822,29 @@ impl<'a> SessionTx<'a> {
             })
             .collect_vec();
 
-        if self.store_tx.supports_par_put() {
-            for tuple in rel_handle.scan_all(self) {
-                let tuple = tuple?;
-                let extracted = extraction_indices
-                    .iter()
-                    .map(|idx| tuple[*idx].clone())
-                    .collect_vec();
-                let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
-                self.store_tx.par_put(&key, &[])?;
-            }
-        } else {
-            for tuple in rel_handle.scan_all(self).collect_vec() {
-                let tuple = tuple?;
-                let extracted = extraction_indices
-                    .iter()
-                    .map(|idx| tuple[*idx].clone())
-                    .collect_vec();
-                let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
-                self.store_tx.put(&key, &[])?;
+        if populate {
+            if self.store_tx.supports_par_put() {
+                for tuple in rel_handle.scan_all(self) {
+                    let tuple = tuple?;
+                    let extracted = extraction_indices
+                        .iter()
+                        .map(|idx| tuple[*idx].clone())
+                        .collect_vec();
+                    let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
+                    self.store_tx
+                        .par_put(&key, &idx_handle.encode_index_val(&extracted)?)?;
+                }
+            } else {
+                for tuple in rel_handle.scan_all(self).collect_vec() {
+                    let tuple = tuple?;
+                    let extracted = extraction_indices
+                        .iter()
+                        .map(|idx| tuple[*idx].clone())
+                        .collect_vec();
+                    let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
+                    self.store_tx
+                        .put(&key, &idx_handle.encode_index_val(&extracted)?)?;
+                }
             }
         }
 
@@ -785,6 +895,10 @@ impl<'a> SessionTx<'a> {
         if self.store_tx.exists(&new_encoded, true)? {
             bail!(RelNameConflictError(new.name.to_string()))
         };
+        ensure!(
+            !self.view_exists(&new)?,
+            ViewNameConflictError(new.name.to_string(), new.span)
+        );
 
         let old_key = DataValue::Str(old.name.clone());
         let old_encoded = vec![old_key].encode_as_key(RelationId::SYSTEM);
@@ -797,6 +911,16 @@ impl<'a> SessionTx<'a> {
                 rel.access_level
             ));
         }
+        if !rel.materialized_views.is_empty() {
+            bail!(RelationHasMaterializedViews(
+                rel.name.to_string(),
+                "renaming relation".to_string(),
+                rel.materialized_views
+            ))
+        }
+        if self.view_exists(&old)? {
+            bail!("Cannot rename the relation holding the materialized view {}", old.name);
+        }
         rel.name = new.name;
 
         let mut meta_val = vec![];
@@ -837,3 +961,13 @@ pub(crate) struct InsufficientAccessLevel(
     pub(crate) String,
     pub(crate) AccessLevel,
 );
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot perform {1} on stored relation '{0}' as materialized views {2:?} depend on it")]
+#[diagnostic(code(tx::relation_has_materialized_views))]
+#[diagnostic(help("Drop the materialized views first"))]
+pub(crate) struct RelationHasMaterializedViews(
+    pub(crate) String,
+    pub(crate) String,
+    pub(crate) Vec<SmartString<LazyCompact>>,
+);
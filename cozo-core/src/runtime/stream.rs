@@ -0,0 +1,278 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::thread::JoinHandle;
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use itertools::Itertools;
+use miette::{miette, Result};
+
+use crate::data::functions::current_validity;
+use crate::data::program::{InputProgram, MagicSymbol};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::{chunk_tuples, Tuple, BATCH_SIZE};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::{parse_script, CozoScript, ParseContext, SourceSpan};
+use crate::query::compile::{partition_driving_scans, CompiledRuleSet};
+use crate::runtime::db::{CompiledQuery, Db, NamedRows, Poison};
+use crate::storage::Storage;
+use crate::utils::ArithModeGuard;
+
+type HeadersSender = Sender<Result<Vec<String>>>;
+type ChunksSender = Sender<Result<Vec<Tuple>>>;
+
+/// The rows of the result of a script, computed while they are consumed.
+/// Returned by [`Db::run_script_iter`].
+///
+/// Dropping the iterator before it is exhausted kills the running query.
+pub struct NamedRowsIter {
+    /// The headers
+    pub headers: Vec<String>,
+    chunks: Receiver<Result<Vec<Tuple>>>,
+    current: std::vec::IntoIter<Tuple>,
+    poison: Poison,
+    /// Joined once the rows run out, to find out if the query thread panicked
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Iterator for NamedRowsIter {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(tuple) = self.current.next() {
+                return Some(Ok(tuple));
+            }
+            match self.chunks.recv() {
+                Ok(Ok(chunk)) => self.current = chunk.into_iter(),
+                Ok(Err(err)) => return Some(Err(err)),
+                Err(_) => return join_query_thread(self.thread.take()?).err().map(Err),
+            }
+        }
+    }
+}
+
+impl Drop for NamedRowsIter {
+    fn drop(&mut self) {
+        self.poison.0.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<S> Db<S>
+where
+    S: for<'s> Storage<'s> + 'static,
+{
+    /// Run the CozoScript passed in, returning an iterator over the rows of the result.
+    /// The `params` argument is a map of parameters.
+    ///
+    /// The script runs on a thread of its own, in a read transaction living as long as the
+    /// iterator. Errors found when parsing and compiling are returned immediately, errors
+    /// found later are yielded by the iterator.
+    ///
+    /// For read-only queries whose rows are known to be distinct and which do not sort
+    /// or aggregate their results, rows are produced as they are consumed, in no particular
+    /// order. Other scripts are run to completion first, as by [`run_script`](Self::run_script),
+    /// and if they return several results, only the rows of the first one are yielded.
+    pub fn run_script_iter(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRowsIter> {
+        let (headers_send, headers_recv) = bounded(1);
+        let (chunks_send, chunks_recv) = bounded(2);
+        let poison = Poison::default();
+        let db = self.clone();
+        let payload = payload.to_string();
+        let thread_poison = poison.clone();
+        let thread = thread::spawn(move || {
+            db.stream_script(&payload, &params, headers_send, chunks_send, thread_poison)
+        });
+        let headers = match headers_recv.recv() {
+            Ok(headers) => headers?,
+            Err(_) => {
+                join_query_thread(thread)?;
+                return Err(miette!("the query thread exited unexpectedly"));
+            }
+        };
+        Ok(NamedRowsIter {
+            headers,
+            chunks: chunks_recv,
+            current: vec![].into_iter(),
+            poison,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Wait for the query thread to exit, turning a panic into an error.
+fn join_query_thread(thread: JoinHandle<()>) -> Result<()> {
+    thread.join().map_err(|payload: Box<dyn Any + Send>| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+            .unwrap_or("unknown cause");
+        miette!("the query thread panicked: {}", msg)
+    })
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the script, sending the headers of the result once they are known, followed by
+    /// the rows in chunks. Errors are sent along the headers channel until the headers are sent.
+    pub(crate) fn stream_script(
+        &'s self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+        headers: HeadersSender,
+        chunks: ChunksSender,
+        poison: Poison,
+    ) {
+        let mut headers = Some(headers);
+        let cur_vld = current_validity();
+        let res = match parse_script(
+            payload,
+            &ParseContext {
+                params,
+                custom_fns: &self.custom_functions.read().unwrap(),
+                custom_aggrs: &self.custom_aggregations.read().unwrap(),
+                in_rule_body: false,
+            },
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+        ) {
+            Ok(CozoScript::Single(p)) if p.out_opts.store_relation.is_none() => {
+                self.stream_query(p, cur_vld, &mut headers, &chunks, poison)
+            }
+            Ok(_) => self
+                .do_run_script(payload, params, cur_vld)
+                .map(|rows| send_rows(rows, &mut headers, &chunks)),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            match headers.take() {
+                Some(headers) => {
+                    let _ = headers.send(Err(err));
+                }
+                None => {
+                    let _ = chunks.send(Err(err));
+                }
+            }
+        }
+    }
+    fn stream_query(
+        &'s self,
+        program: InputProgram,
+        cur_vld: ValidityTs,
+        headers: &mut Option<HeadersSender>,
+        chunks: &ChunksSender,
+        poison: Poison,
+    ) -> Result<()> {
+        let mut tx = self.transact()?;
+        let query = self.compile_query(&mut tx, program)?;
+        let entry_symbol = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        };
+        if !is_streamable(&query, &entry_symbol) {
+            let (rows, _) = self.run_compiled_query(
+                &mut tx,
+                query,
+                cur_vld,
+                &Default::default(),
+                &mut Default::default(),
+                true,
+            )?;
+            send_rows(rows, headers, chunks);
+            return Ok(());
+        }
+
+        let CompiledQuery {
+            mut strata,
+            out_opts,
+            entry_head,
+            ..
+        } = query;
+        let rule = match strata.last_mut().unwrap().remove(&entry_symbol) {
+            Some(CompiledRuleSet::Rules(mut rules)) => rules.pop().unwrap(),
+            _ => unreachable!(),
+        };
+        partition_driving_scans(&self.db, &self.key_samples, &mut strata)?;
+        let _arith = ArithModeGuard::set(out_opts.arith);
+        if let Some(secs) = out_opts.timeout {
+            poison.set_timeout(secs)?;
+        }
+        let _guard = self.register_running_query(&poison)?;
+
+        let stores = tx.evaluate_stores(strata, &rule.contained_rules, poison.clone())?;
+        if let Some(headers) = headers.take() {
+            let _ = headers.send(Ok(entry_head.iter().map(|s| s.to_string()).collect_vec()));
+        }
+        let rows = rule
+            .relation
+            .iter(&tx, None, &stores)?
+            .skip(out_opts.offset.unwrap_or(0))
+            .take(out_opts.limit.unwrap_or(usize::MAX));
+        for chunk in chunk_tuples(Box::new(rows)) {
+            poison.check()?;
+            if chunks.send(chunk).is_err() {
+                // the iterator is dropped
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the rows of the entry rule can be sent as they are computed: they must be
+/// distinct, and must not be aggregated, sorted, asserted on or stored.
+/// Seeded queries are not streamed either, as their random draws must come in order.
+fn is_streamable(query: &CompiledQuery, entry_symbol: &MagicSymbol) -> bool {
+    let out_opts = &query.out_opts;
+    if !out_opts.sorters.is_empty()
+        || out_opts.assertion.is_some()
+        || out_opts.store_relation.is_some()
+        || out_opts.cursor.is_some()
+        || out_opts.seed.is_some()
+    {
+        return false;
+    }
+    let rules = match query.strata.last().and_then(|prog| prog.get(entry_symbol)) {
+        Some(CompiledRuleSet::Rules(rules)) => rules,
+        _ => return false,
+    };
+    let rule = match rules.as_slice() {
+        [rule] => rule,
+        _ => return false,
+    };
+    if rule.aggr.iter().any(|aggr| aggr.is_some()) || rule.contained_rules.contains(entry_symbol) {
+        return false;
+    }
+    match rule.relation.unique_key() {
+        None => false,
+        Some(key) => {
+            let bindings = rule.relation.bindings_after_eliminate();
+            key.iter().all(|k| bindings.contains(k))
+        }
+    }
+}
+
+fn send_rows(rows: NamedRows, headers: &mut Option<HeadersSender>, chunks: &ChunksSender) {
+    if let Some(headers) = headers.take() {
+        let _ = headers.send(Ok(rows.headers));
+    }
+    let mut rows = rows.rows.into_iter();
+    loop {
+        let chunk = rows.by_ref().take(BATCH_SIZE).collect_vec();
+        if chunk.is_empty() || chunks.send(Ok(chunk)).is_err() {
+            break;
+        }
+    }
+}
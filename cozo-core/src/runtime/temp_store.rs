@@ -17,7 +17,7 @@ use either::{Left, Right};
 use itertools::Itertools;
 use miette::Result;
 
-use crate::data::aggr::Aggregation;
+use crate::data::aggr::Aggr;
 use crate::data::tuple::Tuple;
 use crate::data::value::DataValue;
 
@@ -91,7 +91,7 @@ impl RegularTempStore {
 #[derive(Debug)]
 pub(crate) struct MeetAggrStore {
     inner: BTreeMap<Tuple, Tuple>,
-    aggregations: Vec<(Aggregation, Vec<DataValue>)>,
+    aggregations: Vec<(Aggr, Vec<DataValue>)>,
     grouping_len: usize,
 }
 
@@ -106,7 +106,7 @@ impl MeetAggrStore {
     pub(crate) fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
-    pub(crate) fn new(aggrs: Vec<Option<(Aggregation, Vec<DataValue>)>>) -> Result<Self> {
+    pub(crate) fn new(aggrs: Vec<Option<(Aggr, Vec<DataValue>)>>) -> Result<Self> {
         let total_key_len = aggrs.len();
         let mut aggregations = aggrs.into_iter().flatten().collect_vec();
         for (aggr, args) in aggregations.iter_mut() {
@@ -138,6 +138,14 @@ impl MeetAggrStore {
             }
         }
     }
+    /// Combine the groups of `other`, aggregated from another part of the same rows.
+    pub(crate) fn meet_merge(&mut self, other: Self) -> Result<()> {
+        for (mut key, val) in other.inner {
+            key.extend(val);
+            self.meet_put(key)?;
+        }
+        Ok(())
+    }
     fn range_iter(
         &self,
         lower: &Tuple,
@@ -265,7 +273,7 @@ impl EpochStore {
             arity,
         }
     }
-    pub(crate) fn new_meet(aggrs: &[Option<(Aggregation, Vec<DataValue>)>]) -> Result<Self> {
+    pub(crate) fn new_meet(aggrs: &[Option<(Aggr, Vec<DataValue>)>]) -> Result<Self> {
         Ok(Self {
             total: TempStore::MeetAggr(MeetAggrStore::new(aggrs.to_vec())?),
             delta: TempStore::MeetAggr(MeetAggrStore::new(aggrs.to_vec())?),
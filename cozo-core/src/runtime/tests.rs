@@ -8,21 +8,37 @@
  */
 
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::time::Duration;
 
+use crossbeam::channel::{bounded, Receiver, Sender};
+
 use itertools::Itertools;
 use log::debug;
+use miette::bail;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
+use crate::data::functions::current_validity;
+use crate::data::json::JsonValue;
+use crate::data::memcmp::MemCmpEncoder;
 use crate::data::symb::Symbol;
-use crate::data::value::DataValue;
+use crate::data::tuple::{decode_tuple_from_key, TupleT};
+use crate::data::value::{DataValue, Num};
 use crate::fixed_rule::FixedRulePayload;
-use crate::parse::SourceSpan;
+use crate::parse::{
+    parse_script, scan_script, CozoScript, ImperativeQuery, ImperativeStmt, SourceSpan,
+};
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{new_cozo_mem, DbInstance, FixedRule, RegularTempStore};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::CURRENT_STORAGE_VERSION;
+use crate::{
+    new_cozo_mem, Aggregation, DbInstance, FixedRule, MeetAggrObj, NamedRows, NormalAggrObj,
+    RegularTempStore,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -98,7 +114,7 @@ fn test_layers() {
         )
         .unwrap()
         .rows;
-    assert_eq!(res[0][0], DataValue::from(21.))
+    assert_eq!(res[0][0], DataValue::from(21))
 }
 #[test]
 fn test_conditions() {
@@ -355,386 +371,2552 @@ fn imperative_script() {
 }
 
 #[test]
-fn returning_relations() {
+fn imperative_let_and_for() {
     let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        {?[id, price] <- [[1, 10], [2, 20], [3, 30]] :create items {id => price}}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
     let res = db
         .run_script(
             r#"
-        {:create _xxz {a}}
-        {?[a] := a in [5,4,1,2,3] :put _xxz {a}}
-        {?[a] := *_xxz[a], a % 2 == 0 :rm _xxz {a}}
-        {?[a] := *_xxz[b], a = b * 2}
+        %let $max = { ?[max(p)] := *items{price: p} }
+        { ?[id] := *items{id, price}, price == $max }
         "#,
             Default::default(),
         )
         .unwrap();
-    assert_eq!(res.into_json()["rows"], json!([[2], [6], [10]]));
-    let res = db.run_script(
-        r#"
-        {?[a] := *_xxz[b], a = b * 2}
-        "#,
-        Default::default(),
-    );
-    assert!(res.is_err());
-}
-
-#[test]
-fn test_trigger() {
-    let db = new_cozo_mem().unwrap();
-    db.run_script(
-        ":create friends {fr: Int, to: Int => data: Any}",
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        ":create friends.rev {to: Int, fr: Int => data: Any}",
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        r#"
-        ::set_triggers friends
-
-        on put {
-            ?[fr, to, data] := _new[fr, to, data]
-
-            :put friends.rev{ to, fr => data}
-        }
-        on rm {
-            ?[fr, to] := _old[fr, to, data]
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
 
-            :rm friends.rev{ to, fr }
-        }
+    let res = db
+        .run_script(
+            r#"
+        {?[k, v] <- [[1, 5], [3, 7], [4, 9]] :replace _updates {k => v}}
+        %for row in _updates
+            %if { ?[x] := *items{id: $k}, x = true }
+            %then { ?[id, price] := id = $k, *items{id, price: old}, price = old + $v
+                    :put items {id => price} }
+            %else { ?[id, price] <- [[$k, $v]] :put items {id => price} }
+            %end
+        %end
+        { ?[id, price] := *items{id, price} }
         "#,
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        r"?[fr, to, data] <- [[1,2,3]] :put friends {fr, to => data}",
-        Default::default(),
-    )
-    .unwrap();
-    let ret = db
-        .export_relations(["friends", "friends.rev"].into_iter())
+            Default::default(),
+        )
         .unwrap();
-    let frs = ret.get("friends").unwrap();
     assert_eq!(
-        vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)],
-        frs.rows[0]
+        res.into_json()["rows"],
+        json!([[1, 15], [2, 20], [3, 37], [4, 9]])
     );
 
-    let frs_rev = ret.get("friends.rev").unwrap();
-    assert_eq!(
-        vec![DataValue::from(2), DataValue::from(1), DataValue::from(3)],
-        frs_rev.rows[0]
-    );
-    db.run_script(
-        r"?[fr, to] <- [[1,2], [2,3]] :rm friends {fr, to}",
-        Default::default(),
+    let res = db
+        .run_script(
+            r#"
+        %for r in { ?[n] <- [[1], [2], [3]] }
+            %if { ?[x] := x = $n > 1 } %then %break %end
+            { ?[id, price] := id = $n + 10, price = $n :put items {id => price} }
+        %end
+        { ?[count(id)] := *items{id} }
+        "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[5]]));
+
+    let err = db
+        .run_script(
+            r#"
+        %let $p = { ?[p] := *items{price: p} }
+        { ?[a] <- [[$p]] }
+        "#,
+            Default::default(),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("single value"));
+
+    // deferred queries are parsed once, unless their parameters shape the query
+    let script = r#"
+        { ?[id, price] <- [[0, 0]] :create _first {id => price} }
+        %for r in { ?[n] <- [[1], [2]] }
+            { ?[id, price] := id = $n + 20, price = $n :put items {id => price} }
+            { ?[id, price] := *items{id, price} :limit $n :put _first {id => price} }
+        %end
+        { ?[id, price] := *_first{id, price} }
+        "#;
+    let body = match parse_script(
+        script,
+        &Default::default(),
+        &db.fixed_rules.read().unwrap(),
+        current_validity(),
     )
-    .unwrap();
-    let ret = db
-        .export_relations(["friends", "friends.rev"].into_iter())
+    .unwrap()
+    {
+        CozoScript::Imperative(ps) => match ps.into_iter().nth(1) {
+            Some(ImperativeStmt::For { body, .. }) => body,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+    assert!(matches!(
+        &body[..],
+        [
+            ImperativeStmt::Program {
+                prog: ImperativeQuery::Deferred { prog: Some(_), .. }
+            },
+            ImperativeStmt::Program {
+                prog: ImperativeQuery::Deferred { prog: None, .. }
+            }
+        ]
+    ));
+    let res = db.run_script(script, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[0, 0], [1, 15], [2, 20]]));
+    let res = db
+        .run_script(
+            "?[id, price] := *items{id, price}, id > 20",
+            Default::default(),
+        )
         .unwrap();
-    let frs = ret.get("friends").unwrap();
-    assert!(frs.rows.is_empty());
+    assert_eq!(res.into_json()["rows"], json!([[21, 1], [22, 2]]));
+
+    let script = r#"
+        %for r in { ?[n] <- [[1]] }
+            { ?[x] := x = $m }
+        %end
+        "#;
+    let err = db.run_script(script, Default::default()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::param_not_found");
+    let label = err.labels().unwrap().next().unwrap();
+    assert_eq!(label.offset(), script.find("$m").unwrap());
 }
 
 #[test]
-fn test_callback() {
+fn imperative_try_catch() {
     let db = new_cozo_mem().unwrap();
-    let mut collected = vec![];
-    let (_id, receiver) = db.register_callback("friends", None);
-    db.run_script(
-        ":create friends {fr: Int, to: Int => data: Any}",
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        r"?[fr, to, data] <- [[1,2,3],[4,5,6]] :put friends {fr, to => data}",
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        r"?[fr, to, data] <- [[1,2,4],[4,7,6]] :put friends {fr, to => data}",
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        r"?[fr, to] <- [[1,9],[4,5]] :rm friends {fr, to}",
-        Default::default(),
-    )
-    .unwrap();
-    std::thread::sleep(Duration::from_secs_f64(0.01));
-    while let Ok(d) = receiver.try_recv() {
-        collected.push(d);
-    }
-    let collected = collected;
-    assert_eq!(collected[0].0, CallbackOp::Put);
-    assert_eq!(collected[0].1.rows.len(), 2);
-    assert_eq!(collected[0].1.rows[0].len(), 3);
-    assert_eq!(collected[0].2.rows.len(), 0);
-    assert_eq!(collected[1].0, CallbackOp::Put);
-    assert_eq!(collected[1].1.rows.len(), 2);
-    assert_eq!(collected[1].1.rows[0].len(), 3);
-    assert_eq!(collected[1].2.rows.len(), 1);
-    assert_eq!(
-        collected[1].2.rows[0],
-        vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)]
-    );
-    assert_eq!(collected[2].0, CallbackOp::Rm);
-    assert_eq!(collected[2].1.rows.len(), 2);
-    assert_eq!(collected[2].1.rows[0].len(), 2);
-    assert_eq!(collected[2].2.rows.len(), 1);
-    assert_eq!(collected[2].2.rows[0].len(), 3);
+    db.run_script(r"?[k] <- [[1]] :create kv {k}", Default::default())
+        .unwrap();
+    let res = db
+        .run_script(
+            r#"
+        {?[k] <- [[10]] :replace _tmp {k}}
+        %try
+            { ?[k] <- [[2]] :put kv {k} }
+            { ?[k] <- [[20]] :put _tmp {k} }
+            { ?[k] <- [[3]] :assert none }
+            { ?[k] <- [[4]] :put kv {k} }
+        %catch _err
+            { ?[k] <- [[5]] :put kv {k} }
+            %return _err _tmp
+        %end
+    "#,
+            Default::default(),
+        )
+        .unwrap();
+    let caught = &res.rows[0];
+    assert_eq!(res.headers, ["code", "message", "span"]);
+    assert_eq!(caught[0], DataValue::from("eval::assert_none_failure"));
+    assert!(matches!(&caught[2], DataValue::List(l) if l.len() == 2));
+    assert_eq!(res.next.unwrap().into_json()["rows"], json!([[10]]));
+    let res = db.run_script("?[k] := *kv{k}", Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [5]]));
+
+    let res = db
+        .run_script(
+            r#"
+        %try
+            { ?[k] <- [[6]] :put kv {k} }
+        %catch
+            { ?[k] <- [[7]] :put kv {k} }
+        %end
+        { ?[k] := *kv{k} }
+    "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [5], [6]]));
 }
 
 #[test]
-fn test_index() {
+fn optional_atoms() {
     let db = new_cozo_mem().unwrap();
     db.run_script(
-        ":create friends {fr: Int, to: Int => data: Any}",
-        Default::default(),
-    )
-    .unwrap();
-
-    db.run_script(
-        r"?[fr, to, data] <- [[1,2,3],[4,5,6]] :put friends {fr, to => data}",
+        r"
+        {?[id, name] <- [[1, 'alice'], [2, 'bob'], [3, 'carol']] :create users {id => name}}
+        {?[uid, bio] <- [[1, 'hi'], [3, 'yo']] :create profiles {uid => bio}}
+    ",
         Default::default(),
     )
     .unwrap();
 
-    assert!(db
-        .run_script("::index create friends:rev {to, no}", Default::default())
-        .is_err());
-    db.run_script("::index create friends:rev {to, data}", Default::default())
+    let expected = json!([["alice", "hi"], ["bob", null], ["carol", "yo"]]);
+    let res = db
+        .run_script(
+            "?[name, bio] := *users{id, name}, optional *profiles{uid: id, bio}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], expected);
+    // the position of the optional atom in the body does not matter
+    let res = db
+        .run_script(
+            "?[name, bio] := optional *profiles{uid: id, bio}, *users{id, name}",
+            Default::default(),
+        )
         .unwrap();
+    assert_eq!(res.into_json()["rows"], expected);
 
-    db.run_script(
-        r"?[fr, to, data] <- [[1,2,5],[6,5,7]] :put friends {fr, to => data}",
-        Default::default(),
-    )
-    .unwrap();
-    db.run_script(
-        r"?[fr, to] <- [[4,5]] :rm friends {fr, to}",
-        Default::default(),
-    )
-    .unwrap();
+    let expl = db
+        .run_script(
+            "::explain { ?[name, bio] := *users{id, name}, optional *profiles{uid: id, bio} }",
+            Default::default(),
+        )
+        .unwrap();
+    let ops = expl.into_json()["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row.as_array().unwrap()[4].clone())
+        .collect_vec();
+    assert!(ops.contains(&json!("stored_outer_prefix_join")));
 
-    let rels_data = db
-        .export_relations(["friends", "friends:rev"].into_iter())
+    // joining on a non-key column
+    let res = db
+        .run_script(
+            "?[id, uid] := *users{id}, optional *profiles{uid, bio: 'yo'}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 3], [2, 3], [3, 3]]));
+    let res = db
+        .run_script(
+            "?[id, uid] := *users{id}, optional *profiles{uid, bio: 'nope'}",
+            Default::default(),
+        )
         .unwrap();
     assert_eq!(
-        rels_data["friends"].clone().into_json()["rows"],
-        json!([[1, 2, 5], [6, 5, 7]])
-    );
-    assert_eq!(
-        rels_data["friends:rev"].clone().into_json()["rows"],
-        json!([[2, 5, 1], [5, 7, 6]])
+        res.into_json()["rows"],
+        json!([[1, null], [2, null], [3, null]])
     );
 
-    let rels = db.run_script("::relations", Default::default()).unwrap();
-    assert_eq!(rels.rows[1][0], DataValue::from("friends:rev"));
-    assert_eq!(rels.rows[1][1], DataValue::from(3));
-    assert_eq!(rels.rows[1][2], DataValue::from("index"));
-
-    let cols = db
-        .run_script("::columns friends:rev", Default::default())
-        .unwrap();
-    assert_eq!(cols.rows.len(), 3);
-
     let res = db
         .run_script(
-            "?[fr, data] := *friends:rev{to: 2, fr, data}",
+            r"
+        with_bio[id, n] := *profiles{uid: id}, n = 1
+        ?[name, n] := *users{id, name}, optional with_bio[id, n]
+    ",
             Default::default(),
         )
         .unwrap();
-    assert_eq!(res.into_json()["rows"], json!([[1, 5]]));
-
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["alice", 1], ["bob", null], ["carol", 1]])
+    );
     let res = db
         .run_script(
-            "?[fr, data] := *friends{to: 2, fr, data}",
+            r"
+        by_bio[bio, id] := *profiles{uid: id, bio}
+        ?[name, bio] := *users{id, name}, optional by_bio[bio, id]
+    ",
             Default::default(),
         )
         .unwrap();
-    assert_eq!(res.into_json()["rows"], json!([[1, 5]]));
+    assert_eq!(res.into_json()["rows"], expected);
 
-    let expl = db
+    let res = db
         .run_script(
-            "::explain { ?[fr, data] := *friends{to: 2, fr, data} }",
+            "?[name] := *users{id, name}, optional *profiles{uid: id, bio}, is_null(bio)",
             Default::default(),
         )
         .unwrap();
-    let joins = expl.into_json()["rows"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|row| row.as_array().unwrap()[5].clone())
-        .collect_vec();
-    assert!(joins.contains(&json!(":friends:rev")));
-}
+    assert_eq!(res.into_json()["rows"], json!([["bob"]]));
 
-#[test]
-fn test_custom_rules() {
-    let db = new_cozo_mem().unwrap();
-    struct Custom;
-
-    impl FixedRule for Custom {
-        fn arity(
-            &self,
-            _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
-            _rule_head: &[Symbol],
-            _span: SourceSpan,
-        ) -> miette::Result<usize> {
-            Ok(1)
-        }
-
-        fn run(
-            &self,
-            payload: FixedRulePayload<'_, '_>,
-            out: &'_ mut RegularTempStore,
-            _poison: Poison,
-        ) -> miette::Result<()> {
-            let rel = payload.get_input(0)?;
-            let mult = payload.integer_option("mult", Some(2))?;
-            for maybe_row in rel.iter()? {
-                let row = maybe_row?;
-                let mut sum = 0;
-                for col in row {
-                    let d = col.get_int().unwrap_or(0);
-                    sum += d;
-                }
-                sum *= mult;
-                out.put(vec![DataValue::from(sum)])
-            }
-            Ok(())
-        }
-    }
-
-    db.register_fixed_rule("SumCols".to_string(), Custom)
-        .unwrap();
-    let res = db
+    assert!(db
         .run_script(
-            r#"
-        rel[] <- [[1,2,3,4],[5,6,7,8]]
-        ?[x] <~ SumCols(rel[], mult: 100)
-    "#,
+            "?[name] := *users{id, name}, not optional *profiles{uid: id}",
             Default::default(),
         )
-        .unwrap();
-    assert_eq!(res.into_json()["rows"], json!([[1000], [2600]]));
+        .is_err());
+    assert!(db
+        .run_script(
+            "?[name, x] := *users{id, name}, optional *profiles{uid: x, bio: x}",
+            Default::default(),
+        )
+        .is_err());
 }
 
 #[test]
-fn test_index_short() {
+fn subqueries_in_expressions() {
     let db = new_cozo_mem().unwrap();
     db.run_script(
-        ":create friends {fr: Int, to: Int => data: Any}",
+        r"
+        {?[id, name] <- [[1, 'alice'], [2, 'bob'], [3, 'carol']] :create users {id => name}}
+        {?[id, uid, amount] <- [[1, 1, 10], [2, 1, 5], [3, 3, 7]] :create orders {id => uid, amount}}
+    ",
         Default::default(),
     )
     .unwrap();
 
-    db.run_script(
-        r"?[fr, to, data] <- [[1,2,3],[4,5,6]] :put friends {fr, to => data}",
-        Default::default(),
-    )
-    .unwrap();
+    let res = db
+        .run_script(
+            "?[name] := *users{id, name}, exists { *orders{uid: id} }",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice"], ["carol"]]));
+    let res = db
+        .run_script(
+            "?[name] := *users{id, name}, !exists { *orders{uid: id, amount}, amount > 8 }",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["bob"], ["carol"]]));
 
-    db.run_script("::index create friends:rev {to}", Default::default())
+    // aggregations over no rows give their empty value
+    let res = db
+        .run_script(
+            "?[name, n] := *users{id, name}, n = { ?[count(o)] := *orders{id: o, uid: id} }",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["alice", 2], ["bob", 0], ["carol", 1]])
+    );
+    let res = db
+        .run_script(
+            "?[name, a] := *users{id, name}, a = { ?[a] := *orders{id: 3, uid: id, amount: a} }",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["alice", null], ["bob", null], ["carol", 7]])
+    );
+
+    // correlated through a comparison only
+    let res = db
+        .run_script(
+            r"
+        ?[id, n] := *users{id}, n = { ?[count(o)] := *orders{id: o, amount}, amount > id * 5 }
+    ",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 2], [2, 0], [3, 0]]));
+    // with the values of the subquery, and those computed from them, filtered on
+    let res = db
+        .run_script(
+            r"
+        ?[id, n] := *users{id}, n = { ?[count(o)] := *orders{id: o, amount}, amount > id * 5 },
+                    n > 0, m = n * 2, m < 10
+    ",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 2]]));
+
+    // nested subqueries
+    let res = db
+        .run_script(
+            r"
+        ?[name] := *users{id, name},
+                   exists { *orders{uid: id, amount}, amount == { ?[max(a)] := *orders{amount: a} } }
+    ",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice"]]));
+    // correlated only through the nested subquery
+    let res = db
+        .run_script(
+            r"
+        ?[name] := *users{id, name},
+                   exists { *orders{id: o}, exists { *orders{id: o, uid: id, amount}, amount > 6 } }
+    ",
+            Default::default(),
+        )
         .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice"], ["carol"]]));
 
+    // views referred to in subqueries are inlined
     db.run_script(
-        r"?[fr, to, data] <- [[1,2,5],[6,5,7]] :put friends {fr, to => data}",
+        "::view create big_orders { ?[uid] := *orders{uid, amount}, amount > 8 }",
         Default::default(),
     )
     .unwrap();
+    let res = db
+        .run_script(
+            "?[name] := *users{id, name}, exists { *big_orders{uid: id} }",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice"]]));
+
+    // subqueries have no rule body to be correlated with elsewhere
+    for script in [
+        "?[n] <- [[exists { *orders{id: 1} }]]",
+        "?[id] := *users{id} :limit { ?[count(o)] := *orders{id: o} }",
+    ] {
+        let err = db.run_script(script, Default::default()).unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "parser::subquery_outside_rule_body"
+        );
+    }
+
+    assert!(db
+        .run_script(
+            "?[name, a] := *users{id, name}, a = { ?[a] := *orders{uid: id, amount: a} }",
+            Default::default(),
+        )
+        .is_err());
+    assert!(db
+        .run_script(
+            "?[name, a] := *users{id, name}, a = { ?[a, b] := *orders{uid: id, amount: a, id: b} }",
+            Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn collations() {
+    let db = new_cozo_mem().unwrap();
     db.run_script(
-        r"?[fr, to] <- [[4,5]] :rm friends {fr, to}",
+        r"
+        {:create users {name: String collate nocase => age: Int}}
+        {?[name, age] <- [['Alice', 30], ['bob', 25]] :put users {name => age}}
+        {?[name, age] <- [['ALICE', 31], ['Carol', 40]] :put users {name => age}}
+    ",
         Default::default(),
     )
     .unwrap();
 
-    let rels_data = db
-        .export_relations(["friends", "friends:rev"].into_iter())
+    // keys differing only in case are the same key, with the last casing kept
+    let res = db
+        .run_script("?[name, age] := *users{name, age}", Default::default())
         .unwrap();
     assert_eq!(
-        rels_data["friends"].clone().into_json()["rows"],
-        json!([[1, 2, 5], [6, 5, 7]])
-    );
-    assert_eq!(
-        rels_data["friends:rev"].clone().into_json()["rows"],
-        json!([[2, 1], [5, 6]])
+        res.into_json()["rows"],
+        json!([["ALICE", 31], ["Carol", 40], ["bob", 25]])
     );
-
-    let rels = db.run_script("::relations", Default::default()).unwrap();
-    assert_eq!(rels.rows[1][0], DataValue::from("friends:rev"));
-    assert_eq!(rels.rows[1][1], DataValue::from(2));
-    assert_eq!(rels.rows[1][2], DataValue::from("index"));
-
-    let cols = db
-        .run_script("::columns friends:rev", Default::default())
+    let res = db
+        .run_script("?[age] := *users{name: 'alice', age}", Default::default())
         .unwrap();
-    assert_eq!(cols.rows.len(), 2);
+    assert_eq!(res.into_json()["rows"], json!([[31]]));
+    let res = db
+        .run_script("?[name] := *users{name}, name > 'C'", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["Carol"], ["bob"]]));
 
-    let expl = db
+    db.run_script("::index create users:by_age {age}", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[name, age] <- [['Dave', 25]] :put users {name => age}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
         .run_script(
-            "::explain { ?[fr, data] := *friends{to: 2, fr, data} }",
+            "?[name] := *users:by_age{age: 25, name}",
             Default::default(),
         )
-        .unwrap()
-        .into_json();
-
-    for row in expl["rows"].as_array().unwrap() {
-        println!("{}", row);
-    }
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["Dave"], ["bob"]]));
+    db.run_script("?[name] <- [['BOB']] :rm users {name}", Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[name] := *users{name, age: 25}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["Dave"]]));
 
-    let joins = expl["rows"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|row| row.as_array().unwrap()[5].clone())
-        .collect_vec();
-    assert!(joins.contains(&json!(":friends:rev")));
+    let cols = db
+        .run_script("::columns users", Default::default())
+        .unwrap();
+    assert_eq!(cols.rows[0][3], DataValue::from("String collate nocase"));
 
     let res = db
         .run_script(
-            "?[fr, data] := *friends{to: 2, fr, data}",
+            "?[s] <- [['b'], ['A'], ['C']] :sort -s collate nocase",
             Default::default(),
         )
         .unwrap();
-    assert_eq!(res.into_json()["rows"], json!([[1, 5]]));
+    assert_eq!(res.into_json()["rows"], json!([["C"], ["b"], ["A"]]));
+
+    // time travel over collated keys
+    db.run_script(
+        r"
+        {:create hist {name: String collate nocase, at: Validity => v}}
+        {?[name, at, v] <- [['x', [1, true], 'old'], ['X', [2, true], 'new'], ['y', [1, true], 'y']]
+         :put hist {name, at => v}}
+    ",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[name, v] := *hist{name, v @ 'NOW'}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["X", "new"], ["y", "y"]]));
+
+    assert!(db
+        .run_script(
+            "?[s] <- [['a']] :sort s collate klingon",
+            Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_script(
+            ":create bad {l: [String collate nocase]}",
+            Default::default()
+        )
+        .is_err());
 }
 
 #[test]
-fn test_multi_tx() {
-    let db = DbInstance::new("mem", "", "").unwrap();
-    let tx = db.multi_transaction(true);
-    tx.run_script(":create a {a}", Default::default()).unwrap();
-    tx.run_script("?[a] <- [[1]] :put a {a}", Default::default())
+fn sort_nulls_and_expressions() {
+    let db = new_cozo_mem().unwrap();
+    let data = "?[name, score] <- [['a', 3], ['b', null], ['c', 1], ['d', 'x']]";
+
+    let res = db
+        .run_script(&format!("{data} :sort score"), Default::default())
         .unwrap();
-    assert!(tx.run_script(":create a {a}", Default::default()).is_err());
-    tx.run_script("?[a] <- [[2]] :put a {a}", Default::default())
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["b", null], ["c", 1], ["a", 3], ["d", "x"]])
+    );
+    let res = db
+        .run_script(
+            &format!("{data} :sort score nulls last"),
+            Default::default(),
+        )
         .unwrap();
-    tx.run_script("?[a] <- [[3]] :put a {a}", Default::default())
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["c", 1], ["a", 3], ["d", "x"], ["b", null]])
+    );
+    let res = db
+        .run_script(
+            &format!("{data} :sort -score nulls first"),
+            Default::default(),
+        )
         .unwrap();
-    tx.commit().unwrap();
     assert_eq!(
-        db.run_script("?[a] := *a[a]", Default::default())
-            .unwrap()
-            .into_json()["rows"],
-        json!([[1], [2], [3]])
+        res.into_json()["rows"],
+        json!([["b", null], ["d", "x"], ["a", 3], ["c", 1]])
     );
 
-    let db = DbInstance::new("mem", "", "").unwrap();
-    let tx = db.multi_transaction(true);
-    tx.run_script(":create a {a}", Default::default()).unwrap();
-    tx.run_script("?[a] <- [[1]] :put a {a}", Default::default())
+    // sort keys given by expressions of the output variables
+    let res = db
+        .run_script(
+            r"
+        ?[name, a, b] <- [['x', 1, 5], ['y', 4, 1], ['z', 2, 2]]
+        :sort -(a * b), name
+    ",
+            Default::default(),
+        )
         .unwrap();
-    assert!(tx.run_script(":create a {a}", Default::default()).is_err());
-    tx.run_script("?[a] <- [[2]] :put a {a}", Default::default())
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["x", 1, 5], ["y", 4, 1], ["z", 2, 2]])
+    );
+    let res = db
+        .run_script(
+            r"
+        ?[name, a] <- [['x', 3], ['y', -1], ['z', 2]]
+        :sort abs(a - 2) nulls last, name
+        :limit 2
+    ",
+            Default::default(),
+        )
         .unwrap();
-    tx.run_script("?[a] <- [[3]] :put a {a}", Default::default())
+    assert_eq!(res.into_json()["rows"], json!([["z", 2], ["x", 3]]));
+    assert!(db
+        .run_script("?[a] <- [[1]] :sort a + b", Default::default())
+        .is_err());
+
+    let res = db
+        .run_script(
+            r"
+        s[name, score] <- [['a', 3], ['b', null], ['c', 1]]
+        ?[rank, name] <~ ReorderSort(s[name, score], out: [name], sort_by: score,
+                                     descending: true, nulls: 'first', take: 10)
+    ",
+            Default::default(),
+        )
         .unwrap();
-    tx.abort().unwrap();
-    assert!(db.run_script("?[a] := *a[a]", Default::default()).is_err());
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "b"], [2, "a"], [3, "c"]])
+    );
+}
+
+#[test]
+fn returning_relations() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+        {:create _xxz {a}}
+        {?[a] := a in [5,4,1,2,3] :put _xxz {a}}
+        {?[a] := *_xxz[a], a % 2 == 0 :rm _xxz {a}}
+        {?[a] := *_xxz[b], a = b * 2}
+        "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [6], [10]]));
+    let res = db.run_script(
+        r#"
+        {?[a] := *_xxz[b], a = b * 2}
+        "#,
+        Default::default(),
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_trigger() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        ":create friends {fr: Int, to: Int => data: Any}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        ":create friends.rev {to: Int, fr: Int => data: Any}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ::set_triggers friends
+
+        on put {
+            ?[fr, to, data] := _new[fr, to, data]
+
+            :put friends.rev{ to, fr => data}
+        }
+        on rm {
+            ?[fr, to] := _old[fr, to, data]
+
+            :rm friends.rev{ to, fr }
+        }
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,3]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+    let ret = db
+        .export_relations(["friends", "friends.rev"].into_iter())
+        .unwrap();
+    let frs = ret.get("friends").unwrap();
+    assert_eq!(
+        vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)],
+        frs.rows[0]
+    );
+
+    let frs_rev = ret.get("friends.rev").unwrap();
+    assert_eq!(
+        vec![DataValue::from(2), DataValue::from(1), DataValue::from(3)],
+        frs_rev.rows[0]
+    );
+    db.run_script(
+        r"?[fr, to] <- [[1,2], [2,3]] :rm friends {fr, to}",
+        Default::default(),
+    )
+    .unwrap();
+    let ret = db
+        .export_relations(["friends", "friends.rev"].into_iter())
+        .unwrap();
+    let frs = ret.get("friends").unwrap();
+    assert!(frs.rows.is_empty());
+}
+
+#[test]
+fn test_callback() {
+    let db = new_cozo_mem().unwrap();
+    let mut collected = vec![];
+    let (_id, receiver) = db.register_callback("friends", None);
+    db.run_script(
+        ":create friends {fr: Int, to: Int => data: Any}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,3],[4,5,6]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,4],[4,7,6]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r"?[fr, to] <- [[1,9],[4,5]] :rm friends {fr, to}",
+        Default::default(),
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_secs_f64(0.01));
+    while let Ok(d) = receiver.try_recv() {
+        collected.push(d);
+    }
+    let collected = collected;
+    assert_eq!(collected[0].0, CallbackOp::Put);
+    assert_eq!(collected[0].1.rows.len(), 2);
+    assert_eq!(collected[0].1.rows[0].len(), 3);
+    assert_eq!(collected[0].2.rows.len(), 0);
+    assert_eq!(collected[1].0, CallbackOp::Put);
+    assert_eq!(collected[1].1.rows.len(), 2);
+    assert_eq!(collected[1].1.rows[0].len(), 3);
+    assert_eq!(collected[1].2.rows.len(), 1);
+    assert_eq!(
+        collected[1].2.rows[0],
+        vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)]
+    );
+    assert_eq!(collected[2].0, CallbackOp::Rm);
+    assert_eq!(collected[2].1.rows.len(), 2);
+    assert_eq!(collected[2].1.rows[0].len(), 2);
+    assert_eq!(collected[2].2.rows.len(), 1);
+    assert_eq!(collected[2].2.rows[0].len(), 3);
+}
+
+#[test]
+fn test_index() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        ":create friends {fr: Int, to: Int => data: Any}",
+        Default::default(),
+    )
+    .unwrap();
+
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,3],[4,5,6]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+
+    assert!(db
+        .run_script("::index create friends:rev {to, no}", Default::default())
+        .is_err());
+    db.run_script("::index create friends:rev {to, data}", Default::default())
+        .unwrap();
+
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,5],[6,5,7]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r"?[fr, to] <- [[4,5]] :rm friends {fr, to}",
+        Default::default(),
+    )
+    .unwrap();
+
+    let rels_data = db
+        .export_relations(["friends", "friends:rev"].into_iter())
+        .unwrap();
+    assert_eq!(
+        rels_data["friends"].clone().into_json()["rows"],
+        json!([[1, 2, 5], [6, 5, 7]])
+    );
+    assert_eq!(
+        rels_data["friends:rev"].clone().into_json()["rows"],
+        json!([[2, 5, 1], [5, 7, 6]])
+    );
+
+    let rels = db.run_script("::relations", Default::default()).unwrap();
+    assert_eq!(rels.rows[1][0], DataValue::from("friends:rev"));
+    assert_eq!(rels.rows[1][1], DataValue::from(3));
+    assert_eq!(rels.rows[1][2], DataValue::from("index"));
+
+    let cols = db
+        .run_script("::columns friends:rev", Default::default())
+        .unwrap();
+    assert_eq!(cols.rows.len(), 3);
+
+    let res = db
+        .run_script(
+            "?[fr, data] := *friends:rev{to: 2, fr, data}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 5]]));
+
+    let res = db
+        .run_script(
+            "?[fr, data] := *friends{to: 2, fr, data}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 5]]));
+
+    let expl = db
+        .run_script(
+            "::explain { ?[fr, data] := *friends{to: 2, fr, data} }",
+            Default::default(),
+        )
+        .unwrap();
+    let joins = expl.into_json()["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row.as_array().unwrap()[5].clone())
+        .collect_vec();
+    assert!(joins.contains(&json!(":friends:rev")));
+}
+
+#[test]
+fn test_custom_rules() {
+    let db = new_cozo_mem().unwrap();
+    struct Custom;
+
+    impl FixedRule for Custom {
+        fn arity(
+            &self,
+            _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+            _rule_head: &[Symbol],
+            _span: SourceSpan,
+        ) -> miette::Result<usize> {
+            Ok(1)
+        }
+
+        fn run(
+            &self,
+            payload: FixedRulePayload<'_, '_>,
+            out: &'_ mut RegularTempStore,
+            _poison: Poison,
+        ) -> miette::Result<()> {
+            let rel = payload.get_input(0)?;
+            let mult = payload.integer_option("mult", Some(2))?;
+            for maybe_row in rel.iter()? {
+                let row = maybe_row?;
+                let mut sum = 0;
+                for col in row {
+                    let d = col.get_int().unwrap_or(0);
+                    sum += d;
+                }
+                sum *= mult;
+                out.put(vec![DataValue::from(sum)])
+            }
+            Ok(())
+        }
+    }
+
+    db.register_fixed_rule("SumCols".to_string(), Custom)
+        .unwrap();
+    let res = db
+        .run_script(
+            r#"
+        rel[] <- [[1,2,3,4],[5,6,7,8]]
+        ?[x] <~ SumCols(rel[], mult: 100)
+    "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1000], [2600]]));
+}
+
+#[test]
+fn test_custom_aggregation() {
+    struct StrJoin;
+
+    struct StrJoinObj {
+        sep: String,
+        parts: Vec<String>,
+    }
+
+    impl NormalAggrObj for StrJoinObj {
+        fn set(&mut self, value: &DataValue) -> miette::Result<()> {
+            match value.get_str() {
+                Some(s) => self.parts.push(s.to_string()),
+                None => bail!("strings are required"),
+            }
+            Ok(())
+        }
+
+        fn get(&self) -> miette::Result<DataValue> {
+            Ok(DataValue::from(self.parts.join(&self.sep)))
+        }
+    }
+
+    impl Aggregation for StrJoin {
+        fn normal_init(&self, args: &[DataValue]) -> miette::Result<Box<dyn NormalAggrObj>> {
+            let sep = args.first().and_then(|v| v.get_str()).unwrap_or(",");
+            Ok(Box::new(StrJoinObj {
+                sep: sep.to_string(),
+                parts: vec![],
+            }))
+        }
+    }
+
+    struct Lowest {
+        idempotent: bool,
+    }
+
+    #[derive(Default)]
+    struct LowestObj {
+        found: Option<i64>,
+    }
+
+    impl NormalAggrObj for LowestObj {
+        fn set(&mut self, value: &DataValue) -> miette::Result<()> {
+            let i = value.get_int().unwrap();
+            self.found = Some(self.found.map_or(i, |f| f.min(i)));
+            Ok(())
+        }
+
+        fn get(&self) -> miette::Result<DataValue> {
+            Ok(self.found.map_or(DataValue::Null, DataValue::from))
+        }
+    }
+
+    struct MeetLowest;
+
+    impl MeetAggrObj for MeetLowest {
+        fn init_val(&self) -> DataValue {
+            DataValue::Null
+        }
+
+        fn update(&self, left: &mut DataValue, right: &DataValue) -> miette::Result<bool> {
+            let r = right.get_int().unwrap();
+            match left.get_int() {
+                Some(l) if l <= r => Ok(false),
+                _ => {
+                    *left = DataValue::from(r);
+                    Ok(true)
+                }
+            }
+        }
+    }
+
+    impl Aggregation for Lowest {
+        fn normal_init(&self, _args: &[DataValue]) -> miette::Result<Box<dyn NormalAggrObj>> {
+            Ok(Box::<LowestObj>::default())
+        }
+
+        fn meet_init(&self, _args: &[DataValue]) -> miette::Result<Option<Box<dyn MeetAggrObj>>> {
+            Ok(Some(Box::new(MeetLowest)))
+        }
+
+        fn is_idempotent(&self) -> bool {
+            self.idempotent
+        }
+    }
+
+    let db = new_cozo_mem().unwrap();
+    db.register_aggregation("str_join".to_string(), StrJoin)
+        .unwrap();
+    db.register_aggregation("lowest".to_string(), Lowest { idempotent: true })
+        .unwrap();
+    db.register_aggregation("lowest_once".to_string(), Lowest { idempotent: false })
+        .unwrap();
+    assert!(db
+        .register_aggregation("count".to_string(), StrJoin)
+        .is_err());
+    assert!(db
+        .register_aggregation("str_join".to_string(), StrJoin)
+        .is_err());
+
+    let res = db
+        .run_script(
+            "d[k, v] <- [[1, 'a'], [1, 'b'], [2, 'c']] ?[k, str_join(v, '-')] := d[k, v]",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.headers, vec!["k", "str_join(v)"]);
+    assert_eq!(res.into_json()["rows"], json!([[1, "a-b"], [2, "c"]]));
+
+    let res = db
+        .run_script(
+            "d[k, v] <- [[1, 3], [1, 2], [2, 5]] ?[k, lowest_once(v)] := d[k, v]",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 2], [2, 5]]));
+
+    let shortest = r"
+        edge[] <- [[1, 2, 1], [2, 3, 1], [1, 3, 5], [3, 4, 1]]
+        dist[n, AGGR(d)] := n = 1, d = 0
+        dist[n, AGGR(d)] := dist[m, d0], edge[m, n, w], d = d0 + w
+        ?[n, d] := dist[n, d]
+    ";
+    let res = db
+        .run_script(&shortest.replace("AGGR", "lowest"), Default::default())
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, 0], [2, 1], [3, 2], [4, 3]])
+    );
+    assert!(db
+        .run_script(&shortest.replace("AGGR", "lowest_once"), Default::default())
+        .is_err());
+
+    assert!(db.unregister_aggregation("str_join").unwrap());
+    assert!(db
+        .run_script("?[str_join(v)] := v in ['a']", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_custom_function() {
+    let db = new_cozo_mem().unwrap();
+    db.register_function("double".to_string(), 1, |args| match args[0].get_int() {
+        Some(i) => Ok(DataValue::from(i * 2)),
+        None => bail!("an integer is required"),
+    })
+    .unwrap();
+
+    let res = db
+        .run_script(
+            "?[x, y] := x in [1, 2, 3], y = double(x), double(y) > 5",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 4], [3, 6]]));
+
+    assert!(db
+        .run_script("?[y] := y = double('a')", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("?[y] := y = double(1, 2)", Default::default())
+        .is_err());
+    assert!(db
+        .register_function("double".to_string(), 1, |_| Ok(DataValue::Null))
+        .is_err());
+    assert!(db
+        .register_function("length".to_string(), 1, |_| Ok(DataValue::Null))
+        .is_err());
+
+    assert!(db.unregister_function("double").unwrap());
+    assert!(!db.unregister_function("double").unwrap());
+    assert!(db
+        .run_script("?[y] := y = double(1)", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_index_short() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        ":create friends {fr: Int, to: Int => data: Any}",
+        Default::default(),
+    )
+    .unwrap();
+
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,3],[4,5,6]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+
+    db.run_script("::index create friends:rev {to}", Default::default())
+        .unwrap();
+
+    db.run_script(
+        r"?[fr, to, data] <- [[1,2,5],[6,5,7]] :put friends {fr, to => data}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r"?[fr, to] <- [[4,5]] :rm friends {fr, to}",
+        Default::default(),
+    )
+    .unwrap();
+
+    let rels_data = db
+        .export_relations(["friends", "friends:rev"].into_iter())
+        .unwrap();
+    assert_eq!(
+        rels_data["friends"].clone().into_json()["rows"],
+        json!([[1, 2, 5], [6, 5, 7]])
+    );
+    assert_eq!(
+        rels_data["friends:rev"].clone().into_json()["rows"],
+        json!([[2, 1], [5, 6]])
+    );
+
+    let rels = db.run_script("::relations", Default::default()).unwrap();
+    assert_eq!(rels.rows[1][0], DataValue::from("friends:rev"));
+    assert_eq!(rels.rows[1][1], DataValue::from(2));
+    assert_eq!(rels.rows[1][2], DataValue::from("index"));
+
+    let cols = db
+        .run_script("::columns friends:rev", Default::default())
+        .unwrap();
+    assert_eq!(cols.rows.len(), 2);
+
+    let expl = db
+        .run_script(
+            "::explain { ?[fr, data] := *friends{to: 2, fr, data} }",
+            Default::default(),
+        )
+        .unwrap()
+        .into_json();
+
+    for row in expl["rows"].as_array().unwrap() {
+        println!("{}", row);
+    }
+
+    let joins = expl["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row.as_array().unwrap()[5].clone())
+        .collect_vec();
+    assert!(joins.contains(&json!(":friends:rev")));
+
+    let res = db
+        .run_script(
+            "?[fr, data] := *friends{to: 2, fr, data}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 5]]));
+}
+
+#[test]
+fn test_multi_tx() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let tx = db.multi_transaction(true);
+    tx.run_script(":create a {a}", Default::default()).unwrap();
+    tx.run_script("?[a] <- [[1]] :put a {a}", Default::default())
+        .unwrap();
+    assert!(tx.run_script(":create a {a}", Default::default()).is_err());
+    tx.run_script("?[a] <- [[2]] :put a {a}", Default::default())
+        .unwrap();
+    tx.run_script("?[a] <- [[3]] :put a {a}", Default::default())
+        .unwrap();
+    tx.commit().unwrap();
+    assert_eq!(
+        db.run_script("?[a] := *a[a]", Default::default())
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1], [2], [3]])
+    );
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let tx = db.multi_transaction(true);
+    tx.run_script(":create a {a}", Default::default()).unwrap();
+    tx.run_script("?[a] <- [[1]] :put a {a}", Default::default())
+        .unwrap();
+    assert!(tx.run_script(":create a {a}", Default::default()).is_err());
+    tx.run_script("?[a] <- [[2]] :put a {a}", Default::default())
+        .unwrap();
+    tx.run_script("?[a] <- [[3]] :put a {a}", Default::default())
+        .unwrap();
+    tx.abort().unwrap();
+    assert!(db.run_script("?[a] := *a[a]", Default::default()).is_err());
+}
+
+#[test]
+fn test_multi_tx_savepoints() {
+    let sqlite_path = std::env::temp_dir().join("_cozo_test_multi_tx_savepoints.db");
+    let _ = std::fs::remove_file(&sqlite_path);
+    for (engine, path) in [("mem", ""), ("sqlite", sqlite_path.to_str().unwrap())] {
+        let db = DbInstance::new(engine, path, "").unwrap();
+        db.run_script(":create a {a}", Default::default()).unwrap();
+        let tx = db.multi_transaction(true);
+        let put = |a: i64| {
+            tx.run_script(&format!("?[a] <- [[{a}]] :put a {{a}}"), Default::default())
+                .unwrap()
+        };
+        put(1);
+        tx.savepoint("s1").unwrap();
+        put(2);
+        tx.savepoint("s2").unwrap();
+        put(3);
+        tx.rollback_to("s1").unwrap();
+        put(4);
+        // s2 was removed by rolling back to s1
+        assert!(tx.release("s2").is_err());
+        assert!(tx.rollback_to("s2").is_err());
+        tx.savepoint("s3").unwrap();
+        put(5);
+        tx.release("s3").unwrap();
+        tx.rollback_to("s1").unwrap();
+        put(6);
+        tx.release("s1").unwrap();
+        assert!(tx.rollback_to("s1").is_err());
+        tx.commit().unwrap();
+        assert_eq!(
+            db.run_script("?[a] := *a[a]", Default::default())
+                .unwrap()
+                .into_json()["rows"],
+            json!([[1], [6]]),
+            "{engine}"
+        );
+    }
+    let _ = std::fs::remove_file(&sqlite_path);
+}
+
+#[test]
+fn test_prepared_query() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        ?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c'], [4, 'd']]
+        :create kv {k => v}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+
+    let query = db
+        .prepare("?[k, v] := *kv{k, v}, k > $min, v != $skip")
+        .unwrap();
+    for (min, skip, expected) in [
+        (1, "c", json!([[2, "b"], [4, "d"]])),
+        (0, "a", json!([[2, "b"], [3, "c"], [4, "d"]])),
+    ] {
+        let params = BTreeMap::from([
+            ("min".to_string(), DataValue::from(min)),
+            ("skip".to_string(), DataValue::from(skip)),
+        ]);
+        let res = query.run(params.clone()).unwrap().into_json();
+        assert_eq!(res["rows"], expected);
+        let direct = db.run_script(query.script(), params).unwrap().into_json();
+        assert_eq!(direct["rows"], expected);
+    }
+    assert!(query.run(Default::default()).is_err());
+
+    // parameters within lists, aggregations and constant rules
+    let query = db
+        .prepare("?[count(k), collect(v, $n)] := *kv{k, v}, k in [$a, $b]")
+        .unwrap();
+    let res = query
+        .run(BTreeMap::from([
+            ("a".to_string(), DataValue::from(1)),
+            ("b".to_string(), DataValue::from(3)),
+            ("n".to_string(), DataValue::from(1)),
+        ]))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, ["a"]]]));
+    let query = db
+        .prepare("?[k, v] <- [[$k, $v]] :put kv {k => v}")
+        .unwrap();
+    for (k, v) in [(5, "e"), (6, "f")] {
+        query
+            .run(BTreeMap::from([
+                ("k".to_string(), DataValue::from(k)),
+                ("v".to_string(), DataValue::from(v)),
+            ]))
+            .unwrap();
+    }
+    let res = db
+        .run_script("?[k, v] := *kv{k, v}, k > 4", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[5, "e"], [6, "f"]]));
+}
+
+#[test]
+fn test_prepared_query_schema_change() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[a, b] <- [[1, 2]] :create rel {a => b}",
+        Default::default(),
+    )
+    .unwrap();
+    let query = db.prepare("?[x, y] := *rel[x, y], x == $x").unwrap();
+    let params = BTreeMap::from([("x".to_string(), DataValue::from(1))]);
+    assert_eq!(
+        query.run(params.clone()).unwrap().into_json()["rows"],
+        json!([[1, 2]])
+    );
+
+    db.run_script("::remove rel", Default::default()).unwrap();
+    assert!(query.run(params.clone()).is_err());
+
+    db.run_script(
+        "?[b, a] <- [[3, 1]] :create rel {b => a}",
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        query.run(params.clone()).unwrap().into_json()["rows"],
+        json!([])
+    );
+    let params = BTreeMap::from([("x".to_string(), DataValue::from(3))]);
+    assert_eq!(
+        query.run(params).unwrap().into_json()["rows"],
+        json!([[3, 1]])
+    );
+}
+
+#[test]
+fn test_prepared_query_registrations() {
+    let db = new_cozo_mem().unwrap();
+    db.register_function("scale".to_string(), 1, |args| {
+        Ok(DataValue::from(args[0].get_int().unwrap() * 10))
+    })
+    .unwrap();
+    let query = db.prepare("?[y] := y = scale($x)").unwrap();
+    let params = BTreeMap::from([("x".to_string(), DataValue::from(2))]);
+    assert_eq!(
+        query.run(params.clone()).unwrap().into_json()["rows"],
+        json!([[20]])
+    );
+
+    assert!(db.unregister_function("scale").unwrap());
+    assert!(query.run(params.clone()).is_err());
+
+    db.register_function("scale".to_string(), 1, |args| {
+        Ok(DataValue::from(args[0].get_int().unwrap() * 100))
+    })
+    .unwrap();
+    assert_eq!(
+        query.run(params).unwrap().into_json()["rows"],
+        json!([[200]])
+    );
+}
+
+#[test]
+fn test_plan_cache() {
+    let db = new_cozo_mem().unwrap();
+    db.set_plan_cache_capacity(2);
+    db.run_script(":create rel {a => b}", Default::default())
+        .unwrap();
+    let put = "?[a, b] <- [[$a, $b]] :put rel {a => b}";
+    let get = "?[b] := *rel{a: $a, b}";
+    for i in 0..10 {
+        let params = BTreeMap::from([
+            ("a".to_string(), DataValue::from(i)),
+            ("b".to_string(), DataValue::from(i * i)),
+        ]);
+        db.run_script(put, params.clone()).unwrap();
+        let res = db.run_script(get, params).unwrap();
+        assert_eq!(res.into_json()["rows"], json!([[i * i]]));
+        // scripts that cannot be planned are run directly
+        let res = db
+            .run_script("?[x] := x = rand_int(0, 0)", Default::default())
+            .unwrap();
+        assert_eq!(res.into_json()["rows"], json!([[0]]));
+    }
+    // functions with varying results are known by their definitions
+    for f in [
+        "now()",
+        "rand_float()",
+        "rand_uuid_v1()",
+        "rand_choose([1, 2])",
+    ] {
+        let scan = scan_script(&format!("?[x] := x = {f}")).unwrap();
+        assert!(!scan.is_deterministic, "{f}");
+    }
+    assert!(scan_script("?[x] := x = abs(-1)").unwrap().is_deterministic);
+    db.run_script("::remove rel", Default::default()).unwrap();
+    db.run_script(":create rel {a, b}", Default::default())
+        .unwrap();
+    let params = BTreeMap::from([
+        ("a".to_string(), DataValue::from(1)),
+        ("b".to_string(), DataValue::from(2)),
+    ]);
+    db.run_script(put, params.clone()).unwrap_err();
+    let res = db.run_script(get, params).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([]));
+}
+
+#[test]
+fn test_run_script_iter() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        digit[d] <- [[0], [1], [2], [3], [4], [5], [6], [7], [8], [9]]
+        ?[k, v] := a in [0, 1, 2], digit[b], digit[c], digit[d],
+                   k = 1000 * a + 100 * b + 10 * c + d, v = mod(k, 7)
+        :create kv {k => v}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let collect_sorted = |script: &str| {
+        let it = db.run_script_iter(script, Default::default()).unwrap();
+        let headers = it.headers.clone();
+        let mut rows: Vec<_> = it.map(|row| row.unwrap()).collect();
+        rows.sort();
+        (headers, rows)
+    };
+
+    // streamed as computed, with rules evaluated beforehand
+    let script = r#"
+        small[k] := *kv{k, v}, v == 0
+        ?[v, k] := small[k], *kv{k, v}
+    "#;
+    let (headers, rows) = collect_sorted(script);
+    assert_eq!(headers, vec!["v", "k"]);
+    assert_eq!(
+        rows,
+        db.run_script(script, Default::default()).unwrap().rows
+    );
+    assert_eq!(rows.len(), 429);
+
+    // seeded queries are reproducible when streamed too
+    let script = "?[k, x] := *kv{k}, x = rand_int(0, 1000000000) :seed 1";
+    let (_, rows) = collect_sorted(script);
+    assert_eq!(rows, collect_sorted(script).1);
+    assert_eq!(
+        rows,
+        db.run_script(script, Default::default()).unwrap().rows
+    );
+
+    // the arithmetic mode applies to streamed rows too
+    let script = format!(
+        "?[k, x] := *kv{{k, v}}, x = k + v + {} :arith wrapping",
+        i64::MAX
+    );
+    let (_, rows) = collect_sorted(&script);
+    assert_eq!(
+        rows,
+        db.run_script(&script, Default::default()).unwrap().rows
+    );
+
+    let (_, rows) = collect_sorted("?[k] := *kv{k} :limit 10 :offset 2990");
+    assert_eq!(rows.len(), 10);
+    assert!(rows.iter().all(|row| row[0].get_int().unwrap() >= 2990));
+
+    // not known to be distinct, or sorted: computed in full first
+    let (_, rows) = collect_sorted("?[v] := *kv{v}");
+    assert_eq!(rows.len(), 7);
+    let rows: Vec<_> = db
+        .run_script_iter("?[k] := *kv{k} :order -k :limit 3", Default::default())
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec![DataValue::from(2999)],
+            vec![DataValue::from(2998)],
+            vec![DataValue::from(2997)]
+        ]
+    );
+
+    // writes and system ops are run as usual
+    let (headers, _) = collect_sorted("?[k, v] <- [[5000, 0]] :put kv {k => v}");
+    assert_eq!(headers, vec!["status"]);
+    let (_, rows) = collect_sorted("::relations");
+    assert_eq!(rows.len(), 1);
+
+    // errors in parsing and compiling are reported immediately
+    assert!(db
+        .run_script_iter("?[k] := *kv{k", Default::default())
+        .is_err());
+    assert!(db
+        .run_script_iter("?[k] := *nonexistent{k}", Default::default())
+        .is_err());
+
+    // dropping the iterator early is fine
+    let mut it = db
+        .run_script_iter("?[k, v] := *kv{k, v}", Default::default())
+        .unwrap();
+    assert!(it.next().unwrap().is_ok());
+    drop(it);
+    assert_eq!(
+        db.run_script("?[count(k)] := *kv{k}", Default::default())
+            .unwrap()
+            .rows[0][0],
+        DataValue::from(3001)
+    );
+
+    // a panic of the query thread after the headers are sent ends the rows with an error
+    db.register_function("explode".to_string(), 1, |args| {
+        if args[0] == DataValue::from(2990) {
+            panic!("exploded");
+        }
+        Ok(DataValue::from(true))
+    })
+    .unwrap();
+    let it = db
+        .run_script_iter("?[k, v] := *kv{k, v}, explode(k)", Default::default())
+        .unwrap();
+    let rows: Vec<_> = it.collect();
+    let err = rows.last().unwrap().as_ref().unwrap_err();
+    assert!(err.to_string().contains("exploded"));
+    assert!(rows[..rows.len() - 1].iter().all(|row| row.is_ok()));
+}
+
+#[test]
+fn test_cursor_pagination() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        digit[d] <- [[0], [1], [2], [3], [4], [5], [6], [7], [8], [9]]
+        ?[k, v] := a in [0, 1, 2], digit[b], digit[c], digit[d],
+                   k = 1000 * a + 100 * b + 10 * c + d, v = mod(k, 7)
+        :create kv {k => v}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let paginate = |script: &str| {
+        let mut pages = 0;
+        let mut collected = vec![];
+        let mut cursor = DataValue::Null;
+        loop {
+            let res = db
+                .run_script(script, BTreeMap::from([("cursor".to_string(), cursor)]))
+                .unwrap();
+            pages += 1;
+            collected.extend(res.rows);
+            match res.cursor {
+                None => break,
+                Some(token) => cursor = DataValue::from(token),
+            }
+        }
+        (pages, collected)
+    };
+
+    for script in [
+        // resumed by a range scan of the stored relation
+        "?[k, v] := *kv{k, v} :limit 700 :cursor $cursor",
+        "?[k, v] := *kv{k, v}, v != 3 :limit 700 :cursor $cursor",
+        // resumed by skipping the computed results
+        "?[v, k] := *kv{k, v} :limit 700 :cursor $cursor",
+        "?[k, v] := *kv{k, v} :order -v :limit 700 :cursor $cursor",
+    ] {
+        let (pages, rows) = paginate(script);
+        let expected = db
+            .run_script(
+                &script.replace(":limit 700 :cursor $cursor", ""),
+                Default::default(),
+            )
+            .unwrap()
+            .rows;
+        let n = expected.len();
+        assert_eq!(pages, n / 700 + 1, "{script}");
+        assert_eq!(rows, expected, "{script}");
+    }
+
+    let res = db
+        .run_script("?[k] := *kv{k} :limit 2 :cursor", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(0)], vec![DataValue::from(1)]]
+    );
+    let token = res.clone().cursor.unwrap();
+    assert_eq!(res.into_json()["cursor"], json!(token));
+    let res = db
+        .run_script(
+            "?[k] := *kv{k} :limit 2 :offset 1 :cursor $c",
+            BTreeMap::from([("c".to_string(), DataValue::from(token.clone()))]),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(3)], vec![DataValue::from(4)]]
+    );
+
+    // tokens are checked
+    assert!(db
+        .run_script(
+            "?[k] := *kv{k} :order -k :limit 2 :cursor $c",
+            BTreeMap::from([("c".to_string(), DataValue::from(token.clone()))]),
+        )
+        .is_err());
+    assert!(db
+        .run_script("?[k] := *kv{k} :limit 2 :cursor 'abc'", Default::default())
+        .is_err());
+    assert!(db
+        .run_script(
+            "?[k, v] := *kv{k, v} :limit 2 :cursor :put kv {k => v}",
+            Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn test_window() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+        emp[dept, name, salary] <- [['a', 'x', 10], ['a', 'y', 20], ['a', 'z', 20],
+                                    ['a', 'w', 40], ['b', 'u', 5], ['b', 'v', 15]]
+        ?[name, rn, rk, drk, prev, next, running, moving] <~
+            Window(emp[dept, name, salary],
+                   partition_by: [dept],
+                   order_by: salary,
+                   out: [name],
+                   functions: [['row_number'], ['rank'], ['dense_rank'],
+                               ['lag', salary], ['lead', salary, 1, 0],
+                               ['cumulative_sum', salary], ['mean', salary, -1, 0]])
+        "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["u", 1, 1, 1, null, 15, 5, 5.0],
+            ["v", 2, 2, 2, 5, 0, 20, 10.0],
+            ["w", 4, 4, 3, 20, 0, 90, 30.0],
+            ["x", 1, 1, 1, null, 20, 10, 10.0],
+            ["y", 2, 2, 2, 10, 20, 30, 15.0],
+            ["z", 3, 2, 2, 20, 40, 50, 20.0]
+        ])
+    );
+
+    let res = db
+        .run_script(
+            r#"
+        emp[name, salary] <- [['x', 10], ['y', 20], ['z', 30]]
+        ?[name, top, total] <~
+            Window(emp[name, salary],
+                   order_by: salary,
+                   descending: true,
+                   out: [name],
+                   functions: [['max', salary, null, null], ['sum', salary, 0, 1]])
+        "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["x", 30, 10], ["y", 30, 30], ["z", 30, 50]])
+    );
+
+    assert!(db
+        .run_script(
+            "?[x, y] <~ Window(r[x], out: [x], functions: [['nonexistent', x]]) r[x] <- [[1]]",
+            Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn test_views() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        ?[id, name, active] <- [[1, 'alice', true], [2, 'bob', false], [3, 'carol', true]]
+        :create users {id => name, active}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ?[uid, version, email] <- [[1, 1, 'a@old'], [1, 2, 'a@new'], [3, 1, 'c@x']]
+        :create profiles {uid, version => email}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ::view create active_profiles {
+            latest[uid, max(version)] := *profiles{uid, version}
+            ?[id, name, email] := *users{id, name, active: true}, latest[id, version],
+                                  *profiles{uid: id, version, email}
+        }
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script(
+            "?[name, email] := *active_profiles[_, name, email]",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["alice", "a@new"], ["carol", "c@x"]])
+    );
+    // bindings given to the view are pushed into its rules
+    let res = db
+        .run_script(
+            "?[email] := *active_profiles{id: 1, email}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a@new"]]));
+    let expl = db
+        .run_script(
+            "::explain { ?[email] := *active_profiles{id: 1, email} }",
+            Default::default(),
+        )
+        .unwrap()
+        .into_json();
+    // the rules of the view are adorned with the bound column, and the scan of `users`
+    // is joined on it instead of being read in full
+    let view_ops = expl["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|row| row[2] == json!("*active_profiles|Mbff"))
+        .collect_vec();
+    assert!(view_ops
+        .iter()
+        .any(|row| row[4] == json!("load_stored") && row[5] == json!(":users")));
+    assert!(view_ops.iter().any(|row| matches!(
+        row[6].as_array(),
+        Some(joins) if joins.iter().any(|pair| pair[0] == json!("id"))
+    )));
+
+    // views can refer to other views, and be used by fixed rules
+    db.run_script(
+        "::view create active_names { ?[name] := *active_profiles{name} }",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[n] := *active_names[n], n != 'carol'", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice"]]));
+    let res = db
+        .run_script(
+            "?[rank, name] <~ ReorderSort(*active_names[name], out: [name], sort_by: name, descending: true, take: 10)",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "carol"], [2, "alice"]]));
+
+    let res = db.run_script("::views", Default::default()).unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert_eq!(res.rows[0][0], DataValue::from("active_names"));
+
+    // names are shared with stored relations
+    assert!(db
+        .run_script("::view create users { ?[x] := x = 1 }", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("?[x] <- [[1]] :create active_names {x}", Default::default())
+        .is_err());
+    let err = db
+        .run_script("::rename users -> active_names", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::view_name_conflict");
+    // wrong arity, options in the definition
+    assert!(db
+        .run_script("?[n] := *active_names[n, m]", Default::default())
+        .is_err());
+    assert!(db
+        .run_script(
+            "::view create limited { ?[x] := x = 1 :limit 1 }",
+            Default::default()
+        )
+        .is_err());
+
+    db.run_script("::view drop active_names", Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("?[n] := *active_names[n]", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::view drop active_names", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_materialized_views() {
+    let db = new_cozo_mem().unwrap();
+    let rows = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    db.run_script(
+        "?[a, b] <- [[1, 2], [2, 3], [1, 3]] :create edge {a, b}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ::view materialize reach {
+            r[a, b] := *edge{a, b}
+            r[a, c] := r[a, b], *edge{a: b, b: c}
+            ?[a, b] := r[a, b]
+        }
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::view materialize reach_from_one { ?[b] := *reach{a: 1, b} }",
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        rows("?[a, b] := *reach[a, b]"),
+        json!([[1, 2], [1, 3], [2, 3]])
+    );
+
+    db.run_script("?[a, b] <- [[3, 4]] :put edge {a, b}", Default::default())
+        .unwrap();
+    assert_eq!(
+        rows("?[a, b] := *reach[a, b]"),
+        json!([[1, 2], [1, 3], [1, 4], [2, 3], [2, 4], [3, 4]])
+    );
+    // 1 still reaches 3 and 4 by the direct edge
+    db.run_script("?[a, b] <- [[2, 3]] :rm edge {a, b}", Default::default())
+        .unwrap();
+    assert_eq!(
+        rows("?[a, b] := *reach[a, b]"),
+        json!([[1, 2], [1, 3], [1, 4], [3, 4]])
+    );
+    db.run_script("?[a, b] <- [[1, 3]] :rm edge {a, b}", Default::default())
+        .unwrap();
+    assert_eq!(rows("?[a, b] := *reach[a, b]"), json!([[1, 2], [3, 4]]));
+    assert_eq!(rows("?[b] := *reach_from_one[b]"), json!([[2]]));
+
+    // changed values, and views that have to be recomputed
+    db.run_script(
+        r#"
+        ?[id, name, dept] <- [[1, 'alice', 'eng'], [2, 'bob', 'eng'], [3, 'carol', 'ops']]
+        :create person {id => name, dept}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::view materialize headcount { ?[dept, count(id)] := *person{id, dept} }",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[id, name, dept] <- [[2, 'bob', 'ops']] :put person {id => name, dept}",
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        rows("?[d, n] := *headcount[d, n]"),
+        json!([["eng", 1], ["ops", 2]])
+    );
+    db.run_script("?[id] <- [[1]] :rm person {id}", Default::default())
+        .unwrap();
+    assert_eq!(rows("?[d, n] := *headcount[d, n]"), json!([["ops", 2]]));
+
+    let res = db.run_script("::views", Default::default()).unwrap();
+    assert_eq!(res.rows.len(), 3);
+    assert_eq!(res.rows[0][2], DataValue::from(true));
+
+    // the view is only written by its maintenance, and its bases are kept in place
+    assert!(db
+        .run_script("?[a, b] <- [[5, 6]] :put reach {a, b}", Default::default())
+        .is_err());
+    assert!(db.run_script("::remove edge", Default::default()).is_err());
+    assert!(db
+        .run_script(
+            "?[a, b, c] <- [[5, 6, 7]] :replace edge {a, b, c}",
+            Default::default()
+        )
+        .is_err());
+    // replacing the rows of a base keeps the views computed from it up to date
+    db.run_script(
+        "?[a, b] <- [[1, 5], [5, 6]] :replace edge {a, b}",
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        rows("?[a, b] := *reach[a, b]"),
+        json!([[1, 5], [1, 6], [5, 6]])
+    );
+    assert_eq!(rows("?[b] := *reach_from_one[b]"), json!([[5], [6]]));
+    // and so does importing rows
+    db.import_relations(BTreeMap::from([
+        (
+            "edge".to_string(),
+            NamedRows::new(
+                vec!["a".to_string(), "b".to_string()],
+                vec![vec![DataValue::from(6), DataValue::from(7)]],
+            ),
+        ),
+        (
+            "-edge".to_string(),
+            NamedRows::new(
+                vec!["a".to_string(), "b".to_string()],
+                vec![vec![DataValue::from(1), DataValue::from(5)]],
+            ),
+        ),
+    ]))
+    .unwrap();
+    assert_eq!(
+        rows("?[a, b] := *reach[a, b]"),
+        json!([[5, 6], [5, 7], [6, 7]])
+    );
+    assert_eq!(rows("?[b] := *reach_from_one[b]"), json!([]));
+    assert!(db
+        .run_script("::view drop reach", Default::default())
+        .is_err());
+
+    // failing to maintain a view aborts the write
+    db.run_script("?[x] <- [[1]] :create nums {x}", Default::default())
+        .unwrap();
+    db.run_script(
+        "::view materialize positive { ?[x] := *nums{x}, assert(x > 0) }",
+        Default::default(),
+    )
+    .unwrap();
+    assert!(db
+        .run_script("?[x] <- [[2], [-1]] :put nums {x}", Default::default())
+        .is_err());
+    assert_eq!(rows("?[x] := *nums[x]"), json!([[1]]));
+    assert_eq!(rows("?[x] := *positive[x]"), json!([[1]]));
+
+    for view in ["reach_from_one", "reach", "headcount", "positive"] {
+        db.run_script(&format!("::view drop {view}"), Default::default())
+            .unwrap();
+    }
+    db.run_script("::remove edge", Default::default()).unwrap();
+    assert!(db
+        .run_script("?[b] := *reach_from_one[b]", Default::default())
+        .is_err());
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn test_import_from_backup_into_view_base() {
+    let path = std::env::temp_dir().join(format!("cozo-view-base-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let src = new_cozo_mem().unwrap();
+    src.run_script(
+        "?[a, b] <- [[1, 2], [2, 3]] :create edge {a, b}",
+        Default::default(),
+    )
+    .unwrap();
+    src.backup_db(&path).unwrap();
+
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[a, b] <- [[3, 4]] :create edge {a, b}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ::view materialize reach {
+            r[a, b] := *edge{a, b}
+            r[a, c] := r[a, b], *edge{a: b, b: c}
+            ?[a, b] := r[a, b]
+        }
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.import_from_backup(&path, &["edge".to_string()]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let res = db
+        .run_script("?[a, b] := *reach[a, b]", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, 2], [1, 3], [1, 4], [2, 3], [2, 4], [3, 4]])
+    );
+}
+
+#[test]
+fn test_procedures() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create users {id => name}", Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+        ::procedure create add_user($id, $name) {
+            ?[id, name] <- [[$id, $name]]
+            :put users {id => name}
+        }
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ::procedure create user_status($id) {
+            %if { ?[name] := *users{id: $id, name} }
+                %then { ?[status] <- [['exists']] }
+                %else { ?[status] <- [['missing']] }
+            %end
+        }
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+
+    db.run_script("::call add_user(1, 'alice')", Default::default())
+        .unwrap();
+    db.call_procedure(
+        "add_user",
+        BTreeMap::from([
+            ("id".to_string(), DataValue::from(2)),
+            ("name".to_string(), DataValue::from("bob")),
+        ]),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[id, name] := *users{id, name}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "alice"], [2, "bob"]]));
+    let res = db
+        .run_script(
+            "::call user_status($id)",
+            BTreeMap::from([("id".to_string(), DataValue::from(3))]),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["missing"]]));
+    let res = db
+        .call_procedure(
+            "user_status",
+            BTreeMap::from([("id".to_string(), DataValue::from(1))]),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["exists"]]));
+    db.run_script(
+        "::procedure create alice_status() { ::call user_status(1) }",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::call alice_status()", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["exists"]]));
+    db.run_script("::procedure drop alice_status", Default::default())
+        .unwrap();
+
+    let res = db.run_script("::procedures", Default::default()).unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert_eq!(
+        res.rows[0][1],
+        DataValue::List(vec![DataValue::from("id"), DataValue::from("name")])
+    );
+
+    // wrong arguments, undeclared parameters and existing names
+    assert!(db
+        .run_script("::call add_user(3)", Default::default())
+        .is_err());
+    assert!(db
+        .call_procedure("user_status", Default::default())
+        .is_err());
+    assert!(db
+        .run_script(
+            "::procedure create bad() { ?[x] := x = $x }",
+            Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_script(
+            "::procedure create add_user($id) { ?[x] := x = $id }",
+            Default::default()
+        )
+        .is_err());
+
+    // procedures run with the access levels of the relations they touch
+    db.run_script("::access_level read_only users", Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("::call add_user(3, 'carol')", Default::default())
+        .is_err());
+
+    db.run_script("::procedure drop add_user", Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("::call add_user(3, 'carol')", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::procedure drop add_user", Default::default())
+        .is_err());
+
+    // procedures calling themselves stop at the maximal call depth
+    db.run_script(
+        "::procedure create forever() { ::call forever() }",
+        Default::default(),
+    )
+    .unwrap();
+    let err = db
+        .run_script("::call forever()", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::procedure_call_too_deep"
+    );
+    let res = db.run_script("?[x] <- [[1]]", Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+}
+
+#[test]
+fn seeded_randomness() {
+    let db = new_cozo_mem().unwrap();
+    let run = |script: &str| db.run_script(script, Default::default()).unwrap().rows;
+
+    let xs = (0..100)
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let query = format!(
+        r#"
+        r[x] := x in [{xs}]
+        c[choice_rand(x)] := r[x]
+        ?[f, i, u, l, c] := f = rand_float(), i = rand_int(0, 1000000), u = rand_uuid_v4(),
+                            l = rand_choose([1, 2, 3, 4, 5, 6, 7, 8, 9, 10]), c[c]
+    "#
+    );
+    let seeded = format!("{query} :seed 42");
+    let first = run(&seeded);
+    assert_eq!(first, run(&seeded));
+    assert_ne!(first, run(&format!("{query} :seed 43")));
+
+    // the random draws made while parsing follow the seed too
+    let seeded = "?[x, y] <- [[rand_int(0, 1000000), rand_float()]] :seed 1";
+    let first = run(seeded);
+    assert_eq!(first, run(seeded));
+    assert_ne!(first, run(&seeded.replace(":seed 1", ":seed 2")));
+
+    let graph = r#"
+        edges[] <- [[1, 2], [1, 3], [2, 3], [3, 1], [3, 4], [4, 5], [5, 6], [6, 4]]
+        nodes[n] := edges[n, _]
+        nodes[n] := edges[_, n]
+    "#;
+    for rule in [
+        "?[] <~ RandomWalk(edges[], nodes[], nodes[], steps: 10, seed: 7)",
+        "?[] <~ LabelPropagation(edges[], seed: 7)",
+    ] {
+        let script = format!("{graph} {rule}");
+        assert_eq!(run(&script), run(&script));
+    }
+    let unseeded_rule =
+        format!("{graph} ?[] <~ RandomWalk(edges[], nodes[], nodes[], steps: 10) :seed 7");
+    assert_eq!(run(&unseeded_rule), run(&unseeded_rule));
+    let drawn_option = format!(
+        "{graph} ?[] <~ RandomWalk(edges[], nodes[], nodes[], steps: rand_int(5, 50)) :seed 7"
+    );
+    assert_eq!(run(&drawn_option), run(&drawn_option));
+}
+
+#[test]
+fn checked_arithmetic() {
+    let db = new_cozo_mem().unwrap();
+    let run = |script: &str| db.run_script(script, Default::default());
+    let max = i64::MAX;
+
+    for expr in [
+        format!("{max} + 1"),
+        format!("-{max} - 2"),
+        format!("{max} * 2"),
+        format!("-(-{max} - 1)"),
+        format!("abs(-{max} - 1)"),
+    ] {
+        let err = run(&format!("?[x] := x = {expr}")).unwrap_err();
+        let help = err.help().unwrap().to_string();
+        assert!(help.starts_with("Integer overflow"), "{expr}");
+    }
+    let res = run(&format!("?[x] := x = {max} + 1 :arith wrapping")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MIN)]]);
+    let res = run(&format!("?[x] := x = {max} + 1 :arith saturating")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MAX)]]);
+    let res = run(&format!("?[x] <- [[{max} * 2]] :arith saturating")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MAX)]]);
+
+    let data = format!("r[x] <- [[{max}], [1], [-2]]");
+    let res = run(&format!("{data} ?[sum(x)] := r[x]")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MAX - 1)]]);
+    let res = run(&format!("{data} ?[sum(x)] := r[x], x > 0"));
+    assert_eq!(
+        res.unwrap_err().code().unwrap().to_string(),
+        "eval::integer_overflow"
+    );
+    let res = run(&format!("{data} ?[sum(x)] := r[x], x > 0 :arith wrapping")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MIN)]]);
+    let res = run(&format!("{data} ?[product(x)] := r[x] :arith saturating")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MIN)]]);
+    let res = run("?[sum(x)] := x in [1, 2.5]").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(3.5)]]);
+    // only the total is checked, not the intermediate sums
+    let res = run(&format!("?[sum(x)] := x in [-{max}, -2, 5]")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(3 - i64::MAX)]]);
+    let res = run(&format!("?[product(x)] := x in [{max}, 2, 0]")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(0)]]);
+    let data = format!("r[x] <- [[{max}], [3], [-1]]");
+    let res = run(&format!("{data} ?[product(x)] := r[x] :arith saturating")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(i64::MIN)]]);
+    let res = run(&format!("{data} ?[product(x)] := r[x] :arith wrapping")).unwrap();
+    let wrapped = i64::MAX.wrapping_mul(3).wrapping_neg();
+    assert_eq!(res.rows, vec![vec![DataValue::from(wrapped)]]);
+    // integer totals stay exact beyond the precision of floats
+    let res = run("?[sum(x)] := x in [9007199254740993, 0]").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(9007199254740993i64)]]);
+    let err = run("?[x] := x = mod(5, 0)").unwrap_err();
+    assert_eq!(err.help().unwrap().to_string(), "Integer modulo by zero");
+    let res = run(&format!("?[x] := x = mod(-{max} - 1, -1)")).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(0)]]);
+}
+
+#[test]
+fn decimals() {
+    let db = new_cozo_mem().unwrap();
+    let run = |script: &str| db.run_script(script, Default::default());
+    let dec = |s: &str| DataValue::Num(Num::Decimal(s.parse().unwrap()));
+
+    let res =
+        run("?[x, y] := x = to_decimal('0.1') + to_decimal('0.2'), y = x == 0.1 + 0.2").unwrap();
+    assert_eq!(res.rows, vec![vec![dec("0.3"), DataValue::from(false)]]);
+    let res = run("?[x] := x = to_decimal(1, 2) / 3 * 3 - 1").unwrap();
+    assert_eq!(res.rows, vec![vec![dec("-0.01")]]);
+    let res = run("?[x] := x = to_decimal('12.345') == to_decimal('12.3450')").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(true)]]);
+    let res = run("?[x] := x = to_decimal('-2.5'), x < -2, is_decimal(x)").unwrap();
+    assert_eq!(res.rows, vec![vec![dec("-2.5")]]);
+    let res =
+        run("?[x, y] := x = round(to_decimal('-2.5')), y = floor(to_decimal('-2.5'))").unwrap();
+    assert_eq!(res.rows, vec![vec![dec("-3"), dec("-3")]]);
+    assert!(run("?[x] := x = to_decimal('1.2.3')").is_err());
+    let res = run("?[s, m, p] := s = to_string(to_decimal('1.50')), \
+                       m = mod(to_decimal('-7.5'), 2), p = pow(to_decimal('1.5'), 2)")
+    .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![
+            DataValue::from("1.50"),
+            dec("-1.5"),
+            DataValue::from(2.25)
+        ]]
+    );
+    let res = run("?[x] := x = 9007199254740993 > to_decimal('9007199254740992')").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(true)]]);
+
+    run(":create prices {item: String => price: Decimal(2)}").unwrap();
+    let data = "[['a', '0.10'], ['b', 0.125], ['c', 3], ['d', -1]]";
+    run(&format!(
+        "?[item, price] <- {data} :put prices {{item => price}}"
+    ))
+    .unwrap();
+    let res = run("?[item, price] := *prices[item, price]").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from("a"), dec("0.10")],
+            vec![DataValue::from("b"), dec("0.13")],
+            vec![DataValue::from("c"), dec("3.00")],
+            vec![DataValue::from("d"), dec("-1.00")],
+        ]
+    );
+    assert_eq!(res.into_json()["rows"][0][1], json!("0.10"));
+    assert!(run("?[item, price] <- [['e', 'x']] :put prices {item => price}").is_err());
+
+    let res = run("?[sum(price)] := *prices[_, price]").unwrap();
+    assert_eq!(res.rows, vec![vec![dec("2.23")]]);
+    let res = run("?[sum(x)] := x in [to_decimal('0.1'), to_decimal('0.1'), 1]").unwrap();
+    assert_eq!(res.rows, vec![vec![dec("1.2")]]);
+    let res = run("?[item, price] := *prices[item, price], price > 0.1 :order -price").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from("c"), dec("3.00")],
+            vec![DataValue::from("b"), dec("0.13")]
+        ]
+    );
+
+    run(":create by_price {price: Decimal(2) => item: String}").unwrap();
+    run("?[price, item] := *prices[item, price] :put by_price {price => item}").unwrap();
+    let res = run("?[item] := *by_price[to_decimal(3, 2), item]").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from("c")]]);
+    let res = run("?[price] := *by_price[price, _], price < 1").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![dec("-1.00")], vec![dec("0.10")], vec![dec("0.13")]]
+    );
+}
+
+#[test]
+fn check_script() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create rel {a: Int => b: Int}", Default::default())
+        .unwrap();
+    let check = |script: &str| db.check_script(script, Default::default());
+    let codes = |res: &JsonValue| {
+        res["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["code"].as_str().unwrap().to_string())
+            .collect_vec()
+    };
+
+    assert_eq!(
+        check("?[a, b] := *rel[a, b]"),
+        json!({"ok": true, "diagnostics": []})
+    );
+    let res = check("?[a] := *rel[a, b");
+    assert_eq!(res["ok"], json!(false));
+    assert_eq!(codes(&res), ["parser::pest"]);
+    assert!(res["diagnostics"][0]["labels"][0]["span"]["offset"].is_number());
+    assert_eq!(
+        codes(&check("?[a] := *nope[a]")),
+        ["query::relation_not_found"]
+    );
+    assert_eq!(
+        codes(&check("?[a] := *rel[a, b, c]")),
+        ["eval::rule_arity_mismatch"]
+    );
+    assert_eq!(
+        codes(&check("?[a] <- [[1]] :put rel {a => c}")),
+        ["eval::required_col_not_found"]
+    );
+    assert_eq!(
+        codes(&check("?[x] <~ NotARule(rel[])")),
+        ["parser::fixed_rule_not_found"]
+    );
+    assert_eq!(
+        codes(&check("r[a] := r[b], a = b + 1, not r[a] ?[a] := r[a]")),
+        ["eval::unstratifiable"]
+    );
+
+    // every query of an imperative script is checked
+    let res = check(
+        r#"
+        { ?[a] := *nope[a] }
+        { ?[a] <- [[1]] :replace _t {a} }
+        { ?[a] := *_t[a] }
+        %try { ?[a] := *rel[a, _] } %catch _err { ?[code] := *_err[code, _, _] } %end
+        { ?[a] := *rel[a, b, c] }
+        "#,
+    );
+    assert_eq!(
+        codes(&res),
+        ["query::relation_not_found", "eval::rule_arity_mismatch"]
+    );
+
+    // stored relations created by the script are used by the queries after them
+    let res = check(
+        r#"
+        { ?[a] <- [[1]] :create other {a} }
+        { ?[a] := *other[a] }
+        { ?[a, b] <- [[1, 2]] :replace rel {a, b} }
+        { ?[a] := *rel[a, b, c] }
+        "#,
+    );
+    assert_eq!(codes(&res), ["eval::rule_arity_mismatch"]);
+
+    // system ops are checked against the catalog
+    assert!(check("::index create rel:idx {b}")["ok"].as_bool().unwrap());
+    assert_eq!(
+        codes(&check("::index create nope:idx {a}")),
+        ["query::relation_not_found"]
+    );
+    assert_eq!(
+        codes(&check("::index create rel:idx {c}")),
+        ["tx::col_in_idx_not_found"]
+    );
+    assert_eq!(
+        codes(&check("::view create v { ?[a] := *nope[a] }")),
+        ["query::relation_not_found"]
+    );
+    assert!(check("::view create v { ?[a] := *rel[a, _] }")["ok"]
+        .as_bool()
+        .unwrap());
+    assert_eq!(
+        codes(&check("::remove nope")),
+        ["query::relation_not_found"]
+    );
+
+    // deferred queries are checked with their parameters unknown, or else warned about
+    let res = check(
+        r#"
+        %let $n = { ?[n] <- [[1]] }
+        { ?[a] := *nope[a], a > $n }
+        { ?[a] := *rel[a, _] :limit $n }
+        "#,
+    );
+    assert_eq!(
+        codes(&res),
+        ["query::relation_not_found", "eval::query_not_checked"]
+    );
+    assert_eq!(res["diagnostics"][1]["severity"], json!("warning"));
+    let res = check("%let $n = { ?[n] <- [[1]] } { ?[a] := *rel[a, _] :limit $n }");
+    assert_eq!(res["ok"], json!(true));
+    assert_eq!(codes(&res), ["eval::query_not_checked"]);
+
+    // nothing is written
+    assert!(check("?[a, b] <- [[1, 2]] :put rel {a => b}")["ok"]
+        .as_bool()
+        .unwrap());
+    assert!(check(":create other {a}")["ok"].as_bool().unwrap());
+    let res = db
+        .run_script("?[a] := *rel[a, _]", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+    assert!(db
+        .run_script("?[a] := *other[a]", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::columns rel:idx", Default::default())
+        .is_err());
+    let res = db.run_script("::views", Default::default()).unwrap();
+    assert!(res.rows.is_empty());
+}
+
+#[test]
+fn check_script_with_concurrent_writes() {
+    // pauses the check in the middle of its transaction: the arity is asked for twice when
+    // the script is parsed, and again when the definition of the view is checked
+    struct Pause {
+        calls: AtomicUsize,
+        entered: Sender<()>,
+        resume: Receiver<()>,
+    }
+
+    impl FixedRule for Pause {
+        fn arity(
+            &self,
+            _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+            _rule_head: &[Symbol],
+            _span: SourceSpan,
+        ) -> miette::Result<usize> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 2 {
+                self.entered.send(()).unwrap();
+                self.resume.recv().unwrap();
+            }
+            Ok(1)
+        }
+
+        fn run(
+            &self,
+            _payload: FixedRulePayload<'_, '_>,
+            _out: &'_ mut RegularTempStore,
+            _poison: Poison,
+        ) -> miette::Result<()> {
+            Ok(())
+        }
+    }
+
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create rel {a}", Default::default())
+        .unwrap();
+    let (entered_tx, entered_rx) = bounded(1);
+    let (resume_tx, resume_rx) = bounded(1);
+    db.register_fixed_rule(
+        "Pause".to_string(),
+        Pause {
+            calls: AtomicUsize::new(0),
+            entered: entered_tx,
+            resume: resume_rx,
+        },
+    )
+    .unwrap();
+
+    thread::scope(|s| {
+        let db = &db;
+        let check =
+            s.spawn(|| db.check_script("::view create v { ?[a] <~ Pause() }", Default::default()));
+        entered_rx.recv().unwrap();
+
+        // the check holds no write transaction, so queries go on while it is in progress
+        let (read_tx, read_rx) = bounded(1);
+        s.spawn(move || {
+            let res = db.run_script("?[a] := *rel[a]", Default::default());
+            read_tx.send(res.map(|res| res.rows)).unwrap();
+        });
+        let read = read_rx.recv_timeout(Duration::from_secs(10));
+        let write = s.spawn(|| db.run_script("?[a] <- [[1]] :put rel {a}", Default::default()));
+        resume_tx.send(()).unwrap();
+        assert!(read.unwrap().unwrap().is_empty());
+        write.join().unwrap().unwrap();
+        assert_eq!(
+            check.join().unwrap(),
+            json!({"ok": true, "diagnostics": []})
+        );
+    });
+
+    // the write is kept, and the view declared by the check is not
+    let res = db
+        .run_script("?[a] := *rel[a]", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, [[DataValue::from(1)]]);
+    let res = db.run_script("::views", Default::default()).unwrap();
+    assert!(res.rows.is_empty());
+}
+
+#[test]
+fn failing_task_error_not_masked_by_kill() {
+    let db = new_cozo_mem().unwrap();
+    // `slow` and `bad` are independent, so they may be evaluated concurrently
+    let script = r#"
+        slow[n] := n = 0
+        slow[m] := slow[n], n < 2000, m = n + 1
+        bad[x] := x = to_uuid('not a uuid')
+        ?[n, x] := slow[n], bad[x]
+    "#;
+    for _ in 0..10 {
+        let err = db.run_script(script, Default::default()).unwrap_err();
+        assert_ne!(err.code().unwrap().to_string(), "eval::killed");
+    }
+}
+
+#[test]
+fn upgrade_storage_version() {
+    let db = new_cozo_mem().unwrap();
+    // rounds down to the float 2^53, so it was stored before that float
+    let big = (1i64 << 53) + 1;
+    let tie = (1i64 << 53) as f64;
+    db.run_script(":create r {k => v}", Default::default())
+        .unwrap();
+    db.run_script(
+        &format!(
+            "?[k, v] <- [[{big}, 'int'], [{tie:.1}, 'float'], [1, 'small']] :put r {{k => v}}"
+        ),
+        Default::default(),
+    )
+    .unwrap();
+
+    // store the keys and the version as an earlier version did
+    let version_key =
+        vec![DataValue::Null, DataValue::from("STORAGE_VERSION")].encode_as_key(RelationId::SYSTEM);
+    let mut tx = db.transact_write().unwrap();
+    let stored: Vec<_> = tx.store_tx.total_scan().try_collect().unwrap();
+    for (key, val) in stored {
+        if decode_tuple_from_key(&key).first() != Some(&DataValue::from(big)) {
+            continue;
+        }
+        let mut old_key = key[..8].to_vec();
+        old_key.encode_datavalue(&DataValue::from(big as f64));
+        old_key.pop();
+        old_key.push(0b00000100);
+        old_key.extend((big as u64 ^ 0x8000000000000000).to_be_bytes());
+        tx.store_tx.del(&key).unwrap();
+        tx.store_tx.put(&old_key, &val).unwrap();
+    }
+    tx.store_tx.put(&version_key, &[0x00]).unwrap();
+    tx.commit_tx().unwrap();
+    drop(tx);
+    // query results are sorted again, so read the keys in storage order
+    let stored_keys = || {
+        let tx = db.transact().unwrap();
+        let keys: Vec<_> = tx
+            .store_tx
+            .total_scan()
+            .map_ok(|(key, _)| decode_tuple_from_key(&key))
+            .filter_ok(|tuple| matches!(tuple.first(), Some(DataValue::Num(_))))
+            .try_collect()
+            .unwrap();
+        keys
+    };
+    let small = vec![DataValue::from(1)];
+    let (int, float) = (vec![DataValue::from(big)], vec![DataValue::from(tie)]);
+    assert_eq!(stored_keys(), [small.clone(), int.clone(), float.clone()]);
+
+    let mut tx = db.transact_write().unwrap();
+    tx.init_storage().unwrap();
+    tx.commit_tx().unwrap();
+    drop(tx);
+    assert_eq!(stored_keys(), [small, float, int]);
+    assert_eq!(
+        db.run_script(
+            "?[v] := *r{k: $k, v}",
+            BTreeMap::from([("k".to_string(), DataValue::from(big))])
+        )
+        .unwrap()
+        .rows,
+        [[DataValue::from("int")]]
+    );
+    let tx = db.transact().unwrap();
+    assert_eq!(
+        tx.store_tx.get(&version_key, false).unwrap(),
+        Some(CURRENT_STORAGE_VERSION.to_vec())
+    );
 }
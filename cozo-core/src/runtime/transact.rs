@@ -9,8 +9,10 @@
 use std::sync::atomic::{AtomicU32, AtomicU64};
 use std::sync::Arc;
 
+use itertools::Itertools;
 use miette::{bail, Result};
 
+use crate::data::memcmp::upgrade_key_encoding;
 use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::runtime::relation::RelationId;
@@ -24,7 +26,9 @@ pub struct SessionTx<'a> {
     pub(crate) temp_store_id: AtomicU32,
 }
 
-pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
+pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x01];
+/// Stores of this version have keys with integers in an earlier encoding, upgraded on opening
+const INEXACT_INT_STORAGE_VERSION: [u8; 1] = [0x00];
 
 fn storage_version_key() -> Vec<u8> {
     let storage_version_tuple = vec![DataValue::Null, DataValue::from("STORAGE_VERSION")];
@@ -51,6 +55,11 @@ impl<'a> SessionTx<'a> {
                     None => {
                         bail!("Storage is used but un-versioned, probably created by an ancient version of Cozo.")
                     }
+                    Some(v) if v == INEXACT_INT_STORAGE_VERSION => {
+                        self.upgrade_key_encodings()?;
+                        self.store_tx
+                            .put(&storage_version_key, &CURRENT_STORAGE_VERSION)?;
+                    }
                     Some(v) => {
                         if &v != &CURRENT_STORAGE_VERSION {
                             bail!(
@@ -67,8 +76,38 @@ impl<'a> SessionTx<'a> {
         Ok(ret)
     }
 
+    /// Encode again the keys stored in an earlier encoding.
+    fn upgrade_key_encodings(&mut self) -> Result<()> {
+        let upgraded: Vec<_> = self
+            .store_tx
+            .total_scan()
+            .filter_map_ok(|(key, val)| upgrade_key_encoding(&key).map(|new| (key, new, val)))
+            .try_collect()?;
+        for (key, new_key, val) in upgraded {
+            self.store_tx.del(&key)?;
+            self.store_tx.put(&new_key, &val)?;
+        }
+        Ok(())
+    }
+
     pub fn commit_tx(&mut self) -> Result<()> {
         self.store_tx.commit()?;
         Ok(())
     }
+
+    /// Savepoints cover both the stored and the temp relations
+    pub(crate) fn set_savepoint(&mut self) -> Result<()> {
+        self.store_tx.set_savepoint()?;
+        self.temp_store_tx.set_savepoint()
+    }
+
+    pub(crate) fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.store_tx.rollback_to_savepoint()?;
+        self.temp_store_tx.rollback_to_savepoint()
+    }
+
+    pub(crate) fn pop_savepoint(&mut self) -> Result<()> {
+        self.store_tx.pop_savepoint()?;
+        self.temp_store_tx.pop_savepoint()
+    }
 }
@@ -0,0 +1,1190 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::current_validity;
+use crate::data::program::{
+    FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
+    InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
+    InputSubquery,
+};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
+use crate::parse::{parse_script, CozoScript, ParseContext, SourceSpan};
+use crate::query::logical::NamedFieldNotFound;
+use crate::query::stored::make_const_rule;
+use crate::runtime::callback::{CallbackCollector, CallbackOp};
+use crate::runtime::db::Db;
+use crate::runtime::relation::{AccessLevel, InputRelationHandle, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::storage::Storage;
+use crate::{FixedRule, NamedRows};
+
+/// Views are stored in the system relation under keys of this prefix, followed by their names.
+const VIEW_KEY_PREFIX: &str = "VIEW";
+
+fn view_key(name: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from(VIEW_KEY_PREFIX),
+        DataValue::from(name),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+/// How a view is kept in the system relation
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ViewHandle {
+    pub(crate) definition: String,
+    /// Whether the rows of the view are kept in the stored relation of the same name
+    #[serde(default)]
+    pub(crate) materialized: bool,
+    /// The stored relations the rows of a materialized view are computed from
+    #[serde(default)]
+    pub(crate) bases: Vec<SmartString<LazyCompact>>,
+}
+
+impl ViewHandle {
+    fn encode(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        self.serialize(&mut Serializer::new(&mut ret).with_struct_map())
+            .unwrap();
+        ret
+    }
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The name {0} is already taken by a relation or view")]
+#[diagnostic(code(eval::view_name_conflict))]
+pub(crate) struct ViewNameConflictError(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Cannot find requested view '{0}'")]
+#[diagnostic(code(query::view_not_found))]
+struct ViewNotFoundError(String, #[label] SourceSpan);
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn view_exists(&self, name: &str) -> Result<bool> {
+        self.store_tx.exists(&view_key(name), false)
+    }
+    fn ensure_view_name_free(&self, name: &Symbol) -> Result<()> {
+        if name.is_temp_store_name() {
+            bail!("Cannot create view with the name of a temp relation")
+        }
+        ensure!(
+            !self.relation_exists(name)? && !self.store_tx.exists(&view_key(name), true)?,
+            ViewNameConflictError(name.to_string(), name.span)
+        );
+        Ok(())
+    }
+    pub(crate) fn create_view(&mut self, name: &Symbol, definition: &str) -> Result<()> {
+        self.ensure_view_name_free(name)?;
+        let handle = ViewHandle {
+            definition: definition.to_string(),
+            materialized: false,
+            bases: vec![],
+        };
+        self.store_tx.put(&view_key(name), &handle.encode())
+    }
+    /// The stored view, or `None` if no view has the name
+    pub(crate) fn get_view(&self, name: &str) -> Result<Option<ViewHandle>> {
+        match self.store_tx.get(&view_key(name), false)? {
+            None => Ok(None),
+            Some(found) => Ok(Some(rmp_serde::from_slice(&found).into_diagnostic()?)),
+        }
+    }
+    /// Removes the view. For a materialized view, the key range of its stored relation
+    /// to be cleared after the transaction commits is returned.
+    pub(crate) fn remove_view(&mut self, name: &Symbol) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let key = view_key(name);
+        let handle = match self.get_view(name)? {
+            None => bail!(ViewNotFoundError(name.to_string(), name.span)),
+            Some(handle) => handle,
+        };
+        self.store_tx.del(&key)?;
+        if !handle.materialized {
+            return Ok(None);
+        }
+        for base in &handle.bases {
+            let mut base = self.get_relation(base, true)?;
+            base.materialized_views.retain(|v| *v != name.name);
+            self.save_relation_handle(&base)?;
+        }
+        self.set_access_level(name.clone(), AccessLevel::Normal)?;
+        Ok(Some(self.destroy_relation(name)?))
+    }
+    /// All views with their names
+    pub(crate) fn list_views(&self) -> Result<Vec<(String, ViewHandle)>> {
+        let lower = view_key("");
+        let upper = view_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            let name = match decode_tuple_from_key(&k_slice).pop() {
+                Some(DataValue::Str(s)) => s.to_string(),
+                _ => continue,
+            };
+            ret.push((name, rmp_serde::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(ret)
+    }
+}
+
+/// The columns of the view defined by the program
+fn view_columns(program: &InputProgram) -> Result<Vec<Symbol>> {
+    Ok(
+        match program.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
+            Some(InputInlineRulesOrFixed::Rules { rules }) => rules.last().unwrap().head.clone(),
+            Some(InputInlineRulesOrFixed::Fixed { fixed }) if !fixed.head.is_empty() => {
+                fixed.head.clone()
+            }
+            _ => (0..program.get_entry_arity()?)
+                .map(|i| Symbol::new(format!("_{i}"), SourceSpan(0, 0)))
+                .collect_vec(),
+        },
+    )
+}
+
+/// Name of the inlined rule standing in for a rule of a view
+fn inlined_rule_name(view: &str, rule: &Symbol) -> Symbol {
+    if rule.is_prog_entry() {
+        Symbol::new(format!("*{view}"), rule.span)
+    } else {
+        Symbol::new(format!("*{view}.{}", rule.name), rule.span)
+    }
+}
+
+/// A place where rules or stored relations are referred to
+enum RuleRef<'x> {
+    Atom(&'x mut InputAtom),
+    FixedArg(&'x mut FixedRuleArg),
+}
+
+/// Call `f` on every atom of the rule body that is not composed of other atoms.
+fn for_each_leaf_atom(
+    atom: &mut InputAtom,
+    f: &mut impl FnMut(RuleRef<'_>) -> Result<()>,
+) -> Result<()> {
+    match atom {
+        InputAtom::Negation { inner, .. } | InputAtom::Optional { inner, .. } => {
+            for_each_leaf_atom(inner, f)
+        }
+        InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+            for a in inner {
+                for_each_leaf_atom(a, f)?;
+            }
+            Ok(())
+        }
+        a => f(RuleRef::Atom(a)),
+    }
+}
+
+fn for_each_subquery_atom(
+    subquery: &mut InputSubquery,
+    f: &mut impl FnMut(RuleRef<'_>) -> Result<()>,
+) -> Result<()> {
+    for atom in subquery.body.iter_mut() {
+        for_each_leaf_atom(atom, f)?;
+    }
+    for nested in subquery.subqueries.iter_mut() {
+        for_each_subquery_atom(nested, f)?;
+    }
+    Ok(())
+}
+
+fn for_each_rule_ref(
+    rules: &mut InputInlineRulesOrFixed,
+    f: &mut impl FnMut(RuleRef<'_>) -> Result<()>,
+) -> Result<()> {
+    match rules {
+        InputInlineRulesOrFixed::Rules { rules } => {
+            for rule in rules {
+                for atom in rule.body.iter_mut() {
+                    for_each_leaf_atom(atom, f)?;
+                }
+                for subquery in rule.subqueries.iter_mut() {
+                    for_each_subquery_atom(subquery, f)?;
+                }
+            }
+        }
+        InputInlineRulesOrFixed::Fixed { fixed } => {
+            for arg in fixed.rule_args.iter_mut() {
+                f(RuleRef::FixedArg(arg))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct ViewInliner<'t, 'a> {
+    tx: &'t SessionTx<'a>,
+    /// Without parameters: views are defined by constant scripts
+    ctx: ParseContext<'t>,
+    fixed_rules: &'t BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    /// The columns of the views found so far
+    views: BTreeMap<SmartString<LazyCompact>, Vec<Symbol>>,
+    /// Inlined rules whose references to views are yet to be resolved
+    pending: Vec<(Symbol, InputInlineRulesOrFixed)>,
+    ignored_count: usize,
+}
+
+impl ViewInliner<'_, '_> {
+    /// The columns of the view, or `None` if the name does not refer to a view
+    fn resolve(&mut self, name: &Symbol) -> Result<Option<Vec<Symbol>>> {
+        if let Some(columns) = self.views.get(&name.name) {
+            return Ok(Some(columns.clone()));
+        }
+        if name.is_temp_store_name() || self.tx.relation_exists(name)? {
+            return Ok(None);
+        }
+        let definition = match self.tx.get_view(name)? {
+            None => return Ok(None),
+            Some(handle) => handle.definition,
+        };
+        let program =
+            match parse_script(&definition, &self.ctx, self.fixed_rules, current_validity())? {
+                CozoScript::Single(p) => p,
+                _ => bail!("The definition of view '{}' is not a query", name),
+            };
+        let columns = view_columns(&program)?;
+        for (rule_name, mut rules) in program.prog {
+            for_each_rule_ref(&mut rules, &mut |rule_ref| {
+                match rule_ref {
+                    RuleRef::Atom(InputAtom::Rule { inner }) => {
+                        inner.name = inlined_rule_name(name, &inner.name)
+                    }
+                    RuleRef::FixedArg(FixedRuleArg::InMem { name: arg_name, .. }) => {
+                        *arg_name = inlined_rule_name(name, arg_name)
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })?;
+            self.pending
+                .push((inlined_rule_name(name, &rule_name), rules));
+        }
+        self.views.insert(name.name.clone(), columns.clone());
+        Ok(Some(columns))
+    }
+    fn next_ignored(&mut self, span: SourceSpan) -> Symbol {
+        self.ignored_count += 1;
+        Symbol::new(format!("~view{}", self.ignored_count), span)
+    }
+    /// Positional arguments for a view from arguments given by column names
+    fn positional_args<T>(
+        &mut self,
+        view: &Symbol,
+        columns: &[Symbol],
+        mut args: BTreeMap<SmartString<LazyCompact>, T>,
+        span: SourceSpan,
+        mut ignored: impl FnMut(Symbol) -> T,
+    ) -> Result<Vec<T>> {
+        for k in args.keys() {
+            ensure!(
+                columns.iter().any(|col| col.name == *k),
+                NamedFieldNotFound(view.to_string(), k.to_string(), span)
+            );
+        }
+        Ok(columns
+            .iter()
+            .map(|col| match args.remove(&col.name) {
+                Some(arg) => arg,
+                None => ignored(self.next_ignored(span)),
+            })
+            .collect_vec())
+    }
+    fn inline_atom(&mut self, atom: &mut InputAtom) -> Result<()> {
+        let (name, valid_at, span) = match atom {
+            InputAtom::Relation {
+                inner:
+                    InputRelationApplyAtom {
+                        name,
+                        valid_at,
+                        span,
+                        ..
+                    },
+            }
+            | InputAtom::NamedFieldRelation {
+                inner:
+                    InputNamedFieldRelationApplyAtom {
+                        name,
+                        valid_at,
+                        span,
+                        ..
+                    },
+            } => (name.clone(), *valid_at, *span),
+            _ => return Ok(()),
+        };
+        let columns = match self.resolve(&name)? {
+            None => return Ok(()),
+            Some(columns) => columns,
+        };
+        ensure!(
+            valid_at.is_none(),
+            ViewTimeTravelError(name.to_string(), span)
+        );
+        let args = match atom {
+            InputAtom::Relation { inner } => std::mem::take(&mut inner.args),
+            InputAtom::NamedFieldRelation { inner } => {
+                let args = std::mem::take(&mut inner.args);
+                self.positional_args(&name, &columns, args, span, |var| Expr::Binding {
+                    var,
+                    tuple_pos: None,
+                })?
+            }
+            _ => unreachable!(),
+        };
+        ensure!(
+            args.len() == columns.len(),
+            ViewArityMismatch(name.to_string(), columns.len(), args.len(), span)
+        );
+        *atom = InputAtom::Rule {
+            inner: InputRuleApplyAtom {
+                name: inlined_rule_name(&name, &Symbol::new(PROG_ENTRY, span)),
+                args,
+                span,
+            },
+        };
+        Ok(())
+    }
+    fn inline_fixed_arg(&mut self, arg: &mut FixedRuleArg) -> Result<()> {
+        let (name, valid_at, span) = match arg {
+            FixedRuleArg::InMem { .. } => return Ok(()),
+            FixedRuleArg::Stored {
+                name,
+                valid_at,
+                span,
+                ..
+            }
+            | FixedRuleArg::NamedStored {
+                name,
+                valid_at,
+                span,
+                ..
+            } => (name.clone(), *valid_at, *span),
+        };
+        let columns = match self.resolve(&name)? {
+            None => return Ok(()),
+            Some(columns) => columns,
+        };
+        ensure!(
+            valid_at.is_none(),
+            ViewTimeTravelError(name.to_string(), span)
+        );
+        let bindings = match arg {
+            FixedRuleArg::Stored { bindings, .. } => std::mem::take(bindings),
+            FixedRuleArg::NamedStored { bindings, .. } => {
+                let bindings = std::mem::take(bindings);
+                self.positional_args(&name, &columns, bindings, span, |var| var)?
+            }
+            FixedRuleArg::InMem { .. } => unreachable!(),
+        };
+        *arg = FixedRuleArg::InMem {
+            name: inlined_rule_name(&name, &Symbol::new(PROG_ENTRY, span)),
+            bindings,
+            span,
+        };
+        Ok(())
+    }
+    fn inline_rules(&mut self, rules: &mut InputInlineRulesOrFixed) -> Result<()> {
+        for_each_rule_ref(rules, &mut |rule_ref| match rule_ref {
+            RuleRef::Atom(atom) => self.inline_atom(atom),
+            RuleRef::FixedArg(arg) => self.inline_fixed_arg(arg),
+        })
+    }
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("View '{0}' cannot be queried at a validity")]
+#[diagnostic(code(query::view_time_travel))]
+struct ViewTimeTravelError(String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("View '{0}' has {1} columns, but {2} are given")]
+#[diagnostic(code(query::view_arity_mismatch))]
+struct ViewArityMismatch(String, usize, usize, #[label] SourceSpan);
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Replace the references to views in the program by applications of their rules,
+    /// which are added to the program. Returns the names of the views referred to.
+    pub(crate) fn inline_views(
+        &'s self,
+        tx: &SessionTx<'_>,
+        program: &mut InputProgram,
+    ) -> Result<Vec<SmartString<LazyCompact>>> {
+        let custom_fns = self.custom_functions.read().unwrap();
+        let custom_aggrs = self.custom_aggregations.read().unwrap();
+        let fixed_rules = self.fixed_rules.read().unwrap();
+        let mut inliner = ViewInliner {
+            tx,
+            ctx: ParseContext {
+                params: &Default::default(),
+                custom_fns: &custom_fns,
+                custom_aggrs: &custom_aggrs,
+                in_rule_body: false,
+            },
+            fixed_rules: &fixed_rules,
+            views: Default::default(),
+            pending: vec![],
+            ignored_count: 0,
+        };
+        for rules in program.prog.values_mut() {
+            inliner.inline_rules(rules)?;
+        }
+        // views may refer to other views
+        while let Some((name, mut rules)) = inliner.pending.pop() {
+            inliner.inline_rules(&mut rules)?;
+            program.prog.insert(name, rules);
+        }
+        Ok(inliner.views.into_keys().collect())
+    }
+}
+
+/// In the rules maintaining a materialized view, the rule standing in for the changed relation
+const BASE_RULE: &str = "_base";
+/// In the rules maintaining a materialized view, the rule standing in for the entry of the view
+const VIEW_RULE: &str = "_view";
+/// The rows of the changed relation that are added or removed
+const DELTA_RULE: &str = "_delta";
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Materialized view {0} cannot be computed from the temp relation {1}")]
+#[diagnostic(code(eval::materialized_view_of_temp))]
+struct MaterializedViewOfTempRelation(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Materialized view {0} has more than one column named {1}")]
+#[diagnostic(code(eval::materialized_view_duplicate_column))]
+#[diagnostic(help("The columns are named after the head of the entry rule"))]
+struct MaterializedViewDuplicateColumn(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Cannot find materialized view {0} computed from stored relation {1}")]
+#[diagnostic(code(eval::materialized_view_not_found))]
+struct MaterializedViewNotFound(String, String);
+
+fn delta_rule_name(name: &Symbol) -> Symbol {
+    if name.name == BASE_RULE {
+        Symbol::new(DELTA_RULE, name.span)
+    } else {
+        Symbol::new(format!("{DELTA_RULE}.{}", name.name), name.span)
+    }
+}
+
+fn rule_atom(name: &str, vars: &[Symbol]) -> InputAtom {
+    InputAtom::Rule {
+        inner: InputRuleApplyAtom {
+            name: Symbol::new(name, SourceSpan(0, 0)),
+            args: vars
+                .iter()
+                .map(|var| Expr::Binding {
+                    var: var.clone(),
+                    tuple_pos: None,
+                })
+                .collect_vec(),
+            span: SourceSpan(0, 0),
+        },
+    }
+}
+
+fn fresh_vars(n: usize) -> Vec<Symbol> {
+    (0..n)
+        .map(|i| Symbol::new(format!("x{i}"), SourceSpan(0, 0)))
+        .collect_vec()
+}
+
+fn as_const_data(tuples: &[Tuple]) -> Vec<DataValue> {
+    tuples
+        .iter()
+        .map(|t| DataValue::List(t.clone()))
+        .collect_vec()
+}
+
+/// The names of the rules the atom refers to, with whether the reference is negated
+/// (or otherwise not monotone)
+fn rule_refs<'x>(atom: &'x InputAtom, negated: bool, refs: &mut Vec<(&'x Symbol, bool)>) {
+    match atom {
+        InputAtom::Rule { inner } => refs.push((&inner.name, negated)),
+        InputAtom::Negation { inner, .. } => rule_refs(inner, !negated, refs),
+        // a new match removes the row padded with nulls, so this is not monotone either
+        InputAtom::Optional { inner, .. } => rule_refs(inner, true, refs),
+        InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+            for a in inner {
+                rule_refs(a, negated, refs)
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The names of the rules the rule refers to, those in subqueries counting as not monotone:
+/// a new row can change the value of a subquery either way
+fn refs_of_rule<'x>(rule: &'x InputInlineRule, refs: &mut Vec<(&'x Symbol, bool)>) {
+    fn subquery_refs<'x>(subquery: &'x InputSubquery, refs: &mut Vec<(&'x Symbol, bool)>) {
+        for atom in &subquery.body {
+            rule_refs(atom, true, refs);
+        }
+        for nested in &subquery.subqueries {
+            subquery_refs(nested, refs);
+        }
+    }
+
+    for atom in &rule.body {
+        rule_refs(atom, false, refs);
+    }
+    for subquery in &rule.subqueries {
+        subquery_refs(subquery, refs);
+    }
+}
+
+fn refs_of_rules(rules: &InputInlineRulesOrFixed) -> Vec<(&Symbol, bool)> {
+    let mut refs = vec![];
+    match rules {
+        InputInlineRulesOrFixed::Rules { rules } => {
+            for rule in rules {
+                refs_of_rule(rule, &mut refs);
+            }
+        }
+        InputInlineRulesOrFixed::Fixed { fixed } => {
+            for arg in &fixed.rule_args {
+                if let FixedRuleArg::InMem { name, .. } = arg {
+                    refs.push((name, false))
+                }
+            }
+        }
+    }
+    refs
+}
+
+/// Replace the `k`-th reference to a rule in `dependent` in the body by a reference to
+/// the changes of that rule. Returns `false` if there are not that many references.
+fn differentiate_at(
+    rule: &mut InputInlineRule,
+    dependent: &BTreeSet<SmartString<LazyCompact>>,
+    k: usize,
+) -> bool {
+    let mut seen = 0;
+    for atom in rule.body.iter_mut() {
+        let _ = for_each_leaf_atom(atom, &mut |rule_ref| {
+            if let RuleRef::Atom(InputAtom::Rule { inner }) = rule_ref {
+                if dependent.contains(&inner.name.name) {
+                    if seen == k {
+                        inner.name = delta_rule_name(&inner.name);
+                    }
+                    seen += 1;
+                }
+            }
+            Ok(())
+        });
+    }
+    seen > k
+}
+
+/// Rules computing the changes to a view caused by the changes to the stored relation `base`,
+/// which are given by the rule `_delta`. Every rule depending on `base` is differentiated
+/// with respect to each of its dependencies in turn, evaluating the other dependencies
+/// against the rule `_base` standing in for `base`. Depending on how the caller defines
+/// `_base`, the result is a superset of the rows derived (on insertion) or no longer
+/// derived (on deletion) as a consequence of the changes.
+///
+/// Returns `None` if the view is not monotone in `base`, in which case it has to be
+/// recomputed from scratch.
+fn delta_program(program: &InputProgram, base: &RelationHandle) -> Option<InputProgram> {
+    let arity = program.get_entry_arity().ok()?;
+    let mut program = program.clone();
+    let entry = program
+        .prog
+        .remove(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0)))?;
+    program
+        .prog
+        .insert(Symbol::new(VIEW_RULE, SourceSpan(0, 0)), entry);
+
+    let base_columns = base
+        .metadata
+        .keys
+        .iter()
+        .chain(base.metadata.non_keys.iter())
+        .map(|col| col.name.clone())
+        .collect_vec();
+    let mut monotone = true;
+    let mut ignored_count = 0;
+    for rules in program.prog.values_mut() {
+        for_each_rule_ref(rules, &mut |rule_ref| {
+            match rule_ref {
+                RuleRef::Atom(atom) => {
+                    let (name, valid_at, span) = match atom {
+                        InputAtom::Relation { inner } => (&inner.name, inner.valid_at, inner.span),
+                        InputAtom::NamedFieldRelation { inner } => {
+                            (&inner.name, inner.valid_at, inner.span)
+                        }
+                        _ => return Ok(()),
+                    };
+                    if name.name != base.name {
+                        return Ok(());
+                    }
+                    if valid_at.is_some() {
+                        monotone = false;
+                        return Ok(());
+                    }
+                    let args = match atom {
+                        InputAtom::Relation { inner } => std::mem::take(&mut inner.args),
+                        InputAtom::NamedFieldRelation { inner } => base_columns
+                            .iter()
+                            .map(|col| match inner.args.remove(col) {
+                                Some(arg) => arg,
+                                None => {
+                                    ignored_count += 1;
+                                    Expr::Binding {
+                                        var: Symbol::new(format!("~delta{ignored_count}"), span),
+                                        tuple_pos: None,
+                                    }
+                                }
+                            })
+                            .collect_vec(),
+                        _ => unreachable!(),
+                    };
+                    *atom = InputAtom::Rule {
+                        inner: InputRuleApplyAtom {
+                            name: Symbol::new(BASE_RULE, span),
+                            args,
+                            span,
+                        },
+                    };
+                }
+                RuleRef::FixedArg(
+                    FixedRuleArg::Stored { name, .. } | FixedRuleArg::NamedStored { name, .. },
+                ) if name.name == base.name => monotone = false,
+                RuleRef::FixedArg(_) => {}
+            }
+            Ok(())
+        })
+        .ok()?;
+    }
+    if !monotone {
+        return None;
+    }
+
+    // the rules depending on the base, directly or indirectly
+    let mut dependent: BTreeSet<SmartString<LazyCompact>> = BTreeSet::new();
+    dependent.insert(SmartString::from(BASE_RULE));
+    loop {
+        let found = program
+            .prog
+            .iter()
+            .filter(|(name, rules)| {
+                !dependent.contains(&name.name)
+                    && refs_of_rules(rules)
+                        .iter()
+                        .any(|(r, _)| dependent.contains(&r.name))
+            })
+            .map(|(name, _)| name.name.clone())
+            .collect_vec();
+        if found.is_empty() {
+            break;
+        }
+        dependent.extend(found);
+    }
+    if !dependent.contains(VIEW_RULE) {
+        return None;
+    }
+
+    let mut delta_rules: BTreeMap<Symbol, Vec<InputInlineRule>> = BTreeMap::new();
+    for (name, rules) in &program.prog {
+        if !dependent.contains(&name.name) {
+            continue;
+        }
+        let rules = match rules {
+            InputInlineRulesOrFixed::Rules { rules } => rules,
+            InputInlineRulesOrFixed::Fixed { .. } => return None,
+        };
+        for rule in rules {
+            if rule.aggr.iter().any(|aggr| aggr.is_some()) {
+                return None;
+            }
+            let mut refs = vec![];
+            refs_of_rule(rule, &mut refs);
+            if refs
+                .iter()
+                .any(|(r, negated)| *negated && dependent.contains(&r.name))
+            {
+                return None;
+            }
+            for k in 0.. {
+                let mut differentiated = rule.clone();
+                if !differentiate_at(&mut differentiated, &dependent, k) {
+                    break;
+                }
+                delta_rules
+                    .entry(delta_rule_name(name))
+                    .or_default()
+                    .push(differentiated);
+            }
+        }
+    }
+    for (name, rules) in delta_rules {
+        program
+            .prog
+            .insert(name, InputInlineRulesOrFixed::Rules { rules });
+    }
+    let head = fresh_vars(arity);
+    let entry = InputInlineRule {
+        body: vec![rule_atom(
+            &delta_rule_name(&Symbol::new(VIEW_RULE, SourceSpan(0, 0))),
+            &head,
+        )],
+        aggr: vec![None; arity],
+        head,
+        subqueries: vec![],
+        span: SourceSpan(0, 0),
+    };
+    program.prog.insert(
+        Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        InputInlineRulesOrFixed::Rules { rules: vec![entry] },
+    );
+    Some(program)
+}
+
+/// Define the rule `_base` standing in for `base` in the program, either as the current rows of
+/// `base`, or, given the changes, as its rows before the changes were made.
+fn define_base(
+    program: &mut InputProgram,
+    base: &RelationHandle,
+    before_changes: Option<(&[Tuple], &[Tuple])>,
+) {
+    let vars = fresh_vars(base.arity());
+    let mut current = InputInlineRule {
+        head: vars.clone(),
+        aggr: vec![None; vars.len()],
+        body: vec![InputAtom::Relation {
+            inner: InputRelationApplyAtom {
+                name: Symbol::new(base.name.clone(), SourceSpan(0, 0)),
+                args: vars
+                    .iter()
+                    .map(|var| Expr::Binding {
+                        var: var.clone(),
+                        tuple_pos: None,
+                    })
+                    .collect_vec(),
+                valid_at: None,
+                span: SourceSpan(0, 0),
+            },
+        }],
+        subqueries: vec![],
+        span: SourceSpan(0, 0),
+    };
+    let mut rules = vec![];
+    if let Some((inserted, deleted)) = before_changes {
+        current.body.push(InputAtom::Negation {
+            inner: Box::new(rule_atom("_inserted", &vars)),
+            span: SourceSpan(0, 0),
+        });
+        rules.push(InputInlineRule {
+            head: vars.clone(),
+            aggr: vec![None; vars.len()],
+            body: vec![rule_atom("_deleted", &vars)],
+            subqueries: vec![],
+            span: SourceSpan(0, 0),
+        });
+        make_const_rule(program, "_inserted", vars.clone(), as_const_data(inserted));
+        make_const_rule(program, "_deleted", vars.clone(), as_const_data(deleted));
+    }
+    rules.push(current);
+    program.prog.insert(
+        Symbol::new(BASE_RULE, SourceSpan(0, 0)),
+        InputInlineRulesOrFixed::Rules { rules },
+    );
+}
+
+impl<'a> SessionTx<'a> {
+    /// Parse the definition of the view, with references to other views inlined
+    fn view_program<'s, S: Storage<'s>>(
+        &self,
+        db: &'s Db<S>,
+        definition: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<InputProgram> {
+        let mut program = parse_script(
+            definition,
+            &ParseContext {
+                params: &Default::default(),
+                custom_fns: &db.custom_functions.read().unwrap(),
+                custom_aggrs: &db.custom_aggregations.read().unwrap(),
+                in_rule_body: false,
+            },
+            &db.fixed_rules.read().unwrap(),
+            cur_vld,
+        )?
+        .get_single_program()?;
+        db.inline_views(self, &mut program)?;
+        Ok(program)
+    }
+    fn run_view_program<'s, S: Storage<'s>>(
+        &mut self,
+        db: &'s Db<S>,
+        program: InputProgram,
+        cur_vld: ValidityTs,
+    ) -> Result<Vec<Tuple>> {
+        let (res, _) = db.run_query(
+            self,
+            program,
+            cur_vld,
+            &Default::default(),
+            &mut Default::default(),
+            false,
+        )?;
+        Ok(res.rows)
+    }
+    /// The program of the materialized view, with its columns and the relations it is computed
+    /// from
+    fn materialized_view_program<'s, S: Storage<'s>>(
+        &self,
+        db: &'s Db<S>,
+        name: &Symbol,
+        definition: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<(
+        InputProgram,
+        Vec<Symbol>,
+        BTreeSet<SmartString<LazyCompact>>,
+    )> {
+        let mut program = self.view_program(db, definition, cur_vld)?;
+        let columns = view_columns(&program)?;
+        let mut seen = BTreeSet::new();
+        for col in &columns {
+            ensure!(
+                seen.insert(&col.name),
+                MaterializedViewDuplicateColumn(name.to_string(), col.to_string(), name.span)
+            );
+        }
+
+        let mut bases = BTreeSet::new();
+        for rules in program.prog.values_mut() {
+            for_each_rule_ref(rules, &mut |rule_ref| {
+                let base = match rule_ref {
+                    RuleRef::Atom(InputAtom::Relation { inner }) => &inner.name,
+                    RuleRef::Atom(InputAtom::NamedFieldRelation { inner }) => &inner.name,
+                    RuleRef::FixedArg(
+                        FixedRuleArg::Stored { name, .. } | FixedRuleArg::NamedStored { name, .. },
+                    ) => name,
+                    _ => return Ok(()),
+                };
+                ensure!(
+                    !base.is_temp_store_name(),
+                    MaterializedViewOfTempRelation(name.to_string(), base.to_string(), base.span)
+                );
+                bases.insert(base.name.clone());
+                Ok(())
+            })?;
+        }
+        Ok((program, columns, bases))
+    }
+    pub(crate) fn create_materialized_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &'s Db<S>,
+        name: &Symbol,
+        definition: &str,
+    ) -> Result<()> {
+        self.ensure_view_name_free(name)?;
+        let cur_vld = current_validity();
+        let (program, columns, bases) =
+            self.materialized_view_program(db, name, definition, cur_vld)?;
+        let rows = self.run_view_program(db, program, cur_vld)?;
+        let metadata = StoredRelationMetadata {
+            keys: columns
+                .iter()
+                .map(|col| ColumnDef {
+                    name: col.name.clone(),
+                    typing: NullableColType {
+                        coltype: ColType::Any,
+                        nullable: true,
+                    },
+                    default_gen: None,
+                })
+                .collect_vec(),
+            non_keys: vec![],
+        };
+        let handle = self.create_relation(InputRelationHandle {
+            name: name.clone(),
+            metadata,
+            key_bindings: columns,
+            dep_bindings: vec![],
+            span: name.span,
+        })?;
+        for row in rows {
+            let key = handle.encode_key_for_store(&row, name.span)?;
+            let val = handle.encode_val_for_store(&row, name.span)?;
+            self.store_tx.put(&key, &val)?;
+        }
+        // the view is written to only by its maintenance
+        self.set_access_level(name.clone(), AccessLevel::ReadOnly)?;
+
+        for base in &bases {
+            let mut base = self.get_relation(base, true)?;
+            base.materialized_views.push(name.name.clone());
+            self.save_relation_handle(&base)?;
+        }
+        let handle = ViewHandle {
+            definition: definition.to_string(),
+            materialized: true,
+            bases: bases.into_iter().collect_vec(),
+        };
+        self.store_tx.put(&view_key(name), &handle.encode())
+    }
+    /// Check the view as `::view create` would create it. Its program is compiled but not run,
+    /// and the view is recorded as a view that is not materialized, for later queries to use.
+    pub(crate) fn check_create_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &'s Db<S>,
+        name: &Symbol,
+        definition: &str,
+        materialized: bool,
+    ) -> Result<()> {
+        self.ensure_view_name_free(name)?;
+        let cur_vld = current_validity();
+        let program = if materialized {
+            self.materialized_view_program(db, name, definition, cur_vld)?
+                .0
+        } else {
+            self.view_program(db, definition, cur_vld)?
+        };
+        db.compile_query(self, program)?;
+        self.create_view(name, definition)
+    }
+    /// Update the materialized views computed from `base` after the rows `inserted` are added
+    /// to and the rows `deleted` are removed from it.
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn maintain_materialized_views<'s, S: Storage<'s>>(
+        &mut self,
+        db: &'s Db<S>,
+        base: &RelationHandle,
+        inserted: Vec<Tuple>,
+        deleted: Vec<Tuple>,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+    ) -> Result<()> {
+        if inserted.is_empty() && deleted.is_empty() {
+            return Ok(());
+        }
+        let mut pending = vec![];
+        for name in &base.materialized_views {
+            match self.get_view(name)? {
+                Some(view) if view.materialized => pending.push((name.clone(), view)),
+                _ => bail!(MaterializedViewNotFound(
+                    name.to_string(),
+                    base.name.to_string()
+                )),
+            }
+        }
+        while !pending.is_empty() {
+            // A view computed from other pending views is maintained first, while these still
+            // hold their old rows. Changes to the rows it derives from those are then
+            // propagated when the other views are maintained.
+            let idx = pending
+                .iter()
+                .position(|(name, _)| !pending.iter().any(|(_, view)| view.bases.contains(name)))
+                .unwrap();
+            let (name, view) = pending.remove(idx);
+            let view_relation = self.get_relation(&name, false)?;
+            let program = self.view_program(db, &view.definition, cur_vld)?;
+            let (to_insert, to_delete) = match delta_program(&program, base) {
+                Some(delta) => self.view_changes_by_delta(
+                    db, program, delta, base, &inserted, &deleted, cur_vld,
+                )?,
+                None => {
+                    let new_rows: BTreeSet<Tuple> = self
+                        .run_view_program(db, program, cur_vld)?
+                        .into_iter()
+                        .collect();
+                    let old_rows: BTreeSet<Tuple> = view_relation.scan_all(self).try_collect()?;
+                    (
+                        new_rows.difference(&old_rows).cloned().collect_vec(),
+                        old_rows.difference(&new_rows).cloned().collect_vec(),
+                    )
+                }
+            };
+            self.apply_view_changes(
+                db,
+                &view_relation,
+                to_insert,
+                to_delete,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+            )?;
+        }
+        Ok(())
+    }
+    /// The rows to be added to and removed from the view with the given program (and its delta
+    /// program) in consequence of the changes to `base`, following the delete-and-rederive
+    /// algorithm: the rows that may have lost their derivations are collected, and those
+    /// that can no longer be derived are removed.
+    #[allow(clippy::mutable_key_type)]
+    #[allow(clippy::too_many_arguments)]
+    fn view_changes_by_delta<'s, S: Storage<'s>>(
+        &mut self,
+        db: &'s Db<S>,
+        mut program: InputProgram,
+        delta: InputProgram,
+        base: &RelationHandle,
+        inserted: &[Tuple],
+        deleted: &[Tuple],
+        cur_vld: ValidityTs,
+    ) -> Result<(Vec<Tuple>, Vec<Tuple>)> {
+        let base_vars = fresh_vars(base.arity());
+        let mut to_delete = vec![];
+        if !deleted.is_empty() {
+            let mut over_delete = delta.clone();
+            define_base(&mut over_delete, base, Some((inserted, deleted)));
+            make_const_rule(
+                &mut over_delete,
+                DELTA_RULE,
+                base_vars.clone(),
+                as_const_data(deleted),
+            );
+            let candidates = self.run_view_program(db, over_delete, cur_vld)?;
+            if !candidates.is_empty() {
+                let vars = fresh_vars(candidates[0].len());
+                let entry = program
+                    .prog
+                    .remove(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0)))
+                    .unwrap();
+                program
+                    .prog
+                    .insert(Symbol::new(VIEW_RULE, SourceSpan(0, 0)), entry);
+                make_const_rule(
+                    &mut program,
+                    "_candidates",
+                    vars.clone(),
+                    as_const_data(&candidates),
+                );
+                program.prog.insert(
+                    Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+                    InputInlineRulesOrFixed::Rules {
+                        rules: vec![InputInlineRule {
+                            head: vars.clone(),
+                            aggr: vec![None; vars.len()],
+                            body: vec![
+                                rule_atom("_candidates", &vars),
+                                rule_atom(VIEW_RULE, &vars),
+                            ],
+                            subqueries: vec![],
+                            span: SourceSpan(0, 0),
+                        }],
+                    },
+                );
+                let rederived: BTreeSet<Tuple> = self
+                    .run_view_program(db, program, cur_vld)?
+                    .into_iter()
+                    .collect();
+                to_delete = candidates
+                    .into_iter()
+                    .filter(|row| !rederived.contains(row))
+                    .collect_vec();
+            }
+        }
+        let mut to_insert = vec![];
+        if !inserted.is_empty() {
+            let mut grow = delta;
+            define_base(&mut grow, base, None);
+            make_const_rule(&mut grow, DELTA_RULE, base_vars, as_const_data(inserted));
+            to_insert = self.run_view_program(db, grow, cur_vld)?;
+        }
+        Ok((to_insert, to_delete))
+    }
+    /// Write the changes to the stored relation of a materialized view, and propagate them
+    #[allow(clippy::too_many_arguments)]
+    fn apply_view_changes<'s, S: Storage<'s>>(
+        &mut self,
+        db: &'s Db<S>,
+        view: &RelationHandle,
+        to_insert: Vec<Tuple>,
+        to_delete: Vec<Tuple>,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+    ) -> Result<()> {
+        let mut deleted = vec![];
+        for row in to_delete {
+            let key = view.encode_key_for_store(&row, Default::default())?;
+            if self.store_tx.exists(&key, false)? {
+                self.store_tx.del(&key)?;
+                for (idx_rel, extractor) in view.indices.values() {
+                    let idx_tup = extractor.iter().map(|i| row[*i].clone()).collect_vec();
+                    let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                    self.store_tx.del(&encoded)?;
+                }
+                deleted.push(row);
+            }
+        }
+        let mut inserted = vec![];
+        for row in to_insert {
+            let key = view.encode_key_for_store(&row, Default::default())?;
+            if !self.store_tx.exists(&key, false)? {
+                let val = view.encode_val_for_store(&row, Default::default())?;
+                self.store_tx.put(&key, &val)?;
+                for (idx_rel, extractor) in view.indices.values() {
+                    let idx_tup = extractor.iter().map(|i| row[*i].clone()).collect_vec();
+                    let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                    self.store_tx
+                        .put(&encoded, &idx_rel.encode_index_val(&idx_tup)?)?;
+                }
+                inserted.push(row);
+            }
+        }
+
+        if callback_targets.contains(&view.name) {
+            let headers = view
+                .metadata
+                .keys
+                .iter()
+                .map(|col| col.name.to_string())
+                .collect_vec();
+            let target_collector = callback_collector.entry(view.name.clone()).or_default();
+            if !deleted.is_empty() {
+                target_collector.push((
+                    CallbackOp::Rm,
+                    NamedRows::new(headers.clone(), deleted.clone()),
+                    NamedRows::new(headers.clone(), deleted.clone()),
+                ));
+            }
+            if !inserted.is_empty() {
+                target_collector.push((
+                    CallbackOp::Put,
+                    NamedRows::new(headers.clone(), inserted.clone()),
+                    NamedRows::new(headers, vec![]),
+                ));
+            }
+        }
+
+        if view.materialized_views.is_empty() {
+            return Ok(());
+        }
+        self.maintain_materialized_views(
+            db,
+            view,
+            inserted,
+            deleted,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+        )
+    }
+}
@@ -35,6 +35,31 @@ pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     Ok(ret)
 }
 
+/// The number of samples taken by [`MemStorage`] when asked to split a range.
+const MEM_SAMPLES: usize = 64;
+
+/// `n - 1` keys evenly spaced between `first` and `last`, which must be ascending,
+/// interpolating the first 16 bytes in which they differ.
+fn key_space_points(first: &[u8], last: &[u8], n: usize) -> Vec<Vec<u8>> {
+    let common = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    let window = |key: &[u8]| {
+        let mut bytes = [0u8; 16];
+        for (b, k) in bytes.iter_mut().zip(key.iter().skip(common)) {
+            *b = *k;
+        }
+        u128::from_be_bytes(bytes)
+    };
+    let (lo, hi) = (window(first), window(last));
+    let step = (hi - lo) / n as u128;
+    (1..n)
+        .map(|i| {
+            let mut key = first[..common].to_vec();
+            key.extend_from_slice(&(lo + step * i as u128).to_be_bytes());
+            key
+        })
+        .collect()
+}
+
 /// The non-persistent storage
 #[derive(Default, Clone)]
 pub struct MemStorage {
@@ -51,7 +76,7 @@ impl<'s> Storage<'s> for MemStorage {
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         Ok(if write {
             let wtr = self.store.write().unwrap();
-            MemTx::Writer(wtr, Default::default())
+            MemTx::Writer(wtr, Default::default(), Default::default())
         } else {
             let rdr = self.store.read().unwrap();
             MemTx::Reader(rdr)
@@ -85,6 +110,40 @@ impl<'s> Storage<'s> for MemStorage {
         Ok(())
     }
 
+    fn sample_keys(
+        &'s self,
+        lower: &[u8],
+        upper: &[u8],
+        rows_per_sample: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        // a running write transaction holds the lock: sampling is only a hint, so give up
+        let rdr = match self.store.try_read() {
+            Ok(rdr) => rdr,
+            Err(_) => return Ok(vec![]),
+        };
+        // the number of keys in a range is only known by walking it: only enough keys to
+        // make splitting worthwhile are counted, and the samples are found by dividing the
+        // key space evenly instead, which gives even partitions if the keys are evenly spread
+        let range = || rdr.range(lower.to_vec()..upper.to_vec());
+        if range().nth(2 * rows_per_sample.max(1)).is_none() {
+            return Ok(vec![]);
+        }
+        let first = range().next().unwrap().0;
+        let last = range().next_back().unwrap().0;
+        let mut samples: Vec<Vec<u8>> = vec![];
+        for point in key_space_points(first, last, MEM_SAMPLES) {
+            let sample = match rdr.range(point..upper.to_vec()).next() {
+                Some((k, _)) => k,
+                None => break,
+            };
+            if sample.as_slice() > lower && !matches!(samples.last(), Some(prev) if sample <= prev)
+            {
+                samples.push(sample.clone());
+            }
+        }
+        Ok(samples)
+    }
+
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
@@ -103,14 +162,63 @@ pub enum MemTx<'s> {
     Writer(
         ShardedLockWriteGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>,
         BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        UndoLog<Option<Vec<u8>>>,
     ),
 }
 
+/// Emulates savepoints for changes kept in a `BTreeMap`, by recording the previous values
+/// of keys the first time they are written after each savepoint.
+#[derive(Default)]
+pub struct UndoLog<V> {
+    frames: Vec<BTreeMap<Vec<u8>, Option<V>>>,
+}
+
+impl<V: Clone> UndoLog<V> {
+    /// Must be called before each write to `store`
+    pub(crate) fn record(&mut self, store: &BTreeMap<Vec<u8>, V>, key: &[u8]) {
+        if let Some(frame) = self.frames.last_mut() {
+            if !frame.contains_key(key) {
+                frame.insert(key.to_vec(), store.get(key).cloned());
+            }
+        }
+    }
+    pub(crate) fn set_savepoint(&mut self) {
+        self.frames.push(Default::default())
+    }
+    pub(crate) fn rollback_to_savepoint(&mut self, store: &mut BTreeMap<Vec<u8>, V>) -> Result<()> {
+        match self.frames.pop() {
+            None => bail!("no savepoint to roll back to"),
+            Some(frame) => {
+                for (k, v) in frame {
+                    match v {
+                        None => store.remove(&k),
+                        Some(v) => store.insert(k, v),
+                    };
+                }
+                Ok(())
+            }
+        }
+    }
+    pub(crate) fn pop_savepoint(&mut self) -> Result<()> {
+        match self.frames.pop() {
+            None => bail!("no savepoint to remove"),
+            Some(frame) => {
+                if let Some(parent) = self.frames.last_mut() {
+                    for (k, v) in frame {
+                        parent.entry(k).or_insert(v);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 impl<'s> StoreTx<'s> for MemTx<'s> {
     fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.get(key).cloned(),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
+            MemTx::Writer(wtr, cache, _) => match cache.get(key) {
                 Some(r) => r.clone(),
                 None => wtr.get(key).cloned(),
             },
@@ -122,7 +230,8 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
+            MemTx::Writer(_, cache, undo) => {
+                undo.record(cache, key);
                 cache.insert(key.to_vec(), Some(val.to_vec()));
                 Ok(())
             }
@@ -142,7 +251,8 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
+            MemTx::Writer(_, cache, undo) => {
+                undo.record(cache, key);
                 cache.insert(key.to_vec(), None);
                 Ok(())
             }
@@ -152,7 +262,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.contains_key(key),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
+            MemTx::Writer(wtr, cache, _) => match cache.get(key) {
                 Some(r) => r.is_some(),
                 None => wtr.contains_key(key),
             },
@@ -162,7 +272,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn commit(&mut self) -> Result<()> {
         match self {
             MemTx::Reader(_) => Ok(()),
-            MemTx::Writer(wtr, cached) => {
+            MemTx::Writer(wtr, cached, _) => {
                 let mut cache = BTreeMap::default();
                 mem::swap(&mut cache, cached);
                 for (k, mv) in cache {
@@ -180,6 +290,27 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
         }
     }
 
+    fn set_savepoint(&mut self) -> Result<()> {
+        if let MemTx::Writer(_, _, undo) = self {
+            undo.set_savepoint()
+        }
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        match self {
+            MemTx::Reader(_) => Ok(()),
+            MemTx::Writer(_, cache, undo) => undo.rollback_to_savepoint(cache),
+        }
+    }
+
+    fn pop_savepoint(&mut self) -> Result<()> {
+        match self {
+            MemTx::Reader(_) => Ok(()),
+            MemTx::Writer(_, _, undo) => undo.pop_savepoint(),
+        }
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -193,7 +324,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 rdr.range(lower.to_vec()..upper.to_vec())
                     .map(|(k, v)| Ok(decode_tuple_from_kv(k, v))),
             ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIter {
+            MemTx::Writer(wtr, cache, _) => Box::new(CacheIter {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -218,7 +349,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 }
                 .map(Ok),
             ),
-            MemTx::Writer(stored, delta) => Box::new(
+            MemTx::Writer(stored, delta, _) => Box::new(
                 SkipDualIterator {
                     stored,
                     delta,
@@ -244,7 +375,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 rdr.range(lower.to_vec()..upper.to_vec())
                     .map(|(k, v)| Ok((k.clone(), v.clone()))),
             ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+            MemTx::Writer(wtr, cache, _) => Box::new(CacheIterRaw {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -259,7 +390,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     {
         match self {
             MemTx::Reader(rdr) => Box::new(rdr.iter().map(|(k, v)| Ok((k.clone(), v.clone())))),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+            MemTx::Writer(wtr, cache, _) => Box::new(CacheIterRaw {
                 change_iter: cache.iter().fuse(),
                 db_iter: wtr.iter().fuse(),
                 change_cache: None,
@@ -7,13 +7,14 @@
  */
 
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Result};
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::decode_tuple_from_kv;
 
 pub(crate) mod mem;
+pub(crate) mod overlay;
 #[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
 #[cfg(feature = "storage-sled")]
@@ -45,6 +46,25 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// have the concept of compaction.
     fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()>;
 
+    /// Sample keys within the range, for splitting scans of the range into partitions.
+    /// `lower` is inclusive whereas `upper` is exclusive.
+    ///
+    /// The returned keys must be strictly ascending and lie strictly within the range,
+    /// and should be approximately `rows_per_sample` rows apart.
+    /// They need not be keys that actually exist in the storage,
+    /// and it is OK to return fewer keys than asked for, or none at all.
+    /// The default implementation returns no keys, in which case scans are never split.
+    /// The samples are kept for a few seconds, so the call need not be cheap,
+    /// but it should not walk the whole range.
+    fn sample_keys(
+        &'s self,
+        _lower: &[u8],
+        _upper: &[u8],
+        _rows_per_sample: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        Ok(vec![])
+    }
+
     /// Put multiple key-value pairs into the database.
     /// No duplicate data will be sent, and the order data come in is strictly ascending.
     /// There will be no other access to the database while this function is running.
@@ -62,6 +82,14 @@ pub trait StoreTx<'s>: Sync {
     /// the key has not been modified outside the transaction.
     fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>>;
 
+    /// Get multiple keys in one go, with the same semantics as [`get`](Self::get) for each key.
+    /// The results must be in the same order as the keys.
+    /// The default implementation calls `get` for each key in turn: engines for which
+    /// each call is a round trip should override it.
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get(key, for_update)).collect()
+    }
+
     /// Put a key-value pair into the storage. In case of existing key,
     /// the storage engine needs to overwrite the old value.
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()>;
@@ -87,6 +115,24 @@ pub trait StoreTx<'s>: Sync {
     /// and discard all changes introduced by this transaction.
     fn commit(&mut self) -> Result<()>;
 
+    /// Mark a savepoint in a transaction. Savepoints nest: they are rolled back to or
+    /// removed in the reverse order in which they are set.
+    /// The default implementation returns an error, meaning that the engine does not support
+    /// savepoints.
+    fn set_savepoint(&mut self) -> Result<()> {
+        bail!("savepoints are not supported by this storage engine")
+    }
+
+    /// Discard all changes introduced since the last savepoint, and remove that savepoint.
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        bail!("savepoints are not supported by this storage engine")
+    }
+
+    /// Remove the last savepoint, keeping the changes introduced since it was set.
+    fn pop_savepoint(&mut self) -> Result<()> {
+        bail!("savepoints are not supported by this storage engine")
+    }
+
     /// Scan on a range. `lower` is inclusive whereas `upper` is exclusive.
     /// The default implementation calls [`range_scan_owned`](Self::range_scan) and converts the results.
     ///
@@ -0,0 +1,172 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::iter;
+
+use miette::{bail, Result};
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::{decode_tuple_from_kv, RelationId};
+use crate::storage::mem::UndoLog;
+use crate::storage::StoreTx;
+
+/// A read transaction with an in-memory catalog on top of it, used for checking scripts.
+/// The relations, indices, views and procedures declared by the script are recorded in the
+/// catalog, and are seen by everything read afterwards, but nothing is ever written to the
+/// storage, and other transactions are not held up.
+pub(crate) struct CatalogOverlayTx<'s> {
+    base: Box<dyn StoreTx<'s> + 's>,
+    changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    undo: UndoLog<Option<Vec<u8>>>,
+}
+
+impl<'s> CatalogOverlayTx<'s> {
+    pub(crate) fn new(base: Box<dyn StoreTx<'s> + 's>) -> Self {
+        Self {
+            base,
+            changes: Default::default(),
+            undo: Default::default(),
+        }
+    }
+    fn record(&mut self, key: &[u8], val: Option<Vec<u8>>) -> Result<()> {
+        if !key.starts_with(&RelationId::SYSTEM.raw_encode()) {
+            bail!("only the catalog can be changed when checking a script")
+        }
+        self.undo.record(&self.changes, key);
+        self.changes.insert(key.to_vec(), val);
+        Ok(())
+    }
+}
+
+/// The rows of `base` with `changes` applied. Catalogs are small, so the rows are collected.
+fn merge_changes<'a>(
+    base: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+    changes: impl Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)>,
+) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a> {
+    let mut merged: BTreeMap<_, _> = match base.collect::<Result<_>>() {
+        Ok(merged) => merged,
+        Err(err) => return Box::new(iter::once(Err(err))),
+    };
+    for (k, v) in changes {
+        match v {
+            None => merged.remove(k),
+            Some(v) => merged.insert(k.clone(), v.clone()),
+        };
+    }
+    Box::new(merged.into_iter().map(Ok))
+}
+
+impl<'s> StoreTx<'s> for CatalogOverlayTx<'s> {
+    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
+        match self.changes.get(key) {
+            Some(v) => Ok(v.clone()),
+            None => self.base.get(key, false),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key, Some(val.to_vec()))
+    }
+
+    fn supports_par_put(&self) -> bool {
+        false
+    }
+
+    fn par_put(&self, _key: &[u8], _val: &[u8]) -> Result<()> {
+        panic!()
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.record(key, None)
+    }
+
+    fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
+        match self.changes.get(key) {
+            Some(v) => Ok(v.is_some()),
+            None => self.base.exists(key, false),
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        bail!("the catalog of a checked script is never committed")
+    }
+
+    fn set_savepoint(&mut self) -> Result<()> {
+        self.undo.set_savepoint();
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.undo.rollback_to_savepoint(&mut self.changes)
+    }
+
+    fn pop_savepoint(&mut self) -> Result<()> {
+        self.undo.pop_savepoint()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        let mut changes = self
+            .changes
+            .range(lower.to_vec()..upper.to_vec())
+            .peekable();
+        if changes.peek().is_none() {
+            return self.base.range_scan_tuple(lower, upper);
+        }
+        Box::new(
+            merge_changes(self.base.range_scan(lower, upper), changes)
+                .map(|kv| kv.map(|(k, v)| decode_tuple_from_kv(&k, &v))),
+        )
+    }
+
+    /// Only rows of relations are scanned with validities, and they are never changed.
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.base.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let mut changes = self
+            .changes
+            .range(lower.to_vec()..upper.to_vec())
+            .peekable();
+        if changes.peek().is_none() {
+            return self.base.range_scan(lower, upper);
+        }
+        merge_changes(self.base.range_scan(lower, upper), changes)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        if self.changes.is_empty() {
+            return self.base.total_scan();
+        }
+        merge_changes(self.base.total_scan(), self.changes.iter())
+    }
+}
@@ -142,6 +142,17 @@ impl Storage<'_> for RocksDbStorage {
         self.db.range_compact(lower, upper).into_diagnostic()
     }
 
+    fn sample_keys(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        rows_per_sample: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        // only the SST files are sampled: rows still in the memtables are not counted,
+        // so relations written recently may be split into fewer partitions than they could
+        Ok(self.db.sample_keys(lower, upper, rows_per_sample))
+    }
+
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
@@ -166,6 +177,10 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         Ok(self.db_tx.get(key, for_update)?.map(|v| v.to_vec()))
     }
 
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        Ok(self.db_tx.multi_get(keys, for_update)?)
+    }
+
     #[inline]
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
         Ok(self.db_tx.put(key, val)?)
@@ -193,6 +208,19 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         Ok(self.db_tx.commit()?)
     }
 
+    fn set_savepoint(&mut self) -> Result<()> {
+        self.db_tx.save();
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        Ok(self.db_tx.rollback_to_save()?)
+    }
+
+    fn pop_savepoint(&mut self) -> Result<()> {
+        Ok(self.db_tx.pop_save()?)
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
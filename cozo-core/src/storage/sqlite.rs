@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -157,6 +158,9 @@ const QUERIES: [&str; N_QUERIES] = [
     "select k, v from cozo where k >= ? and k < ? order by k limit 1;",
 ];
 
+/// Keeps the number of bound parameters well below the limits of older Sqlite versions.
+const MULTI_GET_CHUNK_SIZE: usize = 500;
+
 const GET_QUERY: usize = 0;
 const PUT_QUERY: usize = 1;
 const DEL_QUERY: usize = 2;
@@ -193,6 +197,18 @@ impl<'s> SqliteTx<'s> {
             *stmt = Some(prepared)
         }
     }
+    /// Savepoints only make sense in write transactions: read transactions are not wrapped
+    /// in `begin` and `commit`, so a `savepoint` would start a new transaction.
+    fn run_savepoint_query(&self, query: &str) -> Result<()> {
+        if let Right(ShardedLockWriteGuard { .. }) = self.lock {
+            self.conn
+                .as_ref()
+                .unwrap()
+                .execute(query)
+                .into_diagnostic()?;
+        }
+        Ok(())
+    }
 }
 
 impl<'s> StoreTx<'s> for SqliteTx<'s> {
@@ -212,6 +228,31 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
         })
     }
 
+    fn multi_get(&self, keys: &[Vec<u8>], _for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut found = BTreeMap::new();
+        for chunk in keys.chunks(MULTI_GET_CHUNK_SIZE) {
+            let query = format!(
+                "select k, v from cozo where k in ({});",
+                vec!["?"; chunk.len()].join(", ")
+            );
+            let mut statement = self
+                .conn
+                .as_ref()
+                .unwrap()
+                .prepare(query)
+                .into_diagnostic()?;
+            for (i, key) in chunk.iter().enumerate() {
+                statement.bind((i + 1, &key[..])).into_diagnostic()?;
+            }
+            while statement.next().into_diagnostic()? == State::Row {
+                let k = statement.read::<Vec<u8>, _>(0).into_diagnostic()?;
+                let v = statement.read::<Vec<u8>, _>(1).into_diagnostic()?;
+                found.insert(k, v);
+            }
+        }
+        Ok(keys.iter().map(|key| found.get(key).cloned()).collect())
+    }
+
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
         self.par_put(key, val)
     }
@@ -271,6 +312,19 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
         Ok(())
     }
 
+    fn set_savepoint(&mut self) -> Result<()> {
+        self.run_savepoint_query("savepoint cozo_savepoint;")
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        // unlike in the other engines, a Sqlite savepoint survives being rolled back to
+        self.run_savepoint_query("rollback to cozo_savepoint; release cozo_savepoint;")
+    }
+
+    fn pop_savepoint(&mut self) -> Result<()> {
+        self.run_savepoint_query("release cozo_savepoint;")
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
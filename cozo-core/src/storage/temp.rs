@@ -14,7 +14,7 @@ use miette::Result;
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
-use crate::storage::mem::SkipIterator;
+use crate::storage::mem::{SkipIterator, UndoLog};
 use crate::storage::{Storage, StoreTx};
 
 #[derive(Default, Clone)]
@@ -30,6 +30,7 @@ impl<'s> Storage<'s> for TempStorage {
     fn transact(&'s self, _write: bool) -> Result<Self::Tx> {
         Ok(TempTx {
             store: Default::default(),
+            undo: Default::default(),
         })
     }
 
@@ -51,6 +52,7 @@ impl<'s> Storage<'s> for TempStorage {
 
 pub(crate) struct TempTx {
     store: BTreeMap<Vec<u8>, Vec<u8>>,
+    undo: UndoLog<Vec<u8>>,
 }
 
 impl<'s> StoreTx<'s> for TempTx {
@@ -59,6 +61,7 @@ impl<'s> StoreTx<'s> for TempTx {
     }
 
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.undo.record(&self.store, key);
         self.store.insert(key.to_vec(), val.to_vec());
         Ok(())
     }
@@ -72,6 +75,7 @@ impl<'s> StoreTx<'s> for TempTx {
     }
 
     fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.undo.record(&self.store, key);
         self.store.remove(key);
         Ok(())
     }
@@ -84,6 +88,19 @@ impl<'s> StoreTx<'s> for TempTx {
         Ok(())
     }
 
+    fn set_savepoint(&mut self) -> Result<()> {
+        self.undo.set_savepoint();
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.undo.rollback_to_savepoint(&mut self.store)
+    }
+
+    fn pop_savepoint(&mut self) -> Result<()> {
+        self.undo.pop_savepoint()
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
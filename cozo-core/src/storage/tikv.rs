@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::ops::Bound::{Excluded, Included};
 use std::sync::{Arc, Mutex};
 use std::{iter, thread};
@@ -123,6 +124,24 @@ impl<'s> StoreTx<'s> for TiKvTx {
         }
     }
 
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut tx = self.tx.lock().unwrap();
+        let pairs = if for_update {
+            RT.block_on(tx.batch_get_for_update(keys.to_vec()))
+                .into_diagnostic()?
+        } else {
+            RT.block_on(tx.batch_get(keys.to_vec()))
+                .into_diagnostic()?
+                .collect_vec()
+        };
+        // only the pairs found are returned, in no particular order
+        let found: BTreeMap<Vec<u8>, Vec<u8>> = pairs
+            .into_iter()
+            .map(|pair| (Vec::from(pair.key().clone()), pair.into_value()))
+            .collect();
+        Ok(keys.iter().map(|key| found.get(key).cloned()).collect())
+    }
+
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
         self.par_put(key, val)
     }
@@ -6,6 +6,12 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::{Cell, RefCell};
+
+use rand::prelude::*;
+
+use crate::data::program::ArithMode;
+
 #[inline(always)]
 pub(crate) fn swap_option_result<T, E>(d: Result<Option<T>, E>) -> Option<Result<T, E>> {
     match d {
@@ -14,3 +20,47 @@ pub(crate) fn swap_option_result<T, E>(d: Result<Option<T>, E>) -> Option<Result
         Err(e) => Some(Err(e)),
     }
 }
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+    static ARITH_MODE: Cell<ArithMode> = const { Cell::new(ArithMode::Checked) };
+}
+
+/// Run `f` with the random functions and aggregations of the current thread drawing from
+/// an RNG seeded by `seed`, or from the thread RNG if `seed` is `None`.
+pub(crate) fn with_seeded_rng<T>(seed: Option<u64>, f: impl FnOnce() -> T) -> T {
+    let prev = SEEDED_RNG.with(|r| r.replace(seed.map(StdRng::seed_from_u64)));
+    let ret = f();
+    SEEDED_RNG.with(|r| r.replace(prev));
+    ret
+}
+
+/// Call `f` with the seeded RNG set by [`with_seeded_rng`] if there is one,
+/// otherwise with the thread RNG. `f` must not itself call `with_rng`.
+pub(crate) fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    SEEDED_RNG.with(|r| match r.borrow_mut().as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut thread_rng()),
+    })
+}
+
+/// The arithmetic mode of the integer operations and aggregations on the current thread.
+pub(crate) fn arith_mode() -> ArithMode {
+    ARITH_MODE.with(|m| m.get())
+}
+
+/// Sets the arithmetic mode of the current thread, restoring the previous one when dropped.
+/// Work sent to other threads must set the mode there again.
+pub(crate) struct ArithModeGuard(ArithMode);
+
+impl ArithModeGuard {
+    pub(crate) fn set(mode: ArithMode) -> Self {
+        Self(ARITH_MODE.with(|m| m.replace(mode)))
+    }
+}
+
+impl Drop for ArithModeGuard {
+    fn drop(&mut self) {
+        ARITH_MODE.with(|m| m.set(self.0))
+    }
+}
@@ -1059,7 +1059,7 @@ fn len_of_names_count() {
 
     assert_eq!(
         rows["rows"],
-        serde_json::Value::from_str(r#"[[891.0]]"#).unwrap()
+        serde_json::Value::from_str(r#"[[891]]"#).unwrap()
     );
     dbg!(len_of_names_count.elapsed());
 }
@@ -40,6 +40,9 @@ const {CozoDb} = require('.');
         console.error(e.display);
     }
 
+    db.registerFunction('triple', 1, (x) => x * 3)
+    console.log((await db.run('?[a, b] := a in [1, 2], b = triple(a)')).rows)
+
     console.log((await db.exportRelations(['test']))['test']['rows'])
 
     const tx = db.multiTransact(true);
@@ -58,6 +61,7 @@ const {CozoDb} = require('.');
 
     db.unregisterCallback(cb_id)
     db.unregisterNamedRule('Pipipy')
+    db.unregisterFunction('triple')
 })()
 
 function sleep(ms) {
@@ -36,6 +36,56 @@ class CozoTx {
     commit() {
         return native.commit_tx(this.tx_id)
     }
+
+    savepoint(name) {
+        return native.savepoint_tx(this.tx_id, name)
+    }
+
+    rollbackTo(name) {
+        return native.rollback_to_tx(this.tx_id, name)
+    }
+
+    release(name) {
+        return native.release_tx(this.tx_id, name)
+    }
+}
+
+class CozoRowsIter {
+    constructor(id, headers, chunkSize) {
+        this.iter_id = id;
+        this.headers = headers;
+        this.chunkSize = chunkSize;
+    }
+
+    nextRows() {
+        return new Promise((resolve, reject) => {
+            native.next_rows(this.iter_id, this.chunkSize, (err, rows) => {
+                if (err) {
+                    reject(JSON.parse(err))
+                } else {
+                    resolve(rows)
+                }
+            })
+        })
+    }
+
+    close() {
+        return native.close_iter(this.iter_id)
+    }
+
+    async* [Symbol.asyncIterator]() {
+        try {
+            while (true) {
+                const rows = await this.nextRows();
+                if (rows.length === 0) {
+                    return
+                }
+                yield* rows
+            }
+        } finally {
+            this.close()
+        }
+    }
 }
 
 class CozoDb {
@@ -64,6 +114,20 @@ class CozoDb {
         })
     }
 
+    runIter(script, params, chunkSize) {
+        chunkSize = chunkSize || 1024;
+        return new Promise((resolve, reject) => {
+            params = params || {};
+            native.query_db_iter(this.db_id, script, params, (err, result) => {
+                if (err) {
+                    reject(JSON.parse(err))
+                } else {
+                    resolve(new CozoRowsIter(result.id, result.headers, chunkSize))
+                }
+            })
+        })
+    }
+
     exportRelations(relations, as_objects) {
         return new Promise((resolve, reject) => {
             native.export_relations(this.db_id, relations, (err, data) => {
@@ -153,6 +217,20 @@ class CozoDb {
     unregisterNamedRule(name) {
         return native.unregister_named_rule(this.db_id, name)
     }
+
+    registerFunction(name, arity, cb) {
+        return native.register_function(this.db_id, name, arity, (...args) => {
+            try {
+                return [true, cb(...args)]
+            } catch (e) {
+                return [false, '' + e]
+            }
+        })
+    }
+
+    unregisterFunction(name) {
+        return native.unregister_function(this.db_id, name)
+    }
 }
 
 module.exports = {CozoDb: CozoDb}
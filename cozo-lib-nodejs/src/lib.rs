@@ -10,7 +10,7 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use crossbeam::channel::Sender;
+use crossbeam::channel::{bounded, Sender};
 use lazy_static::lazy_static;
 use miette::{miette, Result};
 use neon::prelude::*;
@@ -45,6 +45,10 @@ fn named_rows2js<'a>(cx: &mut impl Context<'a>, nr: &NamedRows) -> JsResult<'a,
     ret.set(cx, "headers", headers)?;
     let rows = rows2js(cx, &nr.rows)?;
     ret.set(cx, "rows", rows)?;
+    if let Some(cursor) = &nr.cursor {
+        let cursor = cx.string(cursor);
+        ret.set(cx, "cursor", cursor)?;
+    }
     Ok(ret)
 }
 
@@ -93,6 +97,8 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
         DataValue::Num(n) => match n {
             Num::Int(i) => cx.number(*i as f64).as_value(cx),
             Num::Float(f) => cx.number(*f).as_value(cx),
+            // as strings, since JS numbers would lose precision
+            Num::Decimal(d) => cx.string(d.to_string()).as_value(cx),
         },
         DataValue::Str(s) => cx.string(s).as_value(cx),
         DataValue::Bytes(b) => {
@@ -125,7 +131,7 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
             target_l.set(cx, 1, a)?;
             target_l.as_value(cx)
         }
-        DataValue::Bot => cx.undefined().as_value(cx),
+        DataValue::Bot | DataValue::Param(_) => cx.undefined().as_value(cx),
     })
 }
 
@@ -209,6 +215,8 @@ struct Handles {
     current_cbs: Mutex<BTreeMap<u32, Sender<Result<NamedRows>>>>,
     nxt_tx_id: AtomicU32,
     txs: Mutex<BTreeMap<u32, Arc<MultiTransaction>>>,
+    nxt_iter_id: AtomicU32,
+    iters: Mutex<BTreeMap<u32, Arc<Mutex<NamedRowsIter>>>>,
 }
 
 lazy_static! {
@@ -335,6 +343,42 @@ fn commit_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     }
 }
 
+fn savepoint_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let tx = get_tx!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    match tx.savepoint(&name) {
+        Ok(_) => Ok(cx.undefined()),
+        Err(err) => {
+            let msg = cx.string(err.to_string());
+            cx.throw(msg)
+        }
+    }
+}
+
+fn rollback_to_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let tx = get_tx!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    match tx.rollback_to(&name) {
+        Ok(_) => Ok(cx.undefined()),
+        Err(err) => {
+            let msg = cx.string(err.to_string());
+            cx.throw(msg)
+        }
+    }
+}
+
+fn release_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let tx = get_tx!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    match tx.release(&name) {
+        Ok(_) => Ok(cx.undefined()),
+        Err(err) => {
+            let msg = cx.string(err.to_string());
+            cx.throw(msg)
+        }
+    }
+}
+
 fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let db = get_db!(cx);
     let query = cx.argument::<JsString>(1)?.value(&mut cx);
@@ -416,6 +460,108 @@ fn query_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     }
 }
 
+fn query_db_iter(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let db = get_db!(cx);
+    let query = cx.argument::<JsString>(1)?.value(&mut cx);
+    let params_js = cx.argument::<JsObject>(2)?;
+    let mut params = BTreeMap::new();
+    js2params(&mut cx, params_js, &mut params)?;
+
+    let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+
+    let channel = cx.channel();
+
+    thread::spawn(move || {
+        let result = db.run_script_iter(&query, params).map(|iter| {
+            let headers = iter.headers.clone();
+            let id = HANDLES.nxt_iter_id.fetch_add(1, Ordering::AcqRel);
+            HANDLES
+                .iters
+                .lock()
+                .unwrap()
+                .insert(id, Arc::new(Mutex::new(iter)));
+            (id, headers)
+        });
+        channel.send(move |mut cx| {
+            let callback = callback.into_inner(&mut cx);
+            let this = cx.undefined();
+            match result {
+                Ok((id, headers)) => {
+                    let obj = cx.empty_object();
+                    let id = cx.number(id);
+                    obj.set(&mut cx, "id", id)?;
+                    let js_headers = cx.empty_array();
+                    for (i, header) in headers.iter().enumerate() {
+                        let header = cx.string(header);
+                        js_headers.set(&mut cx, i as u32, header)?;
+                    }
+                    obj.set(&mut cx, "headers", js_headers)?;
+                    let obj = obj.as_value(&mut cx);
+                    let err = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, obj])?;
+                }
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(&query)).to_string();
+                    let err = cx.string(&reports).as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err])?;
+                }
+            }
+            Ok(())
+        });
+    });
+
+    Ok(cx.undefined())
+}
+
+/// Pull at most the given number of rows from the iterator. An empty array of rows
+/// means the iterator is exhausted, and it is closed.
+fn next_rows(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let n = cx.argument::<JsNumber>(1)?.value(&mut cx) as usize;
+    let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+    let iter = match HANDLES.iters.lock().unwrap().get(&id).cloned() {
+        None => {
+            let s = cx.string("iterator closed");
+            return cx.throw(s);
+        }
+        Some(iter) => iter,
+    };
+
+    let channel = cx.channel();
+
+    thread::spawn(move || {
+        let result: Result<Vec<_>> = iter.lock().unwrap().by_ref().take(n).collect();
+        if !matches!(&result, Ok(rows) if !rows.is_empty()) {
+            HANDLES.iters.lock().unwrap().remove(&id);
+        }
+        channel.send(move |mut cx| {
+            let callback = callback.into_inner(&mut cx);
+            let this = cx.undefined();
+            match result {
+                Ok(rows) => {
+                    let js_vals = rows2js(&mut cx, &rows)?.as_value(&mut cx);
+                    let err = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, js_vals])?;
+                }
+                Err(err) => {
+                    let reports = format_error_as_json(err, None).to_string();
+                    let err = cx.string(&reports).as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err])?;
+                }
+            }
+            Ok(())
+        });
+    });
+
+    Ok(cx.undefined())
+}
+
+fn close_iter(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let iter = HANDLES.iters.lock().unwrap().remove(&id);
+    Ok(cx.boolean(iter.is_some()))
+}
+
 fn backup_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let db = get_db!(cx);
     let path = cx.argument::<JsString>(1)?.value(&mut cx);
@@ -702,11 +848,72 @@ fn unregister_named_rule(mut cx: FunctionContext) -> JsResult<JsBoolean> {
     Ok(cx.boolean(removed))
 }
 
+fn register_function(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let db = get_db!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let arity = cx.argument::<JsNumber>(2)?.value(&mut cx) as usize;
+    let callback = Arc::new(cx.argument::<JsFunction>(3)?.root(&mut cx));
+    let channel = cx.channel();
+    let func = move |args: &[DataValue]| -> Result<DataValue> {
+        // the callback runs on the Javascript thread, while the query waits for its result
+        let (sender, receiver) = bounded(1);
+        let cb = callback.clone();
+        let args = args.to_vec();
+        channel.send(move |mut cx| {
+            let callback = cb.to_inner(&mut cx);
+            let mut args_js = Vec::with_capacity(args.len());
+            for arg in &args {
+                args_js.push(value2js(&mut cx, arg)?);
+            }
+            let this = cx.undefined();
+            // the callback returns [true, result] on success and [false, message] on failure
+            let ret: Handle<JsArray> = callback
+                .call(&mut cx, this, args_js)?
+                .downcast_or_throw(&mut cx)?;
+            let ok: Handle<JsBoolean> = ret.get(&mut cx, 0)?;
+            let payload: Handle<JsValue> = ret.get(&mut cx, 1)?;
+            if ok.value(&mut cx) {
+                let mut val = DataValue::Null;
+                js2value(&mut cx, payload, &mut val)?;
+                let _ = sender.send(Ok(val));
+            } else {
+                let msg = payload.to_string(&mut cx)?.value(&mut cx);
+                let _ = sender.send(Err(miette!(msg)));
+            }
+            Ok(())
+        });
+        receiver
+            .recv()
+            .map_err(|_| miette!("Javascript function failed"))?
+    };
+    if let Err(err) = db.register_function(name, arity, func) {
+        let msg = cx.string(err.to_string());
+        return cx.throw(msg);
+    }
+    Ok(cx.undefined())
+}
+
+fn unregister_function(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let db = get_db!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let removed = match db.unregister_function(&name) {
+        Ok(b) => b,
+        Err(msg) => {
+            let msg = cx.string(msg.to_string());
+            return cx.throw(msg);
+        }
+    };
+    Ok(cx.boolean(removed))
+}
+
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("open_db", open_db)?;
     cx.export_function("close_db", close_db)?;
     cx.export_function("query_db", query_db)?;
+    cx.export_function("query_db_iter", query_db_iter)?;
+    cx.export_function("next_rows", next_rows)?;
+    cx.export_function("close_iter", close_iter)?;
     cx.export_function("backup_db", backup_db)?;
     cx.export_function("restore_db", restore_db)?;
     cx.export_function("export_relations", export_relations)?;
@@ -720,8 +927,13 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
         respond_to_named_rule_invocation,
     )?;
     cx.export_function("unregister_named_rule", unregister_named_rule)?;
+    cx.export_function("register_function", register_function)?;
+    cx.export_function("unregister_function", unregister_function)?;
     cx.export_function("abort_tx", abort_tx)?;
     cx.export_function("commit_tx", commit_tx)?;
+    cx.export_function("savepoint_tx", savepoint_tx)?;
+    cx.export_function("rollback_to_tx", rollback_to_tx)?;
+    cx.export_function("release_tx", release_tx)?;
     cx.export_function("multi_transact", multi_transact)?;
     cx.export_function("query_tx", query_tx)?;
     Ok(())
@@ -102,6 +102,7 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
         DataValue::Num(num) => match num {
             Num::Int(i) => i.into_py(py),
             Num::Float(f) => f.into_py(py),
+            Num::Decimal(d) => d.to_string().into_py(py),
         },
         DataValue::Str(s) => s.as_str().into_py(py),
         DataValue::Bytes(b) => PyBytes::new(py, &b).into(),
@@ -118,7 +119,7 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
         DataValue::Validity(vld) => {
             [vld.timestamp.0 .0.into_py(py), vld.is_assert.0.into_py(py)].into_py(py)
         }
-        DataValue::Bot => py.None(),
+        DataValue::Bot | DataValue::Param(_) => py.None(),
     }
 }
 
@@ -141,7 +142,14 @@ fn named_rows_to_py(named_rows: NamedRows, py: Python<'_>) -> PyObject {
         None => py.None(),
         Some(nxt) => named_rows_to_py(*nxt, py),
     };
-    BTreeMap::from([("rows", rows), ("headers", headers), ("next", next)]).into_py(py)
+    let cursor = named_rows.cursor.into_py(py);
+    BTreeMap::from([
+        ("rows", rows),
+        ("headers", headers),
+        ("next", next),
+        ("cursor", cursor),
+    ])
+    .into_py(py)
 }
 
 #[pyclass]
@@ -154,6 +162,13 @@ struct CozoDbMulTx {
     tx: MultiTransaction,
 }
 
+#[pyclass]
+struct CozoRowsIter {
+    #[pyo3(get)]
+    headers: Vec<String>,
+    rows: NamedRowsIter,
+}
+
 const DB_CLOSED_MSG: &str = r##"{"ok":false,"message":"database closed"}"##;
 
 #[pymethods]
@@ -183,6 +198,32 @@ impl CozoDbPy {
             Err(PyException::new_err(DB_CLOSED_MSG))
         }
     }
+    pub fn run_script_iter(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+    ) -> PyResult<CozoRowsIter> {
+        if let Some(db) = &self.db {
+            let params = convert_params(params)?;
+            match py.allow_threads(|| db.run_script_iter(query, params)) {
+                Ok(rows) => Ok(CozoRowsIter {
+                    headers: rows.headers.clone(),
+                    rows,
+                }),
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(query)).to_string();
+                    let json_mod = py.import("json")?;
+                    let loads_fn = json_mod.getattr("loads")?;
+                    let args = PyTuple::new(py, [PyString::new(py, &reports)]);
+                    let msg = loads_fn.call1(args)?;
+                    Err(PyException::new_err(PyObject::from(msg)))
+                }
+            }
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
     pub fn register_callback(&self, rel: &str, callback: &PyAny) -> PyResult<u32> {
         if let Some(db) = &self.db {
             let cb: Py<PyAny> = callback.into();
@@ -231,6 +272,32 @@ impl CozoDbPy {
             Err(PyException::new_err(DB_CLOSED_MSG))
         }
     }
+    pub fn register_function(&self, name: String, arity: usize, callback: &PyAny) -> PyResult<()> {
+        if let Some(db) = &self.db {
+            let cb: Py<PyAny> = callback.into();
+            db.register_function(name, arity, move |args| -> Result<DataValue> {
+                Python::with_gil(|py| {
+                    let py_args =
+                        PyTuple::new(py, args.iter().map(|v| value_to_py(v.clone(), py)));
+                    let res = cb.as_ref(py).call1(py_args).into_diagnostic()?;
+                    py_to_value(res).into_diagnostic()
+                })
+            })
+            .map_err(report2py)
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
+    pub fn unregister_function(&self, name: &str) -> PyResult<bool> {
+        if let Some(db) = &self.db {
+            match db.unregister_function(name) {
+                Ok(b) => Ok(b),
+                Err(err) => Err(PyException::new_err(err.to_string())),
+            }
+        } else {
+            Ok(false)
+        }
+    }
     pub fn unregister_callback(&self, id: u32) -> bool {
         if let Some(db) = &self.db {
             db.unregister_callback(id)
@@ -331,6 +398,21 @@ impl CozoDbMulTx {
             .commit()
             .map_err(|err| PyException::new_err(err.to_string()))
     }
+    pub fn savepoint(&self, name: &str) -> PyResult<()> {
+        self.tx
+            .savepoint(name)
+            .map_err(|err| PyException::new_err(err.to_string()))
+    }
+    pub fn rollback_to(&self, name: &str) -> PyResult<()> {
+        self.tx
+            .rollback_to(name)
+            .map_err(|err| PyException::new_err(err.to_string()))
+    }
+    pub fn release(&self, name: &str) -> PyResult<()> {
+        self.tx
+            .release(name)
+            .map_err(|err| PyException::new_err(err.to_string()))
+    }
     pub fn run_script(&self, py: Python<'_>, query: &str, params: &PyDict) -> PyResult<PyObject> {
         let params = convert_params(params)?;
         match py.allow_threads(|| self.tx.run_script(query, params)) {
@@ -347,9 +429,30 @@ impl CozoDbMulTx {
     }
 }
 
+#[pymethods]
+impl CozoRowsIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let rows = &mut slf.rows;
+        match py.allow_threads(|| rows.next()) {
+            None => Ok(None),
+            Some(Ok(row)) => Ok(Some(
+                row.into_iter()
+                    .map(|val| value_to_py(val, py))
+                    .collect::<Vec<_>>()
+                    .into_py(py),
+            )),
+            Some(Err(err)) => Err(report2py(err)),
+        }
+    }
+}
+
 #[pymodule]
 fn cozo_embedded(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<CozoDbPy>()?;
     m.add_class::<CozoDbMulTx>()?;
+    m.add_class::<CozoRowsIter>()?;
     Ok(())
 }
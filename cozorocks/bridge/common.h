@@ -23,6 +23,8 @@ using namespace std;
 
 struct RocksDbStatus;
 struct DbOpts;
+struct KeySample;
+struct MultiGetValue;
 
 typedef Status::Code StatusCode;
 typedef Status::SubCode StatusSubCode;
@@ -6,6 +6,7 @@
 
 #include <iostream>
 #include <memory>
+#include <algorithm>
 #include "db.h"
 #include "cozorocks/src/bridge/mod.rs.h"
 #include "rocksdb/utilities/options_util.h"
@@ -135,6 +136,43 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
     return db;
 }
 
+rust::Vec<KeySample>
+RocksDbBridge::sample_keys(RustBytes lower, RustBytes upper, size_t rows_per_sample) const {
+    // the smallest keys of the SST files serve as the samples, so that no data has to be read
+    auto lower_s = convert_slice(lower);
+    auto upper_s = convert_slice(upper);
+    vector<LiveFileMetaData> files;
+    get_base_db()->GetLiveFilesMetaData(&files);
+    vector<pair<string, uint64_t>> candidates;
+    for (auto &file: files) {
+        if (file.column_family_name != kDefaultColumnFamilyName) {
+            continue;
+        }
+        Slice key(file.smallestkey);
+        if (key.compare(lower_s) > 0 && key.compare(upper_s) < 0) {
+            candidates.emplace_back(file.smallestkey, file.num_entries);
+        }
+    }
+    sort(candidates.begin(), candidates.end());
+
+    rust::Vec<KeySample> ret;
+    uint64_t accumulated = 0;
+    string last;
+    for (auto &candidate: candidates) {
+        accumulated += candidate.second;
+        if (accumulated >= rows_per_sample && (ret.empty() || candidate.first > last)) {
+            KeySample sample;
+            for (auto c: candidate.first) {
+                sample.key.push_back(static_cast<uint8_t>(c));
+            }
+            ret.push_back(std::move(sample));
+            last = candidate.first;
+            accumulated = 0;
+        }
+    }
+    return ret;
+}
+
 RocksDbBridge::~RocksDbBridge() {
     if (destroy_on_exit && (db != nullptr)) {
         cerr << "destroying database on exit: " << db_path << endl;
@@ -101,6 +101,8 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
+    rust::Vec<KeySample> sample_keys(RustBytes lower, RustBytes upper, size_t rows_per_sample) const;
+
     void compact_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
         CompactRangeOptions options;
         auto cf = db->DefaultColumnFamily();
@@ -16,4 +16,45 @@ void TxBridge::start() {
         tx.reset(txn);
     }
     assert(tx);
-}
\ No newline at end of file
+}
+rust::Vec<MultiGetValue>
+TxBridge::multi_get(RustBytes keys, rust::Slice<const size_t> key_lens, bool for_update,
+                    RocksDbStatus &status) const {
+    // `keys` holds all the keys concatenated, with their lengths given in `key_lens`
+    vector<Slice> keys_;
+    keys_.reserve(key_lens.size());
+    size_t offset = 0;
+    for (auto len: key_lens) {
+        keys_.emplace_back(reinterpret_cast<const char *>(keys.data()) + offset, len);
+        offset += len;
+    }
+    vector<PinnableSlice> values(keys_.size());
+    vector<Status> statuses(keys_.size());
+    if (for_update) {
+        for (size_t i = 0; i < keys_.size(); ++i) {
+            statuses[i] = tx->GetForUpdate(*r_opts, cf_handle, keys_[i], &values[i]);
+        }
+    } else {
+        tx->MultiGet(*r_opts, cf_handle, keys_.size(), keys_.data(), values.data(), statuses.data());
+    }
+
+    rust::Vec<MultiGetValue> ret;
+    ret.reserve(keys_.size());
+    for (size_t i = 0; i < keys_.size(); ++i) {
+        MultiGetValue item;
+        if (statuses[i].ok()) {
+            item.found = true;
+            item.value.reserve(values[i].size());
+            for (size_t j = 0; j < values[i].size(); ++j) {
+                item.value.push_back(static_cast<uint8_t>(values[i].data()[j]));
+            }
+        } else if (statuses[i].IsNotFound()) {
+            item.found = false;
+        } else {
+            write_status(statuses[i], status);
+            return ret;
+        }
+        ret.push_back(std::move(item));
+    }
+    return ret;
+}
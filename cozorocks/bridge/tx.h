@@ -105,6 +105,9 @@ struct TxBridge {
         }
     }
 
+    rust::Vec<MultiGetValue>
+    multi_get(RustBytes keys, rust::Slice<const size_t> key_lens, bool for_update, RocksDbStatus &status) const;
+
     inline void put(RustBytes key, RustBytes val, RocksDbStatus &status) const {
         write_status(tx->Put(convert_slice(key), convert_slice(val)), status);
     }
@@ -177,6 +177,14 @@ impl RocksDb {
             Err(status)
         }
     }
+    #[inline]
+    pub fn sample_keys(&self, lower: &[u8], upper: &[u8], rows_per_sample: usize) -> Vec<Vec<u8>> {
+        self.inner
+            .sample_keys(lower, upper, rows_per_sample)
+            .into_iter()
+            .map(|sample| sample.key)
+            .collect()
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
@@ -51,6 +51,17 @@ pub(crate) mod ffi {
         pub message: String,
     }
 
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct KeySample {
+        pub key: Vec<u8>,
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct MultiGetValue {
+        pub found: bool,
+        pub value: Vec<u8>,
+    }
+
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum StatusCode {
         kOk = 0,
@@ -126,6 +137,12 @@ pub(crate) mod ffi {
         fn transact(self: &RocksDbBridge) -> UniquePtr<TxBridge>;
         fn del_range(self: &RocksDbBridge, lower: &[u8], upper: &[u8], status: &mut RocksDbStatus);
         fn put(self: &RocksDbBridge, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
+        fn sample_keys(
+            self: &RocksDbBridge,
+            lower: &[u8],
+            upper: &[u8],
+            rows_per_sample: usize,
+        ) -> Vec<KeySample>;
         fn compact_range(
             self: &RocksDbBridge,
             lower: &[u8],
@@ -163,6 +180,13 @@ pub(crate) mod ffi {
             status: &mut RocksDbStatus,
         ) -> UniquePtr<PinnableSlice>;
         fn exists(self: &TxBridge, key: &[u8], for_update: bool, status: &mut RocksDbStatus);
+        fn multi_get(
+            self: &TxBridge,
+            keys: &[u8],
+            key_lens: &[usize],
+            for_update: bool,
+            status: &mut RocksDbStatus,
+        ) -> Vec<MultiGetValue>;
         fn put(self: &TxBridge, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
         fn del(self: &TxBridge, key: &[u8], status: &mut RocksDbStatus);
         fn commit(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
@@ -137,6 +137,27 @@ impl Tx {
             _ => Err(status),
         }
     }
+    /// Get multiple keys in one go. The results are in the same order as the keys.
+    pub fn multi_get(
+        &self,
+        keys: &[Vec<u8>],
+        for_update: bool,
+    ) -> Result<Vec<Option<Vec<u8>>>, RocksDbStatus> {
+        let key_lens = keys.iter().map(|k| k.len()).collect::<Vec<_>>();
+        let concatenated = keys.concat();
+        let mut status = RocksDbStatus::default();
+        let ret = self
+            .inner
+            .multi_get(&concatenated, &key_lens, for_update, &mut status);
+        if status.is_ok() {
+            Ok(ret
+                .into_iter()
+                .map(|v| if v.found { Some(v.value) } else { None })
+                .collect())
+        } else {
+            Err(status)
+        }
+    }
     #[inline]
     pub fn commit(&mut self) -> Result<(), RocksDbStatus> {
         let mut status = RocksDbStatus::default();